@@ -0,0 +1,89 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use liblisp::arena::Arena;
+use liblisp::eval::*;
+use liblisp::expression::*;
+use std::convert::TryFrom;
+
+// 深さ depth の (list (list ... ) 1) という入れ子のリストを作る
+fn nested_list_source(depth: usize) -> String {
+    let mut src = String::new();
+    for _ in 0..depth {
+        src.push_str("(list ");
+    }
+    src.push('1');
+    for _ in 0..depth {
+        src.push_str(" 1)");
+    }
+    return src;
+}
+
+// 深さ depth の (add 1 (add 1 ... 0)) という式を作る
+fn deep_add_source(depth: usize) -> String {
+    let mut src = String::new();
+    for _ in 0..depth {
+        src.push_str("(add 1 ");
+    }
+    src.push('0');
+    for _ in 0..depth {
+        src.push(')');
+    }
+    return src;
+}
+
+// 要素数 n の (list 0 1 2 ... ) という式を作る
+fn long_list_source(n: usize) -> String {
+    let mut src = String::from("(list");
+    for i in 0..n {
+        src.push_str(&format!(" {}", i));
+    }
+    src.push(')');
+    return src;
+}
+
+fn parse_benchmark(c: &mut Criterion) {
+    let nested = nested_list_source(200);
+    c.bench_function("parse nested list (depth 200)", |b| {
+        b.iter(|| Expression::try_from(black_box(nested.as_bytes())).unwrap())
+    });
+
+    let long = long_list_source(1000);
+    c.bench_function("parse long list (1000 elements)", |b| {
+        b.iter(|| Expression::try_from(black_box(long.as_bytes())).unwrap())
+    });
+    c.bench_function("parse long list into arena (1000 elements)", |b| {
+        b.iter(|| {
+            let arena = Arena::new();
+            black_box(Expression::parse_in(&arena, black_box(&long)).unwrap());
+            return arena.len();
+        })
+    });
+}
+
+fn eval_benchmark(c: &mut Criterion) {
+    let while_sum = "(progn (set *i* 0) (set *a* 0) (while (lt *i* 1000) (progn (set *a* (add *i* *a*)) (set *i* (add *i* 1)))) *a*)";
+    let exp = Expression::try_from(while_sum.as_bytes()).unwrap();
+    c.bench_function("eval while sum (1000 iterations)", |b| {
+        b.iter(|| eval(black_box(&exp)).unwrap())
+    });
+
+    let deep = deep_add_source(500);
+    let exp = Expression::try_from(deep.as_bytes()).unwrap();
+    c.bench_function("eval deep recursion (depth 500)", |b| {
+        b.iter(|| eval(black_box(&exp)).unwrap())
+    });
+
+    let long = long_list_source(1000);
+    let exp = Expression::try_from(long.as_bytes()).unwrap();
+    c.bench_function("eval list construction (1000 elements)", |b| {
+        b.iter(|| eval(black_box(&exp)).unwrap())
+    });
+
+    let nested = nested_list_source(200);
+    let exp = Expression::try_from(nested.as_bytes()).unwrap();
+    c.bench_function("eval nested list construction (depth 200)", |b| {
+        b.iter(|| eval(black_box(&exp)).unwrap())
+    });
+}
+
+criterion_group!(benches, parse_benchmark, eval_benchmark);
+criterion_main!(benches);
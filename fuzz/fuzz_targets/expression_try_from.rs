@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use liblisp::expression::*;
+use std::convert::TryFrom;
+
+// 任意の byte 列を読み込ませ、panic しないことを確認する
+fuzz_target!(|data: &[u8]| {
+    let _ = Expression::try_from(data);
+});
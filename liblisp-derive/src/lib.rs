@@ -0,0 +1,129 @@
+//!
+//! liblisp の `ToLisp` / `FromLisp` を構造体に実装する derive マクロ
+//!
+//! 名前付きフィールドを持つ構造体を、`((:field value) ...)` の形式の連想リストと相互変換する。
+//! キーはフィールド名の `_` を `-` に置き換えたキーワードになる（`max_size` なら `:max-size`）
+//!
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident};
+
+/// 構造体を、フィールドの宣言順に並べた連想リストに変換する `ToLisp` を実装する
+#[proc_macro_derive(ToLisp)]
+pub fn derive_to_lisp(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match named_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let entries = fields.iter().map(|f| {
+        let key = keyword_name(f);
+        return quote! {
+            (
+                ::liblisp::types::Type::Keyword(#key.into()),
+                ::liblisp::convert::ToLisp::to_lisp(&self.#f),
+            )
+        };
+    });
+
+    let expanded = quote! {
+        impl #impl_generics ::liblisp::convert::ToLisp for #name #ty_generics #where_clause {
+            fn to_lisp(&self) -> ::liblisp::types::Type {
+                let entries: &[(::liblisp::types::Type, ::liblisp::types::Type)] = &[#(#entries),*];
+                return ::liblisp::convert::ToLisp::to_lisp(entries);
+            }
+        }
+    };
+    return expanded.into();
+}
+
+/// 連想リスト（もしくは Vector）から構造体を作る `FromLisp` を実装する。
+///
+/// キーの順序は問わない。キーが無いフィールドは `Void` から変換するので、`Option` のフィールドは省略できる。
+/// 知らないキーや、キーワードでないキーがあれば `EvalError::TypeMismatch`
+#[proc_macro_derive(FromLisp)]
+pub fn derive_from_lisp(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match named_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    // 生成するコードの変数名と衝突しないよう、フィールドごとの値は別名の変数に入れる
+    let slots: Vec<Ident> = fields
+        .iter()
+        .map(|f| format_ident!("__field_{}", f))
+        .collect();
+    let decls = slots.iter().map(|slot| {
+        return quote! {
+            let mut #slot: ::core::option::Option<::liblisp::types::Type> = ::core::option::Option::None;
+        };
+    });
+    let arms = fields.iter().zip(&slots).map(|(f, slot)| {
+        let key = keyword_name(f);
+        // 同じキーが複数あれば、&key と同様に最初の値を使う
+        return quote! {
+            ::core::option::Option::Some(#key) => {
+                if #slot.is_none() {
+                    #slot = ::core::option::Option::Some(__value);
+                }
+            }
+        };
+    });
+    let inits = fields.iter().zip(&slots).map(|(f, slot)| {
+        return quote! {
+            #f: ::liblisp::convert::FromLisp::from_lisp(
+                &#slot.unwrap_or(::liblisp::types::Type::Void),
+            )?
+        };
+    });
+
+    let expanded = quote! {
+        impl #impl_generics ::liblisp::convert::FromLisp for #name #ty_generics #where_clause {
+            fn from_lisp(
+                t: &::liblisp::types::Type,
+            ) -> ::core::result::Result<Self, ::liblisp::eval::EvalError> {
+                #(#decls)*
+                for (__key, __value) in ::liblisp::convert::alist_entries(t)? {
+                    match __key.as_keyword() {
+                        #(#arms)*
+                        _ => {
+                            return ::core::result::Result::Err(
+                                ::liblisp::eval::EvalError::TypeMismatch,
+                            );
+                        }
+                    }
+                }
+                return ::core::result::Result::Ok(#name { #(#inits),* });
+            }
+        }
+    };
+    return expanded.into();
+}
+
+// 名前付きフィールドを持つ構造体なら、フィールド名の一覧を返す
+fn named_fields(input: &DeriveInput) -> Result<Vec<Ident>, syn::Error> {
+    if let Data::Struct(s) = &input.data {
+        if let Fields::Named(fields) = &s.fields {
+            return Ok(fields
+                .named
+                .iter()
+                .map(|f| f.ident.clone().unwrap())
+                .collect());
+        }
+    }
+    return Err(syn::Error::new_spanned(
+        &input.ident,
+        "ToLisp / FromLisp can only be derived for structs with named fields",
+    ));
+}
+
+// フィールド名を、連想リストのキーにするキーワードの名前（先頭の : を除く）に変換する
+fn keyword_name(field: &Ident) -> String {
+    let name = field.to_string();
+    return name.trim_start_matches("r#").replace('_', "-");
+}
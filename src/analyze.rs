@@ -0,0 +1,364 @@
+//!
+//! 評価前に式を調べて、誤りの可能性がある箇所を報告する静的解析を定義
+//!
+
+use crate::eval::{builtin_arity, is_builtin};
+use crate::expression::*;
+use crate::lexer::Span;
+use crate::visit::*;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// 静的解析で見つかった、誤りの可能性がある箇所
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    UndefinedVariable(Rc<str>), // どこでも定義・代入されていない変数を参照している
+    UnknownFunction(Rc<str>), // 組み込み関数でも、定義された関数やマクロでもない名前を呼び出している
+    BadArity {
+        name: Rc<str>,
+        min: usize,
+        max: Option<usize>, // None なら上限なし
+        actual: usize,
+    }, // 組み込み関数に渡す引数の数が誤っている
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::UndefinedVariable(v) => {
+                return write!(f, "variable {} is never defined", v);
+            }
+            Diagnostic::UnknownFunction(name) => {
+                return write!(f, "unknown function {}", name);
+            }
+            Diagnostic::BadArity {
+                name,
+                min,
+                max,
+                actual,
+            } => {
+                return write!(f, "{}", arity_message(name, *min, *max, *actual));
+            }
+        }
+    }
+}
+
+/// 引数の数の範囲（最小, 最大）を、`2`、`1 to 3`、`at least 1` のような文字列にする。最大が `None` なら上限は無い
+pub fn arity_text(min: usize, max: Option<usize>) -> String {
+    match max {
+        Some(max) if max == min => {
+            return format!("{}", min);
+        }
+        Some(max) => {
+            return format!("{} to {}", min, max);
+        }
+        None => {
+            return format!("at least {}", min);
+        }
+    }
+}
+
+/// 引数の数が誤っていることを伝える、`add expects 2 arguments, got 1` のようなメッセージ。
+/// 評価時の `EvalError::ArityMismatch` と、解析時の `Diagnostic::BadArity` で共通の文言を使う
+pub fn arity_message(name: &str, min: usize, max: Option<usize>, actual: usize) -> String {
+    let unit = if min == 1 && max.is_none_or(|max| max == 1) {
+        "argument"
+    } else {
+        "arguments"
+    };
+    return format!(
+        "{} expects {} {}, got {}",
+        name,
+        arity_text(min, max),
+        unit,
+        actual
+    );
+}
+
+/// 1 つの式を解析する。`analyze_program` に式を 1 つだけ渡した場合と同じ
+pub fn analyze(exp: &Expression) -> Vec<Diagnostic> {
+    return analyze_program(core::slice::from_ref(exp));
+}
+
+/// 複数の式からなるプログラムを解析し、見つかった箇所を出現順に返す。
+///
+/// 変数は動的スコープなので、プログラム中のどこかで定義・代入・束縛されていれば、
+/// 参照する位置によらず定義済みとみなす。関数も同様に、プログラム中のどこかで `defun` か `defmacro` で
+/// 定義されていれば定義済みとみなす。標準ライブラリ（prelude）の関数も定義済みとして扱う。
+/// ホスト側で `define_var` や `register_fn` により定義したものは分からないので、未定義として報告する
+///
+/// # Examples
+/// ```
+/// use liblisp::analyze::{analyze_program, Diagnostic};
+/// use liblisp::expression::parse_program;
+///
+/// let program = parse_program("(defun f (*x*) (add *x* *y*)) (g (f 1 2))").unwrap();
+/// let diagnostics = analyze_program(&program);
+/// assert_eq!(
+///     diagnostics,
+///     vec![
+///         Diagnostic::UndefinedVariable("*y*".into()),
+///         Diagnostic::UnknownFunction("g".into()),
+///     ]
+/// );
+/// ```
+pub fn analyze_program(program: &[Expression]) -> Vec<Diagnostic> {
+    let program: Vec<&Expression> = program.iter().collect();
+    return analyze_each(&program).into_iter().flatten().collect();
+}
+
+/// `parse_program_located` で読み込んだプログラムを `analyze_program` と同様に解析し、
+/// 見つかった箇所を、それを含むトップレベルの式の位置と組にして出現順に返す。エディタでの表示に使う
+///
+/// # Examples
+/// ```
+/// use liblisp::analyze::{analyze_program_located, Diagnostic};
+/// use liblisp::expression::{parse_program_located, ReaderOptions};
+/// use liblisp::lexer::Span;
+///
+/// let program = parse_program_located("(define *x* 1)\n(add *x* *y*)", &ReaderOptions::default()).unwrap();
+/// assert_eq!(
+///     analyze_program_located(&program),
+///     vec![(Diagnostic::UndefinedVariable("*y*".into()), Span { start: 15, end: 28 })]
+/// );
+/// ```
+pub fn analyze_program_located(program: &[(Expression, Span)]) -> Vec<(Diagnostic, Span)> {
+    let exps: Vec<&Expression> = program.iter().map(|(exp, _)| exp).collect();
+    let mut res = Vec::new();
+    for (diagnostics, (_, span)) in analyze_each(&exps).into_iter().zip(program) {
+        res.extend(diagnostics.into_iter().map(|d| (d, *span)));
+    }
+    return res;
+}
+
+// プログラムを解析し、トップレベルの式ごとに、見つかった箇所を返す
+fn analyze_each(program: &[&Expression]) -> Vec<Vec<Diagnostic>> {
+    let mut analyzer = Analyzer::default();
+    for exp in parse_program(PRELUDE).unwrap_or_default().iter() {
+        walk(exp, &mut analyzer);
+    }
+    // prelude の関数の仮引数は、プログラムの変数の定義とはみなさない
+    analyzer.variables.clear();
+    for exp in program {
+        walk(exp, &mut analyzer);
+    }
+    analyzer.collecting = false;
+    let mut res = Vec::new();
+    for exp in program {
+        walk(exp, &mut analyzer);
+        res.push(core::mem::take(&mut analyzer.diagnostics));
+    }
+    return res;
+}
+
+const PRELUDE: &str = include_str!("prelude.lisp");
+
+// 式を 2 回辿る。1 回目（collecting が true）で定義を集め、2 回目で参照を調べる
+struct Analyzer {
+    collecting: bool,
+    functions: BTreeSet<Rc<str>>, // defun で定義した関数の名前
+    macros: BTreeSet<Rc<str>>,    // defmacro で定義したマクロの名前
+    variables: BTreeSet<Rc<str>>, // 定義・代入・束縛される変数の名前
+    modules: Vec<Rc<str>>,        // 解析中の module の名前。最後が最も内側
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        return Analyzer {
+            collecting: true,
+            functions: BTreeSet::new(),
+            macros: BTreeSet::new(),
+            variables: BTreeSet::new(),
+            modules: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+    }
+}
+
+impl ExpressionVisitor for Analyzer {
+    fn enter(&mut self, exp: &Expression) -> bool {
+        match exp {
+            Expression::Var(v) if !self.collecting && !self.is_defined(&self.variables, v) => {
+                self.diagnostics
+                    .push(Diagnostic::UndefinedVariable(v.clone()));
+            }
+            Expression::ExpressionList(l) => {
+                if let Some(Expression::Atom(name)) = l.head() {
+                    let args = elements(l.tail());
+                    if self.collecting {
+                        self.collect_definition(name, &args);
+                    } else if !self.check_call(name, args.len()) {
+                        return false;
+                    }
+                    if &**name == "module" {
+                        if let Some(Expression::Atom(module)) = args.first() {
+                            self.modules.push(module.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        return true;
+    }
+
+    fn leave(&mut self, exp: &Expression) {
+        if let Expression::ExpressionList(l) = exp {
+            if let (Some(Expression::Atom(name)), Some(Expression::Atom(_))) =
+                (l.head(), l.tail().head())
+            {
+                if &**name == "module" {
+                    self.modules.pop();
+                }
+            }
+        }
+    }
+
+    fn binding(&mut self, exp: &Expression) {
+        if self.collecting {
+            self.collect_vars(exp);
+        }
+    }
+}
+
+impl Analyzer {
+    // defun と defmacro で定義した名前を集める
+    fn collect_definition(&mut self, form: &str, args: &[&Expression]) {
+        if let Some(Expression::Atom(name)) = args.first() {
+            let name = self.qualify(name);
+            if form == "defun" {
+                self.functions.insert(name);
+            } else if form == "defmacro" {
+                self.macros.insert(name);
+            }
+        }
+    }
+
+    // 束縛する位置にある式から、変数を全て集める
+    fn collect_vars(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Var(v) => {
+                let v = self.qualify(v);
+                self.variables.insert(v);
+            }
+            Expression::ExpressionList(l) => {
+                for e in elements(l) {
+                    self.collect_vars(e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 引数が arity 個の (name ...) の呼び出しを調べる。
+    // マクロの呼び出しなら、引数は任意の構文を取れるので、引数を辿らないよう false を返す
+    fn check_call(&mut self, name: &Rc<str>, arity: usize) -> bool {
+        if self.is_defined(&self.macros, name) {
+            return false;
+        }
+        // defun で定義した関数は、同じ名前の組み込み関数より優先される
+        if self.is_defined(&self.functions, name) {
+            return true;
+        }
+        if let Some((min, max)) = builtin_arity(name) {
+            if arity < min || max.is_some_and(|max| arity > max) {
+                self.diagnostics.push(Diagnostic::BadArity {
+                    name: name.clone(),
+                    min,
+                    max,
+                    actual: arity,
+                });
+            }
+        } else if !is_builtin(name) {
+            self.diagnostics
+                .push(Diagnostic::UnknownFunction(name.clone()));
+        }
+        return true;
+    }
+
+    // module の中では、評価器と同様に、module で修飾した名前でも探す
+    fn is_defined(&self, names: &BTreeSet<Rc<str>>, name: &Rc<str>) -> bool {
+        return names.contains(name) || names.contains(&self.qualify(name));
+    }
+
+    // 評価器の Context::qualify と同じ規則で、名前を module で修飾する
+    fn qualify(&self, name: &Rc<str>) -> Rc<str> {
+        let module = match self.modules.last() {
+            Some(module) if !name.contains(':') => module,
+            _ => {
+                return name.clone();
+            }
+        };
+        if let Some(inner) = name.strip_prefix('*').and_then(|n| n.strip_suffix('*')) {
+            return Rc::from(format!("*{}:{}*", module, inner));
+        }
+        return Rc::from(format!("{}:{}", module, name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyze::*;
+    use alloc::string::String;
+
+    fn diagnostics(src: &str) -> Vec<String> {
+        let program = parse_program(src).unwrap();
+        return analyze_program(&program)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+    }
+
+    #[test]
+    fn analyze_tests() {
+        let clean = vec![
+            "(add 1 2)",
+            "(progn (define *x* 1) (add *x* 2))",
+            "(defun f (*a* &optional *b*) (list *a* *b*)) (f 1)",
+            "(let (((list *a* *b*) (list 1 2))) (add *a* *b*))",
+            "(dolist (*e* (list 1 2)) (add *e* 1))",
+            "(defmacro print (*x*) *x*) (print (anything *goes* here))",
+            "(try (raise 1) (catch *e* *e*))",
+            "(match 1 (*n* *n*) (_ 0))",
+            "(quote (f *undefined*))",
+            "(define *l* 1) `(f ,*l* ,@(list *l*))",
+            "(block b (return-from b 1))",
+            "(boundp *maybe*)",
+            "(module m (define *x* 1) (defun f () *x*)) (m:f) (list *m:x*)",
+            // prelude の関数
+            "(abs (max 1 2))",
+            // 動的スコープなので、定義より前の参照も許す
+            "(defun f () *late*) (define *late* 1)",
+            // 組み込み関数を上書きした関数の引数の数は、定義に従う
+            "(defun add (*a* *b* *c*) *a*) (add 1 2 3)",
+        ];
+        for src in clean {
+            assert_eq!(diagnostics(src), Vec::<String>::new(), "{}", src);
+        }
+
+        let cases = vec![
+            ("(add *x* 1)", vec!["variable *x* is never defined"]),
+            ("(frob 1)", vec!["unknown function frob"]),
+            ("(add 1 2 3)", vec!["add expects 2 arguments, got 3"]),
+            (
+                "(gensym 1 2)",
+                vec!["gensym expects 0 to 1 arguments, got 2"],
+            ),
+            ("(block)", vec!["block expects at least 1 argument, got 0"]),
+            (
+                "(defun f (*a*) (g *b*))",
+                vec!["unknown function g", "variable *b* is never defined"],
+            ),
+            ("`(a ,*x*)", vec!["variable *x* is never defined"]),
+            ("(module m (defun f () 1)) (f)", vec!["unknown function f"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(diagnostics(src), expected, "{}", src);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//!
+//! 読み込んだ式を arena に確保して表現するための定義
+//!
+//! `Expression` はリストの要素ごとに `Rc` を確保するが、`Node` はリストの要素を arena 上の連続した領域に並べ、
+//! 名前や文字列はソースの文字列をそのまま参照する。読み込んだ式は arena ごとまとめて解放される
+//!
+//! 評価器は `Expression` の上で動くので、arena で速くなるのは読み込みだけで、評価は速くならない。
+//! `Node` を評価する `eval::eval_node` は、式全体を `Expression` に変換してから評価する
+//!
+
+use crate::expression::*;
+use crate::lexer::{unescape, write_escaped};
+use crate::types::Int;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// `Expression::parse_in` で読み込んだ式の、リストの要素を確保する領域
+pub struct Arena<'a> {
+    lists: typed_arena::Arena<Node<'a>>,
+}
+
+impl<'a> Default for Arena<'a> {
+    fn default() -> Self {
+        return Arena::new();
+    }
+}
+
+impl<'a> Arena<'a> {
+    /// 空の `Arena` を新規作成
+    pub fn new() -> Arena<'a> {
+        return Arena {
+            lists: typed_arena::Arena::new(),
+        };
+    }
+
+    /// リストの要素 `items` を、arena 上の連続した領域に移す
+    pub fn alloc_list(&'a self, items: Vec<Node<'a>>) -> &'a [Node<'a>] {
+        return self.lists.alloc_extend(items);
+    }
+
+    /// これまでに確保したリストの要素の数
+    pub fn len(&self) -> usize {
+        return self.lists.len();
+    }
+
+    /// まだ何も確保していないかどうか
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+/// arena に確保した Lisp の式。各要素は `Expression` の同名の要素に対応する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node<'a> {
+    Int(Int),
+    Atom(&'a str),
+    Var(&'a str),
+    Str(&'a str), // 前後の " を除いたソースの文字列。エスケープシーケンスはそのまま含む
+    Keyword(&'a str), // :x の形式。名前は先頭の : を除いて持つ
+    List(&'a [Node<'a>]),
+}
+
+impl<'a> Node<'a> {
+    /// 同じ式を表す `Expression` に変換する
+    pub fn to_expression(&self) -> Expression {
+        match self {
+            Node::Int(i) => {
+                return Expression::Int(*i);
+            }
+            Node::Atom(a) => {
+                return Expression::Atom(Rc::from(*a));
+            }
+            Node::Var(v) => {
+                return Expression::Var(Rc::from(*v));
+            }
+            Node::Str(s) => {
+                // 読み込んだ Node のエスケープシーケンスは字句解析器が検査済み。
+                // 不正なものを含む Node を直接作った場合は、そのままの文字列にする
+                return Expression::Str(Rc::from(unescape(s).as_deref().unwrap_or(s)));
+            }
+            Node::Keyword(k) => {
+                return Expression::Keyword(Rc::from(*k));
+            }
+            Node::List(items) => {
+                let list = items
+                    .iter()
+                    .rev()
+                    .fold(ExpressionList::new(), |acc, n| acc.cons(&n.to_expression()));
+                return Expression::ExpressionList(Rc::new(list));
+            }
+        }
+    }
+}
+
+/// `Expression` の `Display` と同じ形式で出力する
+impl<'a> fmt::Display for Node<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Node::Int(i) => {
+                return write!(f, "{}", i);
+            }
+            Node::Atom(a) => {
+                return write!(f, "{}", a);
+            }
+            Node::Var(v) => {
+                return write!(f, "{}", v);
+            }
+            Node::Str(s) => {
+                return write_escaped(f, unescape(s).as_deref().unwrap_or(s));
+            }
+            Node::Keyword(k) => {
+                return write!(f, ":{}", k);
+            }
+            Node::List(items) => {
+                write!(f, "(")?;
+                for (i, n) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", n)?;
+                }
+                return write!(f, ")");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arena::*;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn parse_in_tests() {
+        let arena = Arena::new();
+        let cases = vec![
+            "12345",
+            "atom",
+            "*var*",
+            "\"a string\"",
+            ":key",
+            "()",
+            "(add 1 (mul *a* 2))",
+            "'(a `(b ,c ,@d))",
+            "(match *x* ((list 1 _) :one) (_ \"other\"))",
+            r#"("a\"b\n" "\u{1F600}")"#,
+        ];
+        for src in cases {
+            let node = Expression::parse_in(&arena, src).unwrap();
+            let expected = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(node.to_expression(), expected, "{}", src);
+            assert_eq!(node.to_string(), expected.to_string(), "{}", src);
+        }
+        // 名前はソースの文字列を参照し、リストの要素だけを arena に確保する
+        let arena = Arena::new();
+        let src = "(a (b c) d)";
+        let node = Expression::parse_in(&arena, src).unwrap();
+        assert_eq!(arena.len(), 5);
+        if let Node::List([Node::Atom(a), Node::List(inner), Node::Atom(_)]) = node {
+            assert_eq!(a.as_ptr(), src[1..].as_ptr());
+            assert_eq!(inner, &[Node::Atom("b"), Node::Atom("c")]);
+        } else {
+            assert!(false);
+        }
+
+        // 文字列はエスケープシーケンスを含むソースの文字列を参照し、Expression に変換するときに置き換える
+        let node = Expression::parse_in(&arena, r#""a\tb""#).unwrap();
+        assert_eq!(node, Node::Str(r#"a\tb"#));
+        assert_eq!(node.to_expression(), Expression::Str("a\tb".into()));
+
+        let mut context = crate::eval::Context::new();
+        let node = Expression::parse_in(&arena, "(progn (set *a* 2) (mul *a* 3))").unwrap();
+        assert_eq!(
+            crate::eval::eval_node(&node, &mut context),
+            Ok(crate::types::Type::Int(6))
+        );
+
+        let errors = vec![
+            ("(a", ExpressionConversionError::UnexpectedEof),
+            ("(a))", ExpressionConversionError::InvalidToken),
+            ("1a", ExpressionConversionError::InvalidToken),
+        ];
+        for (src, expected) in errors {
+            assert_eq!(Expression::parse_in(&arena, src), Err(expected), "{}", src);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//!
+//! liblisp の Language Server。標準入出力で LSP の JSON-RPC を読み書きし、`lsp::LanguageService` の結果を返す
+//!
+//! ドキュメントの同期は内容全体を送る方式（TextDocumentSyncKind.Full）のみ。
+//! 診断（textDocument/publishDiagnostics）・ホバー・定義へのジャンプ・補完・整形に対応する
+//!
+
+use liblisp::eval::SymbolKind;
+use liblisp::format::FormatOptions;
+use liblisp::lsp::{LanguageService, Position, Range, Severity};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+// 未対応のメソッドに返すエラーコード（JSON-RPC の Method not found）
+const METHOD_NOT_FOUND: i64 = -32601;
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut service = LanguageService::new();
+    let mut shutdown = false;
+    while let Some(message) = read_message(&mut input) {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = message.get("id").cloned();
+        if method == "exit" {
+            std::process::exit(if shutdown { 0 } else { 1 });
+        }
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "lisp-lsp" },
+            })),
+            "shutdown" => {
+                shutdown = true;
+                Some(Value::Null)
+            }
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                publish_diagnostics(&mut output, &mut service, uri, text);
+                None
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                // 内容全体を送る方式なので、最後の変更が新しい内容になる
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    publish_diagnostics(&mut output, &mut service, uri, text);
+                }
+                None
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                service.close(uri);
+                send_notification(
+                    &mut output,
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+                None
+            }
+            "textDocument/hover" => {
+                let (uri, position) = text_document_position(params);
+                Some(match service.hover(uri, position) {
+                    Some(contents) => json!({
+                        "contents": { "kind": "markdown", "value": contents },
+                    }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/definition" => {
+                let (uri, position) = text_document_position(params);
+                Some(match service.definition(uri, position) {
+                    Some((uri, range)) => json!({ "uri": uri, "range": range_to_json(range) }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/completion" => {
+                let (uri, position) = text_document_position(params);
+                let items: Vec<Value> = service
+                    .completion(uri, position)
+                    .into_iter()
+                    .map(|c| json!({ "label": &*c.label, "kind": completion_kind(c.kind) }))
+                    .collect();
+                Some(Value::Array(items))
+            }
+            "textDocument/formatting" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let options = FormatOptions {
+                    indent: params["options"]["tabSize"].as_u64().unwrap_or(2) as usize,
+                    ..FormatOptions::default()
+                };
+                Some(match service.formatting(uri, &options) {
+                    Some(text) => {
+                        // ドキュメント全体を置き換える。末尾を超える位置は、ドキュメントの末尾とみなされる
+                        let range = Range {
+                            start: Position::default(),
+                            end: Position {
+                                line: u32::MAX,
+                                character: 0,
+                            },
+                        };
+                        json!([{ "range": range_to_json(range), "newText": text }])
+                    }
+                    None => Value::Null,
+                })
+            }
+            _ => None,
+        };
+        // 通知（id の無いメッセージ）には応答しない
+        if let Some(id) = id {
+            let response = match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("unsupported method {}", method) },
+                }),
+            };
+            write_message(&mut output, &response);
+        }
+    }
+}
+
+// Content-Length ヘッダで区切られたメッセージを 1 つ読む。入力の終わりか、読めないメッセージなら None
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    return serde_json::from_slice(&body).ok();
+}
+
+fn write_message(output: &mut impl Write, message: &Value) {
+    let body = serde_json::to_vec(message).unwrap();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n", body.len());
+    let _ = output.write_all(&body);
+    let _ = output.flush();
+}
+
+fn send_notification(output: &mut impl Write, method: &str, params: Value) {
+    write_message(
+        output,
+        &json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+    );
+}
+
+// ドキュメントを更新し、その診断を通知する
+fn publish_diagnostics(
+    output: &mut impl Write,
+    service: &mut LanguageService,
+    uri: &str,
+    text: &str,
+) {
+    let diagnostics: Vec<Value> = service
+        .update(uri, text)
+        .into_iter()
+        .map(|d| {
+            let severity = match d.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            return json!({
+                "range": range_to_json(d.range),
+                "severity": severity,
+                "source": "liblisp",
+                "message": d.message,
+            });
+        })
+        .collect();
+    send_notification(
+        output,
+        "textDocument/publishDiagnostics",
+        json!({ "uri": uri, "diagnostics": diagnostics }),
+    );
+}
+
+// TextDocumentPositionParams の URI と位置
+fn text_document_position(params: &Value) -> (&str, Position) {
+    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+    let position = Position {
+        line: params["position"]["line"].as_u64().unwrap_or(0) as u32,
+        character: params["position"]["character"].as_u64().unwrap_or(0) as u32,
+    };
+    return (uri, position);
+}
+
+fn range_to_json(range: Range) -> Value {
+    return json!({
+        "start": { "line": range.start.line, "character": range.start.character },
+        "end": { "line": range.end.line, "character": range.end.character },
+    });
+}
+
+// LSP の CompletionItemKind
+fn completion_kind(kind: SymbolKind) -> u32 {
+    match kind {
+        SymbolKind::Builtin | SymbolKind::Function | SymbolKind::HostFunction => {
+            return 3; // Function
+        }
+        SymbolKind::SpecialForm => {
+            return 14; // Keyword
+        }
+        SymbolKind::Macro => {
+            return 15; // Snippet
+        }
+        SymbolKind::Variable => {
+            return 6; // Variable
+        }
+    }
+}
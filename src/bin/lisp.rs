@@ -0,0 +1,130 @@
+//!
+//! liblisp のコマンドラインツール
+//!
+//! - `lisp run <file>`   : 標準ライブラリを読み込んだ `Context` でファイルを評価し、最後の値を出力する。
+//!   終了コードは、最後の値の `Type::exit_code`（整数ならその下位 8 ビット、それ以外は 0）か、
+//!   エラーになった場合は `EvalError::exit_code`（`(exit n)` なら n、それ以外は 1）
+//! - `lisp check <file>` : ファイルを読み込んで静的解析だけを行い、見つかった箇所を出力する
+//! - `lisp fmt <file>`   : ファイルを整形して出力する
+//!
+
+use liblisp::analyze::analyze_program;
+use liblisp::capabilities::Capabilities;
+use liblisp::eval::{eval_with_context, Context, EvalError};
+use liblisp::expression::{parse_program_located, Expression, ReaderOptions};
+use liblisp::format::{format_source, FormatOptions};
+use liblisp::lexer::Span;
+use liblisp::source_map::SourceMap;
+use liblisp::types::Type;
+use std::process;
+
+const USAGE: &str = "usage: lisp <run|check|fmt> <file>";
+
+// 使い方の誤りや、ファイルを読み込めなかった時の終了コード
+const EXIT_USAGE: i32 = 2;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let code = match (args.get(1).map(|s| s.as_str()), args.get(2), args.len()) {
+        (Some("run"), Some(path), 3) => run(path),
+        (Some("check"), Some(path), 3) => check(path),
+        (Some("fmt"), Some(path), 3) => fmt(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            EXIT_USAGE
+        }
+    };
+    process::exit(code);
+}
+
+// ファイルを評価し、最後の値が Void でなければ出力する。終了コードは最後の値から決める
+fn run(path: &str) -> i32 {
+    let (map, program) = match load(path) {
+        Ok(loaded) => loaded,
+        Err(code) => {
+            return code;
+        }
+    };
+    let mut context = Context::new_with_stdlib();
+    context.set_capabilities(Capabilities::all());
+    let mut res = Type::Void;
+    for (exp, span) in &program {
+        match eval_with_context(exp, &mut context) {
+            Ok(v) => {
+                res = v;
+            }
+            Err(e) => {
+                if !matches!(e, EvalError::Exit(_)) {
+                    eprint!("{}", e.render(&map, *span));
+                }
+                return e.exit_code();
+            }
+        }
+    }
+    if res != Type::Void {
+        println!("{}", res.to_display_string());
+    }
+    return res.exit_code();
+}
+
+// ファイルを静的解析し、見つかった箇所があれば 1 を返す
+fn check(path: &str) -> i32 {
+    let (_, program) = match load(path) {
+        Ok(loaded) => loaded,
+        Err(code) => {
+            return code;
+        }
+    };
+    let program: Vec<Expression> = program.into_iter().map(|(exp, _)| exp).collect();
+    let diagnostics = analyze_program(&program);
+    for d in &diagnostics {
+        eprintln!("{}: warning: {}", path, d);
+    }
+    if diagnostics.is_empty() {
+        return 0;
+    }
+    return 1;
+}
+
+// ファイルを整形して標準出力に出力する
+fn fmt(path: &str) -> i32 {
+    let (map, _) = match load(path) {
+        Ok(loaded) => loaded,
+        Err(code) => {
+            return code;
+        }
+    };
+    match format_source(map.source(), &FormatOptions::default()) {
+        Ok(formatted) => {
+            print!("{}", formatted);
+            return 0;
+        }
+        Err(e) => {
+            // load で読み込めたソースなので、ここには来ない
+            eprintln!("{}: parse error: {:?}", path, e);
+            return 1;
+        }
+    }
+}
+
+// ファイルを読み込み、トップレベルの式を位置と共に返す。
+// 失敗した場合はメッセージを出力し、終了コードを返す
+fn load(path: &str) -> Result<(SourceMap, Vec<(Expression, Span)>), i32> {
+    let src = match std::fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return Err(EXIT_USAGE);
+        }
+    };
+    let map = SourceMap::new(&src);
+    match parse_program_located(&src, &ReaderOptions::default()) {
+        Ok(program) => {
+            return Ok((map, program));
+        }
+        Err(e) => {
+            eprint!("{}: {}", path, e.render(&map));
+            return Err(1);
+        }
+    }
+}
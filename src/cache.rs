@@ -0,0 +1,271 @@
+//!
+//! 読み込んだプログラムを、ソースのハッシュ値をキーにして再利用する `ProgramCache` を定義
+//!
+
+use crate::expression::*;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
+
+/// `ProgramCache` の統計
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,      // キャッシュにあり、読み込みを省略した回数
+    pub misses: u64,    // キャッシュになく、読み込んだ回数。読み込みに失敗した場合も数える
+    pub evictions: u64, // 上限を超えたため、最も長く使われていないプログラムを捨てた回数
+    pub len: usize,     // 現在キャッシュにあるプログラムの数
+}
+
+/// 読み込んだプログラムのキャッシュ。
+/// 同じテンプレートを何度も評価する Web サーバーのように、同じソースを繰り返し評価する場合に、読み込みを省略する。
+///
+/// - キー: ソースの FNV-1a ハッシュ値。ハッシュ値が衝突しても、ソース全体を比較するので別のプログラムを返すことはない
+/// - 上限: `capacity` 個を超えると、最も長く使われていないプログラムを捨てる (LRU)。`capacity` が 0 なら何も保持しない
+/// - 失敗: 読み込みに失敗したソースは保持しないので、次回も読み込んでエラーを返す
+///
+/// # Examples
+/// ```
+/// use liblisp::cache::ProgramCache;
+/// use liblisp::eval::{eval_with_context, Context};
+/// use liblisp::types::Type;
+///
+/// let mut cache = ProgramCache::new(16);
+/// for _ in 0..3 {
+///     let program = cache.get_or_parse("(define *a* 1) (add *a* 2)").unwrap();
+///     let mut context = Context::new();
+///     let mut res = Type::Void;
+///     for exp in program.iter() {
+///         res = eval_with_context(exp, &mut context).unwrap();
+///     }
+///     assert_eq!(res, Type::Int(3));
+/// }
+/// assert_eq!(cache.stats().hits, 2);
+/// assert_eq!(cache.stats().misses, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProgramCache {
+    capacity: usize,
+    options: ReaderOptions,
+    entries: Map<u64, Entry>,
+    clock: u64, // 使われるたびに増やし、Entry の last_used に記録する
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+// キャッシュしたプログラムと、その元のソース
+#[derive(Debug, Clone)]
+struct Entry {
+    src: Rc<str>, // ハッシュ値が衝突した場合に区別するためのソース
+    program: Rc<[Expression]>,
+    last_used: u64,
+}
+
+impl ProgramCache {
+    /// 最大 `capacity` 個のプログラムを保持する、`ReaderOptions::default()` の設定で読み込むキャッシュを新規作成
+    pub fn new(capacity: usize) -> ProgramCache {
+        return ProgramCache::with_options(capacity, ReaderOptions::default());
+    }
+
+    /// `options` の設定で読み込む、`new` と同様のキャッシュを新規作成
+    pub fn with_options(capacity: usize, options: ReaderOptions) -> ProgramCache {
+        return ProgramCache {
+            capacity,
+            options,
+            entries: Map::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        };
+    }
+
+    /// `src` を読み込んだプログラムを返す。キャッシュにあれば読み込みを省略する。
+    /// 返すプログラムはキャッシュと共有するので、複製の費用はかからない
+    pub fn get_or_parse(
+        &mut self,
+        src: &str,
+    ) -> Result<Rc<[Expression]>, ExpressionConversionError> {
+        let key = fnv1a(src.as_bytes());
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if &*entry.src == src {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                return Ok(entry.program.clone());
+            }
+        }
+        self.misses += 1;
+        let program: Rc<[Expression]> = parse_program_with(src, &self.options)?.into();
+        if self.capacity == 0 {
+            return Ok(program);
+        }
+        // ハッシュ値が衝突したプログラムは置き換える
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                src: src.into(),
+                program: program.clone(),
+                last_used: self.clock,
+            },
+        );
+        return Ok(program);
+    }
+
+    /// `src` のプログラムがキャッシュにあるかどうか。統計や LRU の順序は変えない
+    pub fn contains(&self, src: &str) -> bool {
+        return self
+            .entries
+            .get(&fnv1a(src.as_bytes()))
+            .is_some_and(|entry| &*entry.src == src);
+    }
+
+    /// `src` のプログラムをキャッシュから捨てる。あれば true
+    pub fn remove(&mut self, src: &str) -> bool {
+        let key = fnv1a(src.as_bytes());
+        if !self.contains(src) {
+            return false;
+        }
+        self.entries.remove(&key);
+        return true;
+    }
+
+    /// キャッシュを空にする。統計の回数は残す
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 現在キャッシュにあるプログラムの数
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    /// キャッシュが空かどうか
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// 保持するプログラムの数の上限
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    /// 上限を `capacity` に変える。超えている分は、最も長く使われていないものから捨てる
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    /// ヒットやミスの回数と、現在の数
+    pub fn stats(&self) -> CacheStats {
+        return CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            len: self.entries.len(),
+        };
+    }
+
+    // 最も長く使われていないプログラムを捨てる。上限は大きくないことを想定し、全体を走査して探す
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| *key);
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+            self.evictions += 1;
+        }
+    }
+}
+
+// FNV-1a (64 bit) ハッシュ値。実行ごとに変わらず、no_std でも使える
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::*;
+
+    #[test]
+    fn program_cache_tests() {
+        let mut cache = ProgramCache::new(2);
+        let a = cache.get_or_parse("(add 1 2)").unwrap();
+        let b = cache.get_or_parse("(add 1 2)").unwrap();
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(a[0].to_string(), "(add 1 2)");
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                evictions: 0,
+                len: 1
+            }
+        );
+
+        // 読み込みに失敗したソースは保持しない
+        for _ in 0..2 {
+            assert_eq!(
+                cache.get_or_parse("(add 1").unwrap_err(),
+                ExpressionConversionError::UnexpectedEof
+            );
+        }
+        assert_eq!(cache.stats().misses, 3);
+        assert!(!cache.contains("(add 1"));
+
+        // 最も長く使われていない "(list 1)" が捨てられる
+        cache.get_or_parse("(list 1)").unwrap();
+        cache.get_or_parse("(add 1 2)").unwrap();
+        cache.get_or_parse("(list 2)").unwrap();
+        assert!(cache.contains("(add 1 2)"));
+        assert!(!cache.contains("(list 1)"));
+        assert!(cache.contains("(list 2)"));
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.len(), 2);
+
+        cache.set_capacity(1);
+        assert!(!cache.contains("(add 1 2)"));
+        assert!(cache.contains("(list 2)"));
+        assert_eq!(cache.stats().evictions, 2);
+
+        assert!(cache.remove("(list 2)"));
+        assert!(!cache.remove("(list 2)"));
+        assert!(cache.is_empty());
+
+        // 上限が 0 なら何も保持しない
+        let mut cache = ProgramCache::new(0);
+        cache.get_or_parse("1").unwrap();
+        cache.get_or_parse("1").unwrap();
+        assert_eq!(cache.stats().misses, 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn program_cache_options_tests() {
+        let options = ReaderOptions {
+            braces: true,
+            ..ReaderOptions::default()
+        };
+        let mut cache = ProgramCache::with_options(4, options);
+        let program = cache.get_or_parse("{:a 1}").unwrap();
+        assert_eq!(program[0].to_string(), "(list (list :a 1))");
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().misses, 1);
+    }
+}
@@ -0,0 +1,41 @@
+//!
+//! 副作用のある組み込み関数を使えるかどうかの設定を定義
+//!
+
+/// `Context::set_capabilities` で設定する、副作用のある組み込み関数の利用許可。
+/// デフォルトでは全て禁止なので、信頼できないスクリプトを評価する場合もそのまま使える。
+/// 許可されていない組み込み関数を呼び出すと `EvalError::CapabilityDenied` になる
+///
+/// # Examples
+/// ```
+/// use liblisp::capabilities::Capabilities;
+/// use liblisp::eval::{eval_with_context, Context, EvalError};
+/// use liblisp::expression::Expression;
+/// use std::convert::TryFrom;
+///
+/// let exp = Expression::try_from("(exit 3)".as_bytes()).unwrap();
+/// let mut context = Context::new();
+/// assert_eq!(eval_with_context(&exp, &mut context), Err(EvalError::CapabilityDenied));
+///
+/// context.set_capabilities(Capabilities { allow_os: true, ..Capabilities::default() });
+/// assert_eq!(eval_with_context(&exp, &mut context), Err(EvalError::Exit(3)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub allow_os: bool, // getenv, argv, exit のような、OS とやりとりする組み込み関数
+    pub allow_fs: bool, // slurp, spit, file-exists のような、FileSystem を読み書きする組み込み関数
+    pub allow_process: bool, // sh のような、外部のプロセスを起動する組み込み関数
+    pub allow_net: bool, // http-get, http-post のような、ネットワークにアクセスする組み込み関数
+}
+
+impl Capabilities {
+    /// 全ての組み込み関数を許可する
+    pub fn all() -> Capabilities {
+        return Capabilities {
+            allow_os: true,
+            allow_fs: true,
+            allow_process: true,
+            allow_net: true,
+        };
+    }
+}
@@ -0,0 +1,491 @@
+//!
+//! ホスト側の Rust の値と、Lisp の値 `Type` との相互変換を定義
+//!
+
+use crate::eval::{EvalError, NativeFn};
+use crate::opaque::Opaque;
+use crate::types::*;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// 構造体を連想リストと相互変換する derive マクロ。`derive` feature で有効になる
+#[cfg(feature = "derive")]
+pub use liblisp_derive::{FromLisp, ToLisp};
+
+/// Rust の値を Lisp の値に変換する。
+///
+/// 真偽値は `1` / `0`、`Vec` とタプルはリスト、`HashMap` と `BTreeMap` は `((k v) ...)` の形式の連想リスト、
+/// `None` と `()` は `Void` になる
+pub trait ToLisp {
+    fn to_lisp(&self) -> Type;
+}
+
+/// Lisp の値を Rust の値に変換する。型が合わなければ `EvalError::TypeMismatch`
+///
+/// 対応する形式は `ToLisp` と同じ。リストを受け取る変換は、`Vector` も受け付ける
+pub trait FromLisp: Sized {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError>;
+}
+
+// 要素を先頭から順に並べたリストを作る
+fn list_of(items: Vec<Type>) -> Type {
+    let l = items
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Type::TypeList(Rc::new(l));
+}
+
+// 長さが n のリスト（もしくは Vector）の要素を取り出す
+fn elements_of(t: &Type, n: usize) -> Result<Vec<Type>, EvalError> {
+    let items = Vec::<Type>::try_from(t.clone())?;
+    if items.len() != n {
+        return Err(EvalError::TypeMismatch);
+    }
+    return Ok(items);
+}
+
+/// `((k v) ...)` の形式の連想リスト（もしくは Vector）から、キーと値の組を先頭から順に取り出す。
+/// `#[derive(FromLisp)]` が生成するコードから使う
+pub fn alist_entries(t: &Type) -> Result<Vec<(Type, Type)>, EvalError> {
+    return Vec::<(Type, Type)>::from_lisp(t);
+}
+
+impl ToLisp for Type {
+    fn to_lisp(&self) -> Type {
+        return self.clone();
+    }
+}
+
+impl FromLisp for Type {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return Ok(t.clone());
+    }
+}
+
+impl ToLisp for Opaque {
+    fn to_lisp(&self) -> Type {
+        return Type::Opaque(self.clone());
+    }
+}
+
+impl FromLisp for Opaque {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        match t {
+            Type::Opaque(o) => {
+                return Ok(o.clone());
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+}
+
+// Int に収まる整数型を、Int に変換する ToLisp を実装する
+macro_rules! impl_to_lisp_for_int {
+    ($($t:ty),*) => {
+        $(
+            impl ToLisp for $t {
+                #[allow(clippy::useless_conversion)]
+                fn to_lisp(&self) -> Type {
+                    return Type::Int(Int::from(*self));
+                }
+            }
+        )*
+    };
+}
+
+// 整数型を Int から取り出す FromLisp を実装する。この型に収まらなければ IntOverflow
+macro_rules! impl_from_lisp_for_int {
+    ($($t:ty),*) => {
+        $(
+            impl FromLisp for $t {
+                fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+                    return <$t>::try_from(t.clone());
+                }
+            }
+        )*
+    };
+}
+
+// Int の幅に応じて、Int に収まる整数型だけ ToLisp を実装する
+impl_to_lisp_for_int!(i32);
+#[cfg(not(all(feature = "int32", not(feature = "int64"), not(feature = "int128"))))]
+impl_to_lisp_for_int!(i64);
+#[cfg(feature = "int128")]
+impl_to_lisp_for_int!(i128);
+impl_from_lisp_for_int!(i32, i64, i128);
+
+impl ToLisp for bool {
+    fn to_lisp(&self) -> Type {
+        return Type::Int(if *self { 1 } else { 0 });
+    }
+}
+
+/// `0` を偽、それ以外の `Int` を真とする。`if` の条件と同じ解釈
+impl FromLisp for bool {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return Ok(t.expect_int()? != 0);
+    }
+}
+
+impl ToLisp for str {
+    fn to_lisp(&self) -> Type {
+        return Type::Str(self.into());
+    }
+}
+
+impl ToLisp for String {
+    fn to_lisp(&self) -> Type {
+        return Type::Str(self.as_str().into());
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return t.expect_str().map(String::from);
+    }
+}
+
+impl ToLisp for () {
+    fn to_lisp(&self) -> Type {
+        return Type::Void;
+    }
+}
+
+impl FromLisp for () {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        match t {
+            Type::Void => {
+                return Ok(());
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+}
+
+impl<T: ToLisp + ?Sized> ToLisp for &T {
+    fn to_lisp(&self) -> Type {
+        return (**self).to_lisp();
+    }
+}
+
+impl<T: ToLisp> ToLisp for Option<T> {
+    fn to_lisp(&self) -> Type {
+        match self {
+            Some(v) => {
+                return v.to_lisp();
+            }
+            None => {
+                return Type::Void;
+            }
+        }
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        match t {
+            Type::Void => {
+                return Ok(None);
+            }
+            _ => {
+                return T::from_lisp(t).map(Some);
+            }
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for [T] {
+    fn to_lisp(&self) -> Type {
+        return list_of(self.iter().map(ToLisp::to_lisp).collect());
+    }
+}
+
+impl<T: ToLisp> ToLisp for Vec<T> {
+    fn to_lisp(&self) -> Type {
+        return self.as_slice().to_lisp();
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return Vec::<Type>::try_from(t.clone())?
+            .iter()
+            .map(T::from_lisp)
+            .collect();
+    }
+}
+
+impl<A: ToLisp, B: ToLisp> ToLisp for (A, B) {
+    fn to_lisp(&self) -> Type {
+        return list_of(vec![self.0.to_lisp(), self.1.to_lisp()]);
+    }
+}
+
+impl<A: FromLisp, B: FromLisp> FromLisp for (A, B) {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        let items = elements_of(t, 2)?;
+        return Ok((A::from_lisp(&items[0])?, B::from_lisp(&items[1])?));
+    }
+}
+
+impl<A: ToLisp, B: ToLisp, C: ToLisp> ToLisp for (A, B, C) {
+    fn to_lisp(&self) -> Type {
+        return list_of(vec![self.0.to_lisp(), self.1.to_lisp(), self.2.to_lisp()]);
+    }
+}
+
+impl<A: FromLisp, B: FromLisp, C: FromLisp> FromLisp for (A, B, C) {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        let items = elements_of(t, 3)?;
+        return Ok((
+            A::from_lisp(&items[0])?,
+            B::from_lisp(&items[1])?,
+            C::from_lisp(&items[2])?,
+        ));
+    }
+}
+
+impl<K: ToLisp, V: ToLisp> ToLisp for BTreeMap<K, V> {
+    fn to_lisp(&self) -> Type {
+        return list_of(self.iter().map(|e| e.to_lisp()).collect());
+    }
+}
+
+impl<K: FromLisp + Ord, V: FromLisp> FromLisp for BTreeMap<K, V> {
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return Vec::<(K, V)>::from_lisp(t).map(|v| v.into_iter().collect());
+    }
+}
+
+/// 出力が実行ごとに変わらないよう、連想リストはキーを Lisp の値にした順序で並べる
+#[cfg(feature = "std")]
+impl<K: ToLisp, V: ToLisp, S> ToLisp for HashMap<K, V, S> {
+    fn to_lisp(&self) -> Type {
+        let mut entries: Vec<(Type, Type)> = self
+            .iter()
+            .map(|(k, v)| (k.to_lisp(), v.to_lisp()))
+            .collect();
+        entries.sort();
+        return entries.to_lisp();
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> FromLisp for HashMap<K, V, S>
+where
+    K: FromLisp + Eq + core::hash::Hash,
+    V: FromLisp,
+    S: core::hash::BuildHasher + Default,
+{
+    fn from_lisp(t: &Type) -> Result<Self, EvalError> {
+        return Vec::<(K, V)>::from_lisp(t).map(|v| v.into_iter().collect());
+    }
+}
+
+/// 関数の戻り値を、Lisp の値に変換する。`ToLisp` を実装した値と、その `Result` に対応する
+pub trait IntoLispResult {
+    fn into_lisp_result(self) -> Result<Type, EvalError>;
+}
+
+impl<T: ToLisp> IntoLispResult for T {
+    fn into_lisp_result(self) -> Result<Type, EvalError> {
+        return Ok(self.to_lisp());
+    }
+}
+
+impl<T: ToLisp> IntoLispResult for Result<T, EvalError> {
+    fn into_lisp_result(self) -> Result<Type, EvalError> {
+        return self.map(|v| v.to_lisp());
+    }
+}
+
+/// `Context::register_fn` で登録できる関数。
+///
+/// 評価済みの引数をそのまま受け取る `Fn(&[Type]) -> Result<Type, EvalError>` の他に、
+/// 引数が `FromLisp`、戻り値が `IntoLispResult` を実装した、引数 6 個までの関数に対応する。
+/// 後者は、引数の数が違えば `EvalError::ArityMismatch`、引数を変換できなければ `EvalError::ArgumentTypeMismatch` になる。
+/// エラーには、登録する名前 `name` と、受け取る引数の Rust での型名（`core::any::type_name`）を使う。
+/// `Args` は実装を区別するためだけの型で、呼び出し側で指定する必要はない
+pub trait IntoNativeFn<Args> {
+    fn into_native_fn(self, name: &str) -> NativeFn;
+}
+
+/// `IntoNativeFn` で、引数を変換せずに受け取る関数を表す
+pub struct RawArgs;
+
+impl<F> IntoNativeFn<RawArgs> for F
+where
+    F: Fn(&[Type]) -> Result<Type, EvalError> + 'static,
+{
+    fn into_native_fn(self, _name: &str) -> NativeFn {
+        return Rc::new(self);
+    }
+}
+
+// 引数の数ごとに、型付きの関数の IntoNativeFn を実装する
+macro_rules! impl_into_native_fn {
+    ($n:expr; $($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoNativeFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoLispResult,
+            $($arg: FromLisp,)*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn into_native_fn(self, name: &str) -> NativeFn {
+                let name = String::from(name);
+                return Rc::new(move |args: &[Type]| {
+                    if args.len() != $n {
+                        return Err(EvalError::ArityMismatch {
+                            name: name.clone(),
+                            min: $n,
+                            max: Some($n),
+                            actual: args.len(),
+                        });
+                    }
+                    let mut it = args.iter();
+                    let mut index = 0;
+                    $(
+                        index += 1;
+                        let $arg = $arg::from_lisp(it.next().unwrap()).map_err(|_| {
+                            return EvalError::ArgumentTypeMismatch {
+                                name: name.clone(),
+                                index,
+                                expected: String::from(core::any::type_name::<$arg>()),
+                            };
+                        })?;
+                    )*
+                    return (self)($($arg),*).into_lisp_result();
+                });
+            }
+        }
+    };
+}
+
+impl_into_native_fn!(0;);
+impl_into_native_fn!(1; A);
+impl_into_native_fn!(2; A, B);
+impl_into_native_fn!(3; A, B, C);
+impl_into_native_fn!(4; A, B, C, D);
+impl_into_native_fn!(5; A, B, C, D, E);
+impl_into_native_fn!(6; A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use crate::convert::*;
+
+    // 往復して元の値に戻ることを確認する
+    fn roundtrip<T: ToLisp + FromLisp + PartialEq + core::fmt::Debug>(v: T) {
+        assert_eq!(T::from_lisp(&v.to_lisp()), Ok(v));
+    }
+
+    #[test]
+    fn to_lisp_tests() {
+        assert_eq!(1.to_lisp(), Type::Int(1));
+        assert_eq!(true.to_lisp(), Type::Int(1));
+        assert_eq!(false.to_lisp(), Type::Int(0));
+        assert_eq!("s".to_lisp(), Type::Str("s".into()));
+        assert_eq!(None::<i32>.to_lisp(), Type::Void);
+        assert_eq!(Some(1).to_lisp(), Type::Int(1));
+        assert_eq!(vec![1, 2].to_lisp().to_string(), "(1 2)");
+        assert_eq!((1, "a", true).to_lisp().to_string(), "(1 \"a\" 1)");
+    }
+
+    #[test]
+    fn from_lisp_tests() {
+        roundtrip(3);
+        roundtrip(true);
+        roundtrip(String::from("s"));
+        roundtrip(Some(vec![1, 2, 3]));
+        roundtrip(None::<String>);
+        roundtrip((1, String::from("a")));
+        roundtrip(vec![(1, true, String::from("x"))]);
+        roundtrip(BTreeMap::from([(1, vec![1]), (2, vec![])]));
+
+        assert_eq!(bool::from_lisp(&Type::Int(2)), Ok(true));
+        let v = Type::Vector(Rc::new(vec![Type::Int(1), Type::Int(2)]));
+        assert_eq!(Vec::<i32>::from_lisp(&v), Ok(vec![1, 2]));
+
+        let cases = vec![
+            i32::from_lisp(&Type::Str("1".into())).map(|_| ()),
+            String::from_lisp(&Type::Atom("a".into())).map(|_| ()),
+            Vec::<i32>::from_lisp(&vec!["a"].to_lisp()).map(|_| ()),
+            <(i32, i32)>::from_lisp(&vec![1, 2, 3].to_lisp()).map(|_| ()),
+            Option::<i32>::from_lisp(&Type::Str("a".into())).map(|_| ()),
+            <()>::from_lisp(&Type::Int(0)),
+        ];
+        for (i, res) in cases.into_iter().enumerate() {
+            assert_eq!(res, Err(EvalError::TypeMismatch), "case {}", i);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hashmap_tests() {
+        let mut m = HashMap::new();
+        m.insert("b".to_string(), 2);
+        m.insert("a".to_string(), 1);
+        assert_eq!(m.to_lisp().to_string(), "((\"a\" 1) (\"b\" 2))");
+        roundtrip(m);
+    }
+
+    #[test]
+    fn native_fn_tests() {
+        let add = (|a: i32, b: i32| a + b).into_native_fn("add");
+        assert_eq!(add(&[Type::Int(1), Type::Int(2)]), Ok(Type::Int(3)));
+        assert_eq!(
+            add(&[Type::Int(1)]),
+            Err(EvalError::ArityMismatch {
+                name: "add".into(),
+                min: 2,
+                max: Some(2),
+                actual: 1,
+            })
+        );
+        let err = add(&[Type::Int(1), Type::Str("2".into())]).unwrap_err();
+        assert_eq!(
+            err,
+            EvalError::ArgumentTypeMismatch {
+                name: "add".into(),
+                index: 2,
+                expected: "i32".into(),
+            }
+        );
+        assert_eq!(err.to_string(), "add expects i32 as argument 2");
+
+        let greet = (|name: String, times: Option<i32>| name.repeat(times.unwrap_or(1) as usize))
+            .into_native_fn("greet");
+        assert_eq!(
+            greet(&[Type::Str("a".into()), Type::Int(3)]),
+            Ok(Type::Str("aaa".into()))
+        );
+        assert_eq!(
+            greet(&[Type::Str("a".into()), Type::Void]),
+            Ok(Type::Str("a".into()))
+        );
+
+        let checked = (|a: i32, b: i32| a.checked_div(b).ok_or(EvalError::DivisionByZero))
+            .into_native_fn("checked");
+        assert_eq!(checked(&[Type::Int(6), Type::Int(3)]), Ok(Type::Int(2)));
+        assert_eq!(
+            checked(&[Type::Int(1), Type::Int(0)]),
+            Err(EvalError::DivisionByZero)
+        );
+
+        let unit = (|| ()).into_native_fn("unit");
+        assert_eq!(unit(&[]), Ok(Type::Void));
+        let sum = (|v: Vec<i32>| v.iter().sum::<i32>()).into_native_fn("sum");
+        assert_eq!(sum(&[vec![1, 2, 3].to_lisp()]), Ok(Type::Int(6)));
+    }
+}
@@ -0,0 +1,330 @@
+//!
+//! 変数の環境（スコープの連なり）を定義
+//!
+
+use crate::types::Type;
+use crate::util::List;
+use alloc::borrow::Cow;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
+
+/// 1 つのスコープの変数テーブル
+pub type Frame = Map<Rc<str>, Binding>;
+
+/// 変数の束縛
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Binding {
+    pub value: Type,
+    pub constant: bool, // defconst で定義した定数なら true 。定数は set や define で書き換えられない
+}
+
+impl Binding {
+    /// 書き換え可能な変数の束縛を作る
+    pub fn new(value: Type) -> Binding {
+        return Binding {
+            value,
+            constant: false,
+        };
+    }
+}
+
+/// 変数の環境。グローバルなスコープと、関数呼び出しなどで積んだローカルなスコープからなる。
+///
+/// 各スコープは `Rc` で共有する永続的な構造で、ローカルなスコープは内側から順に連結リストでつなぐ。
+/// そのため、スコープを積む・取り除く操作と、環境全体の複製はスコープの数や変数の数によらず O(1) で行える。
+/// 複製した環境同士はスコープを共有し、書き換える時に、書き換えるスコープとそこまでの連結だけを複製する
+///
+/// `child` で作った環境は、元の環境のグローバルなスコープを書き換えない層として下に持ち、
+/// 自身のグローバルなスコープには、新しく定義した変数と、書き換えた変数の複製だけを持つ
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    global: Rc<Frame>,
+    bases: List<Rc<Frame>>, // child の元になった環境のグローバルなスコープ。先頭が最も新しい層
+    locals: List<Rc<Frame>>, // 先頭が最も内側のスコープ
+    depth: usize,           // ローカルなスコープの数
+}
+
+impl Env {
+    /// 空のグローバルなスコープだけを持つ環境を作る
+    pub fn new() -> Env {
+        return Env::default();
+    }
+
+    /// スコープの一覧（先頭がグローバルなスコープ）から環境を作る
+    pub fn from_frames(frames: Vec<Frame>) -> Env {
+        let mut frames = frames.into_iter();
+        let mut env = Env {
+            global: Rc::new(frames.next().unwrap_or_default()),
+            bases: List::new(),
+            locals: List::new(),
+            depth: 0,
+        };
+        for frame in frames {
+            env.push(frame);
+        }
+        return env;
+    }
+
+    /// グローバルなスコープだけを持つ、この環境を元にした環境を作る。
+    /// 元の環境のグローバルな変数は、複製せずに共有する。作った環境での定義や書き換えは、元の環境には影響しない
+    pub fn child(&self) -> Env {
+        let bases = if self.global.is_empty() {
+            self.bases.clone()
+        } else {
+            self.bases.cons(&self.global)
+        };
+        return Env {
+            global: Rc::new(Frame::default()),
+            bases,
+            locals: List::new(),
+            depth: 0,
+        };
+    }
+
+    /// スコープの一覧。先頭がグローバルなスコープで、最後が最も内側のスコープ。
+    /// `child` で作った環境では、元の環境の変数も含めたグローバルなスコープを作って返す
+    pub fn frames(&self) -> Vec<Cow<'_, Frame>> {
+        let mut res = Vec::with_capacity(self.depth + 1);
+        let mut rest = &self.locals;
+        while let List::Cons(frame, tail) = rest {
+            res.push(Cow::Borrowed(&**frame));
+            rest = tail;
+        }
+        if self.bases.is_empty() {
+            res.push(Cow::Borrowed(&*self.global));
+        } else {
+            let mut layers: Vec<&Frame> = self.bases.iter().map(|f| &**f).collect();
+            layers.reverse();
+            layers.push(&self.global);
+            let mut global = Frame::default();
+            for layer in layers {
+                global.extend(layer.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+            res.push(Cow::Owned(global));
+        }
+        res.reverse();
+        return res;
+    }
+
+    /// ローカルなスコープの中にいなければ true
+    pub fn is_global_scope(&self) -> bool {
+        return self.depth == 0;
+    }
+
+    /// グローバルなスコープを書き換えるために取り出す。他の環境と共有していれば、先に複製する
+    pub fn global_mut(&mut self) -> &mut Frame {
+        return Rc::make_mut(&mut self.global);
+    }
+
+    /// グローバルな変数の数。`child` で作った環境では、元の環境の変数も含めて、同じ名前の変数は 1 つと数える
+    pub fn global_count(&self) -> usize {
+        if self.bases.is_empty() {
+            return self.global.len();
+        }
+        let names: BTreeSet<&str> = self
+            .bases
+            .iter()
+            .chain(core::iter::once(&self.global))
+            .flat_map(|frame| frame.keys().map(|k| &**k))
+            .collect();
+        return names.len();
+    }
+
+    /// グローバルな変数を探す。`child` で作った環境では、見つからなければ元の環境を新しい順に探す
+    pub fn find_global(&self, name: &str) -> Option<&Binding> {
+        if let Some(b) = self.global.get(name) {
+            return Some(b);
+        }
+        return self.bases.iter().find_map(|frame| frame.get(name));
+    }
+
+    /// `find_global` と同様に探し、書き換えるために取り出す。
+    /// 元の環境の変数は、自身のグローバルなスコープに複製してから取り出す
+    pub fn find_global_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        if !self.global.contains_key(name) {
+            let (key, binding) = self
+                .bases
+                .iter()
+                .find_map(|frame| frame.get_key_value(name))?;
+            let (key, binding) = (key.clone(), binding.clone());
+            self.global_mut().insert(key, binding);
+        }
+        return self.global_mut().get_mut(name);
+    }
+
+    /// 最も内側のスコープを書き換えるために取り出す
+    pub fn current_mut(&mut self) -> &mut Frame {
+        if self.is_global_scope() {
+            return self.global_mut();
+        }
+        match &mut self.locals {
+            List::Cons(frame, _) => {
+                return Rc::make_mut(frame);
+            }
+            List::Nil => {
+                unreachable!("locals is empty only in the global scope");
+            }
+        }
+    }
+
+    /// ローカルなスコープから、内側から順に変数を探す
+    pub fn find_local(&self, name: &str) -> Option<&Binding> {
+        let mut rest = &self.locals;
+        while let List::Cons(frame, tail) = rest {
+            if let Some(b) = frame.get(name) {
+                return Some(b);
+            }
+            rest = tail;
+        }
+        return None;
+    }
+
+    /// `find_local` と同様に探し、書き換えるために取り出す
+    pub fn find_local_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        return find_mut(&mut self.locals, name);
+    }
+
+    /// 新しいローカルなスコープを積む
+    pub fn push(&mut self, frame: Frame) {
+        self.locals = self.locals.cons(&Rc::new(frame));
+        self.depth += 1;
+    }
+
+    /// 最も内側のローカルなスコープを取り除いて返す。ローカルなスコープが無ければ何もせず None を返す
+    pub fn pop(&mut self) -> Option<Rc<Frame>> {
+        if let List::Cons(frame, tail) = &self.locals {
+            let frame = frame.clone();
+            self.locals = (**tail).clone();
+            self.depth -= 1;
+            return Some(frame);
+        }
+        return None;
+    }
+}
+
+// 連結したスコープから、内側から順に変数を探す。
+// 通る連結とスコープは、他の環境と共有していれば複製する
+fn find_mut<'a>(locals: &'a mut List<Rc<Frame>>, name: &str) -> Option<&'a mut Binding> {
+    match locals {
+        List::Nil => {
+            return None;
+        }
+        List::Cons(frame, tail) => {
+            if frame.contains_key(name) {
+                return Rc::make_mut(frame).get_mut(name);
+            }
+            return find_mut(Rc::make_mut(tail), name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::*;
+    use crate::types::Int;
+
+    fn frame(entries: &[(&str, Int)]) -> Frame {
+        return entries
+            .iter()
+            .map(|(n, v)| (Rc::from(*n), Binding::new(Type::Int(*v))))
+            .collect();
+    }
+
+    fn value(b: Option<&Binding>) -> Option<Type> {
+        return b.map(|b| b.value.clone());
+    }
+
+    #[test]
+    fn scope_tests() {
+        let mut env = Env::new();
+        env.global_mut()
+            .insert(Rc::from("*g*"), Binding::new(Type::Int(0)));
+        env.push(frame(&[("*a*", 1), ("*b*", 1)]));
+        env.push(frame(&[("*a*", 2)]));
+        assert_eq!(env.frames().len(), 3);
+        assert!(!env.is_global_scope());
+        assert_eq!(value(env.find_local("*a*")), Some(Type::Int(2)));
+        assert_eq!(value(env.find_local("*b*")), Some(Type::Int(1)));
+        assert_eq!(value(env.find_local("*g*")), None);
+        assert_eq!(value(env.find_global("*g*")), Some(Type::Int(0)));
+
+        env.find_local_mut("*b*").unwrap().value = Type::Int(3);
+        env.current_mut()
+            .insert(Rc::from("*c*"), Binding::new(Type::Int(4)));
+        let sizes: Vec<usize> = env.frames().iter().map(|f| f.len()).collect();
+        assert_eq!(sizes, vec![1, 2, 2]);
+
+        env.pop();
+        assert_eq!(value(env.find_local("*a*")), Some(Type::Int(1)));
+        assert_eq!(value(env.find_local("*b*")), Some(Type::Int(3)));
+        assert_eq!(value(env.find_local("*c*")), None);
+        env.pop();
+        env.pop();
+        assert!(env.is_global_scope());
+        assert_eq!(env.frames().len(), 1);
+    }
+
+    #[test]
+    fn persistence_tests() {
+        // 複製した環境はスコープを共有し、書き換えても互いに影響しない
+        let mut env = Env::from_frames(vec![frame(&[("*g*", 0)]), frame(&[("*a*", 1)])]);
+        env.push(frame(&[("*b*", 2)]));
+        let saved = env.clone();
+        assert!(Rc::ptr_eq(&env.global, &saved.global));
+
+        env.find_local_mut("*a*").unwrap().value = Type::Int(10);
+        env.global_mut()
+            .insert(Rc::from("*g*"), Binding::new(Type::Int(20)));
+        env.pop();
+        env.push(frame(&[("*c*", 3)]));
+
+        assert_eq!(value(saved.find_local("*a*")), Some(Type::Int(1)));
+        assert_eq!(value(saved.find_local("*b*")), Some(Type::Int(2)));
+        assert_eq!(value(saved.find_local("*c*")), None);
+        assert_eq!(value(saved.find_global("*g*")), Some(Type::Int(0)));
+        assert_eq!(value(env.find_local("*a*")), Some(Type::Int(10)));
+        assert_eq!(value(env.find_global("*g*")), Some(Type::Int(20)));
+        assert_eq!(saved.frames().len(), 3);
+        assert_eq!(env.frames().len(), 3);
+    }
+
+    #[test]
+    fn child_tests() {
+        let mut base = Env::from_frames(vec![frame(&[("*a*", 1), ("*b*", 2)])]);
+        let mut child = base.child();
+        // 元の環境のグローバルなスコープは複製せずに共有する
+        assert!(child.global.is_empty());
+        assert_eq!(value(child.find_global("*a*")), Some(Type::Int(1)));
+
+        child.find_global_mut("*a*").unwrap().value = Type::Int(10);
+        child
+            .global_mut()
+            .insert(Rc::from("*c*"), Binding::new(Type::Int(3)));
+        assert_eq!(child.global.len(), 2);
+        assert_eq!(child.global_count(), 3);
+        assert_eq!(value(base.find_global("*a*")), Some(Type::Int(1)));
+        assert_eq!(value(base.find_global("*c*")), None);
+        assert!(child.find_global_mut("*d*").is_none());
+
+        // 子を作った後の元の環境の書き換えは、子には見えない
+        base.find_global_mut("*b*").unwrap().value = Type::Int(20);
+        assert_eq!(value(child.find_global("*b*")), Some(Type::Int(2)));
+
+        // 子の子は、全ての層を新しい順に探す
+        let grandchild = child.child();
+        assert_eq!(value(grandchild.find_global("*a*")), Some(Type::Int(10)));
+        assert_eq!(value(grandchild.find_global("*b*")), Some(Type::Int(2)));
+        let global = &grandchild.frames()[0];
+        let mut names: Vec<&str> = global.keys().map(|k| &**k).collect();
+        names.sort();
+        assert_eq!(names, vec!["*a*", "*b*", "*c*"]);
+        assert_eq!(grandchild.global_count(), 3);
+        assert_eq!(value(global.get("*a*")), Some(Type::Int(10)));
+    }
+}
@@ -2,38 +2,341 @@
 //! Expression を Type に変換する処理を定義
 //!
 
+use crate::analyze::arity_message;
+use crate::arena::Node;
+use crate::capabilities::*;
+use crate::convert::IntoNativeFn;
+use crate::env::*;
 use crate::expression::*;
+use crate::filesystem::*;
+#[cfg(feature = "http")]
+use crate::http::*;
+use crate::lexer::{is_var_name, Span};
+use crate::loader::*;
+use crate::observer::*;
+use crate::opaque::Opaque;
+use crate::output::*;
+use crate::pattern::*;
+#[cfg(feature = "std")]
+use crate::profiler::*;
+use crate::random::*;
+use crate::source_map::SourceMap;
+use crate::trace::*;
 use crate::types::*;
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::rc::Rc;
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
+
+// log feature が有効なら、log クレートの trace! / debug! でイベントを送る。無効なら何もしない
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
 
 /// `eval` 及び `eval_with_context` 呼び出し時のエラー
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EvalError {
     Unexpected,
     TypeMismatch,
     BadArrity,
+    ArityMismatch {
+        name: String,
+        min: usize,
+        max: Option<usize>,
+        actual: usize,
+    }, // 組み込み関数か register_fn で登録した関数に渡した引数の数が、宣言した範囲に無い。最大が None なら上限は無い
+    ArgumentTypeMismatch {
+        name: String,
+        index: usize,
+        expected: String,
+    }, // register_fn で登録した関数の index 番目（1 から数える）の引数を、Rust の型 expected に変換できなかった
     NotImplementation,
     NotFoundFunctionName,
     DoHeadForNil,
     UndefinedVariableReference,
     EvaluatingNonAtomHeadList,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    ReturnOutsideFunction,
+    ReturnFromOutsideBlock(String), // return-from で指定した名前の block の中にいない。block の名前を持つ
+    AssignToUndefinedVariable,
+    AssignToConstant, // defconst で定義した変数を書き換えようとした
+    DivisionByZero,
+    IntOverflow,        // 演算結果が Int に収まらない（bignum feature が無効な場合）
+    IndexOutOfRange,    // Vector の範囲外の添字を参照した
+    MatchFailed,        // 値がどのパターンにもマッチしなかった
+    LoopLimitExceeded,  // while の繰り返し回数が上限を超えた
+    DepthLimitExceeded, // 式の入れ子の深さが、Context に設定した上限を超えた
+    LimitExceeded,      // 変数の数か、変数に代入する値の大きさが、Context に設定した上限を超えた
+    VoidValue,          // 値を持たない Void を、数値の演算や大小の比較の引数にした
+    Incomparable {
+        op: String,
+        left: String,
+        right: String,
+    }, // 比較できない組み合わせの値を比較した。演算子と、両辺の型の名前を持つ
+    Raised(Type),       // (raise v) で送出された値
+    AssertionFailed {
+        expected: Type,
+        actual: Type,
+    },
+    LoadFailed(String), // SourceLoader がソースを読み込めなかった
+    ParseFailed(ExpressionConversionError), // 読み込んだソースを式に変換できなかった
+    InvalidNumber(String), // parse-int に渡した文字列を整数として読めなかった。その文字列を持つ
+    CapabilityDenied,   // Capabilities で許可されていない組み込み関数を呼び出した
+    IoFailed(String),   // FileSystem でのファイルの読み書きに失敗した
+    Exit(i32),          // (exit n) で評価を終了した。プロセスを終了するかどうかはホスト側で決める
+    NativeFunctionPanicked(String), // register_fn で登録した関数が panic した。panic のメッセージを持つ
+    FunctionDisabled(String), // builtin_whitelist などで無効にした組み込み関数を呼び出した。関数名を持つ
+    UnavailableInWorker(String), // pmap のワーカースレッドに渡せないホスト側の設定を使おうとした。設定か関数の名前を持つ
+}
+
+impl EvalError {
+    /// エラーを Lisp の値に変換する。`(catch *e* ...)` で `*e*` に束縛される値になる。
+    /// `raise` で送出された値はそのまま、`AssertionFailed` は `(AssertionFailed expected actual)` というリストに、
+    /// `InvalidNumber` は `(InvalidNumber s)` というリストに、
+    /// `Incomparable` は `(Incomparable op left right)` というリストに、
+    /// それ以外のエラーはエラー名の Atom に変換する。
+    pub fn to_type(&self) -> Type {
+        match self {
+            EvalError::Raised(v) => {
+                return v.clone();
+            }
+            EvalError::AssertionFailed { expected, actual } => {
+                let list = TypeList::new()
+                    .cons(actual)
+                    .cons(expected)
+                    .cons(&Type::Atom(Rc::from("AssertionFailed")));
+                return Type::TypeList(Rc::new(list));
+            }
+            EvalError::InvalidNumber(s) => {
+                let list = TypeList::new()
+                    .cons(&Type::Str(Rc::from(&**s)))
+                    .cons(&Type::Atom(Rc::from("InvalidNumber")));
+                return Type::TypeList(Rc::new(list));
+            }
+            EvalError::Incomparable { op, left, right } => {
+                let list = TypeList::new()
+                    .cons(&Type::Atom(Rc::from(&**right)))
+                    .cons(&Type::Atom(Rc::from(&**left)))
+                    .cons(&Type::Atom(Rc::from(&**op)))
+                    .cons(&Type::Atom(Rc::from("Incomparable")));
+                return Type::TypeList(Rc::new(list));
+            }
+            EvalError::ArityMismatch { .. } => {
+                return Type::Atom(Rc::from("ArityMismatch"));
+            }
+            EvalError::ArgumentTypeMismatch { .. } => {
+                return Type::Atom(Rc::from("ArgumentTypeMismatch"));
+            }
+            _ => {
+                return Type::Atom(Rc::from(format!("{:?}", self)));
+            }
+        }
+    }
+
+    /// `span` の位置の式を評価して起きたエラーとして、`map` を使ってその式をソースの行と共に示す、
+    /// 複数行のメッセージにする。式の位置は `parse_program_located` で得られる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::{parse_program_located, ReaderOptions};
+    /// use liblisp::source_map::SourceMap;
+    ///
+    /// let src = "(define *x* 1)\n(add *x* :a)";
+    /// let program = parse_program_located(src, &ReaderOptions::default()).unwrap();
+    /// let mut context = Context::new();
+    /// eval_with_context(&program[0].0, &mut context).unwrap();
+    /// let (exp, span) = &program[1];
+    /// let err = eval_with_context(exp, &mut context).unwrap_err();
+    /// assert_eq!(
+    ///     err.render(&SourceMap::new(src), *span),
+    ///     "eval error: TypeMismatch\n --> 2:1\n  |\n2 | (add *x* :a)\n  | ^^^^^^^^^^^^\n"
+    /// );
+    /// ```
+    pub fn render(&self, map: &SourceMap, span: Span) -> String {
+        return map.render(span, &format!("eval error: {}", self));
+    }
+
+    /// 評価を中断したエラーを、プロセスの終了コードに変換する。
+    /// `(exit n)` による `Exit(n)` は n、それ以外のエラーは 1 とする
+    pub fn exit_code(&self) -> i32 {
+        if let EvalError::Exit(code) = self {
+            return *code;
+        }
+        return 1;
+    }
+}
+
+/// `ArityMismatch` は `add expects 2 arguments, got 1` 、
+/// `ArgumentTypeMismatch` は `twice expects i32 as argument 1` のような文、それ以外は `{:?}` と同じ
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::ArityMismatch {
+                name,
+                min,
+                max,
+                actual,
+            } => {
+                return write!(f, "{}", arity_message(name, *min, *max, *actual));
+            }
+            EvalError::ArgumentTypeMismatch {
+                name,
+                index,
+                expected,
+            } => {
+                return write!(f, "{} expects {} as argument {}", name, expected, index);
+            }
+            _ => {
+                return write!(f, "{:?}", self);
+            }
+        }
+    }
+}
+
+// 評価を途中で打ち切る理由。エラーの他に、break / continue / return / return-from / exit による脱出を表す。
+// 脱出先（ループや関数呼び出し）に到達するまで、評価器の中を Err として伝播させる。
+// exit には脱出先が無く、try でも捕捉されずにホストまで伝播する
+#[derive(Debug, Clone, PartialEq)]
+enum EvalOutcome {
+    Error(EvalError),
+    Break,
+    Continue,
+    Return(Type),
+    ReturnFrom(Rc<str>, Type), // 脱出先の block の名前と、block の値
+    Exit(i32),
 }
 
+impl From<EvalError> for EvalOutcome {
+    fn from(e: EvalError) -> Self {
+        log_debug!("error: {:?}", e);
+        // マクロ展開などで一旦エラーに変換された exit は、再び脱出として扱う
+        if let EvalError::Exit(code) = e {
+            return EvalOutcome::Exit(code);
+        }
+        return EvalOutcome::Error(e);
+    }
+}
+
+impl EvalOutcome {
+    // 脱出先が見つからなかった脱出をエラーに変換する
+    fn into_error(self) -> EvalError {
+        match self {
+            EvalOutcome::Error(e) => {
+                return e;
+            }
+            EvalOutcome::Break => {
+                return EvalError::BreakOutsideLoop;
+            }
+            EvalOutcome::Continue => {
+                return EvalError::ContinueOutsideLoop;
+            }
+            EvalOutcome::Return(_) => {
+                return EvalError::ReturnOutsideFunction;
+            }
+            EvalOutcome::ReturnFrom(name, _) => {
+                return EvalError::ReturnFromOutsideBlock(String::from(&*name));
+            }
+            EvalOutcome::Exit(code) => {
+                return EvalError::Exit(code);
+            }
+        }
+    }
+}
+
+// 評価済みの引数を受け取る組み込み関数
+type EmbededFn = fn(&TypeList) -> Result<Type, EvalError>;
+
+// 評価済みの引数と Context を受け取る組み込み関数
+type EmbededContextFn = fn(&TypeList, &mut Context) -> Result<Type, EvalOutcome>;
+
+// 引数を関数内部で評価する組み込み関数（特殊形式）
+type EmbededSpecialFn = fn(&ExpressionList, &mut Context) -> Result<Type, EvalOutcome>;
+
+/// `Context::register_fn` で登録した、ホスト側の関数。評価済みの引数を受け取る
+pub type NativeFn = Rc<dyn Fn(&[Type]) -> Result<Type, EvalError>>;
+
+/// `Context::bind_dynamic` で登録した、参照されるたびに値を計算する変数
+pub type DynamicVar = Rc<dyn Fn() -> Type>;
+
+/// `Context::on_var_change` で登録した、変数の値が変わった時に呼び出されるコールバック。
+/// 変数名と、変更前と変更後の値を受け取る。値が無い（変数が未定義）場合は None
+pub type VarObserver = Box<dyn FnMut(&str, Option<&Type>, Option<&Type>)>;
+
+/// `Context::stats` で得られる、評価の統計。利用量に応じた課金や制限に使う
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EvalStats {
+    pub steps: u64,                                 // 評価した式の数
+    pub conses: u64, // 組み込み関数とホスト側の関数が、返り値のリストのために新しく作ったセルの数
+    pub max_depth: usize, // 評価中の式の入れ子の深さの最大値。トップレベルの式を 1 とする
+    pub builtin_calls: BTreeMap<&'static str, u64>, // 組み込み関数（特殊形式を含む）ごとの呼び出し回数
+}
+
+/// `Context::describe` や `Context::complete` で得られる、名前が指す定義の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Builtin,      // 引数を評価してから呼び出す組み込み関数
+    SpecialForm,  // cond や defun のような、引数を評価せずに受け取る組み込み関数
+    Function,     // defun で定義した関数
+    Macro,        // defmacro で定義したマクロ
+    HostFunction, // register_fn で登録したホスト側の関数
+    Variable,     // 変数。bind_dynamic で登録した変数を含む
+}
+
+/// `Context::complete` で得られる、補完の候補
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionItem {
+    pub label: Rc<str>, // 補完する名前
+    pub kind: SymbolKind,
+}
+
+/// `Context::describe` で得られる、関数やマクロの説明。エディタでのシグネチャの表示などに使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Description {
+    pub name: Rc<str>, // 定義の名前。モジュール内で定義したものは、モジュールで修飾した名前
+    pub kind: SymbolKind,
+    pub arity: Option<(usize, Option<usize>)>, // 引数の数の範囲（最小, 最大）。最大が None なら上限なし。ホスト側の関数では分からないので None
+    pub doc: Option<Rc<str>>,                  // defun / defmacro の本体の先頭に書いた文字列
+}
+
+/// `Context::register_method` で登録した、`Opaque` のメソッド。
+/// レシーバと、レシーバとメソッド名を除いた評価済みの引数を受け取る
+pub type NativeMethod = Rc<dyn Fn(&Opaque, &[Type]) -> Result<Type, EvalError>>;
+
 /// `ExpressionList` to `TypeList`
-impl<'a> TypeList<'a> {
-    fn try_from(
-        l: &ExpressionList<'a>,
-        context: &mut Context<'a>,
-    ) -> Result<TypeList<'a>, EvalError> {
+impl TypeList {
+    fn try_from(l: &ExpressionList, context: &mut Context) -> Result<TypeList, EvalOutcome> {
         match l {
             ExpressionList::Nil => {
                 return Ok(TypeList::Nil);
             }
             ExpressionList::Cons(e, left) => {
-                let r = eval_with_context(e, context)?;
-                let r2 = Self::try_from(&**left, context)?;
+                let r = eval_(e, context)?;
+                let r2 = Self::try_from(left, context)?;
                 return Ok(TypeList::Cons(r, Rc::new(r2)));
             }
         }
@@ -56,565 +359,8351 @@ impl<'a> TypeList<'a> {
 /// }
 /// ```
 ///
-pub fn eval<'a>(exp: &Expression<'a>) -> Result<Type<'a>, EvalError> {
+pub fn eval(exp: &Expression) -> Result<Type, EvalError> {
+    let mut context = Context::new();
+    return eval_with_context(exp, &mut context);
+}
+
+/// `args` の変数（`*x*` の形式の名前と値の組）をグローバルな変数として定義した新しい `Context` で、`Expression` を評価する。
+/// 値はソースを経由せずにそのまま渡すので、文字列を連結してソースを組み立てる必要がない。
+/// `*x*` の形式でない名前があれば、評価せずに `EvalError::TypeMismatch` を返す
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::eval_with_args;
+/// use liblisp::expression::Expression;
+/// use liblisp::types::Type;
+/// use std::convert::TryFrom;
+///
+/// let exp = Expression::try_from("(list *input* *n*)".as_bytes()).unwrap();
+/// let input = Type::Str("\") (exit 1) (\"".into());
+/// let res = eval_with_args(&exp, &[("*input*", input), ("*n*", Type::Int(2))]).unwrap();
+/// assert_eq!(res.to_string(), r#"("\") (exit 1) (\"" 2)"#);
+/// ```
+pub fn eval_with_args(exp: &Expression, args: &[(&str, Type)]) -> Result<Type, EvalError> {
     let mut context = Context::new();
+    for (name, value) in args {
+        if !is_var_name(name) {
+            return Err(EvalError::TypeMismatch);
+        }
+        context.define_var(name, value.clone())?;
+    }
     return eval_with_context(exp, &mut context);
 }
 
+// ファイルシステムを持たない wasm32 及び no_std では、デフォルトで読み込みを無効にする
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn default_loader() -> Box<dyn SourceLoader> {
+    return Box::new(FsLoader);
+}
+
+#[cfg(any(not(feature = "std"), target_arch = "wasm32"))]
+fn default_loader() -> Box<dyn SourceLoader> {
+    return Box::new(DisabledLoader);
+}
+
+// ファイルシステムを持たない wasm32 及び no_std では、デフォルトで空のメモリ上のファイルシステムを使う
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn default_filesystem() -> Box<dyn FileSystem> {
+    return Box::new(StdFileSystem);
+}
+
+#[cfg(not(all(feature = "std", not(target_arch = "wasm32"))))]
+fn default_filesystem() -> Box<dyn FileSystem> {
+    return Box::new(MemoryFileSystem::new());
+}
+
+// 標準出力を持たない wasm32 及び no_std では、デフォルトで print の出力を捨てる
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn default_output() -> Box<dyn Output> {
+    return Box::new(StdoutOutput);
+}
+
+#[cfg(not(all(feature = "std", not(target_arch = "wasm32"))))]
+fn default_output() -> Box<dyn Output> {
+    return Box::new(NullOutput);
+}
+
+// `Context::new_with_stdlib` で読み込む、Lisp で書かれた標準ライブラリ
+const PRELUDE: &str = include_str!("prelude.lisp");
+
 /// `eval` 及び `eval_with_context` 実行時に、持ち回す情報を管理する
-pub struct Context<'a> {
-    vartable: HashMap<&'a str, Type<'a>>, // 変数テーブル
+///
+/// 変数・関数・マクロ・フックの定義は全て `Context` が所有し、同じ名前で定義し直すと古い定義は解放される。
+/// 関数呼び出しで積んだスコープは呼び出しから戻る時に取り除かれる。
+/// `Type` の値は `Opaque` を経由しない限り循環しない（`Type` を参照）ので、`Context` を破棄すれば全ての値が解放される。
+/// 中身に `Type` を持つ `Opaque` を経由して循環した値は、`Context` を破棄しても解放されない
+pub struct Context {
+    env: Env,                                         // 変数の環境
+    macrotable: Map<Rc<str>, Rc<Procedure>>, // マクロテーブル。呼び出しのたびに複製しないよう Rc で持つ
+    functable: Map<Rc<str>, Rc<Procedure>>,  // ユーザ定義関数のテーブル
+    native_fns: Map<Rc<str>, NativeFn>,      // register_fn で登録したホスト側の関数のテーブル
+    methods: Map<TypeId, Map<Rc<str>, NativeMethod>>, // register_method で登録した、型ごとのメソッドのテーブル
+    builtins: Map<&'static str, (Builtin, usize)>, // この Context で使える組み込み関数と、その BUILTINS での位置のテーブル
+    disabled_builtins: BTreeSet<&'static str>, // builtin_whitelist などで無効にした組み込み関数の名前
+    hooks: Map<Rc<str>, Vec<Rc<str>>>, // add-hook で登録した、フック名ごとの関数名。登録順に呼び出す
+    tests: Vec<(Rc<str>, Procedure)>,  // deftest で定義したテスト。定義順に実行する
+    gensym_counter: u64,               // gensym で次に使う番号
+    rng: Rc<RefCell<Box<dyn RandomSource>>>, // random で使う乱数生成器。child で作った Context と共有する
+    capabilities: Capabilities,              // 副作用のある組み込み関数の利用許可
+    strict_set: bool,                        // true なら、未定義の変数への set をエラーにする
+    max_loop_iterations: Option<u32>,        // while 1 回あたりの繰り返し回数の上限
+    max_depth: Option<usize>,                // 評価中の式の入れ子の深さの上限
+    max_variables: Option<usize>,            // グローバルな変数の数の上限
+    max_value_size: Option<usize>,           // 変数に代入する値の、文字列で表した時のバイト数の上限
+    loader: Rc<dyn SourceLoader>,            // load / eval_file でソースを読み込む方法
+    filesystem: Rc<RefCell<Box<dyn FileSystem>>>, // slurp / spit で読み書きするファイルシステム
+    output: Rc<RefCell<Box<dyn Output>>>, // print で書き込む先。child で作った Context と共有する
+    #[cfg(feature = "http")]
+    http: Rc<RefCell<Box<dyn HttpClient>>>, // http-get / http-post でリクエストを送るクライアント
+    module: Option<Rc<str>>,              // 評価中のモジュール名。モジュール外なら None
+    tracer: Option<Box<dyn EvalObserver>>, // 式の評価の開始・終了を通知する先
+    var_observers: Vec<VarObserver>,      // on_var_change で登録した、変数の変更を通知する先
+    dynamic_vars: Map<Rc<str>, DynamicVar>, // bind_dynamic で登録した、値を計算する変数のテーブル
+    depth: usize,                         // 評価中の式の入れ子の深さ
+    #[cfg(feature = "std")]
+    profiler: Option<Profiler>, // enable_profiler で有効にしたプロファイラ
+    trace: Option<TraceRecorder>,         // enable_trace で有効にしたトレースの記録
+    stats: EvalStats,                     // stats で得られる評価の統計。builtin_calls は使わない
+    builtin_calls: Vec<u64>, // 組み込み関数ごとの呼び出し回数。毎回名前で探さないよう、BUILTINS での位置で数える
+    in_worker: bool, // pmap のワーカースレッドで作った Context なら true。loader などのホスト側の設定を使えない
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        return Context::new();
+    }
 }
 
-impl<'a> Context<'a> {
+impl Context {
     /// `Context` を新規作成
-    fn new() -> Context<'a> {
+    pub fn new() -> Context {
         return Context {
-            vartable: HashMap::new(),
+            env: Env::new(),
+            macrotable: Map::new(),
+            functable: Map::new(),
+            native_fns: Map::new(),
+            methods: Map::new(),
+            builtins: BUILTINS
+                .iter()
+                .enumerate()
+                .map(|(i, (name, f, _))| (*name, (*f, i)))
+                .collect(),
+            disabled_builtins: BTreeSet::new(),
+            hooks: Map::new(),
+            tests: Vec::new(),
+            gensym_counter: 0,
+            rng: Rc::new(RefCell::new(Box::new(SplitMix64::default()))),
+            capabilities: Capabilities::default(),
+            strict_set: false,
+            max_loop_iterations: None,
+            max_depth: None,
+            max_variables: None,
+            max_value_size: None,
+            loader: Rc::from(default_loader()),
+            filesystem: Rc::new(RefCell::new(default_filesystem())),
+            output: Rc::new(RefCell::new(default_output())),
+            #[cfg(feature = "http")]
+            http: Rc::new(RefCell::new(Box::new(UreqClient))),
+            module: None,
+            tracer: None,
+            var_observers: Vec::new(),
+            dynamic_vars: Map::new(),
+            depth: 0,
+            #[cfg(feature = "std")]
+            profiler: None,
+            trace: None,
+            stats: EvalStats::default(),
+            builtin_calls: Vec::new(),
+            in_worker: false,
         };
     }
-}
 
-/// `Expression` を `Type` に変換する。
-/// このとき、`Context` の情報を参照し、必要があれば `Context` に情報を追加する。
-/// `Expression` で、変数のセットを行い、その値を、次の `eval_with_context` 呼び出しに使いたい場合、この関数を使うと良い。
-pub fn eval_with_context<'a>(
-    exp: &Expression<'a>,
-    context: &mut Context<'a>,
-) -> Result<Type<'a>, EvalError> {
-    match exp {
-        Expression::Int(i) => {
-            return Ok(Type::Int(*i));
-        }
-        Expression::Atom(a) => {
-            return Ok(Type::Atom(*a));
-        }
-        Expression::Var(var) => {
-            if let Some(val) = context.vartable.get(*var) {
-                return Ok(val.clone());
-            } else {
-                return Err(EvalError::UndefinedVariableReference);
-            }
-        }
-        Expression::ExpressionList(clist) => {
-            // 組み込み関数のテーブル
-            let mut embeded_fn_table: HashMap<
-                &'a str,
-                fn(&TypeList<'a>) -> Result<Type<'a>, EvalError>,
-            > = HashMap::new();
-            embeded_fn_table.insert("add", add);
-            embeded_fn_table.insert("sub", sub);
-            embeded_fn_table.insert("mul", mul);
-            embeded_fn_table.insert("div", div);
-            embeded_fn_table.insert("list", list);
-            embeded_fn_table.insert("head", head);
-            embeded_fn_table.insert("tail", tail);
-            embeded_fn_table.insert("gt", gt);
-            embeded_fn_table.insert("lt", lt);
-            embeded_fn_table.insert("eq", eq);
-
-            // 引数を関数内部で評価する組み込み関数のテーブル
-            let mut embeded_fn_table2: HashMap<
-                &str,
-                fn(&ExpressionList<'a>, &mut Context<'a>) -> Result<Type<'a>, EvalError>,
-            > = HashMap::new();
-            embeded_fn_table2.insert("cond", cond);
-            embeded_fn_table2.insert("set", set);
-            embeded_fn_table2.insert("progn", progn);
-            embeded_fn_table2.insert("while", wloop);
+    /// `load` 及び `eval_file` が使う `SourceLoader` を差し替える。
+    /// ファイルシステムへのアクセスを禁止したい場合は `DisabledLoader` を指定する。
+    pub fn set_loader(&mut self, loader: Box<dyn SourceLoader>) {
+        self.loader = Rc::from(loader);
+    }
 
-            // リスト形式をevalする時、先頭のatomを関数名として扱う
-            if let Some(head) = clist.head() {
-                if let Expression::Atom(fun_name) = head {
-                    // 引数を関数内部で評価する組み込み関数の適用
-                    if let Some(f) = embeded_fn_table2.get(*fun_name) {
-                        let r = f(clist.tail(), context)?;
-                        return Ok(r);
-                    }
-                    // 組み込み関数の適用
-                    else if let Some(f) = embeded_fn_table.get(*fun_name) {
-                        // 引数をそれぞれ評価する
-                        let evaluated: TypeList = TypeList::try_from(clist.tail(), context)?;
-                        let result = f(&evaluated)?;
-                        return Ok(result);
-                    } else {
-                        // TODO: ユーザ定義関数の適用
-                        return Err(EvalError::NotFoundFunctionName);
-                    }
-                }
-                // Atomが先頭要素でない場合、評価できない
-                else {
-                    return Err(EvalError::EvaluatingNonAtomHeadList);
-                }
-            } else {
-                return Err(EvalError::Unexpected);
-            }
-        }
+    /// `slurp` 、`spit` 及び `file-exists` が使う `FileSystem` を差し替える。
+    /// これらの組み込み関数を使うには、`Capabilities::allow_fs` で許可する必要がある
+    pub fn set_filesystem(&mut self, filesystem: Box<dyn FileSystem>) {
+        self.filesystem = Rc::new(RefCell::new(filesystem));
     }
-}
 
-// (wloop cond body) という形式の while loop。
-// cond が 1 である限りループを続ける。
-fn wloop<'a>(l: &ExpressionList<'a>, context: &mut Context<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() != 2 {
-        return Err(EvalError::BadArrity);
+    /// `print` が書き込む `Output` を差し替える。
+    /// デフォルトは、wasm32 以外で `std` feature が有効なら標準出力、それ以外は書き込んだ文字列を捨てる `NullOutput`
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = Rc::new(RefCell::new(output));
     }
 
-    let cond = l.head().unwrap();
-    let body = l.tail().head().unwrap();
+    /// `print` が書き込む `Output` を `output` に置き換え、それまでの `Output` を返す。
+    /// `set_output` と異なり、`child` で作った `Context` と共有している書き込み先も置き換わる
+    pub fn replace_output(&mut self, output: Box<dyn Output>) -> Box<dyn Output> {
+        return core::mem::replace(&mut *self.output.borrow_mut(), output);
+    }
 
-    loop {
-        let evaluated_cond = eval_with_context(cond, context)?;
-        if let Type::Int(i) = evaluated_cond {
-            if i == 0 {
-                return Ok(Type::Void);
-            } else {
-                eval_with_context(body, context)?;
-            }
-        } else {
-            return Err(EvalError::TypeMismatch);
-        }
+    /// `http-get` 及び `http-post` が使う `HttpClient` を差し替える。
+    /// これらの組み込み関数を使うには、`Capabilities::allow_net` で許可する必要がある
+    #[cfg(feature = "http")]
+    pub fn set_http_client(&mut self, client: Box<dyn HttpClient>) {
+        self.http = Rc::new(RefCell::new(client));
     }
-}
 
-// リストの要素を順番に評価する。
-// 最後に評価した値を戻り値とする。
-fn progn<'a>(l: &ExpressionList<'a>, context: &mut Context<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() == 0 {
-        return Err(EvalError::BadArrity);
+    /// `path` のソースを `SourceLoader` で読み込み、トップレベルの式を先頭から順にこの `Context` で評価する。
+    /// 最後に評価した式の値を返す。式が一つもない場合は `Type::Void` を返す。
+    pub fn eval_file(&mut self, path: &str) -> Result<Type, EvalError> {
+        self.check_host_resource("loader")?;
+        let src = self.loader.load(path)?;
+        return self.eval_source(&src);
     }
-    // 各要素を順番に評価していく
-    let res = l.clone().into_iter().try_fold(Type::Void, |_, e| {
-        let res = eval_with_context(e.head().unwrap(), context)?;
+
+    // ソース中のトップレベルの式を先頭から順に評価し、最後に評価した値を返す
+    fn eval_source(&mut self, src: &str) -> Result<Type, EvalError> {
+        let program = parse_program(src).map_err(EvalError::ParseFailed)?;
+        let mut res = Type::Void;
+        for exp in &program {
+            res = eval_with_context(exp, self)?;
+        }
         return Ok(res);
-    })?;
-    return Ok(res);
-}
+    }
 
-// 変数に指定された値をセットする
-fn set<'a>(l: &ExpressionList<'a>, context: &mut Context<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() != 2 {
-        return Err(EvalError::BadArrity);
+    /// Lisp で書かれた標準ライブラリ（`not`, `abs`, `max`, `min`, `second`, `last`, `range`）を読み込んだ `Context` を新規作成
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new_with_stdlib();
+    /// let exp = Expression::try_from("(max (abs (sub 0 3)) 2)".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(3)));
+    /// ```
+    pub fn new_with_stdlib() -> Context {
+        let mut context = Context::new();
+        context
+            .eval_source(PRELUDE)
+            .expect("failed to load the prelude");
+        return context;
     }
 
-    let var = l.head().unwrap();
-    let val = eval_with_context(l.tail().head().unwrap(), context)?; // valはset関数に渡されてから評価する
+    /// この `Context` を元にした、新しい `Context` を作る。
+    /// 元の `Context` の変数・関数・マクロ・フック・テストの定義と、ホスト側の設定を引き継ぐ。
+    /// 作った `Context` での定義や変数の書き換えは、元の `Context` には影響しない。
+    /// 同じ定義の上で、互いに影響させずに多数の式を評価するのに使う。
+    ///
+    /// 変数は複製せずに共有し、書き換えた変数だけを作った `Context` に複製するので、
+    /// 作るのにかかる時間は変数の数によらない（関数やマクロのテーブルは、`Rc` の参照を複製する）。
+    /// `SourceLoader`、`FileSystem`、`HttpClient` 及び乱数生成器は、元の `Context` と共有する。
+    /// tracer、プロファイラ、トレースの記録、`on_var_change` のコールバックは引き継がない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut base = Context::new();
+    /// let setup = Expression::try_from("(progn (define *rate* 10) (defun price (*x*) (mul *x* *rate*)))".as_bytes()).unwrap();
+    /// eval_with_context(&setup, &mut base).unwrap();
+    ///
+    /// let inputs = ["(progn (set *rate* 0) (price 1))", "(price 2)"];
+    /// let results: Vec<Type> = inputs
+    ///     .iter()
+    ///     .map(|src| {
+    ///         let mut child = base.child();
+    ///         return eval_with_context(&Expression::try_from(src.as_bytes()).unwrap(), &mut child).unwrap();
+    ///     })
+    ///     .collect();
+    /// assert_eq!(results, vec![Type::Int(0), Type::Int(20)]);
+    /// ```
+    pub fn child(&self) -> Context {
+        return Context {
+            env: self.env.child(),
+            macrotable: self.macrotable.clone(),
+            functable: self.functable.clone(),
+            native_fns: self.native_fns.clone(),
+            methods: self.methods.clone(),
+            builtins: self.builtins.clone(),
+            disabled_builtins: self.disabled_builtins.clone(),
+            hooks: self.hooks.clone(),
+            tests: self.tests.clone(),
+            gensym_counter: self.gensym_counter,
+            rng: self.rng.clone(),
+            capabilities: self.capabilities.clone(),
+            strict_set: self.strict_set,
+            max_loop_iterations: self.max_loop_iterations,
+            max_depth: self.max_depth,
+            max_variables: self.max_variables,
+            max_value_size: self.max_value_size,
+            loader: self.loader.clone(),
+            filesystem: self.filesystem.clone(),
+            output: self.output.clone(),
+            #[cfg(feature = "http")]
+            http: self.http.clone(),
+            module: None,
+            tracer: None,
+            var_observers: Vec::new(),
+            dynamic_vars: self.dynamic_vars.clone(),
+            depth: 0,
+            #[cfg(feature = "std")]
+            profiler: None,
+            trace: None,
+            stats: EvalStats::default(),
+            builtin_calls: Vec::new(),
+            in_worker: self.in_worker,
+        };
+    }
 
-    // varは Var である必要がある
-    if let Expression::Var(varstr) = var {
-        context.vartable.insert(varstr, val.clone());
-        return Ok(val);
-    } else {
-        return Err(EvalError::TypeMismatch);
+    /// 変数、ユーザ定義関数、マクロ及びテストの定義を、`ContextSnapshot` として保存する。
+    /// `SourceLoader` や tracer などの、ホスト側の設定は含まない
+    pub fn snapshot(&self) -> ContextSnapshot {
+        let frames = self
+            .env
+            .frames()
+            .iter()
+            .map(|frame| sorted_entries(frame))
+            .collect();
+        return ContextSnapshot {
+            frames,
+            macros: sorted_entries(&self.macrotable),
+            functions: sorted_entries(&self.functable),
+            hooks: sorted_entries(&self.hooks),
+            tests: self.tests.clone(),
+            gensym_counter: self.gensym_counter,
+        };
     }
-}
 
-// リストを作成する
-fn list<'a>(l: &TypeList<'a>) -> Result<Type<'a>, EvalError> {
-    return Ok(Type::TypeList(Rc::new(l.clone())));
-}
+    /// `snapshot` で保存した状態に戻す。保存後に行った定義は破棄する
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// let def = Expression::try_from("(define *a* 1)".as_bytes()).unwrap();
+    /// eval_with_context(&def, &mut context).unwrap();
+    /// let snapshot = context.snapshot();
+    ///
+    /// let set = Expression::try_from("(set *a* 2)".as_bytes()).unwrap();
+    /// eval_with_context(&set, &mut context).unwrap();
+    /// context.restore(&snapshot);
+    ///
+    /// let exp = Expression::try_from("*a*".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(1)));
+    /// ```
+    pub fn restore(&mut self, snapshot: &ContextSnapshot) {
+        self.env = Env::from_frames(
+            snapshot
+                .frames
+                .iter()
+                .map(|frame| frame.iter().cloned().collect())
+                .collect(),
+        );
+        self.macrotable = snapshot.macros.iter().cloned().collect();
+        self.functable = snapshot.functions.iter().cloned().collect();
+        self.hooks = snapshot.hooks.iter().cloned().collect();
+        self.tests = snapshot.tests.clone();
+        self.gensym_counter = snapshot.gensym_counter;
+        self.module = None;
+        self.depth = 0;
+    }
 
-// リストの先頭要素を取り出す
-fn head<'a>(l: &TypeList<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() != 1 {
-        return Err(EvalError::BadArrity);
+    /// 他のシンボルと衝突しない、新しいシンボルを作る。
+    /// マクロが展開先で使う変数名を想定し、`*#g1*` のように `*` で囲む。
+    /// `#` はリーダーが受け付けない文字なので、ユーザが書いたシンボルとは衝突しない
+    pub fn gensym(&mut self) -> Rc<str> {
+        return self.gensym_with_prefix("g");
     }
-    let a = l.head().unwrap();
-    if let Type::TypeList(b) = a {
-        if let Some(c) = b.head() {
-            return Ok(c.clone());
-        } else {
-            return Err(EvalError::DoHeadForNil);
+
+    /// グローバルな変数 `name` （`*x*` の形式）を定義する。既に存在する場合は上書きする。
+    /// `defconst` で定義した定数の場合は `EvalError::AssignToConstant` になる
+    pub fn define_var(&mut self, name: &str, val: Type) -> Result<(), EvalError> {
+        if self.env.find_global(name).is_some_and(|b| b.constant) {
+            return Err(EvalError::AssignToConstant);
         }
-    } else {
-        return Err(EvalError::TypeMismatch);
+        let frame = self.env.global_mut();
+        frame.insert(Rc::from(name), Binding::new(val));
+        return Ok(());
     }
-}
 
-/// リストの先頭要素外を取り除いたものを返す
-fn tail<'a>(l: &TypeList<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() != 1 {
-        return Err(EvalError::BadArrity);
-    }
-    let a = l.head().unwrap();
-    if let Type::TypeList(b) = a {
-        return Ok(Type::TypeList(Rc::new(b.tail().clone())));
-    } else {
-        return Err(EvalError::TypeMismatch);
+    /// 参照されるたびに `f` で値を計算する、読み取り専用の変数 `name` （`*x*` の形式）を登録する。
+    /// 時刻のような、ホスト側で変化し続ける状態をスクリプトに見せるのに使う。同じ名前の変数が登録済みなら置き換える。
+    ///
+    /// 同じ名前のグローバルな変数より優先される。`set`、グローバルなスコープでの `define` や `defconst` は
+    /// `EvalError::AssignToConstant` になる。`defconst` と同じく、`let` などで作ったローカル変数で隠すことはできる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, EvalError};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::cell::Cell;
+    /// use std::convert::TryFrom;
+    /// use std::rc::Rc;
+    ///
+    /// let clock = Rc::new(Cell::new(0));
+    /// let now = clock.clone();
+    /// let mut context = Context::new();
+    /// context.bind_dynamic("*time*", move || Type::Int(now.get()));
+    /// let run = |context: &mut Context, src: &str| {
+    ///     return eval_with_context(&Expression::try_from(src.as_bytes()).unwrap(), context);
+    /// };
+    /// assert_eq!(run(&mut context, "*time*"), Ok(Type::Int(0)));
+    /// clock.set(100);
+    /// assert_eq!(run(&mut context, "*time*"), Ok(Type::Int(100)));
+    /// assert_eq!(run(&mut context, "(set *time* 0)"), Err(EvalError::AssignToConstant));
+    /// ```
+    pub fn bind_dynamic(&mut self, name: &str, f: impl Fn() -> Type + 'static) {
+        self.dynamic_vars.insert(Rc::from(name), Rc::new(f));
     }
-}
-
-enum ArithType {
-    Add,
-    Sub,
-    Mul,
-    Div,
-}
-
-// 加算を行う
-fn add<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return arith_op(l, ArithType::Add);
-}
-// 減算を行う
-fn sub<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return arith_op(l, ArithType::Sub);
-}
-// 乗算を行う
-fn mul<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return arith_op(l, ArithType::Mul);
-}
-// 除算を行う
-fn div<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return arith_op(l, ArithType::Div);
-}
 
-// 加減乗除の演算を行う
-fn arith_op<'a>(l: &TypeList, tp: ArithType) -> Result<Type<'a>, EvalError> {
-    if l.len() != 2 {
-        return Err(EvalError::BadArrity);
+    /// `bind_dynamic` で登録した変数を取り除く。登録されていなければ false を返す
+    pub fn unbind_dynamic(&mut self, name: &str) -> bool {
+        return self.dynamic_vars.remove(name).is_some();
     }
 
-    let a = l.head().unwrap();
-    let b = l.tail().head().unwrap();
-
-    let aint;
-    let bint;
+    /// Rust の関数を、Lisp から `name` という名前で呼び出せる関数として登録する。
+    /// 関数は評価済みの引数を `&[Type]` で受け取るか、`|a: i32, b: i32| a + b` のように
+    /// `FromLisp` を実装した型で受け取る（詳細は `IntoNativeFn`）。同じ名前の関数が登録済みなら置き換える。
+    /// 組み込み関数と同じ名前の場合は、こちらが優先される。`defun` や `defmacro` による同じ名前の定義は、さらに優先される
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// // 組み込み関数 add を置き換える
+    /// context.register_fn("add", |a: i32, b: i32| a * 10 + b);
+    /// let exp = Expression::try_from("(add 1 2)".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(12)));
+    /// ```
+    pub fn register_fn<Args>(&mut self, name: &str, f: impl IntoNativeFn<Args>) {
+        self.native_fns
+            .insert(Rc::from(name), f.into_native_fn(name));
+    }
 
-    if let Type::Int(num) = a {
-        aint = num;
-    } else {
-        return Err(EvalError::TypeMismatch);
+    /// `Opaque` の中身が `T` の場合に、Lisp から `(send obj :name args ...)` で呼び出せるメソッドを登録する。
+    /// `f` は中身の参照と、評価済みの残りの引数を受け取る。同じ型・同じ名前のメソッドが登録済みなら置き換える。
+    /// 中身を変更するメソッドは、`RefCell` などで内部可変性を持たせた型に対して登録する。
+    /// 登録されていないメソッドの呼び出しは `EvalError::NotFoundFunctionName` になる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::opaque::Opaque;
+    /// use liblisp::types::{Int, Type};
+    /// use std::cell::Cell;
+    /// use std::convert::TryFrom;
+    ///
+    /// struct Counter(Cell<Int>);
+    ///
+    /// let mut context = Context::new();
+    /// context.register_method("incr", |c: &Counter, args: &[Type]| {
+    ///     c.0.set(c.0.get() + args[0].expect_int()?);
+    ///     return Ok(Type::Int(c.0.get()));
+    /// });
+    /// context
+    ///     .define_var("*counter*", Type::Opaque(Opaque::new(Counter(Cell::new(0)))))
+    ///     .unwrap();
+    /// let exp = Expression::try_from("(progn (send *counter* :incr 2) (send *counter* :incr 3))".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(5)));
+    /// ```
+    pub fn register_method<T: Any>(
+        &mut self,
+        name: &str,
+        f: impl Fn(&T, &[Type]) -> Result<Type, EvalError> + 'static,
+    ) {
+        let method: NativeMethod = Rc::new(move |receiver: &Opaque, args: &[Type]| {
+            // 型ごとのテーブルから取り出すので、中身は常に T
+            return f(receiver.downcast_ref::<T>().unwrap(), args);
+        });
+        self.methods
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .insert(Rc::from(name), method);
     }
 
-    if let Type::Int(num) = b {
-        bint = num;
-    } else {
-        return Err(EvalError::TypeMismatch);
+    /// 組み込み関数 `name` を、この `Context` から取り除く。取り除いた名前の呼び出しは、
+    /// 同じ名前のユーザ定義関数やホスト側の関数が無ければ `EvalError::NotFoundFunctionName` になる。
+    /// 組み込み関数が存在しなければ false を返す
+    pub fn remove_builtin(&mut self, name: &str) -> bool {
+        self.disabled_builtins.remove(name);
+        return self.builtins.remove(name).is_some();
     }
 
-    let calc_result = match tp {
-        ArithType::Add => aint + bint,
-        ArithType::Sub => aint - bint,
-        ArithType::Mul => aint * bint,
-        ArithType::Div => aint / bint,
-    };
-    return Ok(Type::Int(calc_result));
-}
+    /// 組み込み関数のうち、`names` に含まれるものだけを使えるようにする。
+    /// それ以外の組み込み関数（`defun` や `define` のような特殊形式も含む）は無効になり、
+    /// 呼び出すと `EvalError::FunctionDisabled` になる。組み込み関数でない名前は無視する。
+    /// 埋め込み先で、使える機能を算術などに限ったサンドボックスを作るのに使う
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, EvalError};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.builtin_whitelist(&["add", "sub", "cond", "progn"]);
+    /// let run = |context: &mut Context, src: &str| {
+    ///     let exp = Expression::try_from(src.as_bytes()).unwrap();
+    ///     return eval_with_context(&exp, context);
+    /// };
+    /// assert_eq!(run(&mut context, "(cond 1 (add 1 2) 0)"), Ok(Type::Int(3)));
+    /// assert_eq!(
+    ///     run(&mut context, "(define *x* 1)"),
+    ///     Err(EvalError::FunctionDisabled("define".into()))
+    /// );
+    /// ```
+    pub fn builtin_whitelist(&mut self, names: &[&str]) {
+        let disabled: Vec<&'static str> = self
+            .builtins
+            .keys()
+            .copied()
+            .filter(|name| !names.contains(name))
+            .collect();
+        self.disable_builtins(&disabled);
+    }
 
-enum CompareType {
-    Gt,
-    Lt,
-    Eq,
-}
+    /// 組み込み関数のうち、`names` に含まれるものを無効にする。
+    /// 無効にした組み込み関数を呼び出すと `EvalError::FunctionDisabled` になる。組み込み関数でない名前は無視する
+    pub fn disable_builtins(&mut self, names: &[&str]) {
+        for name in names {
+            if let Some((name, _)) = self.builtins.remove_entry(*name) {
+                self.disabled_builtins.insert(name);
+            }
+        }
+    }
 
-fn compare<'a>(l: &TypeList, ctype: CompareType) -> Result<Type<'a>, EvalError> {
-    if l.len() != 2 {
-        return Err(EvalError::BadArrity);
+    /// `(name ...)` の呼び出しが、組み込み関数を呼び出すなら true。
+    /// 組み込み関数が取り除かれているか無効になっている場合や、ユーザ定義のマクロ・関数やホスト側の関数で上書きされている場合は false
+    pub fn calls_builtin(&self, name: &str) -> bool {
+        return self.builtins.contains_key(name)
+            && self.resolve(&self.macrotable, name).is_none()
+            && self.resolve(&self.functable, name).is_none()
+            && !self.native_fns.contains_key(name);
     }
 
-    let a = l.head().unwrap();
-    let b = l.tail().head().unwrap();
+    /// `(name ...)` の呼び出しで使われる関数やマクロの、種類・引数の数・ドキュメント文字列を返す。
+    /// 呼び出しと同じく、ユーザ定義のマクロ・関数、ホスト側の関数、組み込み関数の順に探し、見つからなければ `None`
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, SymbolKind};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// let exp = Expression::try_from("(defun sq (*x*) \"x の 2 乗\" (mul *x* *x*))".as_bytes()).unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// let d = context.describe("sq").unwrap();
+    /// assert_eq!(d.kind, SymbolKind::Function);
+    /// assert_eq!(d.arity, Some((1, Some(1))));
+    /// assert_eq!(d.doc.as_deref(), Some("x の 2 乗"));
+    /// assert_eq!(context.describe("cond").unwrap().kind, SymbolKind::SpecialForm);
+    /// ```
+    pub fn describe(&self, name: &str) -> Option<Description> {
+        let user = |table: &Map<Rc<str>, Rc<Procedure>>, kind: SymbolKind| {
+            let q = self.qualify(name).filter(|q| table.contains_key(q));
+            let name: Rc<str> = q.unwrap_or_else(|| Rc::from(name));
+            return table.get(&name).map(|p| {
+                return Description {
+                    name,
+                    kind,
+                    arity: Some(p.arity()),
+                    doc: p.doc.clone(),
+                };
+            });
+        };
+        if let Some(d) = user(&self.macrotable, SymbolKind::Macro) {
+            return Some(d);
+        }
+        if let Some(d) = user(&self.functable, SymbolKind::Function) {
+            return Some(d);
+        }
+        if self.native_fns.contains_key(name) {
+            return Some(Description {
+                name: Rc::from(name),
+                kind: SymbolKind::HostFunction,
+                arity: None,
+                doc: None,
+            });
+        }
+        let (builtin, _) = self.builtins.get(name)?;
+        let kind = match builtin {
+            Builtin::Fn(_) | Builtin::Context(_) => SymbolKind::Builtin,
+            Builtin::Special(_) => SymbolKind::SpecialForm,
+        };
+        return Some(Description {
+            name: Rc::from(name),
+            kind,
+            arity: builtin_arity(name),
+            doc: None,
+        });
+    }
 
-    if let Type::Int(aint) = a {
-        if let Type::Int(bint) = b {
-            let res;
-            match ctype {
-                CompareType::Gt => {
-                    res = if aint > bint { 1 } else { 0 };
-                }
-                CompareType::Lt => {
-                    res = if aint < bint { 1 } else { 0 };
-                }
-                CompareType::Eq => {
-                    res = if aint == bint { 1 } else { 0 };
-                }
+    /// `prefix` で始まる、組み込み関数・ユーザ定義の関数とマクロ・ホスト側の関数・変数の名前を、名前順に返す。
+    /// 同じ名前の関数やマクロは、呼び出しで使われるもの（`describe` と同じ順で最初に見つかるもの）だけを返す。
+    /// 無効にした組み込み関数は含めない。REPL のタブ補完などに使う
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, SymbolKind};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// let exp = Expression::try_from("(progn (define *count* 0) (defun counter () *count*))".as_bytes()).unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// let labels: Vec<String> = context.complete("co").iter().map(|c| c.label.to_string()).collect();
+    /// assert_eq!(labels, vec!["cond", "continue", "count", "counter"]);
+    /// assert_eq!(context.complete("*co")[0].kind, SymbolKind::Variable);
+    /// ```
+    pub fn complete(&self, prefix: &str) -> Vec<CompletionItem> {
+        let mut items: BTreeMap<Rc<str>, SymbolKind> = BTreeMap::new();
+        // 優先度の低いものから登録し、同じ名前は優先度の高いもので上書きする
+        for (name, (builtin, _)) in self.builtins.iter() {
+            let kind = match builtin {
+                Builtin::Fn(_) | Builtin::Context(_) => SymbolKind::Builtin,
+                Builtin::Special(_) => SymbolKind::SpecialForm,
+            };
+            items.insert(Rc::from(*name), kind);
+        }
+        let tables = [
+            (
+                self.native_fns.keys().collect::<Vec<_>>(),
+                SymbolKind::HostFunction,
+            ),
+            (self.functable.keys().collect(), SymbolKind::Function),
+            (self.macrotable.keys().collect(), SymbolKind::Macro),
+            (self.dynamic_vars.keys().collect(), SymbolKind::Variable),
+        ];
+        for (names, kind) in tables {
+            for name in names {
+                items.insert(name.clone(), kind);
             }
-            return Ok(Type::Int(res));
-        } else {
-            return Err(EvalError::TypeMismatch);
         }
-    } else if let Type::Atom(aatom) = a {
-        if let Type::Atom(batom) = b {
-            let res;
-            match ctype {
-                CompareType::Gt => {
-                    res = if aatom > batom { 1 } else { 0 };
-                }
-                CompareType::Lt => {
-                    res = if aatom < batom { 1 } else { 0 };
-                }
-                CompareType::Eq => {
-                    res = if aatom == batom { 1 } else { 0 };
-                }
+        for frame in self.env.frames() {
+            for name in frame.keys() {
+                items.insert(name.clone(), SymbolKind::Variable);
             }
-            return Ok(Type::Int(res));
-        } else {
-            return Err(EvalError::TypeMismatch);
         }
-    } else {
-        return Err(EvalError::TypeMismatch);
+        return items
+            .into_iter()
+            .filter(|(label, _)| label.starts_with(prefix))
+            .map(|(label, kind)| CompletionItem { label, kind })
+            .collect();
     }
-}
 
-// > 演算を行う
-// a > b なら 1 、そうでないなら 0 を返す
-// Atom同士、Int同士の場合のみ演算を許容する
-fn gt<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return compare(l, CompareType::Gt);
-}
-
-// < 演算を行う
-// a < b なら 1 、そうでないなら 0 を返す
-// Atom同士、Int同士の場合のみ演算を許容する
-fn lt<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return compare(l, CompareType::Lt);
-}
+    // 関数 name が見つからなかった時のエラー。無効にした組み込み関数なら FunctionDisabled
+    fn function_not_found(&self, name: &str) -> EvalError {
+        if self.disabled_builtins.contains(name) {
+            return EvalError::FunctionDisabled(String::from(name));
+        }
+        return EvalError::NotFoundFunctionName;
+    }
 
-// == 演算を行う
-// a == b なら 1 、そうでないなら 0 を返す
-// Atom同士、Int同士の場合のみ演算を許容する
-fn eq<'a>(l: &TypeList) -> Result<Type<'a>, EvalError> {
-    return compare(l, CompareType::Eq);
-}
+    /// `defun` で定義した関数、もしくは `register_fn` で登録した関数を、評価済みの引数 `args` で呼び出す。
+    /// 組み込み関数とマクロは呼び出せず、`EvalError::NotFoundFunctionName` になる。
+    /// ホスト側から、スクリプトで定義されたフック関数などを呼び出すのに使う
+    pub fn call(&mut self, fn_name: &str, args: &[Type]) -> Result<Type, EvalError> {
+        if let Some(f) = self.resolve(&self.functable, fn_name) {
+            let args = args
+                .iter()
+                .rev()
+                .fold(TypeList::new(), |acc, t| acc.cons(t));
+            return apply_function(&f, &args, self).map_err(EvalOutcome::into_error);
+        } else if let Some(f) = self.native_fns.get(fn_name).cloned() {
+            return call_native(&f, args);
+        } else {
+            return Err(EvalError::NotFoundFunctionName);
+        }
+    }
+
+    /// フック `hook` に関数 `fn_name` を追加する。`run_hook` は追加した順に関数を呼び出す。
+    /// 既に追加されている関数は、重複して追加しない
+    pub fn add_hook(&mut self, hook: &str, fn_name: &str) {
+        let fns = self.hooks.entry(Rc::from(hook)).or_default();
+        if !fns.iter().any(|f| &**f == fn_name) {
+            fns.push(Rc::from(fn_name));
+        }
+    }
+
+    /// フック `hook` から関数 `fn_name` を取り除く。追加されていなければ何もしない
+    pub fn remove_hook(&mut self, hook: &str, fn_name: &str) {
+        if let Some(fns) = self.hooks.get_mut(hook) {
+            fns.retain(|f| &**f != fn_name);
+        }
+    }
+
+    /// フック `hook` に追加された関数を、引数 `args` で順番に呼び出し、それぞれの戻り値を返す。
+    /// `call` と異なり、特殊形式でない組み込み関数も呼び出せる。
+    /// 関数がエラーになった場合、残りの関数は呼び出さずにそのエラーを返す
+    pub fn run_hook(&mut self, hook: &str, args: &[Type]) -> Result<Vec<Type>, EvalError> {
+        // 呼び出した関数がフックを書き換えても影響を受けないよう、呼び出す前に複製する
+        let fns = self.hooks.get(hook).cloned().unwrap_or_default();
+        let mut res = Vec::new();
+        for f in fns {
+            res.push(apply_named(&f, args, self).map_err(EvalOutcome::into_error)?);
+        }
+        return Ok(res);
+    }
+
+    /// 副作用のある組み込み関数の利用許可を設定する。デフォルトでは全て禁止されている
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// 現在の組み込み関数の利用許可
+    pub fn capabilities(&self) -> &Capabilities {
+        return &self.capabilities;
+    }
+
+    /// `random` 及び `random-seed` で使う乱数生成器を差し替える。
+    /// デフォルトは種 0 の `SplitMix64` なので、差し替えない場合も実行ごとに同じ乱数列になる
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = Rc::new(RefCell::new(rng));
+    }
+
+    // prefix を付けて gensym する
+    fn gensym_with_prefix(&mut self, prefix: &str) -> Rc<str> {
+        self.gensym_counter += 1;
+        return Rc::from(format!("*#{}{}*", prefix, self.gensym_counter));
+    }
+
+    /// 式の評価の開始時と終了時に呼び出される `EvalObserver` を登録する。既に登録されていた場合は置き換える
+    pub fn set_tracer(&mut self, tracer: Box<dyn EvalObserver>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// 変数の値が変わった時に呼び出されるコールバックを追加する。複数追加した場合は、追加した順に呼び出す。
+    ///
+    /// 次の場合に、スクリプトに書かれた変数名と、変更前と変更後に見えている値を渡して呼び出す。
+    /// - `set` （`incf` / `decf` を含む）、`define`、`defconst` で値を設定した時
+    /// - `let` や関数の引数などでローカル変数を束縛した時と、そのスコープを抜けて外側の値に戻った時。
+    ///   外側に同名の変数が無ければ、束縛時の変更前の値と、スコープを抜けた時の変更後の値は None
+    ///
+    /// ホスト側の `define_var` や、エラーになった代入では呼び出さない。
+    /// コールバックを登録していない場合、変数の設定に余分な処理は行わない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::types::Type;
+    /// use std::cell::RefCell;
+    /// use std::convert::TryFrom;
+    /// use std::rc::Rc;
+    ///
+    /// let volume = Rc::new(RefCell::new(Vec::new()));
+    /// let log = volume.clone();
+    /// let mut context = Context::new();
+    /// context.on_var_change(move |name, _old, new| {
+    ///     if name == "*volume*" {
+    ///         log.borrow_mut().push(new.cloned());
+    ///     }
+    /// });
+    /// let exp = Expression::try_from(
+    ///     "(progn (define *volume* 5) (let ((*volume* 0)) (set *volume* 1)) (incf *volume*))".as_bytes(),
+    /// )
+    /// .unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// let int = |i| Some(Type::Int(i));
+    /// assert_eq!(*volume.borrow(), vec![int(5), int(0), int(1), int(5), int(6)]);
+    /// ```
+    pub fn on_var_change(&mut self, f: impl FnMut(&str, Option<&Type>, Option<&Type>) + 'static) {
+        self.var_observers.push(Box::new(f));
+    }
+
+    /// 登録されていた `EvalObserver` を取り外して返す
+    pub fn remove_tracer(&mut self) -> Option<Box<dyn EvalObserver>> {
+        return self.tracer.take();
+    }
+
+    /// プロファイラを有効にする。以降の評価で、組み込み関数及びユーザ定義関数ごとの呼び出し回数と累積の実行時間を記録する。
+    /// 既に有効な場合は、それまでの計測結果を破棄する。
+    /// 時刻の取得に `std::time::Instant` を使うため、wasm32-unknown-unknown では利用できない
+    #[cfg(feature = "std")]
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// プロファイラを無効にする
+    #[cfg(feature = "std")]
+    pub fn disable_profiler(&mut self) {
+        self.profiler = None;
+    }
+
+    /// プロファイラの計測結果。プロファイラが有効でない場合は None
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.enable_profiler();
+    /// let exp = Expression::try_from("(add 1 (add 2 3))".as_bytes()).unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// let report = context.profile_report().unwrap();
+    /// assert_eq!(report.get("add").unwrap().calls, 2);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        return self.profiler.as_ref().map(Profiler::report);
+    }
+
+    /// トレースの記録を有効にする。以降の評価で、評価した式と結果を、評価を始めた順に記録する。
+    /// 既に有効な場合は、それまでの記録を破棄する
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(TraceRecorder::new());
+    }
+
+    /// トレースの記録を無効にする
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// これまでに記録したトレース。記録が有効でない場合は None
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.enable_trace();
+    /// let exp = Expression::try_from("(add 1 (mul 2 3))".as_bytes()).unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// assert_eq!(
+    ///     context.trace().unwrap().to_string(),
+    ///     "(add 1 (mul 2 3)) => 7\n  1 => 1\n  (mul 2 3) => 6\n    2 => 2\n    3 => 3\n"
+    /// );
+    /// ```
+    pub fn trace(&self) -> Option<&Trace> {
+        return self.trace.as_ref().map(TraceRecorder::trace);
+    }
+
+    /// この `Context` でのこれまでの評価の統計。`reset_stats` で 0 に戻すまで積算する。
+    ///
+    /// リストのセルは、組み込み関数とホスト側の関数が返したリストのうち、引数のリストと共有していないセルを数える。
+    /// `quote` や特殊形式が作ったリストと、関数呼び出しのために一時的に作る引数のリストは数えない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// let exp = Expression::try_from("(list 1 (add 2 3))".as_bytes()).unwrap();
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// let stats = context.stats();
+    /// assert_eq!(stats.steps, 5);
+    /// assert_eq!(stats.conses, 2);
+    /// assert_eq!(stats.max_depth, 3);
+    /// assert_eq!(stats.builtin_calls.get("add"), Some(&1));
+    /// ```
+    pub fn stats(&self) -> EvalStats {
+        let mut stats = self.stats.clone();
+        stats.builtin_calls = BUILTINS
+            .iter()
+            .zip(&self.builtin_calls)
+            .filter(|(_, n)| **n > 0)
+            .map(|((name, _, _), n)| (*name, *n))
+            .collect();
+        return stats;
+    }
+
+    /// 評価の統計を 0 に戻す
+    pub fn reset_stats(&mut self) {
+        self.stats = EvalStats::default();
+        self.builtin_calls.clear();
+    }
+
+    // BUILTINS で index の位置にある組み込み関数の呼び出し回数を数える
+    fn count_builtin_call(&mut self, index: usize) {
+        if self.builtin_calls.is_empty() {
+            self.builtin_calls = vec![0; BUILTINS.len()];
+        }
+        self.builtin_calls[index] += 1;
+    }
+
+    /// これまでに記録したトレースを取り出し、記録を空にする。記録は有効なまま続ける。
+    /// 記録が有効でない場合は None
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        return self.trace.as_mut().map(TraceRecorder::take);
+    }
+
+    /// `while` 1 回あたりの繰り返し回数の上限を設定する。`None` の場合（デフォルト）は上限なし。
+    /// 上限を超えて本体を評価しようとすると `EvalError::LoopLimitExceeded` になる。
+    /// `(while cond body max)` や `(while cond body :collect expr max)` のように個別に上限を指定した場合は、
+    /// 小さい方の上限を使う
+    pub fn set_max_loop_iterations(&mut self, max: Option<u32>) {
+        self.max_loop_iterations = max;
+    }
+
+    /// 評価中の式の入れ子の深さ（`EvalStats::max_depth` と同じく、トップレベルの式を 1 とする）の上限を設定する。
+    /// `None` の場合（デフォルト）は上限なし。上限を超えて式を評価しようとすると `EvalError::DepthLimitExceeded` になる。
+    /// 評価器は再帰で式を評価するので、上限が無いと、止まらない再帰でホストのスタックが溢れる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, EvalError};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.set_max_depth(Some(100));
+    /// let exp = Expression::try_from("(progn (defun f () (f)) (f))".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Err(EvalError::DepthLimitExceeded));
+    /// ```
+    pub fn set_max_depth(&mut self, max: Option<usize>) {
+        self.max_depth = max;
+    }
+
+    /// スクリプトが作れるグローバルな変数の数の上限を設定する。`None` の場合（デフォルト）は上限なし。
+    /// `set`、グローバルなスコープでの `define` や `defconst` で新しい変数を作ると上限を超える場合は、
+    /// `EvalError::LimitExceeded` になる。既にある変数の書き換えや、関数の仮引数や `let` のようなローカルな変数は数えない。
+    /// `child` で作った `Context` では、元の `Context` の変数も数える。
+    /// ホスト側の `define_var` では上限を超えても変数を作れる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, EvalError};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.set_max_variables(Some(2));
+    /// let exp = Expression::try_from("(progn (set *a* 1) (set *b* 2) (set *a* 3))".as_bytes()).unwrap();
+    /// assert!(eval_with_context(&exp, &mut context).is_ok());
+    /// let exp = Expression::try_from("(set *c* 3)".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Err(EvalError::LimitExceeded));
+    /// ```
+    pub fn set_max_variables(&mut self, max: Option<usize>) {
+        self.max_variables = max;
+    }
+
+    /// `set`、`define` や `defconst` で変数に代入する値の大きさの上限を、値を文字列で表した時のバイト数で設定する。
+    /// `None` の場合（デフォルト）は上限なし。上限を超える値を代入すると `EvalError::LimitExceeded` になり、変数は変わらない。
+    /// 文字列で表した時の大きさは、値の `Display` の出力（文字列なら `"` を含む）のバイト数とする。
+    /// 関数の引数や `let` で束縛する値は制限しない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context, EvalError};
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let mut context = Context::new();
+    /// context.set_max_value_size(Some(8));
+    /// let exp = Expression::try_from("(set *a* (list 1 2 3))".as_bytes()).unwrap();
+    /// assert!(eval_with_context(&exp, &mut context).is_ok());
+    /// let exp = Expression::try_from("(set *a* (list 1 2 3 4))".as_bytes()).unwrap();
+    /// assert_eq!(eval_with_context(&exp, &mut context), Err(EvalError::LimitExceeded));
+    /// ```
+    pub fn set_max_value_size(&mut self, max: Option<usize>) {
+        self.max_value_size = max;
+    }
+
+    // set_max_value_size の上限を超える値なら LimitExceeded
+    fn check_value_size(&self, val: &Type) -> Result<(), EvalError> {
+        if let Some(max) = self.max_value_size {
+            if exceeds_display_size(val, max) {
+                return Err(EvalError::LimitExceeded);
+            }
+        }
+        return Ok(());
+    }
+
+    // グローバルな変数 name を新しく作ると、set_max_variables の上限を超えるなら LimitExceeded
+    fn check_new_global(&self, name: &str) -> Result<(), EvalError> {
+        let max = match self.max_variables {
+            Some(max) => max,
+            None => {
+                return Ok(());
+            }
+        };
+        if self.env.find_global(name).is_none() && self.env.global_count() >= max {
+            return Err(EvalError::LimitExceeded);
+        }
+        return Ok(());
+    }
+
+    /// 未定義の変数に対する `set` の挙動を切り替える。
+    /// `true` の場合は `EvalError::AssignToUndefinedVariable` になり、
+    /// `false` の場合（デフォルト）は、従来通りグローバルなスコープに変数を作成する。
+    pub fn set_strict_set(&mut self, strict: bool) {
+        self.strict_set = strict;
+    }
+
+    // 評価中のモジュールで修飾した名前を返す。関数名 f は m:f に、変数名 *x* は *m:x* になる。
+    // モジュール外の場合や、既に修飾されている名前の場合は None
+    fn qualify(&self, name: &str) -> Option<Rc<str>> {
+        let module = self.module.as_ref()?;
+        if name.contains(':') {
+            return None;
+        }
+        if let Some(inner) = name.strip_prefix('*').and_then(|n| n.strip_suffix('*')) {
+            return Some(Rc::from(format!("*{}:{}*", module, inner)));
+        }
+        return Some(Rc::from(format!("{}:{}", module, name)));
+    }
+
+    // 関数やマクロのテーブルから名前を探す。モジュール内では、モジュールで修飾した名前を優先する
+    fn resolve(&self, table: &Map<Rc<str>, Rc<Procedure>>, name: &str) -> Option<Rc<Procedure>> {
+        if let Some(q) = self.qualify(name) {
+            if let Some(p) = table.get(&q) {
+                return Some(p.clone());
+            }
+        }
+        return table.get(name).cloned();
+    }
+
+    // 内側のスコープから順に変数を探す。
+    // グローバルなスコープでは、モジュール内ならモジュールで修飾した名前を優先する
+    fn lookup(&self, name: &str) -> Option<&Type> {
+        if let Some(b) = self.env.find_local(name) {
+            return Some(&b.value);
+        }
+        if let Some(q) = self.qualify(name) {
+            if let Some(b) = self.env.find_global(&q) {
+                return Some(&b.value);
+            }
+        }
+        return self.env.find_global(name).map(|b| &b.value);
+    }
+
+    // 変数 name の値。ローカル変数、bind_dynamic で登録した変数、グローバルな変数の順に探す
+    fn value_of(&self, name: &str) -> Option<Type> {
+        if self.is_dynamic(name) {
+            return Some(self.dynamic_vars[name]());
+        }
+        return self.lookup(name).cloned();
+    }
+
+    /// 変数 `name` （`*x*` の形式）が、現在のスコープで参照できるなら true。`boundp` と同じ規則で調べる
+    pub fn is_bound(&self, name: &str) -> bool {
+        return self.lookup(name).is_some() || self.is_dynamic(name);
+    }
+
+    // 変数 name が、ローカル変数に隠されていない、bind_dynamic で登録した変数なら true
+    fn is_dynamic(&self, name: &str) -> bool {
+        return !self.dynamic_vars.is_empty()
+            && self.dynamic_vars.contains_key(name)
+            && self.env.find_local(name).is_none();
+    }
+
+    // on_var_change で登録したコールバックに、変数 name の変更を通知する
+    fn notify_var_change(&mut self, name: &str, old: Option<&Type>, new: Option<&Type>) {
+        for observer in self.var_observers.iter_mut() {
+            observer(name, old, new);
+        }
+    }
+
+    // 変数 name を update で書き換え、コールバックが登録されていれば、書き換えの前後の値を通知する
+    fn observe_update(
+        &mut self,
+        name: &str,
+        update: impl FnOnce(&mut Context) -> Result<(), EvalError>,
+    ) -> Result<(), EvalError> {
+        if self.var_observers.is_empty() {
+            return update(self);
+        }
+        let old = self.value_of(name);
+        update(self)?;
+        let new = self.value_of(name);
+        self.notify_var_change(name, old.as_ref(), new.as_ref());
+        return Ok(());
+    }
+
+    // 現在のスコープに変数を作成する。既に存在する場合は上書きするが、定数は上書きできない。
+    // モジュール内でグローバルなスコープに作成する場合は、モジュールで修飾した名前にする
+    fn define(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        log_trace!("define {} = {}", name, val);
+        return self.observe_update(&name.clone(), |context| {
+            return context.define_binding(name, val);
+        });
+    }
+
+    // define の本体
+    fn define_binding(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        if self.env.is_global_scope() && self.is_dynamic(&name) {
+            return Err(EvalError::AssignToConstant);
+        }
+        let name = match self.qualify(&name) {
+            Some(q) if self.env.is_global_scope() => q,
+            _ => name,
+        };
+        // child で作った Context では、元の Context の定数も書き換えられない
+        if self.env.is_global_scope() && self.env.find_global(&name).is_some_and(|b| b.constant) {
+            return Err(EvalError::AssignToConstant);
+        }
+        if self
+            .env
+            .current_mut()
+            .get(&name)
+            .is_some_and(|b| b.constant)
+        {
+            return Err(EvalError::AssignToConstant);
+        }
+        self.check_value_size(&val)?;
+        if self.env.is_global_scope() {
+            self.check_new_global(&name)?;
+        }
+        self.env.current_mut().insert(name, Binding::new(val));
+        return Ok(());
+    }
+
+    // define と同様に、現在のスコープに定数を作成する。既に存在する場合は、定数であっても上書きする。
+    // bind_dynamic で登録した変数は上書きできない
+    fn define_constant(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        log_trace!("defconst {} = {}", name, val);
+        return self.observe_update(&name.clone(), |context| {
+            return context.define_constant_binding(name, val);
+        });
+    }
+
+    // define_constant の本体
+    fn define_constant_binding(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        if self.env.is_global_scope() && self.is_dynamic(&name) {
+            return Err(EvalError::AssignToConstant);
+        }
+        let name = match self.qualify(&name) {
+            Some(q) if self.env.is_global_scope() => q,
+            _ => name,
+        };
+        self.check_value_size(&val)?;
+        if self.env.is_global_scope() {
+            self.check_new_global(&name)?;
+        }
+        let binding = Binding {
+            value: val,
+            constant: true,
+        };
+        self.env.current_mut().insert(name, binding);
+        return Ok(());
+    }
+
+    // 変数を持つもっとも内側のスコープで、変数の値を書き換える。定数なら AssignToConstant
+    fn assign(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        log_trace!("set {} = {}", name, val);
+        return self.observe_update(&name.clone(), |context| {
+            return context.assign_binding(name, val);
+        });
+    }
+
+    // assign の本体
+    fn assign_binding(&mut self, name: Rc<str>, val: Type) -> Result<(), EvalError> {
+        if self.is_dynamic(&name) {
+            return Err(EvalError::AssignToConstant);
+        }
+        let size = self.check_value_size(&val);
+        let qualified = self.qualify(&name);
+        let binding = if self.env.find_local(&name).is_some() {
+            self.env.find_local_mut(&name)
+        } else {
+            match qualified
+                .as_ref()
+                .filter(|q| self.env.find_global(q).is_some())
+            {
+                Some(q) => self.env.find_global_mut(q),
+                None => self.env.find_global_mut(&name),
+            }
+        };
+        if let Some(b) = binding {
+            if b.constant {
+                return Err(EvalError::AssignToConstant);
+            }
+            size?;
+            b.value = val;
+            return Ok(());
+        }
+        if self.strict_set {
+            return Err(EvalError::AssignToUndefinedVariable);
+        }
+        size?;
+        let name = qualified.unwrap_or(name);
+        self.check_new_global(&name)?;
+        self.env.global_mut().insert(name, Binding::new(val));
+        return Ok(());
+    }
+
+    // 評価の開始・終了を通知する先。プロファイラ、トレースの記録、tracer の順に通知する
+    fn observers<'a>(&'a mut self) -> impl Iterator<Item = &'a mut (dyn EvalObserver + 'a)> {
+        #[cfg(feature = "std")]
+        let profiler = self.profiler.as_mut().map(|p| p as &mut dyn EvalObserver);
+        #[cfg(not(feature = "std"))]
+        let profiler = None;
+        let trace = self.trace.as_mut().map(|t| t as &mut dyn EvalObserver);
+        let tracer = self
+            .tracer
+            .as_mut()
+            .map(|t| &mut **t as &mut dyn EvalObserver);
+        return profiler.into_iter().chain(trace).chain(tracer);
+    }
+
+    // 評価の開始・終了を通知する先があるかどうか
+    fn is_observed(&self) -> bool {
+        #[cfg(feature = "std")]
+        let profiling = self.profiler.is_some();
+        #[cfg(not(feature = "std"))]
+        let profiling = false;
+        return profiling || self.trace.is_some() || self.tracer.is_some();
+    }
+
+    // 評価中のモジュールを module に切り替えて f を実行し、実行後に元に戻す
+    fn in_module<T>(&mut self, module: Option<Rc<str>>, f: impl FnOnce(&mut Context) -> T) -> T {
+        let saved = core::mem::replace(&mut self.module, module);
+        let res = f(self);
+        self.module = saved;
+        return res;
+    }
+
+    // 変数・関数・マクロ・フックの定義を保存して f を実行し、実行後に元に戻す。
+    // f の中で行った定義や変数の書き換えは、呼び出し元には残らない
+    #[cfg(not(feature = "parallel"))]
+    fn isolated<T>(&mut self, f: impl FnOnce(&mut Context) -> T) -> T {
+        let env = self.env.clone();
+        let functable = self.functable.clone();
+        let macrotable = self.macrotable.clone();
+        let hooks = self.hooks.clone();
+        let res = f(self);
+        self.env = env;
+        self.functable = functable;
+        self.macrotable = macrotable;
+        self.hooks = hooks;
+        return res;
+    }
+
+    // pmap のワーカースレッドで作った Context なら、ホスト側の設定 name は使えないので UnavailableInWorker
+    fn check_host_resource(&self, name: &str) -> Result<(), EvalError> {
+        if self.in_worker {
+            return Err(EvalError::UnavailableInWorker(String::from(name)));
+        }
+        return Ok(());
+    }
+
+    // 変数を束縛した新しいスコープで f を実行し、実行後にスコープを破棄する。
+    // on_var_change のコールバックが登録されていれば、束縛した時とスコープを抜けた時に通知する
+    fn with_bindings<T>(
+        &mut self,
+        bindings: Vec<(Rc<str>, Type)>,
+        f: impl FnOnce(&mut Context) -> T,
+    ) -> T {
+        if !self.var_observers.is_empty() {
+            return self.with_observed_bindings(bindings, f);
+        }
+        self.env.push(
+            bindings
+                .into_iter()
+                .map(|(name, val)| (name, Binding::new(val)))
+                .collect(),
+        );
+        let res = f(self);
+        self.env.pop();
+        return res;
+    }
+
+    // with_bindings で、変数の変更を通知する場合
+    fn with_observed_bindings<T>(
+        &mut self,
+        bindings: Vec<(Rc<str>, Type)>,
+        f: impl FnOnce(&mut Context) -> T,
+    ) -> T {
+        let names: Vec<Rc<str>> = bindings.iter().map(|(name, _)| name.clone()).collect();
+        let outer: Vec<Option<Type>> = names.iter().map(|n| self.value_of(n)).collect();
+        self.env.push(
+            bindings
+                .into_iter()
+                .map(|(name, val)| (name, Binding::new(val)))
+                .collect(),
+        );
+        for (name, old) in names.iter().zip(&outer) {
+            let new = self.value_of(name);
+            self.notify_var_change(name, old.as_ref(), new.as_ref());
+        }
+        let res = f(self);
+        let frame = self.env.pop().unwrap();
+        for name in &names {
+            let old = frame.get(name).map(|b| &b.value);
+            let new = self.value_of(name);
+            self.notify_var_change(name, old, new.as_ref());
+        }
+        return res;
+    }
+}
+
+// ユーザ定義の関数及びマクロ。
+// 変数は動的スコープで解決するため、関数は定義時の環境を捕捉しない（クロージャではない）。
+// 関数は名前で関数テーブルに登録されるだけで、値や環境から参照されることはないので、
+// 関数と環境の間に Rc の循環参照はできない
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Procedure {
+    params: ParamList,
+    body: ExpressionList,    // 本体。順番に評価し、最後に評価した値を結果とする
+    module: Option<Rc<str>>, // 定義されたモジュール。本体はこのモジュール内で評価する
+    #[cfg_attr(feature = "serde", serde(default))]
+    doc: Option<Rc<str>>, // 本体の先頭に書いたドキュメント文字列
+}
+
+impl Procedure {
+    // 受け取る引数の数の範囲（最小, 最大）。&rest か &key があれば上限は無い
+    fn arity(&self) -> (usize, Option<usize>) {
+        let params = &self.params;
+        let min = params.required.len();
+        if params.rest.is_some() || !params.key.is_empty() {
+            return (min, None);
+        }
+        return (min, Some(min + params.optional.len()));
+    }
+}
+
+// ユーザ定義の関数及びマクロの仮引数。
+// (*a* *b* &optional *c* (*d* default) &rest *e* &key *f* (*g* default)) のように、
+// 必須・省略可能・残り全て・キーワードの順に並べる
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ParamList {
+    required: Vec<Pattern>, // 必須の仮引数。Var の他に、分配束縛のためのリストを含む
+    optional: Vec<(Pattern, Option<Expression>)>, // 省略可能な仮引数と、省略時に評価する式
+    rest: Option<Pattern>,  // 残りの引数全てをリストとして受け取る仮引数
+    key: Vec<(Rc<str>, Option<Expression>)>, // キーワード引数で受け取る仮引数（Var）と、省略時に評価する式
+}
+
+/// `Context::snapshot` で保存した、`Context` の定義の一覧。
+/// `serde` feature を有効にすると、シリアライズしてバイト列として保存できる
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContextSnapshot {
+    frames: Vec<Vec<(Rc<str>, Binding)>>, // 変数テーブルのスタック。各スコープの変数は名前順に並べる
+    macros: Vec<(Rc<str>, Rc<Procedure>)>,
+    functions: Vec<(Rc<str>, Rc<Procedure>)>,
+    hooks: Vec<(Rc<str>, Vec<Rc<str>>)>,
+    tests: Vec<(Rc<str>, Procedure)>,
+    gensym_counter: u64,
+}
+
+#[cfg(feature = "serde")]
+impl ContextSnapshot {
+    /// バイト列（JSON）に変換する。変数の値に `Opaque` を含む場合は、シリアライズできないので失敗する
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
+        return serde_json::to_vec(self);
+    }
+
+    /// `to_bytes` で変換したバイト列から復元する
+    pub fn from_bytes(bytes: &[u8]) -> Result<ContextSnapshot, serde_json::Error> {
+        return serde_json::from_slice(bytes);
+    }
+}
+
+// テーブルの内容を、出力が決定的になるよう名前順に並べて取り出す
+fn sorted_entries<T: Clone>(table: &Map<Rc<str>, T>) -> Vec<(Rc<str>, T)> {
+    let mut entries: Vec<(Rc<str>, T)> =
+        table.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    return entries;
+}
+
+// 値を Display で出力した時のバイト数が max を超えるかどうか。大きな値でも、max を超えた時点で出力を打ち切る
+fn exceeds_display_size(val: &Type, max: usize) -> bool {
+    struct Counter {
+        size: usize,
+        max: usize,
+    }
+    impl core::fmt::Write for Counter {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            self.size += s.len();
+            if self.size > self.max {
+                return Err(core::fmt::Error);
+            }
+            return Ok(());
+        }
+    }
+    let mut counter = Counter { size: 0, max };
+    return core::fmt::Write::write_fmt(&mut counter, format_args!("{}", val)).is_err();
+}
+
+/// `Expression` に含まれるマクロ呼び出しを、`Context` に登録されたマクロを用いて全て展開する。
+/// `(quote ...)` 及び `(quasiquote ...)` の内側は展開しない。
+/// マクロの仮引数は、変数と同じく `*` で囲んだ名前で書く
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::{eval_with_context, macroexpand, Context};
+/// use liblisp::expression::Expression;
+/// use liblisp::types::Type;
+/// use std::convert::TryFrom;
+///
+/// let mut context = Context::new();
+/// let def = "(defmacro unless (*c* *body*) (list (quote cond) *c* 0 *body*))";
+/// eval_with_context(&Expression::try_from(def.as_bytes()).unwrap(), &mut context).unwrap();
+/// let exp = Expression::try_from("(unless (eq 1 2) 10)".as_bytes()).unwrap();
+/// let expanded = macroexpand(&exp, &mut context).unwrap();
+/// assert_eq!(expanded.to_string(), "(cond (eq 1 2) 0 10)");
+/// assert_eq!(eval_with_context(&expanded, &mut context), Ok(Type::Int(10)));
+/// ```
+pub fn macroexpand(exp: &Expression, context: &mut Context) -> Result<Expression, EvalError> {
+    if let Expression::ExpressionList(l) = exp {
+        if let Some(Expression::Atom(name)) = l.head() {
+            if &**name == "quote" || &**name == "quasiquote" {
+                return Ok(exp.clone());
+            }
+            if let Some(m) = context.resolve(&context.macrotable, name) {
+                let expanded = expand_macro(&m, l.tail(), context)?;
+                // 展開結果にマクロ呼び出しが含まれている可能性があるので、さらに展開する
+                return macroexpand(&expanded, context);
+            }
+        }
+        // 各要素を展開する
+        let mut res = ExpressionList::new();
+        for e in l.iter() {
+            res = res.cons(&macroexpand(e, context)?);
+        }
+        return Ok(Expression::ExpressionList(Rc::new(res.reverse())));
+    } else {
+        return Ok(exp.clone());
+    }
+}
+
+// マクロ呼び出しを 1 段階だけ展開する。
+// 仮引数には評価前の引数をデータとして束縛し、マクロ本体を評価した結果を式に戻す
+fn expand_macro(
+    m: &Procedure,
+    args: &ExpressionList,
+    context: &mut Context,
+) -> Result<Expression, EvalError> {
+    let args = args.iter().map(expression_to_type).collect();
+    let bindings = bind_params(&m.params, args, context).map_err(EvalOutcome::into_error)?;
+    let body = &m.body;
+    let res = context.in_module(m.module.clone(), |context| {
+        return context.with_bindings(bindings, |context| {
+            return body.iter().try_fold(Type::Void, |_, e| {
+                return eval_with_context(e, context);
+            });
+        });
+    })?;
+    return type_to_expression(&res);
+}
+
+/// 式をデータとして扱うために `Type` に変換する。`quote` と同じ変換で、
+/// Var は、`*` で囲まれた Atom として表現する
+pub fn expression_to_type(exp: &Expression) -> Type {
+    match exp {
+        Expression::Int(i) => {
+            return Type::Int(*i);
+        }
+        Expression::Atom(a) => {
+            return Type::Atom(a.clone());
+        }
+        Expression::Var(v) => {
+            return Type::Atom(v.clone());
+        }
+        Expression::Str(s) => {
+            return Type::Str(s.clone());
+        }
+        Expression::Keyword(k) => {
+            return Type::Keyword(k.clone());
+        }
+        Expression::ExpressionList(l) => {
+            let mut res = TypeList::new();
+            for e in l.iter() {
+                res = res.cons(&expression_to_type(e));
+            }
+            return Type::TypeList(Rc::new(res.reverse()));
+        }
+    }
+}
+
+/// データとして扱っていた `Type` を式に戻す。`expression_to_type` の逆の変換で、
+/// `*` で囲まれた Atom は Var に戻す。`Void` と `Function` は、評価すると同じ値になる `(void)` と `(function f)` という式にする。
+/// 式で表せない `Ratio` や `Vector` などを含む場合は `EvalError::TypeMismatch`
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::{expression_to_type, type_to_expression};
+/// use liblisp::expression::Expression;
+/// use std::convert::TryFrom;
+///
+/// let exp = Expression::try_from("(add *x* 1)".as_bytes()).unwrap();
+/// let data = expression_to_type(&exp);
+/// assert_eq!(data.to_string(), "(add *x* 1)");
+/// assert_eq!(type_to_expression(&data), Ok(exp));
+/// ```
+pub fn type_to_expression(tp: &Type) -> Result<Expression, EvalError> {
+    match tp {
+        Type::Int(i) => {
+            return Ok(Expression::Int(*i));
+        }
+        Type::Atom(a) => {
+            if a.len() >= 2 && a.starts_with('*') && a.ends_with('*') {
+                return Ok(Expression::Var(a.clone()));
+            } else {
+                return Ok(Expression::Atom(a.clone()));
+            }
+        }
+        Type::Str(s) => {
+            return Ok(Expression::Str(s.clone()));
+        }
+        Type::Keyword(k) => {
+            return Ok(Expression::Keyword(k.clone()));
+        }
+        Type::TypeList(l) => {
+            let mut res = ExpressionList::new();
+            for t in l.iter() {
+                res = res.cons(&type_to_expression(t)?);
+            }
+            return Ok(Expression::ExpressionList(Rc::new(res.reverse())));
+        }
+        #[cfg(feature = "bignum")]
+        Type::BigInt(_) => {
+            return Err(EvalError::TypeMismatch);
+        }
+        Type::Ratio(_, _) => {
+            return Err(EvalError::TypeMismatch);
+        }
+        #[cfg(feature = "bignum")]
+        Type::BigRatio(_) => {
+            return Err(EvalError::TypeMismatch);
+        }
+        Type::Vector(_) => {
+            return Err(EvalError::TypeMismatch);
+        }
+        // ホスト側の値は式に含められない
+        Type::Opaque(_) => {
+            return Err(EvalError::TypeMismatch);
+        }
+        Type::Void => {
+            let l = ExpressionList::new().cons(&Expression::Atom(Rc::from("void")));
+            return Ok(Expression::ExpressionList(Rc::new(l)));
+        }
+        Type::Function(name) => {
+            let l = ExpressionList::new()
+                .cons(&Expression::Atom(name.clone()))
+                .cons(&Expression::Atom(Rc::from("function")));
+            return Ok(Expression::ExpressionList(Rc::new(l)));
+        }
+    }
+}
+
+/// `Expression` を `Type` に変換する。
+/// このとき、`Context` の情報を参照し、必要があれば `Context` に情報を追加する。
+/// `Expression` で、変数のセットを行い、その値を、次の `eval_with_context` 呼び出しに使いたい場合、この関数を使うと良い。
+pub fn eval_with_context(exp: &Expression, context: &mut Context) -> Result<Type, EvalError> {
+    return eval_(exp, context).map_err(EvalOutcome::into_error);
+}
+
+/// `Expression::parse_in` で arena に読み込んだ式を評価する。
+/// 評価器は `Expression` の上で動くので、式全体を一度 `Expression` に変換してから評価する。
+/// 変換で要素ごとに `Rc` を確保するので、`eval_with_context` より速くはならない。
+/// 同じ式を何度も評価するなら、`Node::to_expression` で変換した `Expression` を使い回す方がよい
+pub fn eval_node(node: &Node, context: &mut Context) -> Result<Type, EvalError> {
+    return eval_with_context(&node.to_expression(), context);
+}
+
+// `eval_with_context` の本体。break / continue / return による脱出を、そのまま呼び出し元に返す。
+// tracer が登録されている、もしくはプロファイラが有効なら、評価の開始と終了を通知する
+fn eval_(exp: &Expression, context: &mut Context) -> Result<Type, EvalOutcome> {
+    if context.max_depth.is_some_and(|max| context.depth >= max) {
+        return Err(EvalError::DepthLimitExceeded.into());
+    }
+    context.stats.steps += 1;
+    context.stats.max_depth = core::cmp::max(context.stats.max_depth, context.depth + 1);
+    if !context.is_observed() {
+        context.depth += 1;
+        let res = eval_inner(exp, context);
+        context.depth -= 1;
+        return res;
+    }
+
+    let depth = context.depth;
+    for observer in context.observers() {
+        observer.enter(exp, depth);
+    }
+    context.depth += 1;
+    let res = eval_inner(exp, context);
+    context.depth -= 1;
+    let exit = match &res {
+        Ok(v) => EvalExit::Value(v),
+        Err(EvalOutcome::Error(e)) => EvalExit::Error(e),
+        Err(_) => EvalExit::Escape,
+    };
+    for observer in context.observers() {
+        observer.exit(exp, depth, exit);
+    }
+    return res;
+}
+
+/// name が組み込み関数（`add` のような関数と、`defun` のような特殊形式）の名前なら true。
+/// `Context` ごとの、組み込み関数の削除や上書きは考慮しない（`Context::calls_builtin` を参照）
+pub fn is_builtin(name: &str) -> bool {
+    return BUILTINS.iter().any(|(n, _, _)| *n == name);
+}
+
+/// 組み込み関数 name が受け取る引数の数の範囲（最小, 最大）。最大が `None` なら上限は無い。
+/// 組み込み関数でなければ `None`。呼び出し時には、組み込み関数を実行する前にこの範囲を調べ、
+/// 外れていれば `EvalError::ArityMismatch` にする
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::builtin_arity;
+///
+/// assert_eq!(builtin_arity("add"), Some((2, Some(2))));
+/// assert_eq!(builtin_arity("defun"), Some((3, None)));
+/// assert_eq!(builtin_arity("undefined"), None);
+/// ```
+pub fn builtin_arity(name: &str) -> Option<(usize, Option<usize>)> {
+    return BUILTINS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, _, arity)| *arity);
+}
+
+/// name が `defun` や `cond` のような、引数を評価せずに受け取る組み込み関数（特殊形式）の名前なら true。
+/// `is_builtin` と同様に、`Context` ごとの組み込み関数の削除や上書きは考慮しない
+pub fn is_special_form(name: &str) -> bool {
+    return BUILTINS
+        .iter()
+        .any(|(n, b, _)| *n == name && matches!(b, Builtin::Special(_)));
+}
+
+// 組み込み関数の実装
+#[derive(Clone, Copy)]
+enum Builtin {
+    Fn(EmbededFn),             // 評価済みの引数を受け取る関数
+    Context(EmbededContextFn), // 評価済みの引数と Context を受け取る関数
+    Special(EmbededSpecialFn), // 引数を関数内部で評価する特殊形式
+}
+
+// 組み込み関数が受け取る引数の数の範囲（最小, 最大）。最大が None なら上限なし
+type Arity = (usize, Option<usize>);
+
+// 組み込み関数の一覧と、それぞれの引数の数の範囲。Context::new で、この一覧から Context ごとの組み込み関数のテーブルを作る。
+// 引数の数は呼び出す前に eval_ や apply_named で調べるので、組み込み関数の中では調べない
+const BUILTINS: &[(&str, Builtin, Arity)] = &[
+    ("add", Builtin::Fn(add), (2, Some(2))),
+    ("sub", Builtin::Fn(sub), (2, Some(2))),
+    ("mul", Builtin::Fn(mul), (2, Some(2))),
+    ("div", Builtin::Fn(div), (2, Some(2))),
+    ("floor", Builtin::Fn(floor), (1, Some(1))),
+    ("ceil", Builtin::Fn(ceil), (1, Some(1))),
+    ("truncate", Builtin::Fn(truncate), (1, Some(1))),
+    ("list", Builtin::Fn(list), (0, None)),
+    ("void", Builtin::Fn(void), (0, Some(0))),
+    ("head", Builtin::Fn(head), (1, Some(1))),
+    ("tail", Builtin::Fn(tail), (1, Some(1))),
+    ("gt", Builtin::Fn(gt), (2, Some(2))),
+    ("lt", Builtin::Fn(lt), (2, Some(2))),
+    ("ge", Builtin::Fn(ge), (2, Some(2))),
+    ("le", Builtin::Fn(le), (2, Some(2))),
+    ("eq", Builtin::Fn(eq), (2, Some(2))),
+    ("ne", Builtin::Fn(ne), (2, Some(2))),
+    ("equal", Builtin::Fn(equal), (2, Some(2))),
+    ("intp", Builtin::Fn(intp), (1, Some(1))),
+    ("atomp", Builtin::Fn(atomp), (1, Some(1))),
+    ("listp", Builtin::Fn(listp), (1, Some(1))),
+    ("nullp", Builtin::Fn(nullp), (1, Some(1))),
+    ("vectorp", Builtin::Fn(vectorp), (1, Some(1))),
+    ("send", Builtin::Context(send), (2, None)),
+    ("vector", Builtin::Fn(vector), (0, None)),
+    ("vref", Builtin::Fn(vref), (2, Some(2))),
+    ("vset", Builtin::Fn(vset), (3, Some(3))),
+    ("vlen", Builtin::Fn(vlen), (1, Some(1))),
+    ("list->vector", Builtin::Fn(list_to_vector), (1, Some(1))),
+    ("vector->list", Builtin::Fn(vector_to_list), (1, Some(1))),
+    ("parse", Builtin::Fn(parse_fn), (1, Some(1))),
+    ("unparse", Builtin::Fn(unparse_fn), (1, Some(1))),
+    ("str-split", Builtin::Fn(str_split), (1, Some(2))),
+    ("str-join", Builtin::Fn(str_join), (1, Some(2))),
+    ("str-upper", Builtin::Fn(str_upper), (1, Some(1))),
+    ("str-lower", Builtin::Fn(str_lower), (1, Some(1))),
+    ("str-trim", Builtin::Fn(str_trim), (1, Some(1))),
+    ("str-contains", Builtin::Fn(str_contains), (2, Some(2))),
+    ("to-string", Builtin::Fn(to_string), (1, Some(1))),
+    ("parse-int", Builtin::Fn(parse_int), (1, Some(1))),
+    ("range", Builtin::Fn(range), (1, Some(3))),
+    ("zip", Builtin::Fn(zip), (1, None)),
+    ("enumerate", Builtin::Fn(enumerate), (1, Some(1))),
+    ("take", Builtin::Fn(take), (2, Some(2))),
+    ("drop", Builtin::Fn(drop_), (2, Some(2))),
+    ("raise", Builtin::Fn(raise), (1, Some(1))),
+    ("assert", Builtin::Fn(assert), (1, Some(1))),
+    ("assert-eq", Builtin::Fn(assert_eq), (2, Some(2))),
+    ("cond", Builtin::Special(cond), (3, Some(3))),
+    ("and", Builtin::Special(and), (0, None)),
+    ("or", Builtin::Special(or), (0, None)),
+    ("set", Builtin::Special(set), (2, None)),
+    ("incf", Builtin::Special(incf), (1, Some(2))),
+    ("decf", Builtin::Special(decf), (1, Some(2))),
+    ("define", Builtin::Special(define), (2, Some(2))),
+    ("defconst", Builtin::Special(defconst), (2, Some(2))),
+    ("boundp", Builtin::Special(boundp), (1, Some(1))),
+    ("try", Builtin::Special(try_), (2, None)),
+    ("deftest", Builtin::Special(deftest), (2, None)),
+    ("run-tests", Builtin::Context(run_tests), (0, Some(0))),
+    ("progn", Builtin::Special(progn), (0, None)),
+    ("while", Builtin::Special(wloop), (2, Some(5))),
+    ("quote", Builtin::Special(quote), (1, Some(1))),
+    ("function", Builtin::Special(function), (1, Some(1))),
+    ("funcall", Builtin::Context(funcall), (1, None)),
+    ("describe", Builtin::Context(describe), (1, Some(1))),
+    ("quasiquote", Builtin::Special(quasiquote), (1, Some(1))),
+    ("defmacro", Builtin::Special(defmacro), (3, None)),
+    (
+        "macroexpand",
+        Builtin::Context(macroexpand_fn),
+        (1, Some(1)),
+    ),
+    ("eval", Builtin::Context(eval_fn), (1, Some(1))),
+    ("defun", Builtin::Special(defun), (3, None)),
+    ("break", Builtin::Special(brk), (0, Some(0))),
+    ("continue", Builtin::Special(cont), (0, Some(0))),
+    ("return", Builtin::Special(ret), (0, Some(1))),
+    ("block", Builtin::Special(block), (1, None)),
+    ("return-from", Builtin::Special(return_from), (1, Some(2))),
+    ("let", Builtin::Special(let_), (2, None)),
+    ("match", Builtin::Special(match_), (1, None)),
+    ("dotimes", Builtin::Special(dotimes), (2, None)),
+    ("dolist", Builtin::Special(dolist), (2, None)),
+    ("load", Builtin::Context(load), (1, Some(1))),
+    ("module", Builtin::Special(module), (1, None)),
+    ("gensym", Builtin::Context(gensym), (0, Some(1))),
+    ("random", Builtin::Context(random), (1, Some(1))),
+    ("print", Builtin::Context(print), (0, None)),
+    ("random-seed", Builtin::Context(random_seed), (1, Some(1))),
+    #[cfg(feature = "std")]
+    ("getenv", Builtin::Context(getenv), (1, Some(1))),
+    #[cfg(feature = "std")]
+    ("argv", Builtin::Context(argv), (0, Some(0))),
+    ("exit", Builtin::Context(exit), (0, Some(1))),
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    ("sh", Builtin::Context(sh), (1, Some(1))),
+    ("slurp", Builtin::Context(slurp), (1, Some(1))),
+    ("spit", Builtin::Context(spit), (2, Some(2))),
+    ("file-exists", Builtin::Context(file_exists), (1, Some(1))),
+    ("add-hook", Builtin::Context(add_hook), (2, Some(2))),
+    ("remove-hook", Builtin::Context(remove_hook), (2, Some(2))),
+    ("run-hooks", Builtin::Context(run_hooks), (1, None)),
+    ("pmap", Builtin::Context(pmap), (2, Some(2))),
+    ("sort", Builtin::Context(sort), (1, Some(2))),
+    ("member", Builtin::Fn(member), (2, Some(2))),
+    ("find", Builtin::Context(find), (2, Some(2))),
+    ("position", Builtin::Context(position), (2, Some(2))),
+    ("count", Builtin::Context(count), (2, Some(2))),
+    #[cfg(feature = "http")]
+    ("http-get", Builtin::Context(http_get), (1, Some(1))),
+    #[cfg(feature = "http")]
+    ("http-post", Builtin::Context(http_post), (2, Some(2))),
+];
+
+// 式を 1 つ評価する
+fn eval_inner(exp: &Expression, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match exp {
+        Expression::Int(i) => {
+            return Ok(Type::Int(*i));
+        }
+        Expression::Atom(a) => {
+            return Ok(Type::Atom(a.clone()));
+        }
+        Expression::Str(s) => {
+            return Ok(Type::Str(s.clone()));
+        }
+        // keyword は評価すると自分自身になる
+        Expression::Keyword(k) => {
+            return Ok(Type::Keyword(k.clone()));
+        }
+        Expression::Var(var) => {
+            if let Some(val) = context.value_of(var) {
+                return Ok(val);
+            } else {
+                return Err(EvalError::UndefinedVariableReference.into());
+            }
+        }
+        Expression::ExpressionList(clist) => {
+            // リスト形式をevalする時、先頭のatomを関数名として扱う
+            if let Some(head) = clist.head() {
+                if let Expression::Atom(fun_name) = head {
+                    // 同じ名前の定義があれば、ユーザ定義（マクロ・関数）、ホスト側の関数、組み込み関数の順に優先する
+                    // マクロを展開してから評価する
+                    if let Some(m) = context.resolve(&context.macrotable, fun_name) {
+                        let expanded = expand_macro(&m, clist.tail(), context)?;
+                        return eval_(&expanded, context);
+                    }
+                    // ユーザ定義関数の適用
+                    else if let Some(f) = context.resolve(&context.functable, fun_name) {
+                        let evaluated: TypeList = TypeList::try_from(clist.tail(), context)?;
+                        log_trace!("apply {} ({} args)", fun_name, evaluated.len());
+                        return in_call_span(fun_name, evaluated.len() as usize, || {
+                            return apply_function(&f, &evaluated, context);
+                        });
+                    }
+                    // ホスト側の関数の適用
+                    else if let Some(f) = context.native_fns.get(&**fun_name).cloned() {
+                        let evaluated = TypeList::try_from(clist.tail(), context)?;
+                        let args: Vec<Type> = evaluated.iter().cloned().collect();
+                        log_trace!("apply host function {} ({} args)", fun_name, args.len());
+                        let res = in_call_span(fun_name, args.len(), || {
+                            return call_native(&f, &args);
+                        })?;
+                        context.stats.conses += new_cells(&res, &TypeList::Nil);
+                        return Ok(res);
+                    }
+                    match context.builtins.get(&**fun_name).copied() {
+                        // 引数を関数内部で評価する組み込み関数の適用
+                        Some((Builtin::Special(f), index)) => {
+                            context.count_builtin_call(index);
+                            check_arity(index, clist.tail().len() as usize)?;
+                            return f(clist.tail(), context);
+                        }
+                        // 組み込み関数の適用
+                        Some((Builtin::Fn(f), index)) => {
+                            context.count_builtin_call(index);
+                            check_arity(index, clist.tail().len() as usize)?;
+                            // 引数をそれぞれ評価する
+                            let evaluated: TypeList = TypeList::try_from(clist.tail(), context)?;
+                            log_trace!("apply builtin {} ({} args)", fun_name, evaluated.len());
+                            let res = in_call_span(fun_name, evaluated.len() as usize, || {
+                                return f(&evaluated);
+                            })?;
+                            context.stats.conses += new_cells(&res, &evaluated);
+                            return Ok(res);
+                        }
+                        // Context を使う組み込み関数の適用
+                        Some((Builtin::Context(f), index)) => {
+                            context.count_builtin_call(index);
+                            check_arity(index, clist.tail().len() as usize)?;
+                            let evaluated: TypeList = TypeList::try_from(clist.tail(), context)?;
+                            log_trace!("apply builtin {} ({} args)", fun_name, evaluated.len());
+                            let res = in_call_span(fun_name, evaluated.len() as usize, || {
+                                return f(&evaluated, context);
+                            })?;
+                            context.stats.conses += new_cells(&res, &evaluated);
+                            return Ok(res);
+                        }
+                        None => {
+                            return Err(context.function_not_found(fun_name).into());
+                        }
+                    }
+                }
+                // Atomが先頭要素でない場合、評価できない
+                else {
+                    return Err(EvalError::EvaluatingNonAtomHeadList.into());
+                }
+            } else {
+                // () は評価すると空のリストになる。(list) と同じく偽として扱う
+                return Ok(Type::TypeList(Rc::new(TypeList::new())));
+            }
+        }
+    }
+}
+
+// (wloop cond body) という形式の while loop。
+// cond が真（Type::is_truthy）である限りループを続ける。
+// (wloop cond body max) の形式では、max の評価結果を繰り返し回数の上限とし、
+// 上限を超えて body を評価しようとすると LoopLimitExceeded になる。
+// 戻り値は Void だが、(wloop cond body :collect expr) の形式では、body を評価し終えるたびに
+// expr を評価し、その結果を順に集めたリストを返す（break した場合もそれまでに集めたリストを返す）
+fn wloop(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let cond = l.head().unwrap();
+    let body = l.tail().head().unwrap();
+    let mut rest = l.tail().tail();
+    // (while cond body :collect expr ...) の場合は、繰り返しごとに expr を評価した結果を集める
+    let mut collect = None;
+    if let Some(Expression::Keyword(k)) = rest.head() {
+        if &**k != "collect" {
+            return Err(EvalError::TypeMismatch.into());
+        }
+        collect = Some(rest.tail().head().ok_or(EvalError::BadArrity)?);
+        rest = rest.tail().tail();
+    }
+    if rest.len() > 1 {
+        return Err(EvalError::BadArrity.into());
+    }
+    let mut limit = context.max_loop_iterations;
+    if let Some(max) = rest.head() {
+        match eval_(max, context)? {
+            Type::Int(max) if max >= 0 => {
+                limit = Some(limit.map_or(max as u32, |l| l.min(max as u32)));
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch.into());
+            }
+        }
+    }
+
+    let mut collected = Vec::new();
+    let mut iterations: u32 = 0;
+    loop {
+        if !eval_(cond, context)?.is_truthy() {
+            break;
+        }
+        if limit.is_some_and(|limit| iterations >= limit) {
+            return Err(EvalError::LoopLimitExceeded.into());
+        }
+        iterations += 1;
+        let res = eval_(body, context);
+        let completed = res.is_ok();
+        if !loop_continues(res)? {
+            break;
+        }
+        // continue した繰り返しでは集めない
+        if let (Some(collect), true) = (collect, completed) {
+            collected.push(eval_(collect, context)?);
+        }
+    }
+    if collect.is_none() {
+        return Ok(Type::Void);
+    }
+    let list = collected
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Ok(Type::TypeList(Rc::new(list)));
+}
+
+// ループ本体の評価結果から、ループを続けるかどうかを判定する。
+// break した場合は false を返し、continue した場合は次の繰り返しに移るため true を返す
+fn loop_continues(res: Result<Type, EvalOutcome>) -> Result<bool, EvalOutcome> {
+    match res {
+        Ok(_) | Err(EvalOutcome::Continue) => {
+            return Ok(true);
+        }
+        Err(EvalOutcome::Break) => {
+            return Ok(false);
+        }
+        Err(e) => {
+            return Err(e);
+        }
+    }
+}
+
+// (dotimes (*i* n) body ...) の形式で、*i* を 0 から n - 1 まで変化させながら body を評価する。
+// *i* の束縛は、ループを抜けると元に戻る。戻り値は Void
+fn dotimes(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (var, count) = parse_loop_spec(l.head().unwrap())?;
+    let n;
+    if let Type::Int(i) = eval_(count, context)? {
+        n = i;
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+
+    let body = l.tail();
+    return context.with_bindings(vec![(var.clone(), Type::Int(0))], |context| {
+        for i in 0..n {
+            context.define(var.clone(), Type::Int(i))?;
+            if !loop_continues(eval_sequence(body, context))? {
+                break;
+            }
+        }
+        return Ok(Type::Void);
+    });
+}
+
+// (dolist (*x* l) body ...) の形式で、リスト l の要素を順番に *x* に束縛しながら body を評価する。
+// *x* の束縛は、ループを抜けると元に戻る。戻り値は Void
+fn dolist(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (var, list) = parse_loop_spec(l.head().unwrap())?;
+    let elements;
+    if let Type::TypeList(tl) = eval_(list, context)? {
+        elements = tl;
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+
+    let body = l.tail();
+    return context.with_bindings(vec![(var.clone(), Type::Void)], |context| {
+        for e in elements.iter() {
+            context.define(var.clone(), e.clone())?;
+            if !loop_continues(eval_sequence(body, context))? {
+                break;
+            }
+        }
+        return Ok(Type::Void);
+    });
+}
+
+// dotimes / dolist の (*x* exp) の部分を、変数名と式に分解する
+fn parse_loop_spec(spec: &Expression) -> Result<(Rc<str>, &Expression), EvalError> {
+    if let Expression::ExpressionList(l) = spec {
+        if l.len() != 2 {
+            return Err(EvalError::BadArrity);
+        }
+        if let Expression::Var(v) = l.head().unwrap() {
+            return Ok((v.clone(), l.tail().head().unwrap()));
+        }
+    }
+    return Err(EvalError::TypeMismatch);
+}
+
+// let の束縛指定 (pattern exp) を、パターンと式に分解する
+fn parse_let_spec(spec: &Expression) -> Result<(Pattern, &Expression), EvalError> {
+    if let Expression::ExpressionList(l) = spec {
+        if l.len() != 2 {
+            return Err(EvalError::BadArrity);
+        }
+        let pattern = Pattern::compile_binding(l.head().unwrap())?;
+        return Ok((pattern, l.tail().head().unwrap()));
+    }
+    return Err(EvalError::TypeMismatch);
+}
+
+// (let ((*a* x) (*b* y) ...) body ...) の形式で、変数を束縛した状態で body を順番に評価する。
+// x, y, ... は全て束縛前に評価する。束縛は let を抜けると元に戻る。
+// ((*a* *b*) x) のように書くと、x を評価したリストを分配束縛する。形が合わなければ MatchFailed
+fn let_(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let mut bindings = Vec::new();
+    if let Expression::ExpressionList(specs) = l.head().unwrap() {
+        for spec in specs.iter() {
+            let (pattern, exp) = parse_let_spec(spec)?;
+            let val = eval_(exp, context)?;
+            bindings.extend(pattern.bind(&val).ok_or(EvalError::MatchFailed)?);
+        }
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+
+    let body = l.tail();
+    return context.with_bindings(bindings, |context| {
+        return eval_sequence(body, context);
+    });
+}
+
+// (match x (pattern body ...) ...) の形式で、x を評価した値が最初にマッチした節の body を評価する。
+// body はパターン中の変数を束縛したスコープで評価する。どの節にもマッチしなければ MatchFailed
+fn match_(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let val = eval_(l.head().unwrap(), context)?;
+    for clause in l.tail().iter() {
+        let clause = match clause {
+            Expression::ExpressionList(c) if !c.is_empty() => c,
+            _ => {
+                return Err(EvalError::TypeMismatch.into());
+            }
+        };
+        let pattern = Pattern::compile(clause.head().unwrap())?;
+        if let Some(bindings) = pattern.bind(&val) {
+            return context.with_bindings(bindings, |context| {
+                return eval_sequence(clause.tail(), context);
+            });
+        }
+    }
+    return Err(EvalError::MatchFailed.into());
+}
+
+// 式のリストを順番に評価し、最後に評価した値を返す。空の場合は Void を返す
+fn eval_sequence(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return l.iter().try_fold(Type::Void, |_, e| {
+        return eval_(e, context);
+    });
+}
+
+// リストの要素を順番に評価する。
+// 最後に評価した値を戻り値とする。(progn) のように要素が無い場合は Void を返す
+fn progn(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    // 各要素を順番に評価していく
+    return eval_sequence(l, context);
+}
+
+// 変数に指定された値をセットする。
+// 変数が定義されているもっとも内側のスコープの値を書き換える。
+// (set *a* 1 *b* 2) のように複数の組を並べると、先頭から順に 1 組ずつセットし、最後にセットした値を返す
+fn set(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    if l.is_empty() || !l.len().is_multiple_of(2) {
+        return Err(EvalError::BadArrity.into());
+    }
+
+    let mut res = Type::Void;
+    let mut rest = l;
+    while let Some(var) = rest.head() {
+        let val = eval_(rest.tail().head().unwrap(), context)?; // valはset関数に渡されてから評価する
+
+        // varは Var である必要がある
+        if let Expression::Var(varstr) = var {
+            rest = rest.tail().tail();
+            // 返り値になる最後の値以外は、複製せずにそのまま渡す
+            if rest.is_empty() {
+                res = val.clone();
+            }
+            context.assign(varstr.clone(), val)?;
+        } else {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+    return Ok(res);
+}
+
+// (incf *i*) もしくは (incf *i* n) の形式で、変数の値に n （省略時は 1）を加えてセットする。
+// (set *i* (add *i* n)) と同じ意味で、セットした値を返す
+fn incf(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return update_var(l, context, "add");
+}
+
+// (decf *i*) もしくは (decf *i* n) の形式で、変数の値から n （省略時は 1）を引いてセットする。
+// (set *i* (sub *i* n)) と同じ意味で、セットした値を返す
+fn decf(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return update_var(l, context, "sub");
+}
+
+// incf / decf を (set *i* (op *i* n)) に書き換えて評価する
+fn update_var(l: &ExpressionList, context: &mut Context, op: &str) -> Result<Type, EvalOutcome> {
+    let var = l.head().unwrap();
+    if !matches!(var, Expression::Var(_)) {
+        return Err(EvalError::TypeMismatch.into());
+    }
+    let delta = l.tail().head().cloned().unwrap_or(Expression::Int(1));
+    let update = ExpressionList::new()
+        .cons(&delta)
+        .cons(var)
+        .cons(&Expression::Atom(Rc::from(op)));
+    let set = ExpressionList::new()
+        .cons(&Expression::ExpressionList(Rc::new(update)))
+        .cons(var);
+    return self::set(&set, context);
+}
+
+// (define *x* v) の形式で、現在のスコープに変数を作成する。
+// 外側のスコープに同名の変数があっても、そちらは書き換えない
+fn define(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let var = l.head().unwrap();
+    let val = eval_(l.tail().head().unwrap(), context)?;
+
+    if let Expression::Var(varstr) = var {
+        context.define(varstr.clone(), val.clone())?;
+        return Ok(val);
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+}
+
+// (defconst *x* v) の形式で、現在のスコープに書き換えられない変数を作成する。
+// 作成した変数への set 、同じスコープでの define は AssignToConstant になる。
+// 内側のスコープで同名の変数を作って隠すことはできる
+fn defconst(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let var = l.head().unwrap();
+    let val = eval_(l.tail().head().unwrap(), context)?;
+
+    if let Expression::Var(varstr) = var {
+        context.define_constant(varstr.clone(), val.clone())?;
+        return Ok(val);
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+}
+
+// (try body ... (catch *e* handler ...)) の形式で、body を順番に評価する。
+// body の評価中にエラーが発生した場合は、エラーを値に変換して *e* に束縛し、handler を順番に評価する。
+// break / continue / return による脱出は捕捉しない
+fn try_(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    // 最後の要素が catch 節、それ以外が body
+    let mut body: Vec<&Expression> = l.iter().collect();
+    let clause = body.pop().unwrap();
+    let (var, handler) = parse_catch_clause(clause)?;
+
+    let res = body.iter().try_fold(Type::Void, |_, e| {
+        return eval_(e, context);
+    });
+    match res {
+        Err(EvalOutcome::Error(e)) => {
+            return context.with_bindings(vec![(var, e.to_type())], |context| {
+                return eval_sequence(&handler, context);
+            });
+        }
+        _ => {
+            return res;
+        }
+    }
+}
+
+// (catch *e* handler ...) を、変数名と handler に分解する
+fn parse_catch_clause(clause: &Expression) -> Result<(Rc<str>, ExpressionList), EvalError> {
+    if let Expression::ExpressionList(l) = clause {
+        if l.len() >= 2 && special_form_name(clause) == Some("catch") {
+            if let Expression::Var(v) = l.tail().head().unwrap() {
+                return Ok((v.clone(), l.tail().tail().clone()));
+            }
+        }
+    }
+    return Err(EvalError::TypeMismatch);
+}
+
+// (raise v) の形式で、v をエラーとして送出する。送出した値は try の catch 節で受け取れる
+fn raise(l: &TypeList) -> Result<Type, EvalError> {
+    return Err(EvalError::Raised(l.head().unwrap().clone()));
+}
+
+// (function f) の形式で、関数 f を呼び出すための値 Function を返す。#'f は (function f) と同じ。
+// f は評価しない。ユーザ定義関数、ホスト側の関数、特殊形式でない組み込み関数の名前を受け付け、
+// モジュール内では、モジュールで修飾した名前のユーザ定義関数を優先する
+fn function(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name = match l.head().unwrap() {
+        Expression::Atom(name) => name.clone(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    if let Some(q) = context.qualify(&name) {
+        if context.functable.contains_key(&q) {
+            return Ok(Type::Function(q));
+        }
+    }
+    let callable = context.functable.contains_key(&name)
+        || context.native_fns.contains_key(&*name)
+        || matches!(
+            context.builtins.get(&*name),
+            Some((Builtin::Fn(_) | Builtin::Context(_), _))
+        );
+    if !callable {
+        return Err(context.function_not_found(&name).into());
+    }
+    return Ok(Type::Function(name));
+}
+
+// (funcall f args ...) の形式で、評価した f の関数に、評価した args を渡して呼び出す。
+// f は (function g) で得た Function か、関数名の Atom
+fn funcall(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let f = args
+        .head()
+        .unwrap()
+        .as_function_name()
+        .ok_or(EvalError::TypeMismatch)?;
+    let rest: Vec<Type> = args.tail().iter().cloned().collect();
+    return apply_named(f, &rest, context);
+}
+
+// (describe f) の形式で、関数 f（名前か Function）の説明を
+// ((:name name) (:kind kind) (:min-args n) (:max-args m) (:doc "...")) という連想リストで返す。
+// kind は :builtin 、:special-form 、:function 、:macro 、:host-function のいずれか。
+// 分からない値（上限の無い max-args や、ドキュメント文字列の無い doc など）は空リストとする。
+// 見つからなければ NotFoundFunctionName
+fn describe(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name = args
+        .head()
+        .unwrap()
+        .as_function_name()
+        .ok_or(EvalError::TypeMismatch)?;
+    let d = context
+        .describe(name)
+        .ok_or_else(|| context.function_not_found(name))?;
+    let nil = || Type::TypeList(Rc::new(TypeList::new()));
+    let kind = match d.kind {
+        SymbolKind::Builtin => "builtin",
+        SymbolKind::SpecialForm => "special-form",
+        SymbolKind::Function => "function",
+        SymbolKind::Macro => "macro",
+        SymbolKind::HostFunction => "host-function",
+        SymbolKind::Variable => "variable",
+    };
+    let (min, max) = match d.arity {
+        Some((min, max)) => (
+            Type::Int(min as Int),
+            max.map_or_else(nil, |m| Type::Int(m as Int)),
+        ),
+        None => (nil(), nil()),
+    };
+    let entries = [
+        ("name", Type::Atom(d.name)),
+        ("kind", Type::Keyword(Rc::from(kind))),
+        ("min-args", min),
+        ("max-args", max),
+        ("doc", d.doc.map_or_else(nil, Type::Str)),
+    ];
+    let res = entries.iter().rev().fold(TypeList::new(), |acc, (k, v)| {
+        let entry = TypeList::new().cons(v).cons(&Type::Keyword(Rc::from(*k)));
+        return acc.cons(&Type::TypeList(Rc::new(entry)));
+    });
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// (quote x) の形式で、x を評価せずにデータとして返す
+fn quote(l: &ExpressionList, _context: &mut Context) -> Result<Type, EvalOutcome> {
+    return Ok(expression_to_type(l.head().unwrap()));
+}
+
+// (quasiquote x) の形式で、x を評価せずにデータとして返す。
+// ただし、x の内側の (unquote y) は y の評価結果に置き換え、
+// (unquote-splicing y) は y の評価結果のリストの要素を展開して埋め込む
+fn quasiquote(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return quasiquote_(l.head().unwrap(), 1, context);
+}
+
+// depth は quasiquote の入れ子の深さ。depth が 1 の位置にある unquote だけを評価する
+fn quasiquote_(exp: &Expression, depth: u32, context: &mut Context) -> Result<Type, EvalOutcome> {
+    if let Some(arg) = special_form_arg(exp, "unquote") {
+        if depth == 1 {
+            return eval_(arg, context);
+        }
+        return Ok(wrap_form("unquote", quasiquote_(arg, depth - 1, context)?));
+    }
+    if let Some(arg) = special_form_arg(exp, "unquote-splicing") {
+        // リストの要素以外の位置では、展開先がない
+        if depth == 1 {
+            return Err(EvalError::TypeMismatch.into());
+        }
+        return Ok(wrap_form(
+            "unquote-splicing",
+            quasiquote_(arg, depth - 1, context)?,
+        ));
+    }
+    if let Some(arg) = special_form_arg(exp, "quasiquote") {
+        return Ok(wrap_form(
+            "quasiquote",
+            quasiquote_(arg, depth + 1, context)?,
+        ));
+    }
+
+    if let Expression::ExpressionList(l) = exp {
+        let mut res = Vec::new();
+        for e in l.iter() {
+            match special_form_arg(e, "unquote-splicing") {
+                Some(arg) if depth == 1 => {
+                    if let Type::TypeList(spliced) = eval_(arg, context)? {
+                        for t in spliced.iter() {
+                            res.push(t.clone());
+                        }
+                    } else {
+                        return Err(EvalError::TypeMismatch.into());
+                    }
+                }
+                _ => {
+                    res.push(quasiquote_(e, depth, context)?);
+                }
+            }
+        }
+        let list = res.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+        return Ok(Type::TypeList(Rc::new(list)));
+    } else {
+        return Ok(expression_to_type(exp));
+    }
+}
+
+// exp が (name arg) という形式であれば、arg を返す
+fn special_form_arg<'b>(exp: &'b Expression, name: &str) -> Option<&'b Expression> {
+    if let Expression::ExpressionList(l) = exp {
+        if l.len() == 2 && special_form_name(exp) == Some(name) {
+            return l.tail().head();
+        }
+    }
+    return None;
+}
+
+// exp が先頭要素が Atom のリストであれば、その Atom の名前を返す
+fn special_form_name(exp: &Expression) -> Option<&str> {
+    if let Expression::ExpressionList(l) = exp {
+        if let Some(Expression::Atom(a)) = l.head() {
+            return Some(a);
+        }
+    }
+    return None;
+}
+
+// (name t) というリストを作る
+fn wrap_form(name: &str, tp: Type) -> Type {
+    let list = TypeList::new().cons(&tp).cons(&Type::Atom(Rc::from(name)));
+    return Type::TypeList(Rc::new(list));
+}
+
+// (defmacro name (*a* *b* ...) body ...) の形式でマクロを定義する。
+// マクロの仮引数は、変数と同じく * で囲んだ名前で書く
+fn defmacro(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (name, procedure) = parse_procedure(l, context)?;
+    context.macrotable.insert(name.clone(), Rc::new(procedure));
+    return Ok(Type::Atom(name));
+}
+
+// (defun name (*a* *b* ...) body ...) の形式で関数を定義する。
+// 関数の呼び出し時には、引数を評価してから仮引数に束縛し、body を順番に評価する
+fn defun(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (name, procedure) = parse_procedure(l, context)?;
+    context.functable.insert(name.clone(), Rc::new(procedure));
+    return Ok(Type::Atom(name));
+}
+
+// name (*a* *b* ...) body ... という形式のリストから、名前と Procedure を取り出す。
+// モジュール内で定義する場合、名前はモジュールで修飾する
+fn parse_procedure(
+    l: &ExpressionList,
+    context: &Context,
+) -> Result<(Rc<str>, Procedure), EvalError> {
+    let name;
+    if let Expression::Atom(a) = l.head().unwrap() {
+        name = context.qualify(a).unwrap_or_else(|| a.clone());
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+
+    let params;
+    if let Expression::ExpressionList(ps) = l.tail().head().unwrap() {
+        params = parse_params(ps)?;
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+
+    // 仮引数の後の : type という戻り値の型注釈は、評価時には無視する
+    let mut body = l.tail().tail();
+    if let Some(Expression::Atom(a)) = body.head() {
+        if &**a == ":" && body.len() >= 2 {
+            body = body.tail().tail();
+        }
+    }
+    // 本体の先頭の文字列は、後に式が続く場合だけドキュメント文字列とする
+    let mut doc = None;
+    if let Some(Expression::Str(s)) = body.head() {
+        if body.len() >= 2 {
+            doc = Some(s.clone());
+            body = body.tail();
+        }
+    }
+    let body = body.clone();
+    let module = context.module.clone();
+    return Ok((
+        name,
+        Procedure {
+            params,
+            body,
+            module,
+            doc,
+        },
+    ));
+}
+
+// 仮引数リストを解析する。&optional 及び &key の後の仮引数は、*c* もしくは (*c* default) と書く。
+// &rest の後にはちょうど 1 つの仮引数を書く。&key の仮引数 *c* は、キーワード :c で渡された引数を受け取る
+fn parse_params(ps: &ExpressionList) -> Result<ParamList, EvalError> {
+    enum Section {
+        Required,
+        Optional,
+        Rest,
+        Key,
+    }
+    let mut params = ParamList::default();
+    let mut section = Section::Required;
+    for p in ps.iter() {
+        match (p, &section) {
+            (Expression::Atom(a), Section::Required) if &**a == "&optional" => {
+                section = Section::Optional;
+            }
+            (Expression::Atom(a), Section::Required | Section::Optional) if &**a == "&rest" => {
+                section = Section::Rest;
+            }
+            (Expression::Atom(a), Section::Required | Section::Optional | Section::Rest)
+                if &**a == "&key" =>
+            {
+                if let Section::Rest = section {
+                    if params.rest.is_none() {
+                        return Err(EvalError::TypeMismatch);
+                    }
+                }
+                section = Section::Key;
+            }
+            (_, Section::Required) => {
+                params.required.push(Pattern::compile_binding(p)?);
+            }
+            (Expression::ExpressionList(spec), Section::Optional) => {
+                if spec.len() != 2 {
+                    return Err(EvalError::BadArrity);
+                }
+                let pattern = Pattern::compile_binding(spec.head().unwrap())?;
+                let default = spec.tail().head().unwrap().clone();
+                params.optional.push((pattern, Some(default)));
+            }
+            (_, Section::Optional) => {
+                params.optional.push((Pattern::compile_binding(p)?, None));
+            }
+            (_, Section::Rest) => {
+                if params.rest.is_some() {
+                    return Err(EvalError::TypeMismatch);
+                }
+                params.rest = Some(Pattern::compile_binding(p)?);
+            }
+            (Expression::Var(v), Section::Key) => {
+                params.key.push((v.clone(), None));
+            }
+            (Expression::ExpressionList(spec), Section::Key) => {
+                if spec.len() != 2 {
+                    return Err(EvalError::BadArrity);
+                }
+                if let Expression::Var(v) = spec.head().unwrap() {
+                    let default = spec.tail().head().unwrap().clone();
+                    params.key.push((v.clone(), Some(default)));
+                } else {
+                    return Err(EvalError::TypeMismatch);
+                }
+            }
+            (_, Section::Key) => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+    if let Section::Rest = section {
+        if params.rest.is_none() {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+    return Ok(params);
+}
+
+// 仮引数に引数を対応させ、束縛する変数と値の一覧を返す。
+// 省略された省略可能な仮引数には、それより前の仮引数を束縛したスコープで既定値の式を評価した値を、
+// 既定値の式が無ければ空リストを束縛する。キーワード引数の既定値も同様に扱う。
+// 引数の数が合わなければ BadArrity 、分配束縛で形が合わなければ MatchFailed 、
+// キーワード引数に未知のキーワードやキーワード以外の値が来たら TypeMismatch
+fn bind_params(
+    params: &ParamList,
+    args: Vec<Type>,
+    context: &mut Context,
+) -> Result<Vec<(Rc<str>, Type)>, EvalOutcome> {
+    let (nreq, nopt) = (params.required.len(), params.optional.len());
+    let variadic = params.rest.is_some() || !params.key.is_empty();
+    if args.len() < nreq || (!variadic && args.len() > nreq + nopt) {
+        return Err(EvalError::BadArrity.into());
+    }
+    let mut args = args.into_iter();
+    let mut bindings = Vec::new();
+    for p in params.required.iter() {
+        let a = args.next().unwrap();
+        bindings.extend(p.bind(&a).ok_or(EvalError::MatchFailed)?);
+    }
+    for (p, default) in params.optional.iter() {
+        let a = match (args.next(), default) {
+            (Some(a), _) => a,
+            (None, Some(exp)) => context.with_bindings(bindings.clone(), |context| {
+                return eval_(exp, context);
+            })?,
+            (None, None) => Type::TypeList(Rc::new(TypeList::new())),
+        };
+        bindings.extend(p.bind(&a).ok_or(EvalError::MatchFailed)?);
+    }
+    let rest: Vec<Type> = args.collect();
+    if let Some(p) = &params.rest {
+        let list = rest
+            .iter()
+            .rev()
+            .fold(TypeList::new(), |acc, t| acc.cons(t));
+        let list = Type::TypeList(Rc::new(list));
+        bindings.extend(p.bind(&list).ok_or(EvalError::MatchFailed)?);
+    }
+    if !params.key.is_empty() {
+        if !rest.len().is_multiple_of(2) {
+            return Err(EvalError::BadArrity.into());
+        }
+        let mut keys: Vec<Option<Type>> = vec![None; params.key.len()];
+        for pair in rest.chunks(2) {
+            let i = match &pair[0] {
+                Type::Keyword(k) => params
+                    .key
+                    .iter()
+                    .position(|(v, _)| v.trim_matches('*') == &**k),
+                _ => None,
+            };
+            match i {
+                // 同じキーワードが複数回渡された場合は、最初の値を使う
+                Some(i) if keys[i].is_none() => keys[i] = Some(pair[1].clone()),
+                Some(_) => {}
+                None => {
+                    return Err(EvalError::TypeMismatch.into());
+                }
+            }
+        }
+        for ((v, default), val) in params.key.iter().zip(keys) {
+            let val = match (val, default) {
+                (Some(val), _) => val,
+                (None, Some(exp)) => context.with_bindings(bindings.clone(), |context| {
+                    return eval_(exp, context);
+                })?,
+                (None, None) => Type::TypeList(Rc::new(TypeList::new())),
+            };
+            bindings.push((v.clone(), val));
+        }
+    }
+    return Ok(bindings);
+}
+
+// ユーザ定義関数を、評価済みの引数に適用する。
+// 関数本体での return はここで受け止める。break / continue は関数の外には伝播させない
+fn apply_function(
+    f: &Procedure,
+    args: &TypeList,
+    context: &mut Context,
+) -> Result<Type, EvalOutcome> {
+    let args = args.iter().cloned().collect();
+    let bindings = bind_params(&f.params, args, context)?;
+    let body = &f.body;
+    let res = context.in_module(f.module.clone(), |context| {
+        return context.with_bindings(bindings, |context| {
+            return eval_sequence(body, context);
+        });
+    });
+    match res {
+        Err(EvalOutcome::Return(v)) => {
+            return Ok(v);
+        }
+        Err(EvalOutcome::Break) => {
+            return Err(EvalError::BreakOutsideLoop.into());
+        }
+        Err(EvalOutcome::Continue) => {
+            return Err(EvalError::ContinueOutsideLoop.into());
+        }
+        _ => {
+            return res;
+        }
+    }
+}
+
+// (assert x) の形式で、x が真（Type::is_truthy）であることを確かめる。
+// 偽の場合は、期待値を 1 とした AssertionFailed エラーになる
+fn assert(l: &TypeList) -> Result<Type, EvalError> {
+    let value = l.head().unwrap();
+    if !value.is_truthy() {
+        return Err(EvalError::AssertionFailed {
+            expected: Type::Int(1),
+            actual: value.clone(),
+        });
+    }
+    return Ok(Type::Void);
+}
+
+// (assert-eq expected actual) の形式で、2 つの値が等しいことを確かめる。
+// リストは要素を再帰的に比較する
+fn assert_eq(l: &TypeList) -> Result<Type, EvalError> {
+    let expected = l.head().unwrap();
+    let actual = l.tail().head().unwrap();
+    if expected == actual {
+        return Ok(Type::Void);
+    } else {
+        return Err(EvalError::AssertionFailed {
+            expected: expected.clone(),
+            actual: actual.clone(),
+        });
+    }
+}
+
+// (deftest name body ...) の形式でテストを定義する。
+// 同じ名前のテストを定義した場合は、置き換える
+fn deftest(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name;
+    if let Expression::Atom(a) = l.head().unwrap() {
+        name = a.clone();
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+
+    let procedure = Procedure {
+        params: ParamList::default(),
+        body: l.tail().clone(),
+        module: context.module.clone(),
+        doc: None,
+    };
+    if let Some(t) = context.tests.iter_mut().find(|(n, _)| *n == name) {
+        t.1 = procedure;
+    } else {
+        context.tests.push((name.clone(), procedure));
+    }
+    return Ok(Type::Atom(name));
+}
+
+// (run-tests) の形式で、定義済みのテストを定義順に全て実行する。
+// 戻り値は、成功したテスト名のリストと、失敗したテストの (テスト名 "エラーの文字列") のリストの 2 つ組。
+// テストの中で exit した場合は、残りのテストを実行せずに、そのまま exit する
+fn run_tests(_args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let mut passed = Vec::new();
+    let mut failed = Vec::new();
+    for (name, procedure) in context.tests.clone() {
+        match apply_function(&procedure, &TypeList::new(), context) {
+            Ok(_) => {
+                passed.push(Type::Atom(name));
+            }
+            Err(EvalOutcome::Exit(code)) => {
+                return Err(EvalOutcome::Exit(code));
+            }
+            Err(e) => {
+                let message = Type::Str(Rc::from(e.into_error().to_string()));
+                let failure = TypeList::new().cons(&message).cons(&Type::Atom(name));
+                failed.push(Type::TypeList(Rc::new(failure)));
+            }
+        }
+    }
+    let to_list = |v: Vec<Type>| {
+        let list = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+        return Type::TypeList(Rc::new(list));
+    };
+    let res = TypeList::new()
+        .cons(&to_list(failed))
+        .cons(&to_list(passed));
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// (break) の形式で、もっとも内側の while ループを抜ける
+fn brk(_l: &ExpressionList, _context: &mut Context) -> Result<Type, EvalOutcome> {
+    return Err(EvalOutcome::Break);
+}
+
+// (continue) の形式で、もっとも内側の while ループの次の繰り返しに移る
+fn cont(_l: &ExpressionList, _context: &mut Context) -> Result<Type, EvalOutcome> {
+    return Err(EvalOutcome::Continue);
+}
+
+// (return) 或いは (return x) の形式で、ユーザ定義関数から抜ける。
+// x を指定した場合はその評価結果を、省略した場合は Void を関数の戻り値とする
+fn ret(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match l.head() {
+        None => {
+            return Err(EvalOutcome::Return(Type::Void));
+        }
+        Some(x) => {
+            let v = eval_(x, context)?;
+            return Err(EvalOutcome::Return(v));
+        }
+    }
+}
+
+// (block name body ...) の形式で、body を順に評価し、最後の評価結果を返す。
+// body の評価中に (return-from name x) が評価されると、残りの body を評価せずに x を block の値とする。
+// 脱出先は名前で探し、同じ名前の block が入れ子になっていれば最も内側のものを選ぶ。
+// 変数と同様に、呼び出した関数の中からも呼び出し元の block を抜けられる
+fn block(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name = match l.head().unwrap() {
+        Expression::Atom(name) => name,
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    match eval_sequence(l.tail(), context) {
+        Err(EvalOutcome::ReturnFrom(target, v)) if target == *name => {
+            return Ok(v);
+        }
+        res => {
+            return res;
+        }
+    }
+}
+
+// (return-from name) 或いは (return-from name x) の形式で、名前が name の block から抜ける。
+// x を指定した場合はその評価結果を、省略した場合は Void を block の値とする
+fn return_from(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name = match l.head().unwrap() {
+        Expression::Atom(name) => name.clone(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    match l.tail().head() {
+        None => {
+            return Err(EvalOutcome::ReturnFrom(name, Type::Void));
+        }
+        Some(x) => {
+            let v = eval_(x, context)?;
+            return Err(EvalOutcome::ReturnFrom(name, v));
+        }
+    }
+}
+
+// (macroexpand x) の形式で、x を評価した結果を式とみなし、マクロを展開したものをデータとして返す
+fn macroexpand_fn(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let exp = type_to_expression(args.head().unwrap())?;
+    return Ok(expression_to_type(&macroexpand(&exp, context)?));
+}
+
+// (parse s) の形式で、文字列 s を 1 つの式として読み込み、quote と同じようにデータとして返す
+fn parse_fn(l: &TypeList) -> Result<Type, EvalError> {
+    let src = l.head().unwrap().expect_str()?;
+    let exp = Expression::try_from(src.as_bytes()).map_err(EvalError::ParseFailed)?;
+    return Ok(expression_to_type(&exp));
+}
+
+// (unparse x) の形式で、データ x を式とみなし、parse で読み込める形式の文字列にして返す
+fn unparse_fn(l: &TypeList) -> Result<Type, EvalError> {
+    let exp = type_to_expression(l.head().unwrap())?;
+    return Ok(Type::Str(Rc::from(exp.to_string())));
+}
+
+// (eval x) の形式で、x を評価した結果を式とみなし、さらに評価する
+fn eval_fn(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let exp = type_to_expression(args.head().unwrap())?;
+    return eval_(&exp, context);
+}
+
+// (void) の形式で、Void を返す
+fn void(_l: &TypeList) -> Result<Type, EvalError> {
+    return Ok(Type::Void);
+}
+
+// 値が必要な引数に Void があれば VoidValue にする。
+// Void は文の位置（progn の途中の式や、ループの本体など）で値を捨てるためのもので、数値の演算や大小の比較には使えない
+fn reject_void(l: &TypeList) -> Result<(), EvalError> {
+    if l.iter().any(|t| *t == Type::Void) {
+        return Err(EvalError::VoidValue);
+    }
+    return Ok(());
+}
+
+// リストを作成する
+fn list(l: &TypeList) -> Result<Type, EvalError> {
+    return Ok(Type::TypeList(Rc::new(l.clone())));
+}
+
+// リストの先頭要素を取り出す
+fn head(l: &TypeList) -> Result<Type, EvalError> {
+    let a = l.head().unwrap();
+    if let Type::TypeList(b) = a {
+        if let Some(c) = b.head() {
+            return Ok(c.clone());
+        } else {
+            return Err(EvalError::DoHeadForNil);
+        }
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+}
+
+/// リストの先頭要素外を取り除いたものを返す
+fn tail(l: &TypeList) -> Result<Type, EvalError> {
+    let a = l.head().unwrap();
+    if let Type::TypeList(b) = a {
+        return Ok(Type::TypeList(Rc::new(b.tail().clone())));
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ArithType {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+// 加算を行う
+fn add(l: &TypeList) -> Result<Type, EvalError> {
+    return arith_op(l, ArithType::Add);
+}
+// 減算を行う
+fn sub(l: &TypeList) -> Result<Type, EvalError> {
+    return arith_op(l, ArithType::Sub);
+}
+// 乗算を行う
+fn mul(l: &TypeList) -> Result<Type, EvalError> {
+    return arith_op(l, ArithType::Mul);
+}
+// 除算を行う。割り切れない場合は Ratio になる
+fn div(l: &TypeList) -> Result<Type, EvalError> {
+    return arith_op(l, ArithType::Div);
+}
+
+// 加減乗除の演算を行う
+fn arith_op(l: &TypeList, tp: ArithType) -> Result<Type, EvalError> {
+    reject_void(l)?;
+
+    let a = l.head().unwrap();
+    let b = l.tail().head().unwrap();
+
+    match (a, b) {
+        (Type::Int(aint), Type::Int(bint)) => {
+            return int_arith(*aint, *bint, tp);
+        }
+        (Type::Ratio(_, _), _) | (_, Type::Ratio(_, _)) => {
+            return ratio_arith(to_ratio(a)?, to_ratio(b)?, tp);
+        }
+        #[cfg(feature = "bignum")]
+        (Type::BigRatio(_), _) | (_, Type::BigRatio(_)) => {
+            return ratio_arith(to_ratio(a)?, to_ratio(b)?, tp);
+        }
+        #[cfg(feature = "bignum")]
+        _ => {
+            return bigint_arith(to_bigint(a)?, to_bigint(b)?, tp);
+        }
+        #[cfg(not(feature = "bignum"))]
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// Int 同士の演算を行う。結果が Int に収まらない場合、bignum feature が有効なら BigInt で計算し直し、
+// 無効なら IntOverflow エラーにする
+fn int_arith(a: Int, b: Int, tp: ArithType) -> Result<Type, EvalError> {
+    let calc_result = match tp {
+        ArithType::Add => a.checked_add(b),
+        ArithType::Sub => a.checked_sub(b),
+        ArithType::Mul => a.checked_mul(b),
+        ArithType::Div => {
+            if b == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            if a.checked_rem(b).is_some_and(|r| r != 0) {
+                return make_ratio(a as Wide, b as Wide);
+            }
+            a.checked_div(b)
+        }
+    };
+    match calc_result {
+        Some(n) => {
+            return Ok(Type::Int(n));
+        }
+        #[cfg(feature = "bignum")]
+        None => {
+            return bigint_arith(a.into(), b.into(), tp);
+        }
+        #[cfg(not(feature = "bignum"))]
+        None => {
+            return Err(EvalError::IntOverflow);
+        }
+    }
+}
+
+// 分数の分子・分母の積を計算する整数の型。Int の 2 倍の幅だが、i128 より広い型は無いので、int128 feature では i128
+#[cfg(all(feature = "int32", not(feature = "int64"), not(feature = "int128")))]
+type Wide = i64;
+#[cfg(not(all(feature = "int32", not(feature = "int64"), not(feature = "int128"))))]
+type Wide = i128;
+
+// 分数同士の演算を行う。Wide で計算してから約分する。
+// int128 feature では途中の計算が Wide に収まらないことがあり、その場合は IntOverflow
+#[cfg(not(feature = "bignum"))]
+fn ratio_arith(a: (Int, Int), b: (Int, Int), tp: ArithType) -> Result<Type, EvalError> {
+    let (an, ad) = (a.0 as Wide, a.1 as Wide);
+    let (bn, bd) = (b.0 as Wide, b.1 as Wide);
+    let mul = |x: Wide, y: Wide| x.checked_mul(y).ok_or(EvalError::IntOverflow);
+    match tp {
+        ArithType::Add => {
+            let n = mul(an, bd)?
+                .checked_add(mul(bn, ad)?)
+                .ok_or(EvalError::IntOverflow)?;
+            return make_ratio(n, mul(ad, bd)?);
+        }
+        ArithType::Sub => {
+            let n = mul(an, bd)?
+                .checked_sub(mul(bn, ad)?)
+                .ok_or(EvalError::IntOverflow)?;
+            return make_ratio(n, mul(ad, bd)?);
+        }
+        ArithType::Mul => {
+            return make_ratio(mul(an, bn)?, mul(ad, bd)?);
+        }
+        ArithType::Div => {
+            if bn == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            return make_ratio(mul(an, bd)?, mul(ad, bn)?);
+        }
+    }
+}
+
+// 多倍長整数で分数同士の演算を行い、約分する（bignum feature）。途中の計算は溢れない
+#[cfg(feature = "bignum")]
+fn ratio_arith(
+    a: (RatioInt, RatioInt),
+    b: (RatioInt, RatioInt),
+    tp: ArithType,
+) -> Result<Type, EvalError> {
+    let ((an, ad), (bn, bd)) = (a, b);
+    match tp {
+        ArithType::Add => {
+            return make_big_ratio(&an * &bd + &bn * &ad, ad * bd);
+        }
+        ArithType::Sub => {
+            return make_big_ratio(&an * &bd - &bn * &ad, ad * bd);
+        }
+        ArithType::Mul => {
+            return make_big_ratio(an * bn, ad * bd);
+        }
+        ArithType::Div => {
+            if bn == RatioInt::from(0) {
+                return Err(EvalError::DivisionByZero);
+            }
+            return make_big_ratio(an * bd, ad * bn);
+        }
+    }
+}
+
+// 分子 n, 分母 d （0 以外）の多倍長整数の値を約分して Type に変換する（bignum feature）。
+// 分母が 1 になる場合は整数に、それ以外で分子・分母が Int に収まらない場合は BigRatio にする
+#[cfg(feature = "bignum")]
+fn make_big_ratio(n: RatioInt, d: RatioInt) -> Result<Type, EvalError> {
+    let zero = RatioInt::from(0);
+    let (n, d) = if d < zero { (-n, -d) } else { (n, d) };
+    // ユークリッドの互除法。d は正なので、最大公約数も正になる
+    let (mut a, mut b) = (d.clone(), &n % &d);
+    while b != zero {
+        let r = &a % &b;
+        a = b;
+        b = r;
+    }
+    let (n, d) = (n / &a, d / &a);
+    if d == RatioInt::from(1) {
+        return Ok(from_bigint(n));
+    }
+    match (Int::try_from(&n), Int::try_from(&d)) {
+        (Ok(n), Ok(d)) => {
+            return Ok(Type::Ratio(n, d));
+        }
+        _ => {
+            return Ok(Type::BigRatio(Rc::new((n, d))));
+        }
+    }
+}
+
+// 分数の分子・分母に使う整数の型。bignum feature が有効なら多倍長整数にして、BigInt も分数として扱う
+#[cfg(not(feature = "bignum"))]
+type RatioInt = Int;
+#[cfg(feature = "bignum")]
+type RatioInt = num_bigint::BigInt;
+
+// Int・BigInt・Ratio 又は BigRatio を（分子, 分母）に変換する。それ以外は TypeMismatch。
+// bignum feature が無効なら RatioInt は Int なので、from は同じ型への変換になる
+#[allow(clippy::useless_conversion)]
+fn to_ratio(t: &Type) -> Result<(RatioInt, RatioInt), EvalError> {
+    match t {
+        Type::Int(i) => {
+            return Ok((RatioInt::from(*i), RatioInt::from(1)));
+        }
+        Type::Ratio(n, d) => {
+            return Ok((RatioInt::from(*n), RatioInt::from(*d)));
+        }
+        #[cfg(feature = "bignum")]
+        Type::BigInt(i) => {
+            return Ok(((**i).clone(), RatioInt::from(1)));
+        }
+        #[cfg(feature = "bignum")]
+        Type::BigRatio(r) => {
+            return Ok((**r).clone());
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// 分子 n, 分母 d （0 以外）の値を約分して Type に変換する。分母が 1 になる場合は Int にする。
+// 分子・分母が Int に収まらない場合は IntOverflow（整数になる場合は bignum feature が有効なら BigInt）。
+// Wide::MIN も符号を反転できるよう、絶対値を符号無しの UWide で約分してから符号を付ける
+fn make_ratio(n: Wide, d: Wide) -> Result<Type, EvalError> {
+    let negative = (n < 0) != (d < 0);
+    let (n, d) = (n.unsigned_abs(), d.unsigned_abs());
+    let g = gcd(n, d);
+    let (n, d) = (n / g, d / g);
+    if d == 1 {
+        if let Some(i) = signed_int(n, negative) {
+            return Ok(Type::Int(i));
+        }
+        #[cfg(feature = "bignum")]
+        {
+            let i = num_bigint::BigInt::from(n);
+            return Ok(from_bigint(if negative { -i } else { i }));
+        }
+    }
+    match (signed_int(n, negative), Int::try_from(d)) {
+        (Some(n), Ok(d)) => {
+            return Ok(Type::Ratio(n, d));
+        }
+        _ => {
+            return Err(EvalError::IntOverflow);
+        }
+    }
+}
+
+// Wide の絶対値を表す符号無しの型
+#[cfg(all(feature = "int32", not(feature = "int64"), not(feature = "int128")))]
+type UWide = u64;
+#[cfg(not(all(feature = "int32", not(feature = "int64"), not(feature = "int128"))))]
+type UWide = u128;
+
+// 絶対値 u に符号を付けた Int。Int に収まらない場合は None
+fn signed_int(u: UWide, negative: bool) -> Option<Int> {
+    if !negative || u == 0 {
+        return Int::try_from(u).ok();
+    }
+    // -Int::MIN は Int に収まらないので、1 を引いてから符号を反転する
+    return Int::try_from(u - 1).ok().map(|i| -i - 1);
+}
+
+// a と b の最大公約数。両方 0 の場合は 1
+fn gcd(a: UWide, b: UWide) -> UWide {
+    let (mut a, mut b) = (a, b);
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    if a == 0 {
+        return 1;
+    }
+    return a;
+}
+
+enum RoundType {
+    Floor,
+    Ceil,
+    Truncate,
+}
+
+// (floor x) の形式で、x 以下の最大の整数を返す
+fn floor(l: &TypeList) -> Result<Type, EvalError> {
+    return round(l, RoundType::Floor);
+}
+
+// (ceil x) の形式で、x 以上の最小の整数を返す
+fn ceil(l: &TypeList) -> Result<Type, EvalError> {
+    return round(l, RoundType::Ceil);
+}
+
+// (truncate x) の形式で、x の小数部分を切り捨てた（0 に近づけた）整数を返す
+fn truncate(l: &TypeList) -> Result<Type, EvalError> {
+    return round(l, RoundType::Truncate);
+}
+
+// Ratio を整数に丸める。整数はそのまま返す
+fn round(l: &TypeList, tp: RoundType) -> Result<Type, EvalError> {
+    reject_void(l)?;
+    match l.head().unwrap() {
+        Type::Ratio(n, d) => {
+            // 分母は 2 以上なので、結果は Int に収まる
+            let res = match tp {
+                RoundType::Floor => n.div_euclid(*d),
+                RoundType::Ceil => -(-n).div_euclid(*d),
+                RoundType::Truncate => n / d,
+            };
+            return Ok(Type::Int(res));
+        }
+        Type::Int(i) => {
+            return Ok(Type::Int(*i));
+        }
+        #[cfg(feature = "bignum")]
+        t @ Type::BigInt(_) => {
+            return Ok(t.clone());
+        }
+        #[cfg(feature = "bignum")]
+        Type::BigRatio(r) => {
+            let (n, d) = &**r;
+            // 分母は正なので、商は 0 に向かって丸められる。割り切れない場合だけ Floor と Ceil で調整する
+            let q = n / d;
+            let exact = &q * d == *n;
+            let zero = num_bigint::BigInt::from(0);
+            let res = match tp {
+                RoundType::Floor if !exact && *n < zero => q - 1,
+                RoundType::Ceil if !exact && *n > zero => q + 1,
+                _ => q,
+            };
+            return Ok(from_bigint(res));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// 多倍長整数の演算を行う
+#[cfg(feature = "bignum")]
+fn bigint_arith(
+    a: num_bigint::BigInt,
+    b: num_bigint::BigInt,
+    tp: ArithType,
+) -> Result<Type, EvalError> {
+    let calc_result = match tp {
+        ArithType::Add => a + b,
+        ArithType::Sub => a - b,
+        ArithType::Mul => a * b,
+        ArithType::Div => {
+            if b == num_bigint::BigInt::from(0) {
+                return Err(EvalError::DivisionByZero);
+            }
+            // Int 同士の除算と同じく、割り切れない場合は Ratio にする
+            if &a % &b != num_bigint::BigInt::from(0) {
+                return make_big_ratio(a, b);
+            }
+            a / b
+        }
+    };
+    return Ok(from_bigint(calc_result));
+}
+
+// 整数を多倍長整数に変換する。整数でない場合は TypeMismatch
+#[cfg(feature = "bignum")]
+fn to_bigint(t: &Type) -> Result<num_bigint::BigInt, EvalError> {
+    match t {
+        Type::Int(i) => {
+            return Ok((*i).into());
+        }
+        Type::BigInt(i) => {
+            return Ok((**i).clone());
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// 多倍長整数を Type に変換する。Int に収まる場合は Int にする
+#[cfg(feature = "bignum")]
+fn from_bigint(n: num_bigint::BigInt) -> Type {
+    match Int::try_from(&n) {
+        Ok(i) => {
+            return Type::Int(i);
+        }
+        Err(_) => {
+            return Type::BigInt(Rc::new(n));
+        }
+    }
+}
+
+// 真偽値を Lisp の値に変換する。真なら 1 、偽なら 0 とする
+fn truth(b: bool) -> Type {
+    if b {
+        return Type::Int(1);
+    } else {
+        return Type::Int(0);
+    }
+}
+
+// 比較の演算子
+#[derive(Clone, Copy, PartialEq)]
+enum CompareType {
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl CompareType {
+    // 演算子の組み込み関数名
+    fn name(self) -> &'static str {
+        match self {
+            CompareType::Gt => "gt",
+            CompareType::Lt => "lt",
+            CompareType::Ge => "ge",
+            CompareType::Le => "le",
+            CompareType::Eq => "eq",
+            CompareType::Ne => "ne",
+        }
+    }
+}
+
+fn compare(l: &TypeList, ctype: CompareType) -> Result<Type, EvalError> {
+    let a = l.head().unwrap();
+    let b = l.tail().head().unwrap();
+
+    // eq と ne は文字列を内容で、リストと Vector と Opaque を同一性で比較する
+    if ctype == CompareType::Eq || ctype == CompareType::Ne {
+        if let Some(same) = identical(a, b) {
+            return Ok(truth(same == (ctype == CompareType::Eq)));
+        }
+    }
+    reject_void(l)?;
+
+    let ordering = match order(a, b) {
+        Some(ordering) => ordering,
+        None => {
+            return Err(EvalError::Incomparable {
+                op: String::from(ctype.name()),
+                left: String::from(a.type_name()),
+                right: String::from(b.type_name()),
+            });
+        }
+    };
+    let res = match ctype {
+        CompareType::Gt => ordering == Ordering::Greater,
+        CompareType::Lt => ordering == Ordering::Less,
+        CompareType::Ge => ordering != Ordering::Less,
+        CompareType::Le => ordering != Ordering::Greater,
+        CompareType::Eq => ordering == Ordering::Equal,
+        CompareType::Ne => ordering != Ordering::Equal,
+    };
+    return Ok(truth(res));
+}
+
+// 順序のある値同士の順序。数値同士は数値として、Atom 同士、Keyword 同士は名前で比較する。
+// それ以外の組み合わせは None
+fn order(a: &Type, b: &Type) -> Option<Ordering> {
+    // Ratio を含む場合は、分数として比較する
+    if matches!(a, Type::Ratio(_, _)) || matches!(b, Type::Ratio(_, _)) {
+        return Some(ratio_cmp(to_ratio(a).ok()?, to_ratio(b).ok()?));
+    }
+    #[cfg(feature = "bignum")]
+    if matches!(a, Type::BigRatio(_)) || matches!(b, Type::BigRatio(_)) {
+        return Some(ratio_cmp(to_ratio(a).ok()?, to_ratio(b).ok()?));
+    }
+
+    // BigInt を含む場合は、多倍長整数として比較する
+    #[cfg(feature = "bignum")]
+    if matches!(a, Type::BigInt(_)) || matches!(b, Type::BigInt(_)) {
+        return Some(to_bigint(a).ok()?.cmp(&to_bigint(b).ok()?));
+    }
+
+    match (a, b) {
+        (Type::Int(x), Type::Int(y)) => {
+            return Some(x.cmp(y));
+        }
+        (Type::Atom(x), Type::Atom(y)) => {
+            return Some(x.cmp(y));
+        }
+        (Type::Keyword(x), Type::Keyword(y)) => {
+            return Some(x.cmp(y));
+        }
+        _ => {
+            return None;
+        }
+    }
+}
+
+// 分母が正の分数 a と b の大小（bignum feature）。多倍長整数なので、分母を払って比べる
+#[cfg(feature = "bignum")]
+fn ratio_cmp(a: (RatioInt, RatioInt), b: (RatioInt, RatioInt)) -> Ordering {
+    return (a.0 * &b.1).cmp(&(b.0 * &a.1));
+}
+
+// 分母が正の分数 a と b の大小。分母を払うと Int に収まらないことがあるので、
+// 整数部分を比べ、等しければ小数部分の逆数を比べる（連分数展開）
+#[cfg(not(feature = "bignum"))]
+fn ratio_cmp(a: (Int, Int), b: (Int, Int)) -> Ordering {
+    let (aq, ar) = (a.0.div_euclid(a.1), a.0.rem_euclid(a.1));
+    let (bq, br) = (b.0.div_euclid(b.1), b.0.rem_euclid(b.1));
+    if aq != bq {
+        return aq.cmp(&bq);
+    }
+    match (ar == 0, br == 0) {
+        (true, true) => {
+            return Ordering::Equal;
+        }
+        (true, false) => {
+            return Ordering::Less;
+        }
+        (false, true) => {
+            return Ordering::Greater;
+        }
+        // ar / a.1 と br / b.1 の大小は、逆数 a.1 / ar と b.1 / br の大小の逆になる
+        (false, false) => {
+            return ratio_cmp((b.1, br), (a.1, ar));
+        }
+    }
+}
+
+// eq と ne で、順序ではなく内容や同一性で比較する値同士なら、同じかどうか。それ以外の組み合わせは None。
+// Void はどの値とも比較でき、Void 同士だけが同じになる
+fn identical(a: &Type, b: &Type) -> Option<bool> {
+    match (a, b) {
+        (Type::Void, _) | (_, Type::Void) => {
+            return Some(a == b);
+        }
+        (Type::Str(x), Type::Str(y)) => {
+            return Some(x == y);
+        }
+        (Type::TypeList(x), Type::TypeList(y)) => {
+            return Some(Rc::ptr_eq(x, y) || (x.is_empty() && y.is_empty()));
+        }
+        (Type::Vector(x), Type::Vector(y)) => {
+            return Some(Rc::ptr_eq(x, y));
+        }
+        (Type::Opaque(x), Type::Opaque(y)) => {
+            return Some(x == y);
+        }
+        (Type::Function(x), Type::Function(y)) => {
+            return Some(x == y);
+        }
+        _ => {
+            return None;
+        }
+    }
+}
+
+// > 演算を行う
+// a > b なら 1 、そうでないなら 0 を返す
+// Atom同士、Keyword同士、数値同士の場合のみ演算を許容し、それ以外の組み合わせは Incomparable
+fn gt(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Gt);
+}
+
+// < 演算を行う
+// a < b なら 1 、そうでないなら 0 を返す
+// Atom同士、Keyword同士、数値同士の場合のみ演算を許容し、それ以外の組み合わせは Incomparable
+fn lt(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Lt);
+}
+
+// >= 演算を行う
+// a >= b なら 1 、そうでないなら 0 を返す。比較できる組み合わせは gt と同じ
+fn ge(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Ge);
+}
+
+// <= 演算を行う
+// a <= b なら 1 、そうでないなら 0 を返す。比較できる組み合わせは lt と同じ
+fn le(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Le);
+}
+
+// 同一性、またはプリミティブな値の等価性を調べる
+// 数値同士は数値として、Atom同士、Keyword同士、Str同士は名前・内容で比較する
+// リスト同士、Vector同士、Opaque同士は同じ値（同じ define や引数から得たもの）の場合のみ 1 を返す。空リスト同士は常に 1
+// それ以外の組み合わせは Incomparable
+fn eq(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Eq);
+}
+
+// eq の否定。eq が 1 なら 0 、0 なら 1 を返す。比較できる組み合わせは eq と同じ
+fn ne(l: &TypeList) -> Result<Type, EvalError> {
+    return compare(l, CompareType::Ne);
+}
+
+// 構造による等価性を調べる
+// リストと Vector は要素を再帰的に比較する。種類の異なる値は等しくないとして 0 を返す
+fn equal(l: &TypeList) -> Result<Type, EvalError> {
+    let a = l.head().unwrap();
+    let b = l.tail().head().unwrap();
+    return Ok(truth(a.deep_eq(b)));
+}
+
+// 引数が pred を満たすなら 1 、そうでないなら 0 を返す
+fn type_predicate(l: &TypeList, pred: fn(&Type) -> bool) -> Result<Type, EvalError> {
+    return Ok(truth(pred(l.head().unwrap())));
+}
+
+// Int なら 1 、そうでないなら 0 を返す
+fn intp(l: &TypeList) -> Result<Type, EvalError> {
+    #[cfg(feature = "bignum")]
+    return type_predicate(l, |t| matches!(t, Type::Int(_) | Type::BigInt(_)));
+    #[cfg(not(feature = "bignum"))]
+    return type_predicate(l, |t| matches!(t, Type::Int(_)));
+}
+
+// Atom なら 1 、そうでないなら 0 を返す
+fn atomp(l: &TypeList) -> Result<Type, EvalError> {
+    return type_predicate(l, |t| matches!(t, Type::Atom(_)));
+}
+
+// リスト（空リストを含む）なら 1 、そうでないなら 0 を返す
+fn listp(l: &TypeList) -> Result<Type, EvalError> {
+    return type_predicate(l, |t| matches!(t, Type::TypeList(_)));
+}
+
+// 空リストなら 1 、そうでないなら 0 を返す
+fn nullp(l: &TypeList) -> Result<Type, EvalError> {
+    return type_predicate(l, |t| {
+        if let Type::TypeList(tl) = t {
+            return tl.is_empty();
+        }
+        return false;
+    });
+}
+
+// 文字列ならその中身を返し、そうでないなら TypeMismatch
+fn str_arg(t: &Type) -> Result<&str, EvalError> {
+    if let Type::Str(s) = t {
+        return Ok(s);
+    }
+    return Err(EvalError::TypeMismatch);
+}
+
+// (str-split s sep) の形式で、文字列 s を区切り文字列 sep で分割した文字列のリストを返す。
+// sep を省略した場合は空白で分割し、空の要素は含めない。sep が空文字列なら TypeMismatch
+fn str_split(l: &TypeList) -> Result<Type, EvalError> {
+    let s = str_arg(l.head().unwrap())?;
+    let parts: Vec<&str> = match l.tail().head() {
+        Some(sep) => {
+            let sep = str_arg(sep)?;
+            if sep.is_empty() {
+                return Err(EvalError::TypeMismatch);
+            }
+            s.split(sep).collect()
+        }
+        None => s.split_whitespace().collect(),
+    };
+    let res = parts
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, p| acc.cons(&Type::Str(Rc::from(*p))));
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// (str-join l sep) の形式で、文字列のリスト l の要素を sep で区切って連結する。sep を省略した場合は区切らない
+fn str_join(l: &TypeList) -> Result<Type, EvalError> {
+    let items = match l.head().unwrap() {
+        Type::TypeList(items) => items,
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    };
+    let sep = match l.tail().head() {
+        Some(sep) => str_arg(sep)?,
+        None => "",
+    };
+    let mut res = String::new();
+    for (i, item) in (**items).clone().into_iter().enumerate() {
+        if i != 0 {
+            res.push_str(sep);
+        }
+        res.push_str(str_arg(item.head().unwrap())?);
+    }
+    return Ok(Type::Str(Rc::from(res)));
+}
+
+// 引数の文字列を f で変換した文字列を返す
+fn str_map(l: &TypeList, f: fn(&str) -> String) -> Result<Type, EvalError> {
+    return Ok(Type::Str(Rc::from(f(str_arg(l.head().unwrap())?))));
+}
+
+// (str-upper s) の形式で、s の英字を大文字にした文字列を返す
+fn str_upper(l: &TypeList) -> Result<Type, EvalError> {
+    return str_map(l, |s| s.to_uppercase());
+}
+
+// (str-lower s) の形式で、s の英字を小文字にした文字列を返す
+fn str_lower(l: &TypeList) -> Result<Type, EvalError> {
+    return str_map(l, |s| s.to_lowercase());
+}
+
+// (str-trim s) の形式で、s の前後の空白を除いた文字列を返す
+fn str_trim(l: &TypeList) -> Result<Type, EvalError> {
+    return str_map(l, |s| String::from(s.trim()));
+}
+
+// (str-contains s sub) の形式で、s が sub を含むなら 1 、そうでないなら 0 を返す
+fn str_contains(l: &TypeList) -> Result<Type, EvalError> {
+    let s = str_arg(l.head().unwrap())?;
+    let sub = str_arg(l.tail().head().unwrap())?;
+    return Ok(truth(s.contains(sub)));
+}
+
+// (to-string x) の形式で、x を to_display_string と同じ形式の文字列にする。文字列はそのまま返す
+fn to_string(l: &TypeList) -> Result<Type, EvalError> {
+    return Ok(Type::Str(Rc::from(l.head().unwrap().to_display_string())));
+}
+
+// (parse-int s) の形式で、前後の空白を除いた文字列 s を 10 進数の整数として読む。
+// 読めない場合は、try で捕捉できる InvalidNumber。Int に収まらない場合は IntOverflow（bignum feature が有効なら BigInt）
+fn parse_int(l: &TypeList) -> Result<Type, EvalError> {
+    let s = str_arg(l.head().unwrap())?;
+    let digits = s.trim();
+    let body = digits
+        .strip_prefix(|c| c == '-' || c == '+')
+        .unwrap_or(digits);
+    if body.is_empty() || !body.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(EvalError::InvalidNumber(String::from(s)));
+    }
+    if let Ok(i) = digits.parse::<Int>() {
+        return Ok(Type::Int(i));
+    }
+    #[cfg(feature = "bignum")]
+    if let Ok(i) = digits.parse::<num_bigint::BigInt>() {
+        return Ok(from_bigint(i));
+    }
+    return Err(EvalError::IntOverflow);
+}
+
+// Vector なら 1 、そうでないなら 0 を返す
+fn vectorp(l: &TypeList) -> Result<Type, EvalError> {
+    return type_predicate(l, |t| matches!(t, Type::Vector(_)));
+}
+
+// (send obj :name args ...) の形式で、Opaque の obj に対して、register_method で登録したメソッド name を呼び出す。
+// obj が Opaque でない、もしくは name が Keyword でなければ TypeMismatch。
+// obj の型に name というメソッドが登録されていなければ NotFoundFunctionName
+fn send(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (receiver, name) = match (args.head().unwrap(), args.tail().head().unwrap()) {
+        (Type::Opaque(receiver), Type::Keyword(name)) => (receiver, name),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    let method = context
+        .methods
+        .get(&receiver.type_id())
+        .and_then(|methods| methods.get(&**name))
+        .cloned()
+        .ok_or(EvalError::NotFoundFunctionName)?;
+    let rest: Vec<Type> = args.tail().tail().iter().cloned().collect();
+    return Ok(method(receiver, &rest)?);
+}
+
+// 引数を要素とする Vector を作成する
+fn vector(l: &TypeList) -> Result<Type, EvalError> {
+    let v = l.iter().cloned().collect();
+    return Ok(Type::Vector(Rc::new(v)));
+}
+
+// Vector と添字を取り出す。添字が範囲外なら IndexOutOfRange
+fn vector_index(v: &Type, i: &Type) -> Result<(Rc<Vec<Type>>, usize), EvalError> {
+    match (v, i) {
+        (Type::Vector(v), Type::Int(i)) => {
+            if *i < 0 || *i as usize >= v.len() {
+                return Err(EvalError::IndexOutOfRange);
+            }
+            return Ok((v.clone(), *i as usize));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// (vref v i) の形式で、Vector v の i 番目（0 始まり）の要素を返す
+fn vref(l: &TypeList) -> Result<Type, EvalError> {
+    let (v, i) = vector_index(l.head().unwrap(), l.tail().head().unwrap())?;
+    return Ok(v[i].clone());
+}
+
+// (vset v i x) の形式で、Vector v の i 番目の要素を x に置き換えた Vector を返す。
+// 元の Vector は変更しない（他から参照されていなければ、複製せずにそのまま書き換える）
+fn vset(l: &TypeList) -> Result<Type, EvalError> {
+    let (mut v, i) = vector_index(l.head().unwrap(), l.tail().head().unwrap())?;
+    Rc::make_mut(&mut v)[i] = l.tail().tail().head().unwrap().clone();
+    return Ok(Type::Vector(v));
+}
+
+// Vector の要素数を返す
+fn vlen(l: &TypeList) -> Result<Type, EvalError> {
+    if let Type::Vector(v) = l.head().unwrap() {
+        return Int::try_from(v.len())
+            .map(Type::Int)
+            .map_err(|_| EvalError::IntOverflow);
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+}
+
+// リストを、同じ要素を同じ順に持つ Vector に変換する
+fn list_to_vector(l: &TypeList) -> Result<Type, EvalError> {
+    if let Type::TypeList(tl) = l.head().unwrap() {
+        return vector(tl);
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+}
+
+// Vector を、同じ要素を同じ順に持つリストに変換する
+fn vector_to_list(l: &TypeList) -> Result<Type, EvalError> {
+    if let Type::Vector(v) = l.head().unwrap() {
+        let res = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+        return Ok(Type::TypeList(Rc::new(res)));
+    } else {
+        return Err(EvalError::TypeMismatch);
+    }
+}
+
+// 引数が全てリストであることを確認し、各リストの要素を Vec にして返す
+fn list_args(l: &TypeList) -> Result<Vec<Vec<Type>>, EvalError> {
+    let mut res = Vec::new();
+    for arg in l.iter() {
+        match arg {
+            Type::TypeList(tl) => {
+                res.push(tl.iter().cloned().collect());
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+    return Ok(res);
+}
+
+// Vec の要素を、同じ順に持つリストにする
+fn list_from(v: &[Type]) -> Type {
+    let l = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Type::TypeList(Rc::new(l));
+}
+
+// (range end)、(range start end)、(range start end step) の形式で、
+// start から step ずつ増やした、end を含まない整数のリストを返す。start は 0 、step は 1 を省略時の値とする。
+// step が負なら減らしながら end より大きい間続ける。step が 0 なら TypeMismatch
+fn range(l: &TypeList) -> Result<Type, EvalError> {
+    let mut args = Vec::new();
+    for t in l.iter() {
+        args.push(t.expect_int()?);
+    }
+    let (start, end, step) = match args[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        _ => (args[0], args[1], args[2]),
+    };
+    if step == 0 {
+        return Err(EvalError::TypeMismatch);
+    }
+    let mut res = Vec::new();
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        res.push(Type::Int(i));
+        // Int に収まらなくなった場合は、end を超えている
+        i = match i.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    return Ok(list_from(&res));
+}
+
+// (zip l1 l2 ...) の形式で、各リストの同じ位置の要素を並べたリストのリストを返す。
+// 長さは最も短いリストに合わせる
+fn zip(l: &TypeList) -> Result<Type, EvalError> {
+    let lists = list_args(l)?;
+    let len = lists.iter().map(|v| v.len()).min().unwrap_or(0);
+    let res: Vec<Type> = (0..len)
+        .map(|i| {
+            let row: Vec<Type> = lists.iter().map(|v| v[i].clone()).collect();
+            return list_from(&row);
+        })
+        .collect();
+    return Ok(list_from(&res));
+}
+
+// (enumerate list) の形式で、各要素を (添字 要素) にしたリストを返す。添字は 0 から数える
+fn enumerate(l: &TypeList) -> Result<Type, EvalError> {
+    let lists = list_args(l)?;
+    let res: Vec<Type> = lists[0]
+        .iter()
+        .enumerate()
+        .map(|(i, e)| list_from(&[Type::Int(i as Int), e.clone()]))
+        .collect();
+    return Ok(list_from(&res));
+}
+
+// (take n list) と (drop n list) の引数を取り出す。n が負なら TypeMismatch
+fn count_and_list(l: &TypeList) -> Result<(usize, &TypeList), EvalError> {
+    match (l.head().unwrap(), l.tail().head().unwrap()) {
+        (Type::Int(n), Type::TypeList(tl)) if *n >= 0 => {
+            return Ok((*n as usize, tl));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// (take n list) の形式で、list の先頭から n 個の要素のリストを返す。n が長さ以上ならリスト全体を返す
+fn take(l: &TypeList) -> Result<Type, EvalError> {
+    let (n, tl) = count_and_list(l)?;
+    let res: Vec<Type> = tl.iter().take(n).cloned().collect();
+    return Ok(list_from(&res));
+}
+
+// (drop n list) の形式で、list の先頭から n 個の要素を除いたリストを返す。n が長さ以上なら空リストを返す
+fn drop_(l: &TypeList) -> Result<Type, EvalError> {
+    let (n, tl) = count_and_list(l)?;
+    let mut rest = tl;
+    for _ in 0..n {
+        if rest.is_empty() {
+            break;
+        }
+        rest = rest.tail();
+    }
+    return Ok(Type::TypeList(Rc::new(rest.clone())));
+}
+
+// (module name body ...) の形式で、body をモジュール name の中で順番に評価する。
+// モジュール内で定義した関数・マクロ・グローバル変数は、name:f や *name:x* のように修飾した名前で登録され、
+// モジュールの外からは修飾した名前で参照する。モジュール名を返す
+fn module(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let name = match l.head().unwrap() {
+        Expression::Atom(a) if !a.contains(':') => a.clone(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    context.in_module(Some(name.clone()), |context| {
+        return eval_sequence(l.tail(), context);
+    })?;
+    return Ok(Type::Atom(name));
+}
+
+// (load "path") の形式で、path のソースを読み込み、現在の Context で評価する。
+// 最後に評価した式の値を返す
+fn load(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match args.head().unwrap() {
+        Type::Str(path) => {
+            return Ok(context.eval_file(path)?);
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+}
+
+// (gensym) 又は (gensym "prefix") の形式で、他のシンボルと衝突しない新しいシンボルを返す
+fn gensym(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match args.head() {
+        None => {
+            return Ok(Type::Atom(context.gensym()));
+        }
+        Some(Type::Str(prefix)) => {
+            return Ok(Type::Atom(context.gensym_with_prefix(prefix)));
+        }
+        Some(_) => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+}
+
+// (add-hook hook fn) もしくは (remove-hook hook fn) の評価済みの引数から、フック名と関数名を取り出す
+fn hook_args(args: &TypeList) -> Result<(Rc<str>, Rc<str>), EvalError> {
+    match (
+        args.head().unwrap(),
+        args.tail().head().unwrap().as_function_name(),
+    ) {
+        (Type::Atom(hook), Some(f)) => {
+            return Ok((hook.clone(), Rc::from(f)));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// (add-hook hook fn) の形式で、フック hook に関数名 fn を追加する
+fn add_hook(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (hook, f) = hook_args(args)?;
+    context.add_hook(&hook, &f);
+    return Ok(Type::Void);
+}
+
+// (remove-hook hook fn) の形式で、フック hook から関数名 fn を取り除く
+fn remove_hook(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (hook, f) = hook_args(args)?;
+    context.remove_hook(&hook, &f);
+    return Ok(Type::Void);
+}
+
+// (run-hooks hook args ...) の形式で、フック hook に追加された関数を args で順番に呼び出し、
+// 戻り値のリストを返す
+fn run_hooks(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let hook = match args.head().unwrap() {
+        Type::Atom(hook) => hook.clone(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    let rest: Vec<Type> = args.tail().iter().cloned().collect();
+    let res = context.run_hook(&hook, &rest)?;
+    let res = res.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// (pmap f list) の形式で、list の各要素に関数 f（名前か Function）を適用した結果のリストを返す。
+// f は apply_named と同じく、ユーザ定義関数、ホスト側の関数、特殊形式でない組み込み関数の順に探す。
+// 各要素への適用は呼び出し元の定義を複製した子の Context で行うので、f の中での set や defun は、
+// 呼び出し元にも他の要素への適用にも影響しない。エラーになった要素があれば、先頭に近い要素のエラーを返す
+fn pmap(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (f, elements) = match (
+        args.head().unwrap().as_function_name(),
+        args.tail().head().unwrap(),
+    ) {
+        (Some(f), Type::TypeList(elements)) => (Rc::<str>::from(f), elements.clone()),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    let elements: Vec<Type> = elements.iter().cloned().collect();
+    let res = map_in_children(context, &f, elements)?;
+    let res = res.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// pmap の本体。各要素に対して、定義を保存してから関数を呼び出し、呼び出し後に元に戻す
+#[cfg(not(feature = "parallel"))]
+fn map_in_children(
+    context: &mut Context,
+    f: &str,
+    elements: Vec<Type>,
+) -> Result<Vec<Type>, EvalError> {
+    let mut res = Vec::with_capacity(elements.len());
+    for e in elements {
+        res.push(context.isolated(|context| {
+            return apply_named(f, &[e], context).map_err(EvalOutcome::into_error);
+        })?);
+    }
+    return Ok(res);
+}
+
+// pmap の本体。rayon のスレッドプールで、要素ごとに WorkerConfig から新しい Context を作って関数を呼び出す。
+// 値は Rc を含みスレッド間で受け渡せないので、引数と結果はバイト列にして受け渡す。
+// バイト列にできない Opaque を、変数の値や引数に含む場合は TypeMismatch
+#[cfg(feature = "parallel")]
+fn map_in_children(
+    context: &mut Context,
+    f: &str,
+    elements: Vec<Type>,
+) -> Result<Vec<Type>, EvalError> {
+    use rayon::prelude::*;
+
+    let config = WorkerConfig::new(context)?;
+    let inputs: Vec<(Vec<u8>, u64)> = elements
+        .iter()
+        .map(|e| {
+            let input = serde_json::to_vec(e).map_err(|_| EvalError::TypeMismatch)?;
+            return Ok((input, context.rng.borrow_mut().next_u64()));
+        })
+        .collect::<Result<_, EvalError>>()?;
+    let outputs: Vec<Vec<u8>> = inputs
+        .par_iter()
+        .map(|(input, seed)| {
+            let mut child = config.build(*seed);
+            let arg: Type = serde_json::from_slice(input).expect("broken argument");
+            let res = apply_named(f, &[arg], &mut child).map_err(EvalOutcome::into_error);
+            // 子の Context には Opaque を作る手段が無いので、結果は常にバイト列にできる
+            return serde_json::to_vec(&res).expect("a result is always serializable");
+        })
+        .collect();
+    return outputs
+        .iter()
+        .map(|output| {
+            let res: Result<Type, EvalError> =
+                serde_json::from_slice(output).expect("broken result");
+            return res;
+        })
+        .collect();
+}
+
+// pmap のワーカースレッドで Context を作るための、呼び出し元の Context の設定。
+// 定義（ContextSnapshot）と、スレッド間で受け渡せる設定はそのまま引き継ぐ。
+// loader、filesystem、output、http はスレッド間で受け渡せないので引き継がず、ワーカーの中で使うと UnavailableInWorker になる。
+// register_fn で登録した関数も同じく、呼び出すと UnavailableInWorker になる。
+// bind_dynamic で登録した変数は、pmap を呼び出した時点の値で固定する。
+// tracer、on_var_change、プロファイラ、トレースの記録は、child と同じく引き継がない
+#[cfg(feature = "parallel")]
+struct WorkerConfig {
+    snapshot: Vec<u8>,                    // ContextSnapshot をバイト列にしたもの
+    dynamic_vars: Vec<(String, Vec<u8>)>, // bind_dynamic で登録した変数の名前と、値をバイト列にしたもの
+    native_fns: Vec<String>,              // register_fn で登録した関数の名前
+    builtins: Map<&'static str, (Builtin, usize)>,
+    disabled_builtins: BTreeSet<&'static str>,
+    capabilities: Capabilities,
+    strict_set: bool,
+    max_loop_iterations: Option<u32>,
+    max_depth: Option<usize>,
+    max_variables: Option<usize>,
+    max_value_size: Option<usize>,
+    module: Option<String>,
+    depth: usize, // pmap を呼び出した時点の式の入れ子の深さ。max_depth はワーカーの中での深さも含めて数える
+}
+
+#[cfg(feature = "parallel")]
+impl WorkerConfig {
+    // context の設定を取り出す。変数の値に Opaque を含む場合は TypeMismatch
+    fn new(context: &Context) -> Result<WorkerConfig, EvalError> {
+        let snapshot = context
+            .snapshot()
+            .to_bytes()
+            .map_err(|_| EvalError::TypeMismatch)?;
+        let dynamic_vars = context
+            .dynamic_vars
+            .iter()
+            .map(|(name, f)| {
+                let value = serde_json::to_vec(&f()).map_err(|_| EvalError::TypeMismatch)?;
+                return Ok((String::from(&**name), value));
+            })
+            .collect::<Result<_, EvalError>>()?;
+        return Ok(WorkerConfig {
+            snapshot,
+            dynamic_vars,
+            native_fns: context
+                .native_fns
+                .keys()
+                .map(|name| String::from(&**name))
+                .collect(),
+            builtins: context.builtins.clone(),
+            disabled_builtins: context.disabled_builtins.clone(),
+            capabilities: context.capabilities.clone(),
+            strict_set: context.strict_set,
+            max_loop_iterations: context.max_loop_iterations,
+            max_depth: context.max_depth,
+            max_variables: context.max_variables,
+            max_value_size: context.max_value_size,
+            module: context.module.as_ref().map(|m| String::from(&**m)),
+            depth: context.depth,
+        });
+    }
+
+    // ワーカースレッドで使う Context を作る。random は種 seed の乱数生成器を使う
+    fn build(&self, seed: u64) -> Context {
+        let mut child = Context::new();
+        let snapshot = ContextSnapshot::from_bytes(&self.snapshot).expect("broken snapshot");
+        child.restore(&snapshot);
+        for (name, value) in &self.dynamic_vars {
+            let value: Type = serde_json::from_slice(value).expect("broken variable");
+            child.bind_dynamic(name, move || return value.clone());
+        }
+        for name in &self.native_fns {
+            let err = EvalError::UnavailableInWorker(name.clone());
+            child.native_fns.insert(
+                Rc::from(name.as_str()),
+                Rc::new(move |_: &[Type]| return Err(err.clone())),
+            );
+        }
+        child.builtins = self.builtins.clone();
+        child.disabled_builtins = self.disabled_builtins.clone();
+        child.capabilities = self.capabilities.clone();
+        child.strict_set = self.strict_set;
+        child.max_loop_iterations = self.max_loop_iterations;
+        child.max_depth = self.max_depth;
+        child.max_variables = self.max_variables;
+        child.max_value_size = self.max_value_size;
+        child.module = self.module.as_deref().map(Rc::from);
+        child.depth = self.depth;
+        child.rng = Rc::new(RefCell::new(Box::new(SplitMix64::new(seed))));
+        child.in_worker = true;
+        return child;
+    }
+}
+
+// 関数名 f の関数を、評価済みの引数 args で呼び出す。
+// 評価器と同じく、ユーザ定義関数、ホスト側の関数、組み込み関数の順に探す。特殊形式とマクロは呼び出せない
+fn apply_named(f: &str, args: &[Type], context: &mut Context) -> Result<Type, EvalOutcome> {
+    let to_list = |args: &[Type]| {
+        return args
+            .iter()
+            .rev()
+            .fold(TypeList::new(), |acc, t| acc.cons(t));
+    };
+    if let Some(procedure) = context.resolve(&context.functable, f) {
+        log_trace!("apply {} ({} args)", f, args.len());
+        return in_call_span(f, args.len(), || {
+            return apply_function(&procedure, &to_list(args), context);
+        });
+    } else if let Some(native) = context.native_fns.get(f).cloned() {
+        log_trace!("apply host function {} ({} args)", f, args.len());
+        let res = in_call_span(f, args.len(), || {
+            return call_native(&native, args);
+        })?;
+        context.stats.conses += new_cells(&res, &TypeList::Nil);
+        return Ok(res);
+    }
+    match context.builtins.get(f).copied() {
+        Some((Builtin::Fn(builtin), index)) => {
+            context.count_builtin_call(index);
+            check_arity(index, args.len())?;
+            log_trace!("apply builtin {} ({} args)", f, args.len());
+            let args = to_list(args);
+            let res = in_call_span(f, args.len() as usize, || {
+                return builtin(&args);
+            })?;
+            context.stats.conses += new_cells(&res, &args);
+            return Ok(res);
+        }
+        Some((Builtin::Context(builtin), index)) => {
+            context.count_builtin_call(index);
+            check_arity(index, args.len())?;
+            log_trace!("apply builtin {} ({} args)", f, args.len());
+            let args = to_list(args);
+            let res = in_call_span(f, args.len() as usize, || {
+                return builtin(&args, context);
+            })?;
+            context.stats.conses += new_cells(&res, &args);
+            return Ok(res);
+        }
+        _ => {
+            return Err(context.function_not_found(f).into());
+        }
+    }
+}
+
+// BUILTINS で index の位置にある組み込み関数に、actual 個の引数を渡せるか調べる
+fn check_arity(index: usize, actual: usize) -> Result<(), EvalError> {
+    let (name, _, (min, max)) = BUILTINS[index];
+    if actual < min || max.is_some_and(|max| actual > max) {
+        return Err(EvalError::ArityMismatch {
+            name: String::from(name),
+            min,
+            max,
+            actual,
+        });
+    }
+    return Ok(());
+}
+
+// 組み込み関数やホスト側の関数が返した値 res のうち、その呼び出しで新しく作ったリストのセルの数。
+// 他から参照されていないセルと、引数のリスト args のセル（list などはこれをそのまま返す）を新しく作ったとみなし、
+// 先頭から数えて、他と共有しているセルに着いたらやめる
+fn new_cells(res: &Type, args: &TypeList) -> u64 {
+    let mut cell = match res {
+        Type::TypeList(l) => l,
+        _ => {
+            return 0;
+        }
+    };
+    let mut arg_cells: Vec<*const TypeList> = Vec::new();
+    let mut rest = args;
+    while let TypeList::Cons(_, tail) = rest {
+        arg_cells.push(Rc::as_ptr(tail));
+        rest = tail;
+    }
+    arg_cells.sort_unstable();
+    let mut res = 0;
+    while let TypeList::Cons(_, tail) = &**cell {
+        if Rc::strong_count(cell) > 1 && arg_cells.binary_search(&Rc::as_ptr(cell)).is_err() {
+            break;
+        }
+        res += 1;
+        cell = tail;
+    }
+    return res;
+}
+
+// tracing feature が有効なら、関数 name の適用 call を、"call" という名前の span の中で行う。
+// span 名は静的な文字列である必要があるので、関数名は引数の数と共に function / arity フィールドに持つ
+#[cfg(feature = "tracing")]
+fn in_call_span<T>(name: &str, arity: usize, call: impl FnOnce() -> T) -> T {
+    let span = tracing::trace_span!("call", function = name, arity);
+    let _entered = span.enter();
+    return call();
+}
+
+#[cfg(not(feature = "tracing"))]
+fn in_call_span<T>(_: &str, _: usize, call: impl FnOnce() -> T) -> T {
+    return call();
+}
+
+// 関数名 f の関数を条件として呼び出し、結果が真（Type::is_truthy）なら true を返す
+fn apply_predicate(f: &str, args: &[Type], context: &mut Context) -> Result<bool, EvalOutcome> {
+    return Ok(apply_named(f, args, context)?.is_truthy());
+}
+
+// (sort list) もしくは (sort list f) の形式で、list の要素を並べ替えたリストを返す。
+// f は 2 つの要素を受け取り、1 つ目を 2 つ目より前に置くべきなら真を返す関数（名前か Function）で、省略した場合は lt を使う。
+// 並べ替えは安定で、どちらを前に置くべきでもない要素同士は元の順序を保つ
+fn sort(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let elements: Vec<Type> = match args.head().unwrap() {
+        Type::TypeList(elements) => elements.iter().cloned().collect(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+    let f: Rc<str> = match args.tail().head() {
+        None => Rc::from("lt"),
+        Some(f) => Rc::from(f.as_function_name().ok_or(EvalError::TypeMismatch)?),
+    };
+    let sorted = merge_sort(elements, &mut |a, b| {
+        return apply_predicate(&f, &[a.clone(), b.clone()], context);
+    })?;
+    let res = sorted
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Ok(Type::TypeList(Rc::new(res)));
+}
+
+// less を比較に使う安定なマージソート。
+// less が全順序でなくても、各要素をちょうど 1 回ずつ含む結果を返す
+fn merge_sort(
+    mut v: Vec<Type>,
+    less: &mut dyn FnMut(&Type, &Type) -> Result<bool, EvalOutcome>,
+) -> Result<Vec<Type>, EvalOutcome> {
+    if v.len() <= 1 {
+        return Ok(v);
+    }
+    let right = v.split_off(v.len() / 2);
+    let left = merge_sort(v, less)?;
+    let right = merge_sort(right, less)?;
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // 右の要素が前に置くべきものである場合だけ、右から取り出す
+        if less(b, a)? {
+            res.push(right.next().unwrap());
+        } else {
+            res.push(left.next().unwrap());
+        }
+    }
+    res.extend(left);
+    res.extend(right);
+    return Ok(res);
+}
+
+// (member x list) の形式で、list の中で最初に x と equal で等しい要素から始まる部分リストを返す。
+// 見つからない場合は空リストを返す
+fn member(args: &TypeList) -> Result<Type, EvalError> {
+    let x = args.head().unwrap();
+    let list = match args.tail().head().unwrap() {
+        Type::TypeList(list) => list,
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    };
+    let mut rest: &TypeList = list;
+    while let Some(e) = rest.head() {
+        if e.deep_eq(x) {
+            return Ok(Type::TypeList(Rc::new(rest.clone())));
+        }
+        rest = rest.tail();
+    }
+    return Ok(Type::TypeList(Rc::new(TypeList::new())));
+}
+
+// (find f list) などの、関数（名前か Function）と探索するリストを受け取る組み込み関数の評価済みの引数を取り出す
+fn predicate_and_list(args: &TypeList) -> Result<(Rc<str>, Rc<TypeList>), EvalError> {
+    match (
+        args.head().unwrap().as_function_name(),
+        args.tail().head().unwrap(),
+    ) {
+        (Some(f), Type::TypeList(list)) => {
+            return Ok((Rc::from(f), list.clone()));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch);
+        }
+    }
+}
+
+// (find f list) の形式で、list の要素のうち、関数名 f の関数が 1 を返す最初の要素を返す。
+// 見つからない場合は空リストを返す
+fn find(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (f, list) = predicate_and_list(args)?;
+    for e in list.iter() {
+        if apply_predicate(&f, core::slice::from_ref(e), context)? {
+            return Ok(e.clone());
+        }
+    }
+    return Ok(Type::TypeList(Rc::new(TypeList::new())));
+}
+
+// (position f list) の形式で、list の要素のうち、関数名 f の関数が 1 を返す最初の要素の添字を返す。
+// 添字は 0 から数える。見つからない場合は空リストを返す
+fn position(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (f, list) = predicate_and_list(args)?;
+    for (i, e) in list.iter().enumerate() {
+        if apply_predicate(&f, core::slice::from_ref(e), context)? {
+            return Ok(Type::Int(i as Int));
+        }
+    }
+    return Ok(Type::TypeList(Rc::new(TypeList::new())));
+}
+
+// (count f list) の形式で、list の要素のうち、関数名 f の関数が 1 を返す要素の数を返す
+fn count(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let (f, list) = predicate_and_list(args)?;
+    let mut n = 0;
+    for e in list.iter() {
+        if apply_predicate(&f, core::slice::from_ref(e), context)? {
+            n += 1;
+        }
+    }
+    return Ok(Type::Int(n));
+}
+
+// OS とやりとりする組み込み関数が許可されていなければ CapabilityDenied
+fn require_os(context: &Context) -> Result<(), EvalError> {
+    if !context.capabilities.allow_os {
+        return Err(EvalError::CapabilityDenied);
+    }
+    return Ok(());
+}
+
+// (getenv "NAME") の形式で、環境変数の値を Str で返す。
+// 環境変数が無い、もしくは値が UTF-8 でない場合は空リストを返す
+#[cfg(feature = "std")]
+fn getenv(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    require_os(context)?;
+    if let Type::Str(name) = args.head().unwrap() {
+        match std::env::var(&**name) {
+            Ok(val) => {
+                return Ok(Type::Str(Rc::from(val)));
+            }
+            Err(_) => {
+                return Ok(Type::TypeList(Rc::new(TypeList::new())));
+            }
+        }
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+}
+
+// (argv) の形式で、プロセスのコマンドライン引数を、プログラム名を含めて Str のリストで返す
+#[cfg(feature = "std")]
+fn argv(_args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    require_os(context)?;
+    let args: Vec<Type> = std::env::args().map(|a| Type::Str(Rc::from(a))).collect();
+    let list = args
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, t| acc.cons(t));
+    return Ok(Type::TypeList(Rc::new(list)));
+}
+
+// ホスト側の関数を呼び出す。関数内の panic は捕捉して EvalError::NativeFunctionPanicked にし、
+// 1 つの関数の不具合で埋め込み先のプロセスごと落ちないようにする
+#[cfg(feature = "std")]
+fn call_native(f: &NativeFn, args: &[Type]) -> Result<Type, EvalError> {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    match catch_unwind(AssertUnwindSafe(|| f(args))) {
+        Ok(res) => {
+            return res;
+        }
+        Err(payload) => {
+            let message = if let Some(s) = payload.downcast_ref::<&str>() {
+                String::from(*s)
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                String::from("unknown panic")
+            };
+            return Err(EvalError::NativeFunctionPanicked(message));
+        }
+    }
+}
+
+// no_std では panic を捕捉できないため、そのまま呼び出す
+#[cfg(not(feature = "std"))]
+fn call_native(f: &NativeFn, args: &[Type]) -> Result<Type, EvalError> {
+    return f(args);
+}
+
+// (exit) もしくは (exit n) の形式で、評価を終了する。ホストには EvalError::Exit(n) を返す。
+// n を省略した場合は 0 とする。n が i32 に収まらない場合は IntOverflow
+fn exit(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    require_os(context)?;
+    match args.head() {
+        None => {
+            return Err(EvalOutcome::Exit(0));
+        }
+        Some(code @ Type::Int(_)) => {
+            return Err(EvalOutcome::Exit(i32::try_from(code.clone())?));
+        }
+        Some(_) => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+}
+
+// (sh "command") の形式で、シェル（Windows では cmd）でコマンドを実行し、終了するまで待つ。
+// (終了コード "標準出力" "標準エラー出力") というリストを返す。シグナルで終了した場合の終了コードは -1 とする
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn sh(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    if !context.capabilities.allow_process {
+        return Err(EvalError::CapabilityDenied.into());
+    }
+    let command = match args.head().unwrap() {
+        Type::Str(s) => s.clone(),
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    };
+
+    #[cfg(windows)]
+    let output = std::process::Command::new("cmd")
+        .args(["/C", &*command])
+        .output();
+    #[cfg(not(windows))]
+    let output = std::process::Command::new("sh")
+        .args(["-c", &*command])
+        .output();
+    let output = output.map_err(|e| EvalError::IoFailed(format!("{}: {}", command, e)))?;
+
+    let to_str = |bytes: &[u8]| Type::Str(Rc::from(String::from_utf8_lossy(bytes).into_owned()));
+    let list = TypeList::new()
+        .cons(&to_str(&output.stderr))
+        .cons(&to_str(&output.stdout))
+        .cons(&Type::Int(Int::from(output.status.code().unwrap_or(-1))));
+    return Ok(Type::TypeList(Rc::new(list)));
+}
+
+// (http-get "url") の形式で、GET リクエストを送る。戻り値は http_request と同じ
+#[cfg(feature = "http")]
+fn http_get(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return http_request(args, context, "GET");
+}
+
+// (http-post "url" "body") の形式で、POST リクエストを送る。戻り値は http_request と同じ
+#[cfg(feature = "http")]
+fn http_post(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    return http_request(args, context, "POST");
+}
+
+// HTTP リクエストを送り、(ステータス (("名前" "値") ...) "本文") というリストを返す
+#[cfg(feature = "http")]
+fn http_request(args: &TypeList, context: &mut Context, method: &str) -> Result<Type, EvalOutcome> {
+    if !context.capabilities.allow_net {
+        return Err(EvalError::CapabilityDenied.into());
+    }
+    context.check_host_resource("http")?;
+    let mut strs = Vec::new();
+    for a in args {
+        if let Type::Str(s) = a {
+            strs.push(String::from(&**s));
+        } else {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+    let request = HttpRequest {
+        method: String::from(method),
+        url: strs[0].clone(),
+        body: strs.get(1).cloned(),
+    };
+    let response = context.http.borrow_mut().send(&request)?;
+
+    let to_str = |s: &str| Type::Str(Rc::from(s));
+    let headers = response
+        .headers
+        .iter()
+        .rev()
+        .fold(TypeList::new(), |acc, (k, v)| {
+            let pair = TypeList::new().cons(&to_str(v)).cons(&to_str(k));
+            return acc.cons(&Type::TypeList(Rc::new(pair)));
+        });
+    let list = TypeList::new()
+        .cons(&to_str(&response.body))
+        .cons(&Type::TypeList(Rc::new(headers)))
+        .cons(&Type::Int(Int::from(response.status)));
+    return Ok(Type::TypeList(Rc::new(list)));
+}
+
+// FileSystem を読み書きする組み込み関数が許可されていることを確認し、評価済みの引数を Str として取り出す
+fn fs_args(args: &TypeList, context: &mut Context) -> Result<Vec<Rc<str>>, EvalOutcome> {
+    if !context.capabilities.allow_fs {
+        return Err(EvalError::CapabilityDenied.into());
+    }
+    context.check_host_resource("filesystem")?;
+    let mut res = Vec::new();
+    for a in args {
+        if let Type::Str(s) = a {
+            res.push(s.clone());
+        } else {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+    return Ok(res);
+}
+
+// (slurp "path") の形式で、ファイル全体を Str として読み込む
+fn slurp(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let args = fs_args(args, context)?;
+    let contents = context.filesystem.borrow().read(&args[0])?;
+    return Ok(Type::Str(Rc::from(contents)));
+}
+
+// (spit "path" "contents") の形式で、ファイル全体を contents で置き換える。戻り値は Void
+fn spit(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let args = fs_args(args, context)?;
+    context.filesystem.borrow_mut().write(&args[0], &args[1])?;
+    return Ok(Type::Void);
+}
+
+// (file-exists "path") の形式で、ファイルが存在するなら 1 、そうでないなら 0 を返す
+fn file_exists(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let args = fs_args(args, context)?;
+    return Ok(truth(context.filesystem.borrow().exists(&args[0])));
+}
+
+// (print a b ...) の形式で、引数の値を空白で区切って Output に書き込み、改行する。戻り値は Void。
+// 文字列は " で囲まずに、それ以外は Display で表した文字列を書き込む
+fn print(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let mut line = String::new();
+    for (i, t) in args.iter().enumerate() {
+        if i != 0 {
+            line.push(' ');
+        }
+        match t {
+            Type::Str(s) => line.push_str(s),
+            _ => line.push_str(&t.to_string()),
+        }
+    }
+    line.push('\n');
+    context.check_host_resource("output")?;
+    context.output.borrow_mut().write(&line);
+    return Ok(Type::Void);
+}
+
+// (random n) の形式で、0 以上 n 未満の乱数を返す。n は正の Int
+fn random(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match args.head().unwrap() {
+        Type::Int(n) if *n > 0 => {
+            // 乱数は 64 ビットなので、n が大きいと剰余による偏りが生じ、2^64 以上の値は返さない
+            let r = u128::from(context.rng.borrow_mut().next_u64()) % (*n as u128);
+            return Ok(Type::Int(r as Int));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+}
+
+// (random-seed s) の形式で、乱数の種を s に設定する。s を返す
+fn random_seed(args: &TypeList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    match args.head().unwrap() {
+        Type::Int(s) => {
+            context.rng.borrow_mut().seed(*s as u64);
+            return Ok(Type::Int(*s));
+        }
+        _ => {
+            return Err(EvalError::TypeMismatch.into());
+        }
+    }
+}
+
+// (boundp *v*) の形式で、変数 *v* が定義されていれば 1 、そうでないなら 0 を返す。
+// 変数を評価すると未定義の場合にエラーになるので、引数は評価しない
+fn boundp(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    if let Expression::Var(v) = l.head().unwrap() {
+        return Ok(truth(context.is_bound(v)));
+    } else {
+        return Err(EvalError::TypeMismatch.into());
+    }
+}
 
 // (条件 成立 不成立) という３つ組のリストを受け取り、
-// 条件の評価結果が 0以外 である場合、成立の値を評価する
-// 0である場合、不成立の値を評価する
+// 条件の評価結果が真（Type::is_truthy）である場合、成立の値を評価する
+// 偽（0、Void、空のリスト）である場合、不成立の値を評価する
 // なお、この3つの値は、cond に渡す前に評価しないこと
 // 成立か不成立どちらを実行するか、判明してから評価したいのが理由
 //（条件に関しては評価しても問題ないが、一貫性のため、評価しないこととする）
-fn cond<'a>(l: &ExpressionList<'a>, context: &mut Context<'a>) -> Result<Type<'a>, EvalError> {
-    if l.len() != 3 {
-        return Err(EvalError::BadArrity);
+fn cond(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let cond = l.head().unwrap();
+    let ok = l.tail().head().unwrap();
+    let ng = l.tail().tail().head().unwrap();
+
+    if eval_(cond, context)?.is_truthy() {
+        return eval_(ok, context);
+    } else {
+        return eval_(ng, context);
+    }
+}
+
+// (and x ...) の形式で、引数を左から順に評価し、偽の値があればそこで評価をやめてその値を返す。
+// すべて真なら最後の値を返し、引数が無ければ 1 を返す
+fn and(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let mut res = Type::Int(1);
+    for e in l.iter() {
+        res = eval_(e, context)?;
+        if !res.is_truthy() {
+            break;
+        }
+    }
+    return Ok(res);
+}
+
+// (or x ...) の形式で、引数を左から順に評価し、真の値があればそこで評価をやめてその値を返す。
+// すべて偽なら最後の値を返し、引数が無ければ 0 を返す
+fn or(l: &ExpressionList, context: &mut Context) -> Result<Type, EvalOutcome> {
+    let mut res = Type::Int(0);
+    for e in l.iter() {
+        res = eval_(e, context)?;
+        if res.is_truthy() {
+            break;
+        }
+    }
+    return Ok(res);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::*;
+    use std::convert::TryFrom;
+
+    // 組み込み関数 name に actual 個の引数を渡したときのエラー
+    fn arity_error(name: &str, actual: usize) -> EvalError {
+        let (min, max) = builtin_arity(name).unwrap();
+        return EvalError::ArityMismatch {
+            name: String::from(name),
+            min,
+            max,
+            actual,
+        };
+    }
+
+    #[test]
+    fn arithmetic_tests() {
+        // 四則演算の関数呼び出し
+        {
+            let exp = Expression::try_from("(add 1 2)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(3)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+
+        {
+            let exp = Expression::try_from("(sub 1 2)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(-1)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+
+        // 関数をネストできる
+        {
+            let exp = Expression::try_from("(add (add (sub 1 2) 3) 4)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(6)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+
+        // 引数の数が足りない
+        {
+            let exp = Expression::try_from("(add 1)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(_) => assert!(false),
+                Err(e) => assert_eq!(arity_error("add", 1), e),
+            }
+        }
+
+        // atomが先頭要素でない
+        {
+            let exp = Expression::try_from("(1 2)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(_) => assert!(false),
+                Err(e) => assert_eq!(EvalError::EvaluatingNonAtomHeadList, e),
+            }
+        }
+    }
+
+    // src の MAX と MIN を、Int の最大値と最小値に置き換える
+    fn int_src(src: &str) -> String {
+        return src
+            .replace("MAX", &Int::MAX.to_string())
+            .replace("MIN", &Int::MIN.to_string());
+    }
+
+    #[test]
+    fn int_width_tests() {
+        // int128 > int64 > int32 の順に、有効なうち最も広い幅を使う。どれも無効なら 64 ビット
+        #[cfg(all(feature = "int32", not(feature = "int64"), not(feature = "int128")))]
+        let (max, min) = ("2147483647", "-2147483648");
+        #[cfg(all(
+            not(feature = "int128"),
+            any(feature = "int64", not(feature = "int32"))
+        ))]
+        let (max, min) = ("9223372036854775807", "-9223372036854775808");
+        #[cfg(feature = "int128")]
+        let (max, min) = (
+            "170141183460469231731687303715884105727",
+            "-170141183460469231731687303715884105728",
+        );
+        for (src, expected) in [(max, Int::MAX), (min, Int::MIN)].iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(*expected)), "{}", src);
+        }
+        // 範囲外の整数は読み込めない
+        for src in [format!("{}0", max), format!("{}0", min)].iter() {
+            assert_eq!(
+                Expression::try_from(src.as_bytes()),
+                Err(ExpressionConversionError::IntOverflow),
+                "{}",
+                src
+            );
+        }
+        // 10^10 は 32 ビットには収まらない
+        let exp = Expression::try_from("(mul 100000 100000)".as_bytes()).unwrap();
+        #[cfg(not(all(feature = "int32", not(feature = "int64"), not(feature = "int128"))))]
+        assert_eq!(eval(&exp), Ok(Type::Int(10000000000)));
+        #[cfg(all(
+            feature = "int32",
+            not(feature = "int64"),
+            not(feature = "int128"),
+            not(feature = "bignum")
+        ))]
+        assert_eq!(eval(&exp), Err(EvalError::IntOverflow));
+        #[cfg(all(
+            feature = "int32",
+            not(feature = "int64"),
+            not(feature = "int128"),
+            feature = "bignum"
+        ))]
+        assert!(matches!(eval(&exp), Ok(Type::BigInt(_))));
+    }
+
+    #[cfg(not(feature = "bignum"))]
+    #[test]
+    fn overflow_tests() {
+        let cases = [
+            "(add MAX 1)",
+            "(sub MIN 1)",
+            "(mul MAX 2)",
+            "(mul MIN (sub 0 1))",
+            "(div MIN (sub 0 1))",
+            "(sub (div MIN 2) (div MAX 2))",
+        ];
+        for src in cases.iter() {
+            let src = int_src(src);
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::IntOverflow), "{}", src);
+        }
+        // 上限・下限ちょうどの結果は Int のまま
+        let cases = [
+            ("(add (sub MAX 1) 1)", Int::MAX),
+            ("(sub (add MIN 1) 1)", Int::MIN),
+            ("(mul (div MIN 2) 2)", Int::MIN),
+        ];
+        for (src, expected) in cases.iter() {
+            let src = int_src(src);
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(*expected)), "{}", src);
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn bignum_tests() {
+        let big = |s: &str| Type::BigInt(Rc::new(s.parse().unwrap()));
+        let max = num_bigint::BigInt::from(Int::MAX);
+        let min = num_bigint::BigInt::from(Int::MIN);
+        let ratio = |n: num_bigint::BigInt, d: i32| {
+            return Type::BigRatio(Rc::new((n, num_bigint::BigInt::from(d))));
+        };
+        let cases = [
+            ("(add MAX 1)", Type::BigInt(Rc::new(&max + 1))),
+            ("(mul MAX MAX)", Type::BigInt(Rc::new(&max * &max))),
+            (
+                "(progn (defun fact (*n*) (cond (eq *n* 0) 1 (mul *n* (fact (sub *n* 1))))) (fact 40))",
+                big("815915283247897734345611269596115894272000000000"),
+            ),
+            // Int に収まる結果は Int に戻る
+            ("(div (mul MAX MAX) MAX)", Type::Int(Int::MAX)),
+            ("(sub (add MAX 1) 1)", Type::Int(Int::MAX)),
+            ("(gt (mul MAX MAX) 1)", Type::Int(1)),
+            ("(lt (mul MAX MAX) (mul MAX (add MAX 1)))", Type::Int(1)),
+            ("(eq (mul MAX MAX) (mul MAX MAX))", Type::Int(1)),
+            ("(intp (mul MAX MAX))", Type::Int(1)),
+            // BigInt の除算も、割り切れない場合は Ratio になる
+            ("(div (add MAX 1) 2)", Type::Int(Int::MAX / 2 + 1)),
+            (
+                "(div (mul MAX MAX) (mul 2 MAX))",
+                Type::Ratio(Int::MAX, 2),
+            ),
+            (
+                "(floor (div (mul MAX MAX) (mul 2 MAX)))",
+                Type::Int(Int::MAX / 2),
+            ),
+            // Ratio と BigInt を混ぜた演算と比較
+            ("(mul (div 1 2) (add MAX 1))", Type::Int(Int::MAX / 2 + 1)),
+            ("(lt (div 1 2) (add MAX 1))", Type::Int(1)),
+            ("(gt (div MAX 2) (mul MAX MAX))", Type::Int(0)),
+            ("(eq (mul (div 1 2) (mul 2 MAX)) MAX)", Type::Int(1)),
+            // 分子か分母が Int に収まらない分数は BigRatio になる
+            ("(div (mul MAX 4) 3)", ratio(&max * 4, 3)),
+            ("(add (div 1 3) (mul MAX 2))", ratio(&max * 6 + 1, 3)),
+            ("(div (add MAX 1) 3)", ratio(&max + 1, 3)),
+            ("(add (div 1 2) (mul MAX MAX))", ratio(&max * &max * 2 + 1, 2)),
+            (
+                "(div (div 1 2) (mul MAX MAX))",
+                Type::BigRatio(Rc::new((1.into(), &max * &max * 2))),
+            ),
+            ("(mul (div (mul MAX 4) 3) 3)", Type::BigInt(Rc::new(&max * 4))),
+            ("(sub (div (add MAX 1) 3) (div 1 3))", Type::Ratio(Int::MAX, 3)),
+            ("(floor (div (mul MAX 4) 3))", Type::BigInt(Rc::new(&max * 4 / 3))),
+            ("(ceil (div (mul MAX 4) 3))", Type::BigInt(Rc::new(&max * 4 / 3 + 1))),
+            ("(floor (div (mul MIN 4) 3))", Type::BigInt(Rc::new(&min * 4 / 3 - 1))),
+            ("(truncate (div (mul MIN 4) 3))", Type::BigInt(Rc::new(&min * 4 / 3))),
+            ("(lt (div (mul MAX 4) 3) (div (mul MAX 5) 3))", Type::Int(1)),
+            ("(gt (div (mul MAX 4) 3) (mul MAX 2))", Type::Int(0)),
+            ("(lt (div 1 3) (div (mul MAX 4) 3))", Type::Int(1)),
+        ];
+        for (src, expected) in cases.iter() {
+            let src = int_src(src);
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval(&exp), &Ok(expected.clone()), "{}", src);
+        }
+        let errors = [
+            ("(div (mul MAX MAX) 0)", EvalError::DivisionByZero),
+            (
+                "(div (div 1 2) (sub (add MAX 1) (add MAX 1)))",
+                EvalError::DivisionByZero,
+            ),
+        ];
+        for (src, expected) in errors.iter() {
+            let src = int_src(src);
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval(&exp), &Err(expected.clone()), "{}", src);
+        }
+        assert_eq!(
+            big("10000000000000000000000000000000000000000").to_string(),
+            "10000000000000000000000000000000000000000"
+        );
+        assert_eq!(ratio(&max + 1, 3).to_string(), format!("{}/3", &max + 1));
+    }
+
+    #[test]
+    fn ratio_tests() {
+        let cases = [
+            ("(div 1 3)", Type::Ratio(1, 3)),
+            ("(div 6 3)", Type::Int(2)),
+            ("(div 4 6)", Type::Ratio(2, 3)),
+            ("(div 1 (sub 0 2))", Type::Ratio(-1, 2)),
+            ("(add (div 1 3) (div 1 6))", Type::Ratio(1, 2)),
+            ("(add (div 1 3) (div 2 3))", Type::Int(1)),
+            ("(sub 1 (div 1 3))", Type::Ratio(2, 3)),
+            ("(mul (div 2 3) 3)", Type::Int(2)),
+            ("(div (div 1 2) (div 1 4))", Type::Int(2)),
+            ("(lt (div 1 3) (div 1 2))", Type::Int(1)),
+            ("(gt (div 1 3) 0)", Type::Int(1)),
+            ("(eq (div 2 4) (div 1 2))", Type::Int(1)),
+            ("(floor (div 7 2))", Type::Int(3)),
+            ("(ceil (div 7 2))", Type::Int(4)),
+            ("(truncate (div 7 2))", Type::Int(3)),
+            ("(floor (div (sub 0 7) 2))", Type::Int(-4)),
+            ("(ceil (div (sub 0 7) 2))", Type::Int(-3)),
+            ("(truncate (div (sub 0 7) 2))", Type::Int(-3)),
+            ("(floor 5)", Type::Int(5)),
+            ("(intp (div 1 2))", Type::Int(0)),
+        ];
+        for (src, expected) in cases.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval(&exp), &Ok(expected.clone()), "{}", src);
+        }
+        let errors = [
+            ("(div (div 1 2) 0)", EvalError::DivisionByZero),
+            ("(floor a)", EvalError::TypeMismatch),
+            ("(add (div 1 2) a)", EvalError::TypeMismatch),
+        ];
+        for (src, expected) in errors.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval(&exp), &Err(expected.clone()), "{}", src);
+        }
+        // 分母が Int に収まらない（bignum feature が有効なら BigRatio）
+        let exp = Expression::try_from(int_src("(mul (div 1 MAX) (div 1 (sub MAX 1)))").as_bytes())
+            .unwrap();
+        #[cfg(not(feature = "bignum"))]
+        assert_eq!(eval(&exp), Err(EvalError::IntOverflow));
+        #[cfg(feature = "bignum")]
+        assert_eq!(eval(&exp).map(|t| t.type_name()), Ok("BigRatio"));
+        // 分母を払うと Int に収まらない分数も比較できる
+        let cases = [
+            ("(lt (div 1 MAX) (div 1 (sub MAX 1)))", 1),
+            (
+                "(gt (div (sub MAX 1) MAX) (div (sub MAX 2) (sub MAX 1)))",
+                1,
+            ),
+            ("(lt (div MIN (sub MAX 1)) (div (sub MAX 1) MIN))", 1),
+            ("(eq (div MAX 2) (div MAX 2))", 1),
+        ];
+        for (src, expected) in cases.iter() {
+            let src = int_src(src);
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(*expected)), "{}", src);
+        }
+    }
+
+    #[test]
+    fn comparision_operation_tests() {
+        // gt
+        {
+            let exp = Expression::try_from("(gt 3 2)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(1)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        {
+            let exp = Expression::try_from("(gt 2 3)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(0)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+
+        // lt
+        {
+            let exp = Expression::try_from("(lt 3 2)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(0)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        {
+            let exp = Expression::try_from("(lt 2 3)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(1)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+
+        // eq
+        {
+            let exp = Expression::try_from("(eq 3 3)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(1)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        {
+            let exp = Expression::try_from("(eq 2 3)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(0)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn predicate_tests() {
+        let cases = [
+            ("(intp 1)", 1),
+            ("(intp a)", 0),
+            ("(atomp a)", 1),
+            ("(atomp (list a))", 0),
+            ("(listp (list))", 1),
+            ("(listp (list 1 2))", 1),
+            ("(listp 1)", 0),
+            ("(nullp (list))", 1),
+            ("(nullp (list 1))", 0),
+            ("(nullp 0)", 0),
+            ("(boundp *a*)", 0),
+            ("(progn (set *a* 1) (boundp *a*))", 1),
+            ("(let ((*a* 1)) (boundp *a*))", 1),
+        ];
+        for (src, expected) in cases.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(*expected)), "{}", src);
+        }
+
+        {
+            let exp = Expression::try_from("(intp 1 2)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(arity_error("intp", 2)));
+        }
+        {
+            let exp = Expression::try_from("(boundp a)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+    }
+
+    #[test]
+    fn list_tests() {
+        // list
+        {
+            let exp = eval(&Expression::try_from("(list 1 2 3)".as_bytes()).unwrap());
+            assert_eq!(
+                exp,
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Int(1),
+                    Rc::new(TypeList::Cons(
+                        Type::Int(2),
+                        Rc::new(TypeList::Cons(Type::Int(3), Rc::new(TypeList::Nil)))
+                    ))
+                ))))
+            );
+        }
+        {
+            let exp = eval(&Expression::try_from("(list a b c)".as_bytes()).unwrap());
+            assert_eq!(
+                exp,
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Atom("a".into()),
+                    Rc::new(TypeList::Cons(
+                        Type::Atom("b".into()),
+                        Rc::new(TypeList::Cons(
+                            Type::Atom("c".into()),
+                            Rc::new(TypeList::Nil)
+                        ))
+                    ))
+                ))))
+            );
+        }
+
+        // head
+        {
+            let exp =
+                eval(&Expression::try_from("(head (list 10 (list 20) 30))".as_bytes()).unwrap());
+            assert_eq!(exp, Ok(Type::Int(10)));
+        }
+
+        // tail
+        {
+            let exp = eval(&Expression::try_from("(tail (list 1 2 3))".as_bytes()).unwrap());
+            assert_eq!(
+                exp,
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Int(2),
+                    Rc::new(TypeList::Cons(Type::Int(3), Rc::new(TypeList::Nil)))
+                ))))
+            );
+        }
+    }
+
+    #[test]
+    fn cond_tests() {
+        {
+            let exp = Expression::try_from("(cond (eq 3 2) 10 (mul 20 10))".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(200)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        {
+            let exp = Expression::try_from("(cond (eq 3 3) (div 10 2) 20)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(5)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn progn_tests() {
+        {
+            let exp =
+                Expression::try_from("(progn (set *a* 10) (add *a* (add *a* 20)))".as_bytes())
+                    .unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(40)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn while_tests() {
+        {
+            let exp = Expression::try_from("(progn (set *i* 0) (set *a* 0) (while (lt *i* 10) (progn (set *a* (add *i* *a*)) (set *i* (add *i* 1)))) *a*)".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Int(45)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+        // whileは Void を返す
+        {
+            let exp = Expression::try_from("(progn (set *i* 0) (set *a* 0) (while (lt *i* 10) (progn (set *a* (add *i* *a*)) (set *i* (add *i* 1)))))".as_bytes()).unwrap();
+            match eval(&exp) {
+                Ok(Type::Void) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn while_collect_tests() {
+        let cases = vec![
+            (
+                "(progn (define *i* 0) (while (lt *i* 3) (incf *i*) :collect (mul *i* *i*)))",
+                "(1 4 9)",
+            ),
+            ("(while 0 0 :collect 1)", "()"),
+            // continue した繰り返しでは集めず、break した場合はそれまでに集めたリストを返す
+            (
+                "(progn (define *i* 0) (while 1 (progn (incf *i*) (cond (eq *i* 4) (break) (cond (eq *i* 2) (continue) 0))) :collect *i*))",
+                "(1 3)",
+            ),
+            (
+                "(progn (define *i* 0) (while (lt *i* 3) (incf *i*) :collect *i* 3))",
+                "(1 2 3)",
+            ),
+            ("(while 0 0 :collect)", "BadArrity"),
+            ("(while 0 0 :gather 1)", "TypeMismatch"),
+            ("(while 0 0 :collect 1 2 3)", "while expects 2 to 5 arguments, got 6"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = match eval(&exp) {
+                Ok(v) => format!("{}", v),
+                Err(e) => format!("{}", e),
+            };
+            assert_eq!(res, expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn while_limit_tests() {
+        let cases = vec![
+            // 上限ちょうどの繰り返しは許す
+            (
+                "(progn (define *i* 0) (while (lt *i* 3) (incf *i*) 3) *i*)",
+                Ok(Type::Int(3)),
+            ),
+            (
+                "(progn (define *i* 0) (while (lt *i* 3) (incf *i*) 2) *i*)",
+                Err(EvalError::LoopLimitExceeded),
+            ),
+            (
+                "(progn (define *i* 0) (while 0 (incf *i*) 0) *i*)",
+                Ok(Type::Int(0)),
+            ),
+            ("(while 1 0 (quote a))", Err(EvalError::TypeMismatch)),
+            ("(while 1 0 (sub 0 1))", Err(EvalError::TypeMismatch)),
+            ("(while 1 0 1 2)", Err(EvalError::BadArrity)),
+            (
+                "(progn (define *i* 0) (while 1 (incf *i*) :collect *i* 2))",
+                Err(EvalError::LoopLimitExceeded),
+            ),
+            // try で捕捉できる
+            (
+                "(try (while 1 0 100) (catch *e* *e*))",
+                Ok(Type::Atom("LoopLimitExceeded".into())),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+
+        // Context の上限は while ごとに数え、個別の上限とは小さい方を使う
+        let cases = vec![
+            ("(progn (define *n* 0) (dotimes (*j* 3) (progn (define *i* 0) (while (lt *i* 5) (progn (incf *i*) (incf *n*))))) *n*)", Ok(Type::Int(15))),
+            ("(progn (define *i* 0) (while (lt *i* 6) (incf *i*)) *i*)", Err(EvalError::LoopLimitExceeded)),
+            ("(progn (define *i* 0) (while (lt *i* 6) (incf *i*) 100) *i*)", Err(EvalError::LoopLimitExceeded)),
+            ("(progn (define *i* 0) (while 1 (incf *i*) 2))", Err(EvalError::LoopLimitExceeded)),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            context.set_max_loop_iterations(Some(5));
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn quote_tests() {
+        {
+            let exp = Expression::try_from("(quote (add 1 *a*))".as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Atom("add".into()),
+                    Rc::new(TypeList::Cons(
+                        Type::Int(1),
+                        Rc::new(TypeList::Cons(
+                            Type::Atom("*a*".into()),
+                            Rc::new(TypeList::Nil)
+                        ))
+                    ))
+                ))))
+            );
+        }
+    }
+
+    #[test]
+    fn macro_tests() {
+        // 条件が成立しない場合だけ body を評価する unless マクロ
+        {
+            let exp = Expression::try_from("(progn (defmacro unless (*c* *body*) (list (quote cond) *c* 0 *body*)) (unless (eq 1 2) 10))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(10)));
+        }
+        // 引数は評価されずにマクロに渡される
+        {
+            let exp = Expression::try_from("(progn (set *a* 0) (defmacro unless (*c* *body*) (list (quote cond) *c* 0 *body*)) (unless (eq 1 1) (set *a* 1)) *a*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(0)));
+        }
+        // macroexpand 組み込み関数
+        {
+            let exp = Expression::try_from("(progn (defmacro unless (*c* *body*) (list (quote cond) *c* 0 *body*)) (macroexpand (quote (unless (eq *x* 1) *x*))))".as_bytes()).unwrap();
+            let expected = Expression::try_from("(cond (eq *x* 1) 0 *x*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+        }
+        // 引数の数が合わない
+        {
+            let exp = Expression::try_from(
+                "(progn (defmacro unless (*c* *body*) (list (quote cond) *c* 0 *body*)) (unless 1))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::BadArrity));
+        }
+        // マクロ展開中に束縛した仮引数は、展開後には残らない
+        {
+            let exp = Expression::try_from("(progn (defmacro id (*x*) *x*) (id 1) *x*)".as_bytes())
+                .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::UndefinedVariableReference));
+        }
+    }
+
+    #[test]
+    fn quasiquote_tests() {
+        // unquote と unquote-splicing
+        {
+            let exp = Expression::try_from(
+                "(progn (set *a* 1) (set *b* (list 2 3)) `(x ,*a* ,@*b* (y ,(add *a* 10))))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            let expected = Expression::try_from("(x 1 2 3 (y 11))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+        }
+        // 入れ子になった quasiquote の内側の unquote は評価しない
+        {
+            let exp =
+                Expression::try_from("(progn (set *a* 1) `(x `(y ,*a*) ,*a*))".as_bytes()).unwrap();
+            let expected =
+                Expression::try_from("(x (quasiquote (y (unquote *a*))) 1)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+        }
+        // リストでない値は展開できない
+        {
+            let exp = Expression::try_from("`(x ,@1)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+        // quasiquote を使ったマクロ定義
+        {
+            let exp = Expression::try_from(
+                "(progn (defmacro unless (*c* *body*) `(cond ,*c* 0 ,*body*)) (unless (eq 1 2) 10))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(10)));
+        }
+    }
+
+    #[test]
+    fn macroexpand_tests() {
+        let mut context = Context::new();
+        let def =
+            Expression::try_from("(defmacro twice (*x*) (list (quote add) *x* *x*))".as_bytes())
+                .unwrap();
+        eval_with_context(&def, &mut context).unwrap();
+
+        // ネストしたマクロ呼び出しも展開される。quote の内側は展開しない
+        let exp =
+            Expression::try_from("(list (twice (twice 1)) (quote (twice 2)))".as_bytes()).unwrap();
+        let expected =
+            Expression::try_from("(list (add (add 1 1) (add 1 1)) (quote (twice 2)))".as_bytes())
+                .unwrap();
+        assert_eq!(macroexpand(&exp, &mut context), Ok(expected));
+    }
+
+    #[test]
+    fn defun_tests() {
+        {
+            let exp = Expression::try_from(
+                "(progn (defun double (*x*) (mul *x* 2)) (double 21))".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(42)));
+        }
+        // 再帰呼び出し
+        {
+            let exp = Expression::try_from("(progn (defun fact (*n*) (cond (eq *n* 0) 1 (mul *n* (fact (sub *n* 1))))) (fact 10))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(3628800)));
+        }
+        // 引数の数が合わない
+        {
+            let exp = Expression::try_from(
+                "(progn (defun double (*x*) (mul *x* 2)) (double 1 2))".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::BadArrity));
+        }
+        // 仮引数の束縛は、関数呼び出しが終わると元に戻る
+        {
+            let exp = Expression::try_from(
+                "(progn (set *x* 1) (defun double (*x*) (mul *x* 2)) (add (double 10) *x*))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(21)));
+        }
+    }
+
+    #[test]
+    fn control_flow_tests() {
+        // break で while を抜ける
+        {
+            let exp = Expression::try_from("(progn (set *i* 0) (while 1 (progn (cond (eq *i* 5) (break) 0) (set *i* (add *i* 1)))) *i*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(5)));
+        }
+        // continue で次の繰り返しに移る
+        {
+            let exp = Expression::try_from("(progn (set *i* 0) (set *a* 0) (while (lt *i* 10) (progn (set *i* (add *i* 1)) (cond (eq (mul (truncate (div *i* 2)) 2) *i*) (continue) 0) (set *a* (add *a* *i*)))) *a*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(25)));
+        }
+        // 入れ子のループでは、内側のループだけを抜ける
+        {
+            let exp = Expression::try_from("(progn (set *i* 0) (set *n* 0) (while (lt *i* 3) (progn (set *i* (add *i* 1)) (while 1 (progn (set *n* (add *n* 1)) (break))))) *n*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(3)));
+        }
+        // return で関数を途中で抜ける
+        {
+            let exp = Expression::try_from("(progn (defun f (*x*) (progn (cond (gt *x* 10) (return 10) 0) *x*)) (list (f 5) (f 20)))".as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Int(5),
+                    Rc::new(TypeList::Cons(Type::Int(10), Rc::new(TypeList::Nil)))
+                ))))
+            );
+        }
+        // ループ内で呼ばれた関数の return は、ループを抜けずに関数だけを抜ける
+        {
+            let exp = Expression::try_from("(progn (defun f () (while 1 (return 7))) (set *i* 0) (while (lt *i* 3) (set *i* (add *i* (f)))) *i*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(7)));
+        }
+        // ループや関数の外で使うとエラー
+        {
+            let exp = Expression::try_from("(break)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::BreakOutsideLoop));
+        }
+        {
+            let exp = Expression::try_from("(progn (continue))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::ContinueOutsideLoop));
+        }
+        {
+            let exp = Expression::try_from("(return 1)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::ReturnOutsideFunction));
+        }
+        // 関数本体から break でループを抜けることはできない
+        {
+            let exp = Expression::try_from("(progn (defun f () (break)) (while 1 (f)))".as_bytes())
+                .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::BreakOutsideLoop));
+        }
+    }
+
+    #[test]
+    fn let_tests() {
+        {
+            let exp =
+                Expression::try_from("(let ((*a* 1) (*b* 2)) (add *a* *b*))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(3)));
+        }
+        // 束縛する値は、束縛前の環境で評価される。let を抜けると束縛は元に戻る
+        {
+            let exp = Expression::try_from(
+                "(progn (set *a* 10) (list (let ((*a* 1) (*b* *a*)) (add *a* *b*)) *a*))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(TypeList::Cons(
+                    Type::Int(11),
+                    Rc::new(TypeList::Cons(Type::Int(10), Rc::new(TypeList::Nil)))
+                ))))
+            );
+        }
+        {
+            let exp = Expression::try_from("(progn (let ((*a* 1)) *a*) *a*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::UndefinedVariableReference));
+        }
+    }
+
+    #[test]
+    fn dotimes_dolist_tests() {
+        {
+            let exp = Expression::try_from(
+                "(progn (set *a* 0) (dotimes (*i* 10) (set *a* (add *a* *i*))) *a*)".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(45)));
+        }
+        // ループ変数はループを抜けると元に戻る。break も使える
+        {
+            let exp = Expression::try_from("(progn (set *i* 100) (set *a* 0) (dotimes (*i* 10) (cond (eq *i* 3) (break) 0) (set *a* (add *a* 1))) (add *a* *i*))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(103)));
+        }
+        {
+            let exp = Expression::try_from("(progn (set *a* 0) (dolist (*x* (list 1 2 3)) (cond (eq *x* 2) (continue) 0) (set *a* (add *a* *x*))) *a*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(4)));
+        }
+        {
+            let exp = Expression::try_from("(dolist (*x* 1) *x*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+        {
+            let exp = Expression::try_from("(dotimes (*i*) *i*)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::BadArrity));
+        }
+    }
+
+    #[test]
+    fn try_tests() {
+        // 0 除算をエラーとして捕捉する
+        {
+            let exp = Expression::try_from("(div 1 0)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::DivisionByZero));
+        }
+        {
+            let exp =
+                Expression::try_from("(try (div 1 0) (catch *e* (list failed *e*)))".as_bytes())
+                    .unwrap();
+            let expected = Expression::try_from("(failed DivisionByZero)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+        }
+        // エラーが発生しなければ、body の最後の値を返す
+        {
+            let exp =
+                Expression::try_from("(try (set *a* 1) (add *a* 1) (catch *e* 0))".as_bytes())
+                    .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(2)));
+        }
+        // raise した値を catch で受け取る
+        {
+            let exp = Expression::try_from(
+                "(try (progn (raise (list oops 42)) 0) (catch *e* (head (tail *e*))))".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(42)));
+        }
+        // 捕捉されなかった raise は、値ごと呼び出し元に返る
+        {
+            let exp = Expression::try_from("(raise oops)".as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp),
+                Err(EvalError::Raised(Type::Atom("oops".into())))
+            );
+        }
+        // handler の中で再度 raise すると、外側の try で捕捉される
+        {
+            let exp = Expression::try_from(
+                "(try (try (raise 1) (catch *e* (raise (add *e* 1)))) (catch *e* *e*))".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(2)));
+        }
+        // break / return は捕捉しない
+        {
+            let exp = Expression::try_from(
+                "(progn (set *i* 0) (while 1 (try (break) (catch *e* (set *i* 1)))) *i*)"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(0)));
+        }
+        // catch 節の形式が不正
+        {
+            let exp = Expression::try_from("(try 1 (handle *e* 0))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+    }
+
+    #[test]
+    fn assertion_tests() {
+        {
+            let exp = Expression::try_from(
+                "(progn (assert (eq 1 1)) (assert-eq (list 1 a) (list 1 a)) 10)".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(10)));
+        }
+        {
+            let exp = Expression::try_from("(assert (eq 1 2))".as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp),
+                Err(EvalError::AssertionFailed {
+                    expected: Type::Int(1),
+                    actual: Type::Int(0)
+                })
+            );
+        }
+        {
+            let exp = Expression::try_from("(assert-eq 3 (add 1 1))".as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp),
+                Err(EvalError::AssertionFailed {
+                    expected: Type::Int(3),
+                    actual: Type::Int(2)
+                })
+            );
+        }
+        // 失敗したアサーションは catch できる
+        {
+            let exp =
+                Expression::try_from("(try (assert-eq 3 2) (catch *e* *e*))".as_bytes()).unwrap();
+            let expected = Expression::try_from("(AssertionFailed 3 2)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+        }
+        {
+            // Atom は真
+            let exp = Expression::try_from("(assert a)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Void));
+        }
+    }
+
+    #[test]
+    fn deftest_tests() {
+        let exp = Expression::try_from("(progn (defun double (*x*) (mul *x* 2)) (deftest doubleworks (assert-eq 4 (double 2))) (deftest doublefails (assert-eq 5 (double 2))) (deftest usesreturn (return 0) (assert 0)) (run-tests))".as_bytes()).unwrap();
+        let expected = Expression::try_from(
+            "((doubleworks usesreturn) \
+              ((doublefails \"AssertionFailed { expected: Int(5), actual: Int(4) }\")))"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+
+        // 失敗したテストごとに、エラーの文字列を返す
+        let exp = Expression::try_from(
+            "(progn (deftest t1 (assert 0)) (deftest t2 (raise 1)) (deftest t3 (add 1)) (run-tests))"
+                .as_bytes(),
+        )
+        .unwrap();
+        let expected = Expression::try_from(
+            "(() ((t1 \"AssertionFailed { expected: Int(1), actual: Int(0) }\") \
+                  (t2 \"Raised(Int(1))\") \
+                  (t3 \"add expects 2 arguments, got 1\")))"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+
+        // exit は失敗として扱わず、そのまま抜ける
+        let mut context = Context::new();
+        context.set_capabilities(Capabilities {
+            allow_os: true,
+            ..Capabilities::default()
+        });
+        let exp = Expression::try_from(
+            "(progn (deftest t1 (exit 3)) (deftest t2 (assert 0)) (run-tests))".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Err(EvalError::Exit(3))
+        );
+
+        // 同じ名前で定義し直すと置き換わる
+        let exp = Expression::try_from(
+            "(progn (deftest t1 (assert 0)) (deftest t1 (assert 1)) (run-tests))".as_bytes(),
+        )
+        .unwrap();
+        let expected = Expression::try_from("((t1) ())".as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+    }
+
+    #[test]
+    fn set_tests() {
+        let exp = Expression::try_from("(set *i* 1)".as_bytes()).unwrap();
+        match eval(&exp) {
+            Ok(Type::Int(1)) => assert!(true),
+            _ => assert!(false),
+        }
+
+        let list = |src: &str| eval(&Expression::try_from(src.as_bytes()).unwrap());
+        let cases = vec![
+            // 複数の組は先頭から順にセットする
+            (
+                "(progn (set *a* 1 *b* (add *a* 1)) (list *a* *b*))",
+                list("(list 1 2)"),
+            ),
+            ("(set *a* 1 *b* 2)", Ok(Type::Int(2))),
+            ("(set *a* 1 *b*)", Err(EvalError::BadArrity)),
+            ("(set)", Err(arity_error("set", 0))),
+            ("(set *a* 1 b 2)", Err(EvalError::TypeMismatch)),
+            // incf / decf
+            ("(progn (set *i* 1) (incf *i*) *i*)", Ok(Type::Int(2))),
+            ("(progn (set *i* 1) (incf *i* 10))", Ok(Type::Int(11))),
+            (
+                "(progn (set *i* 10) (decf *i* 5) (decf *i*))",
+                Ok(Type::Int(4)),
+            ),
+            ("(progn (set *i* 1) (decf *i* 2) *i*)", Ok(Type::Int(-1))),
+            (
+                "(progn (set *i* 1) (incf *i* (div 1 2)))",
+                Ok(Type::Ratio(3, 2)),
+            ),
+            (
+                "(incf *undefined*)",
+                Err(EvalError::UndefinedVariableReference),
+            ),
+            (
+                "(progn (set *i* a) (incf *i*))",
+                Err(EvalError::TypeMismatch),
+            ),
+            ("(incf i)", Err(EvalError::TypeMismatch)),
+            ("(incf)", Err(arity_error("incf", 0))),
+            (
+                "(progn (set *i* 1) (incf *i* 1 2))",
+                Err(arity_error("incf", 3)),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn define_tests() {
+        // define は現在のスコープに変数を作るので、外側の変数は書き換わらない
+        {
+            let exp = Expression::try_from(
+                "(progn (define *a* 1) (let ((*b* 0)) (define *a* 2) (set *b* *a*)) *a*)"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(1)));
+        }
+        // set は変数が定義されているスコープの値を書き換える
+        {
+            let exp = Expression::try_from(
+                "(progn (define *a* 1) (let ((*b* 0)) (set *a* 2)) *a*)".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(2)));
+        }
+        // 関数内の define は、関数を抜けると消える
+        {
+            let exp =
+                Expression::try_from("(progn (defun f () (define *a* 1)) (f) *a*)".as_bytes())
+                    .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::UndefinedVariableReference));
+        }
+        // 未定義の変数への set は、デフォルトではグローバルなスコープに変数を作る
+        {
+            let exp = Expression::try_from("(progn (defun f () (set *a* 1)) (f) *a*)".as_bytes())
+                .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(1)));
+        }
+        // strict_set が有効な場合はエラー
+        {
+            let mut context = Context::new();
+            context.set_strict_set(true);
+            let exp = Expression::try_from("(set *a* 1)".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(EvalError::AssignToUndefinedVariable)
+            );
+            let exp =
+                Expression::try_from("(progn (define *a* 1) (set *a* 2))".as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(2)));
+        }
+    }
+
+    // テスト用の、メモリ上のファイルからソースを読み込む SourceLoader
+    struct MemoryLoader(std::collections::HashMap<&'static str, &'static str>);
+
+    impl crate::loader::SourceLoader for MemoryLoader {
+        fn load(&self, path: &str) -> Result<String, EvalError> {
+            match self.0.get(path) {
+                Some(src) => {
+                    return Ok(src.to_string());
+                }
+                None => {
+                    return Err(EvalError::LoadFailed(path.to_string()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_tests() {
+        let mut files = std::collections::HashMap::new();
+        files.insert(
+            "lib.lisp",
+            "(define *base* 10)\n(defun add-base (*x*) (add *x* *base*))\n",
+        );
+        files.insert("main.lisp", "(load \"lib.lisp\")\n(add-base 5)\n");
+        files.insert("empty.lisp", "\n");
+        files.insert("broken.lisp", "(add 1");
+
+        // 読み込んだ定義は、呼び出し元の Context に残る
+        {
+            let mut context = Context::new();
+            context.set_loader(Box::new(MemoryLoader(files.clone())));
+            assert_eq!(context.eval_file("main.lisp"), Ok(Type::Int(15)));
+            let exp = Expression::try_from("(add-base *base*)".as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(20)));
+        }
+        {
+            let mut context = Context::new();
+            context.set_loader(Box::new(MemoryLoader(files.clone())));
+            let exp = Expression::try_from("(progn (load \"lib.lisp\") (add-base 1))".as_bytes())
+                .unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(11)));
+            assert_eq!(context.eval_file("empty.lisp"), Ok(Type::Void));
+            assert_eq!(
+                context.eval_file("broken.lisp"),
+                Err(EvalError::ParseFailed(
+                    ExpressionConversionError::UnexpectedEof
+                ))
+            );
+            assert_eq!(
+                context.eval_file("missing.lisp"),
+                Err(EvalError::LoadFailed("missing.lisp".to_string()))
+            );
+            let exp = Expression::try_from("(load 1)".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(EvalError::TypeMismatch)
+            );
+        }
+        // DisabledLoader では読み込みが全て失敗する
+        {
+            let mut context = Context::new();
+            context.set_loader(Box::new(crate::loader::DisabledLoader));
+            let exp = Expression::try_from("(load \"lib.lisp\")".as_bytes()).unwrap();
+            match eval_with_context(&exp, &mut context) {
+                Err(EvalError::LoadFailed(_)) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn module_tests() {
+        // モジュール内の定義は、修飾した名前で外から参照する
+        {
+            let exp = Expression::try_from(
+                "(progn (module math (define *base* 10) (defun gcd (*a* *b*) (cond (eq *b* 0) *a* (gcd *b* (sub *a* (mul (truncate (div *a* *b*)) *b*)))))) (list (math:gcd 12 18) *math:base*))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(
+                    TypeList::new().cons(&Type::Int(10)).cons(&Type::Int(6))
+                )))
+            );
+        }
+        // 修飾しない名前はモジュールの外からは見えない
+        {
+            let exp = Expression::try_from("(progn (module math (defun f () 1)) (f))".as_bytes())
+                .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::NotFoundFunctionName));
+            let exp = Expression::try_from("(progn (module math (define *a* 1)) *a*)".as_bytes())
+                .unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::UndefinedVariableReference));
+        }
+        // 別のモジュールで同じ名前を定義しても衝突しない
+        {
+            let exp = Expression::try_from(
+                "(progn (module a (define *x* 1) (defun get () *x*)) (module b (define *x* 2) (defun get () *x*)) (list (a:get) (b:get)))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(
+                    TypeList::new().cons(&Type::Int(2)).cons(&Type::Int(1))
+                )))
+            );
+        }
+        // モジュール内からは、モジュールの外で定義した名前も参照できる。set はモジュールの変数を書き換える
+        {
+            let exp = Expression::try_from(
+                "(progn (define *g* 5) (defun twice (*x*) (mul *x* 2)) (module m (define *c* 0) (defun bump () (set *c* (add *c* (twice *g*))))) (m:bump) (m:bump) *m:c*)"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(20)));
+        }
+        // マクロもモジュールで修飾する
+        {
+            let exp = Expression::try_from(
+                "(progn (module m (defmacro twice (*x*) (list (quote add) *x* *x*))) (m:twice 4))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(8)));
+        }
+        {
+            let exp = Expression::try_from("(module a:b 1)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+    }
+
+    #[test]
+    fn stdlib_tests() {
+        let cases = [
+            ("(not 0)", "1"),
+            ("(not 5)", "0"),
+            ("(abs (sub 0 3))", "3"),
+            ("(abs 4)", "4"),
+            ("(max 1 2)", "2"),
+            ("(min 1 2)", "1"),
+            ("(second (list 1 2 3))", "2"),
+            ("(last (list 1 2 3))", "3"),
+            ("(range 0 3)", "(0 1 2)"),
+            ("(range 3 3)", "()"),
+        ];
+        for (src, expected) in cases.iter() {
+            let mut context = Context::new_with_stdlib();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let expected = Expression::try_from(expected.as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Ok(expression_to_type(&expected)),
+                "{}",
+                src
+            );
+        }
+        // 空リストの last はエラー
+        {
+            let mut context = Context::new_with_stdlib();
+            let exp = Expression::try_from("(last (list))".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(EvalError::DoHeadForNil)
+            );
+        }
+        // Context::new では標準ライブラリは読み込まれない
+        {
+            let exp = Expression::try_from("(not 0)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::NotFoundFunctionName));
+        }
+    }
+
+    // テスト用の、通知された内容を文字列として記録する EvalObserver
+    struct RecordingObserver(Rc<std::cell::RefCell<Vec<String>>>);
+
+    impl EvalObserver for RecordingObserver {
+        fn enter(&mut self, exp: &Expression, depth: usize) {
+            self.0.borrow_mut().push(format!("{} > {}", depth, exp));
+        }
+        fn exit(&mut self, exp: &Expression, depth: usize, result: EvalExit) {
+            let res = match result {
+                EvalExit::Value(v) => format!("{:?}", v),
+                EvalExit::Error(e) => format!("{:?}", e),
+                EvalExit::Escape => "escape".to_string(),
+            };
+            self.0
+                .borrow_mut()
+                .push(format!("{} < {} = {}", depth, exp, res));
+        }
+    }
+
+    #[test]
+    fn tracer_tests() {
+        {
+            let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut context = Context::new();
+            context.set_tracer(Box::new(RecordingObserver(log.clone())));
+            let exp = Expression::try_from("(add 1 (mul 2 3))".as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(7)));
+            assert_eq!(
+                *log.borrow(),
+                vec![
+                    "0 > (add 1 (mul 2 3))",
+                    "1 > 1",
+                    "1 < 1 = Int(1)",
+                    "1 > (mul 2 3)",
+                    "2 > 2",
+                    "2 < 2 = Int(2)",
+                    "2 > 3",
+                    "2 < 3 = Int(3)",
+                    "1 < (mul 2 3) = Int(6)",
+                    "0 < (add 1 (mul 2 3)) = Int(7)",
+                ]
+            );
+        }
+        // エラー及び脱出も通知される
+        {
+            let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut context = Context::new();
+            context.set_tracer(Box::new(RecordingObserver(log.clone())));
+            let exp = Expression::try_from("(defun f () (return 1) (div 1 0))".as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            log.borrow_mut().clear();
+            let exp = Expression::try_from("(list (f) (div 1 0))".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(EvalError::DivisionByZero)
+            );
+            assert!(log
+                .borrow()
+                .contains(&"2 < (return 1) = escape".to_string()));
+            assert!(log
+                .borrow()
+                .contains(&"1 < (div 1 0) = DivisionByZero".to_string()));
+            assert_eq!(
+                log.borrow().last(),
+                Some(&"0 < (list (f) (div 1 0)) = DivisionByZero".to_string())
+            );
+        }
+        // 取り外した後は通知されない
+        {
+            let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut context = Context::new();
+            context.set_tracer(Box::new(RecordingObserver(log.clone())));
+            assert!(context.remove_tracer().is_some());
+            let exp = Expression::try_from("(add 1 2)".as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(3)));
+            assert!(log.borrow().is_empty());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn profiler_tests() {
+        // 有効にするまでは計測しない
+        {
+            let context = Context::new();
+            assert!(context.profile_report().is_none());
+        }
+        {
+            let mut context = Context::new();
+            context.enable_profiler();
+            let exp = Expression::try_from(
+                "(progn (defun sq (*x*) (mul *x* *x*)) (dotimes (*i* 3) (sq *i*)) (sq (add 1 1)))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(4)));
+            let report = context.profile_report().unwrap();
+            assert_eq!(report.get("sq").unwrap().calls, 4);
+            assert_eq!(report.get("mul").unwrap().calls, 4);
+            assert_eq!(report.get("add").unwrap().calls, 1);
+            assert_eq!(report.get("defun").unwrap().calls, 1);
+            assert_eq!(report.get("progn").unwrap().calls, 1);
+            assert!(report.get("sub").is_none());
+            // 呼び出し元の累積時間は、内側で呼び出した関数の時間を含む
+            assert!(report.get("progn").unwrap().total >= report.get("sq").unwrap().total);
+            assert_eq!(report.entries[0].0, Rc::from("progn"));
+            assert!(report.to_string().contains("sq"));
+        }
+        // エラーで抜けた呼び出しも計測する
+        {
+            let mut context = Context::new();
+            context.enable_profiler();
+            let exp = Expression::try_from("(add 1 (div 1 0))".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(EvalError::DivisionByZero)
+            );
+            let report = context.profile_report().unwrap();
+            assert_eq!(report.get("div").unwrap().calls, 1);
+            assert_eq!(report.get("add").unwrap().calls, 1);
+            context.disable_profiler();
+            assert!(context.profile_report().is_none());
+        }
+    }
+
+    #[test]
+    fn snapshot_tests() {
+        let mut context = Context::new();
+        let exp = Expression::try_from(
+            "(progn (define *a* 1) (defun f (*x*) (add *x* *a*)) (defmacro m (*x*) *x*) (deftest t1 (assert 1)))"
+                .as_bytes(),
+        )
+        .unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        let snapshot = context.snapshot();
+        assert_eq!(context.snapshot(), snapshot);
+
+        // スナップショット以降の変更は、restore で元に戻る
+        let exp = Expression::try_from(
+            "(progn (set *a* 10) (define *b* 2) (defun f (*x*) 0) (defun g () 0) (defmacro m (*x*) 0))"
+                .as_bytes(),
+        )
+        .unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        context.restore(&snapshot);
+        let cases = [
+            ("(f 1)", Ok(Type::Int(2))),
+            ("(m 5)", Ok(Type::Int(5))),
+            ("*b*", Err(EvalError::UndefinedVariableReference)),
+            ("(g)", Err(EvalError::NotFoundFunctionName)),
+        ];
+        for (src, expected) in cases.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval_with_context(&exp, &mut context), expected, "{}", src);
+        }
+        let exp = Expression::try_from("(run-tests)".as_bytes()).unwrap();
+        let expected = Expression::try_from("((t1) ())".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Ok(expression_to_type(&expected))
+        );
+
+        // 別の Context にも復元できる
+        let mut other = Context::new();
+        other.restore(&snapshot);
+        let exp = Expression::try_from("(f 1)".as_bytes()).unwrap();
+        assert_eq!(eval_with_context(&exp, &mut other), Ok(Type::Int(2)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_serde_tests() {
+        let mut context = Context::new();
+        let exp = Expression::try_from(
+            "(progn (define *s* \"str\") (define *l* (list 1 (quote a))) (defun f (*x*) (add *x* 1)))"
+                .as_bytes(),
+        )
+        .unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        let bytes = context.snapshot().to_bytes().unwrap();
+        let snapshot = ContextSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot, context.snapshot());
+        // 同じ状態からは同じバイト列が得られる
+        assert_eq!(snapshot.to_bytes().unwrap(), bytes);
+
+        let mut restored = Context::new();
+        restored.restore(&snapshot);
+        let exp = Expression::try_from("(list *s* *l* (f 1))".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut restored),
+            eval_with_context(&exp, &mut context)
+        );
+        assert!(ContextSnapshot::from_bytes(b"broken").is_err());
+    }
+
+    #[test]
+    fn string_builtin_tests() {
+        let cases = [
+            ("(str-split \"a,b,,c\" \",\")", "(\"a\" \"b\" \"\" \"c\")"),
+            ("(str-split \"  a b\n c \")", "(\"a\" \"b\" \"c\")"),
+            ("(str-split \"\" \",\")", "(\"\")"),
+            ("(str-join (list \"a\" \"b\" \"c\") \", \")", "\"a, b, c\""),
+            ("(str-join (list \"a\" \"b\"))", "\"ab\""),
+            ("(str-join (list) \",\")", "\"\""),
+            ("(str-join (str-split \"x y\") \"-\")", "\"x-y\""),
+            ("(str-upper \"Hello, world\")", "\"HELLO, WORLD\""),
+            ("(str-upper \"りんご\")", "\"りんご\""),
+            ("(str-lower \"Hello, WORLD\")", "\"hello, world\""),
+            ("(str-trim \"  a b \t\")", "\"a b\""),
+            ("(str-trim \" \")", "\"\""),
+            ("(str-contains \"hello\" \"ell\")", "1"),
+            ("(str-contains \"hello\" \"\")", "1"),
+            ("(str-contains \"hello\" \"Hell\")", "0"),
+            ("(to-string 42)", "\"42\""),
+            ("(to-string \"s\")", "\"s\""),
+            ("(to-string a)", "\"a\""),
+            ("(to-string (list 1 (list 2)))", "\"(1 (2))\""),
+            ("(parse-int \"42\")", "42"),
+            ("(eq (parse-int \" -7 \") (sub 0 7))", "1"),
+            ("(parse-int \"+3\")", "3"),
+            ("(parse-int (to-string 123))", "123"),
+            ("(add (parse-int \"1\") 1)", "2"),
+            // 読めない場合のエラーは try で捕捉できる
+            (
+                "(try (parse-int \"x\") (catch *e* *e*))",
+                "(InvalidNumber \"x\")",
+            ),
+        ];
+        for (src, expected) in cases.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let expected = Expression::try_from(expected.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(expression_to_type(&expected)), "{}", src);
+        }
+
+        let errors = [
+            ("(str-split \"abc\" \"\")", EvalError::TypeMismatch),
+            ("(str-split 1 \",\")", EvalError::TypeMismatch),
+            ("(str-join (list \"a\" 1) \",\")", EvalError::TypeMismatch),
+            ("(str-join \"ab\" \",\")", EvalError::TypeMismatch),
+            ("(str-upper a)", EvalError::TypeMismatch),
+            ("(str-lower 1)", EvalError::TypeMismatch),
+            ("(str-trim (list))", EvalError::TypeMismatch),
+            ("(str-contains \"hello\")", arity_error("str-contains", 1)),
+            ("(to-string)", arity_error("to-string", 0)),
+            ("(parse-int 1)", EvalError::TypeMismatch),
+            (
+                "(parse-int \"12a\")",
+                EvalError::InvalidNumber("12a".into()),
+            ),
+            ("(parse-int \"\")", EvalError::InvalidNumber("".into())),
+            ("(parse-int \"-\")", EvalError::InvalidNumber("-".into())),
+        ];
+        for (src, expected) in errors.iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(&eval(&exp), &Err(expected.clone()), "{}", src);
+        }
+
+        // Int に収まらない整数は、bignum feature が有効なら BigInt 、無効なら IntOverflow
+        let exp = Expression::try_from(
+            "(parse-int \"100000000000000000000000000000000000000000\")".as_bytes(),
+        )
+        .unwrap();
+        #[cfg(not(feature = "bignum"))]
+        assert_eq!(eval(&exp), Err(EvalError::IntOverflow));
+        #[cfg(feature = "bignum")]
+        assert_eq!(
+            eval(&exp),
+            Ok(Type::BigInt(Rc::new(
+                "100000000000000000000000000000000000000000"
+                    .parse()
+                    .unwrap()
+            )))
+        );
+    }
+
+    #[test]
+    fn gensym_tests() {
+        {
+            let mut context = Context::new();
+            let a = context.gensym();
+            let b = context.gensym();
+            assert_ne!(a, b);
+            // ユーザが書けるシンボルとは衝突しない
+            assert!(Expression::try_from(a.as_bytes()).is_err());
+        }
+        {
+            let exp = Expression::try_from("(eq (gensym) (gensym))".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Int(0)));
+            let exp = Expression::try_from("(gensym \"tmp\")".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Ok(Type::Atom("*#tmp1*".into())));
+            let exp = Expression::try_from("(gensym 1)".as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch));
+        }
+        // マクロの展開先で、呼び出し側の変数と衝突しない一時変数として使う
+        {
+            let exp = Expression::try_from(
+                "(progn (defmacro swap (*a* *b*) (let ((*tmp* (gensym))) `(let ((,*tmp* ,*a*)) (set ,*a* ,*b*) (set ,*b* ,*tmp*)))) (define *x* 1) (define *tmp* 2) (swap *x* *tmp*) (list *x* *tmp*))"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                eval(&exp),
+                Ok(Type::TypeList(Rc::new(
+                    TypeList::new().cons(&Type::Int(1)).cons(&Type::Int(2))
+                )))
+            );
+        }
+    }
+
+    // テスト用の、決まった値を順番に返す RandomSource
+    struct FixedRandom(Vec<u64>, usize);
+
+    impl RandomSource for FixedRandom {
+        fn next_u64(&mut self) -> u64 {
+            let r = self.0[self.1 % self.0.len()];
+            self.1 += 1;
+            return r;
+        }
+        fn seed(&mut self, seed: u64) {
+            self.1 = seed as usize;
+        }
+    }
+
+    #[test]
+    fn random_tests() {
+        let draw =
+            "(progn (define *l* (list)) (dotimes (*i* 20) (set *l* `(,(random 10) ,@*l*))) *l*)";
+        // 同じ種からは同じ乱数列になる
+        {
+            let exp = Expression::try_from(format!("(progn (random-seed 7) {})", draw).as_bytes())
+                .unwrap();
+            let first = eval(&exp).unwrap();
+            assert_eq!(eval(&exp), Ok(first.clone()));
+            if let Type::TypeList(l) = &first {
+                for e in l.iter() {
+                    match e {
+                        Type::Int(i) => assert!((0..10).contains(i)),
+                        _ => assert!(false),
+                    }
+                }
+            } else {
+                assert!(false);
+            }
+            let exp = Expression::try_from(format!("(progn (random-seed 8) {})", draw).as_bytes())
+                .unwrap();
+            assert_ne!(eval(&exp), Ok(first));
+        }
+        // 乱数生成器を差し替えられる
+        {
+            let mut context = Context::new();
+            context.set_random_source(Box::new(FixedRandom(vec![3, 14, 15], 0)));
+            let exp = Expression::try_from("(list (random 10) (random 10) (random 10))".as_bytes())
+                .unwrap();
+            let expected = Expression::try_from("(3 4 5)".as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Ok(expression_to_type(&expected))
+            );
+            let exp =
+                Expression::try_from("(progn (random-seed 1) (random 100))".as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(14)));
+        }
+        for src in ["(random 0)", "(random a)", "(random-seed a)"].iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::TypeMismatch), "{}", src);
+        }
+        let exp = Expression::try_from("(random)".as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Err(arity_error("random", 0)));
+    }
+
+    #[test]
+    fn vector_tests() {
+        let vector = |v: Vec<Type>| Type::Vector(Rc::new(v));
+        let cases = vec![
+            (
+                "(vector 1 a (list 2))",
+                Ok(vector(vec![
+                    Type::Int(1),
+                    Type::Atom("a".into()),
+                    eval(&Expression::try_from("(list 2)".as_bytes()).unwrap()).unwrap(),
+                ])),
+            ),
+            ("(vector)", Ok(vector(vec![]))),
+            ("(vref (vector 10 20 30) 1)", Ok(Type::Int(20))),
+            ("(vlen (vector 10 20 30))", Ok(Type::Int(3))),
+            ("(vlen (vector))", Ok(Type::Int(0))),
+            (
+                "(vset (vector 10 20 30) 2 a)",
+                Ok(vector(vec![
+                    Type::Int(10),
+                    Type::Int(20),
+                    Type::Atom("a".into()),
+                ])),
+            ),
+            (
+                "(list->vector (list 1 2))",
+                Ok(vector(vec![Type::Int(1), Type::Int(2)])),
+            ),
+            (
+                "(vector->list (list->vector (list 1 2 3)))",
+                eval(&Expression::try_from("(list 1 2 3)".as_bytes()).unwrap()),
+            ),
+            ("(vectorp (vector))", Ok(Type::Int(1))),
+            ("(vectorp (list))", Ok(Type::Int(0))),
+            ("(vref (vector 1) 1)", Err(EvalError::IndexOutOfRange)),
+            (
+                "(vref (vector 1) (sub 0 1))",
+                Err(EvalError::IndexOutOfRange),
+            ),
+            ("(vset (vector) 0 1)", Err(EvalError::IndexOutOfRange)),
+            ("(vref (list 1) 0)", Err(EvalError::TypeMismatch)),
+            ("(vref (vector 1) a)", Err(EvalError::TypeMismatch)),
+            ("(vlen (list 1))", Err(EvalError::TypeMismatch)),
+            ("(list->vector (vector))", Err(EvalError::TypeMismatch)),
+            ("(vector->list (list))", Err(EvalError::TypeMismatch)),
+            ("(vset (vector 1) 0)", Err(arity_error("vset", 2))),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+
+        // vset は元の Vector を変更しない
+        let exp = Expression::try_from(
+            "(progn (define *v* (vector 1 2)) (define *w* (vset *v* 0 9)) (list (vref *v* 0) (vref *w* 0)))"
+                .as_bytes(),
+        )
+        .unwrap();
+        let expected = Expression::try_from("(1 9)".as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Ok(expression_to_type(&expected)));
+    }
+
+    #[test]
+    fn match_tests() {
+        let cases = vec![
+            (
+                "(match (list 1 2) ((list 1 *y*) *y*) (_ 0))",
+                Ok(Type::Int(2)),
+            ),
+            (
+                "(match (list 3 2) ((list 1 *y*) *y*) (_ 0))",
+                Ok(Type::Int(0)),
+            ),
+            (
+                "(match 0 ((list 1 *y*) *y*) (0 zero) (_ other))",
+                Ok(Type::Atom("zero".into())),
+            ),
+            (
+                "(match (list a (list 4 5)) ((list a (list *x* *y*)) (add *x* *y*)))",
+                Ok(Type::Int(9)),
+            ),
+            ("(match \"s\" (\"t\" 1) (\"s\" 2))", Ok(Type::Int(2))),
+            (
+                "(match (list) ((list) empty))",
+                Ok(Type::Atom("empty".into())),
+            ),
+            (
+                "(match 1 (*x* (define *z* 1) (add *x* *z*)))",
+                Ok(Type::Int(2)),
+            ),
+            ("(match 1 (_))", Ok(Type::Void)),
+            ("(match 1 (2 a))", Err(EvalError::MatchFailed)),
+            ("(match 1 ((vector 1) a))", Err(EvalError::TypeMismatch)),
+            ("(match 1 a)", Err(EvalError::TypeMismatch)),
+            ("(match)", Err(arity_error("match", 0))),
+            // 束縛はその節の中だけで有効
+            ("(progn (match 1 (*x* *x*)) (boundp *x*))", Ok(Type::Int(0))),
+            (
+                "(try (match 1 (2 a)) (catch *e* *e*))",
+                Ok(Type::Atom("MatchFailed".into())),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn destructuring_tests() {
+        let cases = vec![
+            (
+                "(let (((*x* *y*) (list 1 2))) (add *x* *y*))",
+                Ok(Type::Int(3)),
+            ),
+            (
+                "(let (((*x* (_ *y*)) (list 1 (list 2 3))) (*z* 4)) (list *x* *y* *z*))",
+                eval(&Expression::try_from("(list 1 3 4)".as_bytes()).unwrap()),
+            ),
+            (
+                "(progn (defun f ((*a* *b*) *c*) (list *a* *b* *c*)) (f (list 1 2) 3))",
+                eval(&Expression::try_from("(list 1 2 3)".as_bytes()).unwrap()),
+            ),
+            (
+                "(progn (defmacro swap ((*a* *b*)) `(list ,*b* ,*a*)) (swap (1 2)))",
+                eval(&Expression::try_from("(list 2 1)".as_bytes()).unwrap()),
+            ),
+            (
+                "(let (((*x* *y*) (list 1 2 3))) *x*)",
+                Err(EvalError::MatchFailed),
+            ),
+            ("(let (((*x* *y*) 1)) *x*)", Err(EvalError::MatchFailed)),
+            (
+                "(progn (defun f ((*a* *b*)) *a*) (f 1))",
+                Err(EvalError::MatchFailed),
+            ),
+            (
+                "(progn (defun f ((*a* *b*)) *a*) (f (list 1 2) 3))",
+                Err(EvalError::BadArrity),
+            ),
+            (
+                "(let (((*x* 1) (list 1 2))) *x*)",
+                Err(EvalError::TypeMismatch),
+            ),
+            ("(defun f (a) a)", Err(EvalError::TypeMismatch)),
+            // 形が合わない場合のエラーは catch できる
+            (
+                "(try (let (((*x*) (list))) *x*) (catch *e* *e*))",
+                Ok(Type::Atom("MatchFailed".into())),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn optional_rest_params_tests() {
+        let list = |src: &str| eval(&Expression::try_from(src.as_bytes()).unwrap());
+        let cases = vec![
+            ("(progn (defun mylist (&rest *xs*) *xs*) (mylist 1 2 3))", list("(list 1 2 3)")),
+            ("(progn (defun mylist (&rest *xs*) *xs*) (mylist))", list("(list)")),
+            ("(progn (defun f (*a* &optional *b*) (list *a* *b*)) (f 1))", list("(list 1 (list))")),
+            ("(progn (defun f (*a* &optional (*b* (add *a* 1))) (list *a* *b*)) (f 1))", list("(list 1 2)")),
+            ("(progn (defun f (*a* &optional (*b* (add *a* 1))) (list *a* *b*)) (f 1 5))", list("(list 1 5)")),
+            ("(progn (defun f (*a* &optional (*b* 2) &rest *c*) (list *a* *b* *c*)) (f 1 2 3 4))", list("(list 1 2 (list 3 4))")),
+            ("(progn (defun f (&rest (*a* *b*)) (add *a* *b*)) (f 3 4))", Ok(Type::Int(7))),
+            ("(progn (defmacro m (*a* &rest *body*) `(progn ,@*body* ,*a*)) (m 1 (define *x* 2)))", Ok(Type::Int(1))),
+            ("(progn (defun f (*a* &optional *b*) *a*) (f))", Err(EvalError::BadArrity)),
+            ("(progn (defun f (*a* &optional *b*) *a*) (f 1 2 3))", Err(EvalError::BadArrity)),
+            ("(defun f (&rest) 1)", Err(EvalError::TypeMismatch)),
+            ("(defun f (&rest *a* *b*) 1)", Err(EvalError::TypeMismatch)),
+            ("(defun f (&rest *a* &optional *b*) 1)", Err(EvalError::TypeMismatch)),
+            ("(defun f (&optional (*a* 1 2)) 1)", Err(EvalError::BadArrity)),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn keyword_tests() {
+        let list = |src: &str| eval(&Expression::try_from(src.as_bytes()).unwrap());
+        let cases = vec![
+            (":x", Ok(Type::Keyword("x".into()))),
+            ("(eq :x :x)", Ok(Type::Int(1))),
+            ("(eq :x :y)", Ok(Type::Int(0))),
+            (
+                "(eq :x x)",
+                Err(EvalError::Incomparable {
+                    op: "eq".into(),
+                    left: "Keyword".into(),
+                    right: "Atom".into(),
+                }),
+            ),
+            ("(quote (a :b))", list("(list a :b)")),
+            ("(match :y (:x 1) (:y 2))", Ok(Type::Int(2))),
+            (
+                "(progn (defun draw (&key *x* (*y* 5)) (list *x* *y*)) (draw :x 10 :y 20))",
+                list("(list 10 20)"),
+            ),
+            (
+                "(progn (defun draw (&key *x* (*y* 5)) (list *x* *y*)) (draw :y 20 :x 10))",
+                list("(list 10 20)"),
+            ),
+            (
+                "(progn (defun draw (&key *x* (*y* 5)) (list *x* *y*)) (draw))",
+                list("(list (list) 5)"),
+            ),
+            (
+                "(progn (defun draw (*a* &key (*y* (add *a* 1))) (list *a* *y*)) (draw 1))",
+                list("(list 1 2)"),
+            ),
+            (
+                "(progn (defun f (&rest *r* &key *x*) (list *r* *x*)) (f :x 1))",
+                list("(list (list :x 1) 1)"),
+            ),
+            (
+                "(progn (defun draw (&key *x*) *x*) (draw :x))",
+                Err(EvalError::BadArrity),
+            ),
+            (
+                "(progn (defun draw (&key *x*) *x*) (draw :z 1))",
+                Err(EvalError::TypeMismatch),
+            ),
+            (
+                "(progn (defun draw (&key *x*) *x*) (draw x 1))",
+                Err(EvalError::TypeMismatch),
+            ),
+            (
+                "(defun draw (&key (*x* 1 2)) *x*)",
+                Err(EvalError::BadArrity),
+            ),
+            ("(defun draw (&key (*x*)) *x*)", Err(EvalError::BadArrity)),
+            (
+                "(defun draw (&rest &key *x*) *x*)",
+                Err(EvalError::TypeMismatch),
+            ),
+            ("(defun draw (&key x) x)", Err(EvalError::TypeMismatch)),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn defconst_tests() {
+        let cases = vec![
+            ("(progn (defconst *pi* 314) *pi*)", Ok(Type::Int(314))),
+            (
+                "(progn (defconst *pi* 314) (set *pi* 3))",
+                Err(EvalError::AssignToConstant),
+            ),
+            (
+                "(progn (defconst *pi* 314) (incf *pi*))",
+                Err(EvalError::AssignToConstant),
+            ),
+            (
+                "(progn (defconst *pi* 314) (define *pi* 3))",
+                Err(EvalError::AssignToConstant),
+            ),
+            // 再度 defconst するのは許す
+            (
+                "(progn (defconst *pi* 314) (defconst *pi* 3) *pi*)",
+                Ok(Type::Int(3)),
+            ),
+            // 内側のスコープで隠した変数は書き換えられる
+            (
+                "(progn (defconst *pi* 314) (let ((*pi* 1)) (set *pi* 2) *pi*))",
+                Ok(Type::Int(2)),
+            ),
+            (
+                "(progn (defconst *pi* 314) (let ((*pi* 1)) (set *pi* 2)) *pi*)",
+                Ok(Type::Int(314)),
+            ),
+            (
+                "(progn (let ((*x* 1)) (defconst *x* 2) (set *x* 3)))",
+                Err(EvalError::AssignToConstant),
+            ),
+            (
+                "(try (progn (defconst *pi* 314) (set *pi* 3)) (catch *e* *e*))",
+                Ok(Type::Atom("AssignToConstant".into())),
+            ),
+            ("(defconst pi 314)", Err(EvalError::TypeMismatch)),
+            ("(defconst *pi*)", Err(arity_error("defconst", 1))),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+
+        // 定数であることは snapshot / restore で保存される
+        let mut context = Context::new();
+        let exp = Expression::try_from("(defconst *pi* 314)".as_bytes()).unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        let snapshot = context.snapshot();
+        let mut other = Context::new();
+        other.restore(&snapshot);
+        let exp = Expression::try_from("(set *pi* 3)".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut other),
+            Err(EvalError::AssignToConstant)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn os_builtin_tests() {
+        // デフォルトでは禁止されている
+        for src in ["(getenv \"PATH\")", "(argv)", "(exit 1)"].iter() {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(EvalError::CapabilityDenied), "{}", src);
+        }
+
+        let mut context = Context::new();
+        context.set_capabilities(Capabilities::all());
+        assert!(context.capabilities().allow_os);
+        let mut run = |src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, &mut context);
+        };
+        std::env::set_var("LIBLISP_GETENV_TEST", "value");
+        assert_eq!(
+            run("(getenv \"LIBLISP_GETENV_TEST\")"),
+            Ok(Type::Str("value".into()))
+        );
+        assert_eq!(
+            run("(getenv \"LIBLISP_GETENV_UNDEFINED\")"),
+            Ok(Type::TypeList(Rc::new(TypeList::new())))
+        );
+        assert_eq!(run("(getenv HOME)"), Err(EvalError::TypeMismatch));
+        match run("(argv)") {
+            Ok(Type::TypeList(l)) => {
+                assert_eq!(l.len() as usize, std::env::args().count());
+                assert!(matches!(l.head(), Some(Type::Str(_))));
+            }
+            _ => assert!(false),
+        }
+        assert_eq!(run("(argv 1)"), Err(arity_error("argv", 1)));
+
+        // exit は try で捕捉されず、関数やループも抜ける
+        assert_eq!(run("(exit)"), Err(EvalError::Exit(0)));
+        assert_eq!(
+            run("(try (progn (defun f () (while 1 (exit 3))) (f)) (catch *e* 0))"),
+            Err(EvalError::Exit(3))
+        );
+        assert_eq!(
+            run("(progn (defmacro m () (exit 4)) (try (m) (catch *e* 0)))"),
+            Err(EvalError::Exit(4))
+        );
+        assert_eq!(run("(exit a)"), Err(EvalError::TypeMismatch));
+        assert_eq!(run("(exit 1 2)"), Err(arity_error("exit", 2)));
+    }
+
+    #[test]
+    fn filesystem_tests() {
+        let fs = MemoryFileSystem::new();
+        fs.insert("in.txt", "hello");
+        let mut context = Context::new();
+        context.set_filesystem(Box::new(fs.clone()));
+
+        // デフォルトでは禁止されている
+        let exp = Expression::try_from("(slurp \"in.txt\")".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Err(EvalError::CapabilityDenied)
+        );
+
+        context.set_capabilities(Capabilities {
+            allow_fs: true,
+            ..Capabilities::default()
+        });
+        let mut run = |src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, &mut context);
+        };
+        assert_eq!(run("(slurp \"in.txt\")"), Ok(Type::Str("hello".into())));
+        assert_eq!(run("(file-exists \"in.txt\")"), Ok(Type::Int(1)));
+        assert_eq!(run("(file-exists \"out.txt\")"), Ok(Type::Int(0)));
+        assert_eq!(run("(spit \"out.txt\" (slurp \"in.txt\"))"), Ok(Type::Void));
+        assert_eq!(run("(file-exists \"out.txt\")"), Ok(Type::Int(1)));
+        assert_eq!(fs.get("out.txt"), Some("hello".into()));
+        assert_eq!(
+            run("(slurp \"missing.txt\")"),
+            Err(EvalError::IoFailed("missing.txt: no such file".into()))
+        );
+        assert_eq!(run("(slurp in)"), Err(EvalError::TypeMismatch));
+        assert_eq!(run("(spit \"out.txt\")"), Err(arity_error("spit", 1)));
+        assert_eq!(
+            run("(try (slurp \"missing.txt\") (catch *e* 0))"),
+            Ok(Type::Int(0))
+        );
+    }
+
+    #[cfg(all(feature = "std", unix))]
+    #[test]
+    fn sh_tests() {
+        let exp = Expression::try_from("(sh \"echo hi\")".as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Err(EvalError::CapabilityDenied));
+
+        let mut context = Context::new();
+        context.set_capabilities(Capabilities {
+            allow_process: true,
+            ..Capabilities::default()
+        });
+        let mut run = |src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, &mut context).map(|t| t.to_string());
+        };
+        assert_eq!(run("(sh \"echo hi\")"), Ok("(0 \"hi\\n\" \"\")".into()));
+        assert_eq!(
+            run("(sh \"echo oops >&2; exit 3\")"),
+            Ok("(3 \"\" \"oops\\n\")".into())
+        );
+        assert_eq!(run("(sh echo)"), Err(EvalError::TypeMismatch));
+        assert_eq!(run("(sh)"), Err(arity_error("sh", 0)));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_tests() {
+        // 受け取ったリクエストを記録し、決まったレスポンスを返すクライアント
+        struct MockClient(Rc<core::cell::RefCell<Vec<HttpRequest>>>);
+
+        impl HttpClient for MockClient {
+            fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, EvalError> {
+                self.0.borrow_mut().push(request.clone());
+                return Ok(HttpResponse {
+                    status: 200,
+                    headers: vec![("content-type".into(), "text/plain".into())],
+                    body: "ok".into(),
+                });
+            }
+        }
+
+        let exp = Expression::try_from("(http-get \"http://example.com\")".as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Err(EvalError::CapabilityDenied));
+
+        let requests = Rc::new(core::cell::RefCell::new(Vec::new()));
+        let mut context = Context::new();
+        context.set_http_client(Box::new(MockClient(requests.clone())));
+        context.set_capabilities(Capabilities {
+            allow_net: true,
+            ..Capabilities::default()
+        });
+        let mut run = |src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, &mut context).map(|t| t.to_string());
+        };
+        let expected = "(200 ((\"content-type\" \"text/plain\")) \"ok\")";
+        assert_eq!(
+            run("(http-get \"http://example.com\")"),
+            Ok(expected.into())
+        );
+        assert_eq!(
+            run("(http-post \"http://example.com/api\" \"data\")"),
+            Ok(expected.into())
+        );
+        assert_eq!(
+            run("(http-post \"http://example.com\")"),
+            Err(arity_error("http-post", 1))
+        );
+        assert_eq!(run("(http-get url)"), Err(EvalError::TypeMismatch));
+        assert_eq!(
+            *requests.borrow(),
+            vec![
+                HttpRequest {
+                    method: "GET".into(),
+                    url: "http://example.com".into(),
+                    body: None,
+                },
+                HttpRequest {
+                    method: "POST".into(),
+                    url: "http://example.com/api".into(),
+                    body: Some("data".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hook_tests() {
+        let defs = "(progn (define *log* (list)) \
+                    (defun log-a (*x*) (progn (set *log* `(,@*log* (a ,*x*))) 1)) \
+                    (defun log-b (*x*) (progn (set *log* `(,@*log* (b ,*x*))) 2)))";
+        let cases = vec![
+            ("(run-hooks on-save 1)", "()"),
+            ("(progn (add-hook on-save log-a) (add-hook on-save log-b) (run-hooks on-save 1))", "(1 2)"),
+            ("(progn (add-hook on-save log-a) (add-hook on-save log-b) (run-hooks on-save 1) *log*)", "((a 1) (b 1))"),
+            // 同じ関数は重複して追加しない
+            ("(progn (add-hook on-save log-a) (add-hook on-save log-a) (run-hooks on-save 1))", "(1)"),
+            ("(progn (add-hook on-save log-a) (add-hook on-save log-b) (remove-hook on-save log-a) (run-hooks on-save 1))", "(2)"),
+            ("(progn (add-hook on-save log-a) (run-hooks on-load 1))", "()"),
+            // 特殊形式でない組み込み関数も、名前か Function で追加できる
+            ("(progn (add-hook h intp) (add-hook h #'listp) (run-hooks h 1))", "(1 0)"),
+            ("(progn (add-hook h list) (run-hooks h 1 2))", "((1 2))"),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, Ok(expected.to_string()), "{}", src);
+        }
+
+        let errors = vec![
+            ("(add-hook on-save)", arity_error("add-hook", 1)),
+            ("(add-hook on-save 1)", EvalError::TypeMismatch),
+            ("(run-hooks)", arity_error("run-hooks", 0)),
+            ("(run-hooks 1)", EvalError::TypeMismatch),
+            (
+                "(progn (add-hook h undefined) (run-hooks h))",
+                EvalError::NotFoundFunctionName,
+            ),
+            (
+                "(progn (defun f (*x*) *x*) (add-hook h f) (run-hooks h))",
+                EvalError::BadArrity,
+            ),
+        ];
+        for (src, expected) in errors {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(expected), "{}", src);
+        }
+
+        // ホスト側からフックを操作し、呼び出せる
+        let mut context = Context::new();
+        context.register_fn("twice", |a: i32| a * 2);
+        let exp = Expression::try_from(
+            "(progn (defun inc (*x*) (add *x* 1)) (add-hook h inc))".as_bytes(),
+        )
+        .unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        context.add_hook("h", "twice");
+        assert_eq!(
+            context.run_hook("h", &[Type::Int(5)]),
+            Ok(vec![Type::Int(6), Type::Int(10)])
+        );
+        context.remove_hook("h", "inc");
+        assert_eq!(
+            context.run_hook("h", &[Type::Int(5)]),
+            Ok(vec![Type::Int(10)])
+        );
+        assert_eq!(
+            context.run_hook("h", &[]),
+            Err(EvalError::ArityMismatch {
+                name: "twice".into(),
+                min: 1,
+                max: Some(1),
+                actual: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn ownership_tests() {
+        // 定義と破棄を繰り返しても、Context のテーブルやスコープが増え続けない
+        let mut context = Context::new();
+        let src = "(progn \
+                   (defun f (*x*) (let ((*y* (list *x* *x*))) (f2 *y*))) \
+                   (defun f2 (*y*) (vset (vector *y*) 0 *y*)) \
+                   (defmacro m (*x*) *x*) \
+                   (add-hook h f) \
+                   (define *v* (f (list 1 2))) \
+                   (set *v* (vset *v* 0 *v*)) \
+                   (run-hooks h *v*))";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        for _ in 0..1000 {
+            eval_with_context(&exp, &mut context).unwrap();
+        }
+        assert_eq!(context.env.frames().len(), 1);
+        assert_eq!(context.env.frames()[0].len(), 1);
+        assert_eq!(context.functable.len(), 2);
+        assert_eq!(context.macrotable.len(), 1);
+        assert_eq!(context.hooks["h"].len(), 1);
+
+        // Vector に自分自身を入れても循環せず、定義し直すか Context を破棄すると値が解放される
+        let weak_v = |context: &Context| match context.lookup("*v*") {
+            Some(Type::Vector(v)) => Rc::downgrade(v),
+            _ => panic!("*v* is not a vector"),
+        };
+        let v = weak_v(&context);
+        assert!(v.upgrade().is_some());
+        eval_with_context(&exp, &mut context).unwrap();
+        assert!(v.upgrade().is_none());
+        let v = weak_v(&context);
+        drop(context);
+        assert!(v.upgrade().is_none());
+    }
+
+    #[test]
+    fn pmap_tests() {
+        let defs = "(progn (define *count* 0) \
+                    (defun square (*x*) (progn (incf *count*) (mul *x* *x*))) \
+                    (defun fib (*n*) (cond (lt *n* 2) *n* (add (fib (sub *n* 1)) (fib (sub *n* 2))))) \
+                    (defun check (*x*) (cond (eq *x* 0) (raise *x*) *x*)))";
+        let cases = vec![
+            ("(pmap square (list 1 2 3))", "(1 4 9)"),
+            ("(pmap fib (list 10 15 20))", "(55 610 6765)"),
+            ("(pmap square (list))", "()"),
+            // 特殊形式でない組み込み関数も適用できる
+            ("(pmap #'intp (list 1 a 2))", "(1 0 1)"),
+            ("(pmap head (list (list 1 2) (list 3)))", "(1 3)"),
+            // 子の Context での set は、呼び出し元に影響しない
+            ("(progn (pmap square (list 1 2 3)) *count*)", "0"),
+            (
+                "(try (pmap check (list 1 0 2 0)) (catch *e* (list caught *e*)))",
+                "(caught 0)",
+            ),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, Ok(expected.to_string()), "{}", src);
+        }
+
+        let errors = vec![
+            ("(pmap square)", arity_error("pmap", 1)),
+            ("(pmap square 1)", EvalError::TypeMismatch),
+            ("(pmap 1 (list 1))", EvalError::TypeMismatch),
+            ("(pmap undefined (list 1))", EvalError::NotFoundFunctionName),
+            ("(pmap cond (list 1))", EvalError::NotFoundFunctionName),
+            ("(pmap #'add (list 1 2))", arity_error("add", 1)),
+        ];
+        for (src, expected) in errors {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(expected), "{}", src);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn pmap_worker_tests() {
+        let fs = MemoryFileSystem::new();
+        fs.insert("in.txt", "hello");
+        let mut context = Context::new();
+        context.set_filesystem(Box::new(fs));
+        context.set_capabilities(Capabilities {
+            allow_fs: true,
+            ..Capabilities::default()
+        });
+        context.set_max_loop_iterations(Some(10));
+        context.set_max_depth(Some(100));
+        context.set_strict_set(true);
+        context.register_fn("twice", |a: i32| a * 2);
+        context.bind_dynamic("*time*", || Type::Int(100));
+        let mut run = |src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, &mut context);
+        };
+        run("(progn (defun spin (*x*) (while 1 *x*)) \
+             (defun deep (*x*) (deep *x*)) \
+             (defun assign (*x*) (set *undefined* *x*)) \
+             (defun time (*x*) (add *time* *x*)) \
+             (defun read (*path*) (slurp *path*)) \
+             (defun show (*x*) (print *x*)) \
+             (defun load-file (*path*) (load *path*)))")
+        .unwrap();
+
+        // Context の設定を引き継ぐ
+        assert_eq!(
+            run("(pmap spin (list 1))"),
+            Err(EvalError::LoopLimitExceeded)
+        );
+        assert_eq!(
+            run("(pmap deep (list 1))"),
+            Err(EvalError::DepthLimitExceeded)
+        );
+        assert_eq!(
+            run("(pmap assign (list 1))"),
+            Err(EvalError::AssignToUndefinedVariable)
+        );
+        assert_eq!(
+            run("(pmap time (list 1 2))").map(|t| t.to_string()),
+            Ok("(101 102)".to_string())
+        );
+        // スレッド間で受け渡せない設定は、使うとエラーになる
+        let unavailable = |name: &str| Err(EvalError::UnavailableInWorker(name.to_string()));
+        assert_eq!(
+            run("(pmap read (list \"in.txt\"))"),
+            unavailable("filesystem")
+        );
+        assert_eq!(run("(pmap show (list 1))"), unavailable("output"));
+        assert_eq!(run("(pmap twice (list 1))"), unavailable("twice"));
+        assert_eq!(
+            run("(pmap load-file (list \"in.txt\"))"),
+            unavailable("loader")
+        );
+    }
+
+    #[test]
+    fn block_tests() {
+        let defs = "(progn (defun find-first (*l* *x*) \
+                      (block found (dolist (*e* *l*) (cond (eq *e* *x*) (return-from found *e*) 0)) nil)) \
+                    (defun bail () (return-from outer 9)))";
+        let cases = vec![
+            ("(block b 1 2 3)", "3"),
+            ("(block b 1 (return-from b 2) 3)", "2"),
+            ("(block b)", ""),
+            ("(block b (return-from b))", ""),
+            ("(find-first (list 1 2 3) 2)", "2"),
+            ("(find-first (list 1 2 3) 5)", "nil"),
+            // 名前で脱出先を選ぶ
+            ("(block a (block b (return-from a 1) 2) 3)", "1"),
+            ("(block a (block b (return-from b 1) 2) 3)", "3"),
+            // 同じ名前なら最も内側の block
+            ("(list (block a (block a (return-from a 1) 2) 3))", "(3)"),
+            // ループや関数呼び出しをまたいで抜けられる
+            ("(block a (while 1 (return-from a 5)))", "5"),
+            ("(list (block outer (bail) 1) 2)", "(9 2)"),
+            // block を抜けても、try の catch 節には入らない
+            ("(block a (try (return-from a 1) (catch *e* 2)))", "1"),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, Ok(expected.to_string()), "{}", src);
+        }
+
+        let errors = vec![
+            (
+                "(return-from a 1)",
+                EvalError::ReturnFromOutsideBlock("a".into()),
+            ),
+            (
+                "(block a (return-from b 1))",
+                EvalError::ReturnFromOutsideBlock("b".into()),
+            ),
+            ("(block)", arity_error("block", 0)),
+            ("(block 1 2)", EvalError::TypeMismatch),
+            ("(return-from)", arity_error("return-from", 0)),
+            ("(return-from a 1 2)", arity_error("return-from", 3)),
+            ("(return-from 1)", EvalError::TypeMismatch),
+        ];
+        for (src, expected) in errors {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(expected), "{}", src);
+        }
+    }
+
+    #[test]
+    fn trace_tests() {
+        let mut context = Context::new();
+        assert!(context.trace().is_none());
+        context.enable_trace();
+        let src = "(progn (defun f (*x*) (cond (gt *x* 0) (return *x*) (raise 1))) (try (f 0) (catch *e* (f 2))))";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        let expected = [
+            "(progn (defun f (*x*) (cond (gt *x* 0) (return *x*) (raise 1))) (try (f 0) (catch *e* (f 2)))) => 2",
+            "  (defun f (*x*) (cond (gt *x* 0) (return *x*) (raise 1))) => f",
+            "  (try (f 0) (catch *e* (f 2))) => 2",
+            "    (f 0) !! Raised(Int(1))",
+            "      0 => 0",
+            "      (cond (gt *x* 0) (return *x*) (raise 1)) !! Raised(Int(1))",
+            "        (gt *x* 0) => 0",
+            "          *x* => 0",
+            "          0 => 0",
+            "        (raise 1) !! Raised(Int(1))",
+            "          1 => 1",
+            "    (f 2) => 2",
+            "      2 => 2",
+            "      (cond (gt *x* 0) (return *x*) (raise 1)) -> escape",
+            "        (gt *x* 0) => 1",
+            "          *x* => 2",
+            "          0 => 0",
+            "        (return *x*) -> escape",
+            "          *x* => 2",
+            "",
+        ];
+        let trace = context.take_trace().unwrap();
+        assert_eq!(trace.to_string(), expected.join("\n"));
+
+        // 同じ評価は同じトレースになる
+        let mut other = Context::new();
+        other.enable_trace();
+        eval_with_context(&exp, &mut other).unwrap();
+        assert_eq!(other.trace(), Some(&trace));
+
+        // take_trace の後は空の記録から続ける
+        assert_eq!(context.trace().map(|t| t.entries.len()), Some(0));
+        context.disable_trace();
+        eval_with_context(&exp, &mut context).unwrap();
+        assert!(context.trace().is_none());
+    }
+
+    #[test]
+    fn equality_tests() {
+        let cases = vec![
+            // eq は同一性、またはプリミティブな値の等価性
+            ("(eq \"ab\" \"ab\")", Ok(Type::Int(1))),
+            ("(eq \"ab\" \"ac\")", Ok(Type::Int(0))),
+            ("(eq (list) (list))", Ok(Type::Int(1))),
+            ("(eq (list 1) (list 1))", Ok(Type::Int(0))),
+            (
+                "(progn (define *l* (list 1 2)) (eq *l* *l*))",
+                Ok(Type::Int(1)),
+            ),
+            (
+                "(progn (define *v* (list->vector (list 1))) (eq *v* *v*))",
+                Ok(Type::Int(1)),
+            ),
+            (
+                "(eq (list->vector (list 1)) (list->vector (list 1)))",
+                Ok(Type::Int(0)),
+            ),
+            (
+                "(eq (list 1) 1)",
+                Err(EvalError::Incomparable {
+                    op: "eq".into(),
+                    left: "TypeList".into(),
+                    right: "Int".into(),
+                }),
+            ),
+            (
+                "(eq \"a\" a)",
+                Err(EvalError::Incomparable {
+                    op: "eq".into(),
+                    left: "Str".into(),
+                    right: "Atom".into(),
+                }),
+            ),
+            // equal は構造による等価性
+            (
+                "(equal (list 1 (list 2 \"s\")) (list 1 (list 2 \"s\")))",
+                Ok(Type::Int(1)),
+            ),
+            (
+                "(equal (list 1 (list 2)) (list 1 (list 3)))",
+                Ok(Type::Int(0)),
+            ),
+            ("(equal (list 1 2) (list 1 2 3))", Ok(Type::Int(0))),
+            (
+                "(equal (list->vector (list 1 (list :k))) (list->vector (list 1 (list :k))))",
+                Ok(Type::Int(1)),
+            ),
+            ("(equal (list 1) (list->vector (list 1)))", Ok(Type::Int(0))),
+            ("(equal (div 2 4) (div 1 2))", Ok(Type::Int(1))),
+            ("(equal 1 (list 1))", Ok(Type::Int(0))),
+            ("(equal a a)", Ok(Type::Int(1))),
+            ("(equal 1)", Err(arity_error("equal", 1))),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn sort_search_tests() {
+        let defs = "(progn (defun by-head (*a* *b*) (lt (head *a*) (head *b*))) \
+                    (defun evenp (*x*) (eq (mul (floor (div *x* 2)) 2) *x*)))";
+        let cases = vec![
+            ("(sort (list 3 1 2))", "(1 2 3)"),
+            ("(sort (list 3 1 2) gt)", "(3 2 1)"),
+            ("(sort (list))", "()"),
+            ("(sort (list (div 1 2) 1 (div 1 3)))", "(1/3 1/2 1)"),
+            ("(sort (list b c a))", "(a b c)"),
+            // 安定な並べ替え
+            (
+                "(sort (list (list 2 a) (list 1 b) (list 2 c) (list 1 d)) by-head)",
+                "((1 b) (1 d) (2 a) (2 c))",
+            ),
+            ("(member 2 (list 1 2 3))", "(2 3)"),
+            ("(member (list 2) (list 1 (list 2) 3))", "((2) 3)"),
+            ("(member 5 (list 1 2 3))", "()"),
+            ("(find evenp (list 1 3 4 6))", "4"),
+            ("(find evenp (list 1 3))", "()"),
+            ("(position evenp (list 1 3 4 6))", "2"),
+            ("(position evenp (list))", "()"),
+            ("(count evenp (list 1 2 3 4 6))", "3"),
+            ("(count intp (list 1 a (list) 2))", "2"),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, Ok(expected.to_string()), "{}", src);
+        }
+
+        let errors = vec![
+            ("(sort)", arity_error("sort", 0)),
+            ("(sort 1)", EvalError::TypeMismatch),
+            (
+                "(sort (list 1 a))",
+                EvalError::Incomparable {
+                    op: "lt".into(),
+                    left: "Atom".into(),
+                    right: "Int".into(),
+                },
+            ),
+            ("(sort (list 2 1) 1)", EvalError::TypeMismatch),
+            (
+                "(sort (list 2 1) undefined)",
+                EvalError::NotFoundFunctionName,
+            ),
+            ("(member 1)", arity_error("member", 1)),
+            ("(member 1 2)", EvalError::TypeMismatch),
+            ("(find intp 1)", EvalError::TypeMismatch),
+            ("(position 1 (list 1))", EvalError::TypeMismatch),
+            (
+                "(count undefined (list 1))",
+                EvalError::NotFoundFunctionName,
+            ),
+        ];
+        for (src, expected) in errors {
+            let mut context = Context::new();
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Err(expected),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn list_utility_tests() {
+        let cases = vec![
+            ("(range 3)", "(0 1 2)"),
+            ("(range 0)", "()"),
+            ("(range (sub 0 2))", "()"),
+            ("(range 2 5)", "(2 3 4)"),
+            ("(range 0 10 3)", "(0 3 6 9)"),
+            ("(range 3 0 (sub 0 1))", "(3 2 1)"),
+            ("(zip (list 1 2 3) (list a b))", "((1 a) (2 b))"),
+            ("(zip (list 1 2) (list a b) (list :x :y))", "((1 a :x) (2 b :y))"),
+            ("(zip (list 1 2))", "((1) (2))"),
+            ("(zip (list) (list 1))", "()"),
+            ("(enumerate (list a b))", "((0 a) (1 b))"),
+            ("(enumerate (list))", "()"),
+            ("(take 2 (list 1 2 3))", "(1 2)"),
+            ("(take 5 (list 1 2 3))", "(1 2 3)"),
+            ("(take 0 (list 1 2 3))", "()"),
+            ("(drop 2 (list 1 2 3))", "(3)"),
+            ("(drop 5 (list 1 2 3))", "()"),
+            ("(drop 0 (list 1 2 3))", "(1 2 3)"),
+            (
+                "(progn (define *s* 0) (dolist (*p* (enumerate (list 5 6))) (let (((*i* *x*) *p*)) (incf *s* (mul *i* *x*)))) *s*)",
+                "6",
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp).map(|t| t.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                src
+            );
+        }
+        // Int の上限を超えて数えずに止まる
+        let exp = Expression::try_from(int_src("(range (sub MAX 1) MAX 5)").as_bytes()).unwrap();
+        assert_eq!(eval(&exp), Ok(list_from(&[Type::Int(Int::MAX - 1)])));
+
+        let errors = vec![
+            ("(range)", arity_error("range", 0)),
+            ("(range 1 2 3 4)", arity_error("range", 4)),
+            ("(range a)", EvalError::TypeMismatch),
+            ("(range 0 3 0)", EvalError::TypeMismatch),
+            ("(zip)", arity_error("zip", 0)),
+            ("(zip (list 1) 2)", EvalError::TypeMismatch),
+            ("(enumerate 1)", EvalError::TypeMismatch),
+            ("(take (sub 0 1) (list 1))", EvalError::TypeMismatch),
+            ("(drop 1 2)", EvalError::TypeMismatch),
+            ("(take 1)", arity_error("take", 1)),
+        ];
+        for (src, expected) in errors {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(expected), "{}", src);
+        }
+    }
+
+    #[test]
+    fn reflection_tests() {
+        let cases = vec![
+            ("(parse \"(add 1 2)\")", "(add 1 2)"),
+            ("(eq (head (parse \"(add 1 2)\")) (quote add))", "1"),
+            ("(equal (parse \"(f *x* :k)\") (quote (f *x* :k)))", "1"),
+            (
+                "(unparse (quote (f *x* \"s\" :k)))",
+                "\"(f *x* \\\"s\\\" :k)\"",
+            ),
+            (
+                "(equal (parse (unparse (quote (f \"a\\\"b\\n\")))) (quote (f \"a\\\"b\\n\")))",
+                "1",
+            ),
+            ("(unparse (list (quote add) 1 2))", "\"(add 1 2)\""),
+            ("(eval (parse \"(add 1 2)\"))", "3"),
+            ("(eval (list (quote mul) 3 4))", "12"),
+            ("(progn (define *x* 5) (eval (quote *x*)))", "5"),
+            ("(eval (parse (unparse (quote (sub 10 4)))))", "6"),
+            ("(progn (eval (parse \"(defun f () 7)\")) (f))", "7"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp).map(|t| t.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                src
+            );
+        }
+
+        let errors = vec![
+            ("(parse 1)", EvalError::TypeMismatch),
+            (
+                "(parse \"(add 1\")",
+                EvalError::ParseFailed(ExpressionConversionError::UnexpectedEof),
+            ),
+            ("(unparse (div 1 2))", EvalError::TypeMismatch),
+            ("(unparse)", arity_error("unparse", 0)),
+            ("(eval (list->vector (list 1)))", EvalError::TypeMismatch),
+            ("(eval 1 2)", arity_error("eval", 2)),
+        ];
+        for (src, expected) in errors {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), Err(expected), "{}", src);
+        }
+    }
+
+    #[test]
+    fn shadowing_tests() {
+        let run = |context: &mut Context, src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, context).map(|t| t.to_string());
+        };
+
+        // defun と defmacro による定義は、組み込み関数より優先される
+        let mut context = Context::new();
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (defun head (*l*) :mine) (head (list 1)))"
+            ),
+            Ok(":mine".into())
+        );
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (defmacro tail (*x*) (quote :macro)) (tail (list 1)))"
+            ),
+            Ok(":macro".into())
+        );
+        // 関数名を受け取る組み込み関数も、上書きした定義を使う
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (defun lt (*a* *b*) (gt *a* *b*)) (sort (list 1 3 2)))"
+            ),
+            Ok("(3 2 1)".into())
+        );
+        assert!(!context.calls_builtin("head"));
+        assert!(context.calls_builtin("add"));
+
+        // ホスト側の関数は組み込み関数より優先され、ユーザ定義関数はさらに優先される
+        let mut context = Context::new();
+        context.register_fn("add", |a: i32, b: i32| a - b);
+        assert_eq!(run(&mut context, "(add 5 2)"), Ok("3".into()));
+        assert!(!context.calls_builtin("add"));
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (defun add (*a* *b*) (mul *a* *b*)) (add 5 2))"
+            ),
+            Ok("10".into())
+        );
+
+        // 取り除いた組み込み関数は呼び出せない
+        let mut context = Context::new();
+        assert!(context.remove_builtin("mul"));
+        assert!(!context.remove_builtin("mul"));
+        assert!(!context.remove_builtin("no-such-builtin"));
+        assert!(!context.calls_builtin("mul"));
+        assert_eq!(
+            run(&mut context, "(mul 2 3)"),
+            Err(EvalError::NotFoundFunctionName)
+        );
+        assert_eq!(
+            run(&mut context, "(sort (list 1 2) mul)"),
+            Err(EvalError::NotFoundFunctionName)
+        );
+        // 取り除いた後も、同じ名前の関数を定義できる
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (defun mul (*a* *b*) (add *a* *b*)) (mul 2 3))"
+            ),
+            Ok("5".into())
+        );
+        // 他の Context には影響しない
+        assert_eq!(run(&mut Context::new(), "(mul 2 3)"), Ok("6".into()));
     }
 
-    let cond = l.head().unwrap();
-    let ok = l.tail().head().unwrap();
-    let ng = l.tail().tail().head().unwrap();
+    #[test]
+    fn builtin_whitelist_tests() {
+        let run = |context: &mut Context, src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, context).map(|t| t.to_string());
+        };
+        let disabled = |name: &str| Err(EvalError::FunctionDisabled(name.into()));
 
-    let r = eval_with_context(cond, context)?;
+        let mut context = Context::new();
+        context.builtin_whitelist(&["add", "sub", "cond", "progn", "gt", "no-such-builtin"]);
+        assert_eq!(
+            run(&mut context, "(progn (cond (gt 2 1) (add 1 (sub 5 2)) 0))"),
+            Ok("4".into())
+        );
+        assert_eq!(run(&mut context, "(mul 2 3)"), disabled("mul"));
+        assert_eq!(run(&mut context, "(defun f () 1)"), disabled("defun"));
+        assert_eq!(
+            run(&mut context, "(add 1 (head (list 1)))"),
+            disabled("head")
+        );
+        assert_eq!(
+            run(&mut context, "(frob 1)"),
+            Err(EvalError::NotFoundFunctionName)
+        );
+        assert!(!context.calls_builtin("mul"));
 
-    match r {
-        Type::Int(0) => {
-            return eval_with_context(ng, context);
-        }
-        Type::Int(_) => {
-            return eval_with_context(ok, context);
-        }
-        _ => {
-            return Err(EvalError::TypeMismatch);
-        }
+        // try も無効なので、エラーは捕捉できない
+        assert_eq!(
+            run(&mut context, "(try (mul 1 2) (catch *e* 0))"),
+            disabled("try")
+        );
+
+        // ホスト側の関数は無効にならない
+        context.register_fn("mul", |a: i32, b: i32| a * b);
+        assert_eq!(run(&mut context, "(mul 2 3)"), Ok("6".into()));
+
+        // 無効にした組み込み関数を、関数名として渡した場合
+        let mut context = Context::new();
+        context.disable_builtins(&["lt", "define"]);
+        assert_eq!(run(&mut context, "(define *x* 1)"), disabled("define"));
+        assert_eq!(run(&mut context, "(sort (list 2 1))"), disabled("lt"));
+        assert_eq!(
+            run(&mut context, "(sort (list 2 1) gt)"),
+            Ok("(2 1)".into())
+        );
+        // 取り除いた組み込み関数は、見つからない扱いになる
+        context.remove_builtin("lt");
+        assert_eq!(
+            run(&mut context, "(lt 1 2)"),
+            Err(EvalError::NotFoundFunctionName)
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::eval::*;
     #[test]
-    fn arithmetic_tests() {
-        // 四則演算の関数呼び出し
-        {
-            let exp = Expression::try_from("(add 1 2)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(3)) => assert!(true),
-                _ => assert!(false),
-            }
-        }
+    fn opaque_tests() {
+        use core::cell::RefCell;
 
-        {
-            let exp = Expression::try_from("(sub 1 2)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(-1)) => assert!(true),
-                _ => assert!(false),
-            }
+        struct File {
+            lines: RefCell<Vec<String>>,
         }
+        let run = |context: &mut Context, src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, context);
+        };
 
-        // 関数をネストできる
-        {
-            let exp = Expression::try_from("(add (add (sub 1 2) 3) 4)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(6)) => assert!(true),
-                _ => assert!(false),
-            }
-        }
+        let mut context = Context::new();
+        context.register_method("write", |f: &File, args: &[Type]| {
+            let line = args.first().ok_or(EvalError::BadArrity)?.expect_str()?;
+            f.lines.borrow_mut().push(line.into());
+            return Ok(Type::Void);
+        });
+        context.register_method("len", |f: &File, _: &[Type]| {
+            return Ok(Type::Int(f.lines.borrow().len() as Int));
+        });
+        let file = Opaque::new(File {
+            lines: RefCell::new(Vec::new()),
+        });
+        context
+            .define_var("*f*", Type::Opaque(file.clone()))
+            .unwrap();
+        context
+            .define_var(
+                "*g*",
+                Type::Opaque(Opaque::new(File {
+                    lines: RefCell::new(Vec::new()),
+                })),
+            )
+            .unwrap();
+        context
+            .define_var("*n*", Type::Opaque(Opaque::new(1)))
+            .unwrap();
 
-        // 引数の数が足りない
-        {
-            let exp = Expression::try_from("(add 1)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(_) => assert!(false),
-                Err(e) => assert_eq!(EvalError::BadArrity, e),
-            }
+        assert_eq!(
+            run(
+                &mut context,
+                "(progn (send *f* :write \"a\") (send *f* :write \"b\") (send *f* :len))"
+            ),
+            Ok(Type::Int(2))
+        );
+        // ホスト側と同じ値を指す
+        assert_eq!(
+            file.downcast_ref::<File>()
+                .unwrap()
+                .lines
+                .borrow()
+                .join(","),
+            "a,b"
+        );
+        assert_eq!(run(&mut context, "(send *g* :len)"), Ok(Type::Int(0)));
+
+        // 同一性で比較する
+        let cases = vec![
+            ("(eq *f* *f*)", Ok(Type::Int(1))),
+            ("(equal *f* *f*)", Ok(Type::Int(1))),
+            ("(eq *f* *g*)", Ok(Type::Int(0))),
+            ("(equal (list *f*) (list *g*))", Ok(Type::Int(0))),
+            ("(send *f* :write 1)", Err(EvalError::TypeMismatch)),
+            ("(send *f* :close)", Err(EvalError::NotFoundFunctionName)),
+            // 型ごとにメソッドを登録する
+            ("(send *n* :len)", Err(EvalError::NotFoundFunctionName)),
+            ("(send *f* len)", Err(EvalError::TypeMismatch)),
+            ("(send 1 :len)", Err(EvalError::TypeMismatch)),
+            ("(send *f*)", Err(arity_error("send", 1))),
+            // 式には変換できない
+            ("(eval (list quote *f*))", Err(EvalError::TypeMismatch)),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(run(&mut context, src), expected, "{}", src);
         }
 
-        // atomが先頭要素でない
-        {
-            let exp = Expression::try_from("(1 2)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(_) => assert!(false),
-                Err(e) => assert_eq!(EvalError::EvaluatingNonAtomHeadList, e),
+        let res = run(&mut context, "(list *n*)").unwrap();
+        assert_eq!(res.to_string(), "(#<opaque i32>)");
+        assert_eq!(format!("{:?}", Opaque::new(1)), "#<opaque i32>");
+        assert_eq!(
+            res.as_list().unwrap().head().unwrap().as_opaque::<i32>(),
+            Some(&1)
+        );
+
+        // 最後の参照が無くなると解放される
+        let handle = Rc::new(());
+        context
+            .define_var("*h*", Type::Opaque(Opaque::new(handle.clone())))
+            .unwrap();
+        assert_eq!(Rc::strong_count(&handle), 2);
+        run(&mut context, "(set *h* 0)").unwrap();
+        assert_eq!(Rc::strong_count(&handle), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn opaque_serde_tests() {
+        let mut context = Context::new();
+        context
+            .define_var("*o*", Type::Opaque(Opaque::new(1)))
+            .unwrap();
+        assert!(context.snapshot().to_bytes().is_err());
+        assert!(serde_json::to_vec(&Type::Opaque(Opaque::new(1))).is_err());
+    }
+
+    #[test]
+    fn opaque_finalizer_tests() {
+        use core::cell::RefCell;
+
+        let closed: Rc<RefCell<Vec<i32>>> = Rc::new(RefCell::new(Vec::new()));
+        let socket = |port: i32| {
+            let closed = closed.clone();
+            return Opaque::with_finalizer(port, move |port: i32| closed.borrow_mut().push(port));
+        };
+        let run = |context: &mut Context, src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, context).unwrap();
+        };
+
+        let mut context = Context::new();
+        let s = socket(1);
+        let weak = s.downgrade();
+        context.define_var("*s*", Type::Opaque(s)).unwrap();
+        run(&mut context, "(define *l* (list *s* *s*))");
+        // スナップショットも参照を持つ
+        let snapshot = context.snapshot();
+        run(&mut context, "(progn (set *s* 0) (set *l* 0))");
+        assert!(closed.borrow().is_empty());
+        assert!(weak.upgrade().is_some());
+        context.restore(&snapshot);
+        drop(snapshot);
+        run(&mut context, "(progn (set *s* 0) (set *l* 0))");
+        assert_eq!(*closed.borrow(), vec![1]);
+        assert_eq!(weak.upgrade(), None);
+        assert_eq!(format!("{:?}", weak), "#<weak-opaque i32>");
+
+        // 関数の引数やローカル変数の参照は、呼び出しから戻ると無くなる
+        context.define_var("*t*", Type::Opaque(socket(2))).unwrap();
+        run(&mut context, "(defun f (*x*) (let ((*y* *x*)) 0))");
+        run(&mut context, "(progn (f *t*) (set *t* 0))");
+        assert_eq!(*closed.borrow(), vec![1, 2]);
+
+        // Context を破棄すると、持っていた値のファイナライザが呼び出される
+        context.define_var("*u*", Type::Opaque(socket(3))).unwrap();
+        drop(context);
+        assert_eq!(*closed.borrow(), vec![1, 2, 3]);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_tests() {
+        use std::sync::Mutex;
+
+        // 他のテストのイベントも届くので、このテストの名前を含むものだけを見る
+        struct Recorder(Mutex<Vec<String>>);
+        impl log::Log for Recorder {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                return true;
+            }
+            fn log(&self, record: &log::Record) {
+                let line = format!("{} {}", record.level(), record.args());
+                if line.contains("logtest") {
+                    self.0.lock().unwrap().push(line);
+                }
             }
+            fn flush(&self) {}
         }
+        static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+        log::set_logger(&RECORDER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut context = Context::new();
+        context.register_fn("logtest-host", |a: i32| a);
+        let exp = Expression::try_from(
+            "(progn (defun logtest-f (*x*) (logtest-host *x*)) (define *logtest* (logtest-f 1)) (set *logtest* 2) (raise \"logtest\"))"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(eval_with_context(&exp, &mut context).is_err());
+        assert_eq!(
+            *RECORDER.0.lock().unwrap(),
+            vec![
+                "TRACE apply logtest-f (1 args)",
+                "TRACE apply host function logtest-host (1 args)",
+                "TRACE define *logtest* = 1",
+                "TRACE set *logtest* = 2",
+                "DEBUG error: Raised(Str(\"logtest\"))",
+            ]
+        );
     }
 
+    #[cfg(feature = "tracing")]
     #[test]
-    fn comparision_operation_tests() {
-        // gt
-        {
-            let exp = Expression::try_from("(gt 3 2)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(1)) => assert!(true),
-                _ => assert!(false),
-            }
+    fn tracing_tests() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        // 他のテストの span も届くので、関数名に spantest を含むものだけを記録する
+        struct Recorder {
+            next_id: AtomicU64,
+            spans: Mutex<Vec<(u64, String)>>,
+            log: Mutex<Vec<String>>,
         }
-        {
-            let exp = Expression::try_from("(gt 2 3)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(0)) => assert!(true),
-                _ => assert!(false),
+        struct Fields(String);
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+                self.0 += &format!(" {}={:?}", field.name(), value);
+            }
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0 += &format!(" {}={}", field.name(), value);
             }
         }
-
-        // lt
-        {
-            let exp = Expression::try_from("(lt 3 2)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(0)) => assert!(true),
-                _ => assert!(false),
+        // std を無効にした tracing では、static な値への参照を subscriber にする
+        impl tracing::Subscriber for &'static Recorder {
+            fn enabled(&self, _: &Metadata) -> bool {
+                return true;
+            }
+            fn new_span(&self, span: &Attributes) -> Id {
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut fields = Fields(String::from(span.metadata().name()));
+                span.record(&mut fields);
+                if fields.0.contains("spantest") {
+                    self.spans.lock().unwrap().push((id, fields.0));
+                }
+                return Id::from_u64(id);
+            }
+            fn record(&self, _: &Id, _: &Record) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event) {}
+            fn enter(&self, span: &Id) {
+                self.mark("enter", span);
+            }
+            fn exit(&self, span: &Id) {
+                self.mark("exit", span);
             }
         }
-        {
-            let exp = Expression::try_from("(lt 2 3)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(1)) => assert!(true),
-                _ => assert!(false),
+        impl Recorder {
+            fn mark(&self, what: &str, span: &Id) {
+                let spans = self.spans.lock().unwrap();
+                if let Some((_, name)) = spans.iter().find(|(id, _)| *id == span.into_u64()) {
+                    self.log.lock().unwrap().push(format!("{} {}", what, name));
+                }
             }
         }
 
-        // eq
-        {
-            let exp = Expression::try_from("(eq 3 3)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(1)) => assert!(true),
-                _ => assert!(false),
-            }
+        static RECORDER: Recorder = Recorder {
+            next_id: AtomicU64::new(0),
+            spans: Mutex::new(Vec::new()),
+            log: Mutex::new(Vec::new()),
+        };
+        tracing::subscriber::set_global_default(&RECORDER).unwrap();
+
+        let mut context = Context::new();
+        context.register_fn("spantest-host", |a: i32, b: i32| a + b);
+        let exp = Expression::try_from(
+            "(progn (defun spantest-f (*x*) (spantest-host *x* 1)) (spantest-f 1))".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(2)));
+        // 関数の適用は入れ子の span になる
+        assert_eq!(
+            *RECORDER.log.lock().unwrap(),
+            vec![
+                "enter call function=spantest-f arity=1",
+                "enter call function=spantest-host arity=2",
+                "exit call function=spantest-host arity=2",
+                "exit call function=spantest-f arity=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn var_change_tests() {
+        let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut context = Context::new();
+        let recorded = log.clone();
+        context.on_var_change(move |name, old, new| {
+            let show = |t: Option<&Type>| t.map_or(String::from("-"), |t| t.to_string());
+            recorded
+                .borrow_mut()
+                .push(format!("{} {} -> {}", name, show(old), show(new)));
+        });
+        let cases = vec![
+            ("(define *a* 1)", vec!["*a* - -> 1"]),
+            ("(set *a* 2 *b* 3)", vec!["*a* 1 -> 2", "*b* - -> 3"]),
+            ("(incf *a*)", vec!["*a* 2 -> 3"]),
+            ("(defconst *c* 1)", vec!["*c* - -> 1"]),
+            // エラーになった代入は通知しない。catch の変数の束縛は通知する
+            (
+                "(try (set *c* 2) (catch *e* 0))",
+                vec!["*e* - -> AssignToConstant", "*e* AssignToConstant -> -"],
+            ),
+            (
+                "(let ((*a* 10) (*d* 0)) (set *a* 11))",
+                vec![
+                    "*a* 3 -> 10",
+                    "*d* - -> 0",
+                    "*a* 10 -> 11",
+                    "*a* 11 -> 3",
+                    "*d* 0 -> -",
+                ],
+            ),
+            (
+                "(progn (defun f (*x*) *x*) (f 1))",
+                vec!["*x* - -> 1", "*x* 1 -> -"],
+            ),
+            // 例外で抜けた場合も、外側の値に戻る
+            (
+                "(try (let ((*a* 0)) (raise 1)) (catch *e* 0))",
+                vec!["*a* 3 -> 0", "*a* 0 -> 3", "*e* - -> 1", "*e* 1 -> -"],
+            ),
+        ];
+        for (src, expected) in cases {
+            log.borrow_mut().clear();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            assert_eq!(*log.borrow(), expected, "{}", src);
         }
-        {
-            let exp = Expression::try_from("(eq 2 3)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(0)) => assert!(true),
-                _ => assert!(false),
-            }
+        // ホスト側の define_var では通知しない
+        log.borrow_mut().clear();
+        context.define_var("*a*", Type::Int(0)).unwrap();
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn dynamic_var_tests() {
+        let clock = Rc::new(std::cell::Cell::new(0));
+        let now = clock.clone();
+        let mut context = Context::new();
+        context.bind_dynamic("*time*", move || {
+            now.set(now.get() + 1);
+            return Type::Int(now.get());
+        });
+        let cases = vec![
+            // 参照するたびに計算する
+            ("(list *time* *time*)", Ok("(1 2)")),
+            ("(boundp *time*)", Ok("1")),
+            ("(set *time* 0)", Err(EvalError::AssignToConstant)),
+            ("(define *time* 0)", Err(EvalError::AssignToConstant)),
+            ("(defconst *time* 0)", Err(EvalError::AssignToConstant)),
+            // incf は値を読んでから書き込もうとする
+            ("(incf *time*)", Err(EvalError::AssignToConstant)),
+            // ローカル変数で隠すことはできる
+            ("(let ((*time* 0)) (set *time* 5) *time*)", Ok("5")),
+            ("(progn (defun f (*time*) *time*) (f 10))", Ok("10")),
+            ("*time*", Ok("4")),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, expected.map(String::from), "{}", src);
         }
+        assert_eq!(clock.get(), 4);
+
+        assert!(context.unbind_dynamic("*time*"));
+        assert!(!context.unbind_dynamic("*time*"));
+        let exp = Expression::try_from("(boundp *time*)".as_bytes()).unwrap();
+        assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(0)));
     }
 
     #[test]
-    fn list_tests() {
-        // list
-        {
-            let exp = eval(&Expression::try_from("(list 1 2 3)".as_bytes()).unwrap());
-            assert_eq!(
-                exp,
-                Ok(Type::TypeList(Rc::new(TypeList::Cons(
-                    Type::Int(1),
-                    Rc::new(TypeList::Cons(
-                        Type::Int(2),
-                        Rc::new(TypeList::Cons(Type::Int(3), Rc::new(TypeList::Nil)))
-                    ))
-                ))))
-            );
+    fn child_context_tests() {
+        let run = |context: &mut Context, src: &str| {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            return eval_with_context(&exp, context).map(|t| t.to_string());
+        };
+        let mut base = Context::new();
+        base.register_fn("double", |a: i32| a * 2);
+        run(
+            &mut base,
+            "(progn (define *n* 1) (defconst *k* 5) (defun f () (double *n*)))",
+        )
+        .unwrap();
+
+        let mut child = base.child();
+        let cases = vec![
+            ("(f)", Ok("2")),
+            ("(progn (set *n* 10) (f))", Ok("20")),
+            ("(progn (define *m* 3) (defun f () *m*) (f))", Ok("3")),
+            ("(set *k* 0)", Err(EvalError::AssignToConstant)),
+            ("(define *k* 0)", Err(EvalError::AssignToConstant)),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(run(&mut child, src), expected.map(String::from), "{}", src);
         }
-        {
-            let exp = eval(&Expression::try_from("(list a b c)".as_bytes()).unwrap());
+        // 子での定義や書き換えは、元の Context に影響しない
+        assert_eq!(
+            run(&mut base, "(list *n* (f) (boundp *m*))"),
+            Ok("(1 2 0)".into())
+        );
+        assert_eq!(run(&mut base.child(), "*n*"), Ok("1".into()));
+
+        // スナップショットは、元の Context の変数も含む
+        let snapshot = child.snapshot();
+        let mut restored = Context::new();
+        restored.restore(&snapshot);
+        assert_eq!(
+            run(&mut restored, "(list *n* *k* *m*)"),
+            Ok("(10 5 3)".into())
+        );
+    }
+
+    #[test]
+    fn limit_tests() {
+        use crate::eval::*;
+
+        let cases = vec![
+            ("(progn (set *a* 1) (set *b* 2) (set *a* 3) (list *a* *b*))", Ok(Type::Int(2))),
+            ("(progn (set *a* 1) (set *b* 2) (set *c* 3))", Err(EvalError::LimitExceeded)),
+            ("(progn (define *a* 1) (defconst *b* 2) (define *c* 3))", Err(EvalError::LimitExceeded)),
+            ("(progn (set *a* 1) (defconst *b* 2) (defconst *c* 3))", Err(EvalError::LimitExceeded)),
+            // ローカルな変数は数えない
+            ("(progn (set *a* 1) (set *b* 2) (let ((*c* 3) (*d* 4)) (progn (define *e* 5) (add *c* (add *d* *e*)))))", Ok(Type::Int(12))),
+            ("(progn (set *a* 1) (defun f (*x* *y*) (add *x* *y*)) (f 1 2))", Ok(Type::Int(3))),
+            // catch で捕捉できる
+            ("(progn (set *a* 1) (set *b* 2) (try (set *c* 3) (catch *e* *e*)))", Ok(Type::Atom("LimitExceeded".into()))),
+            // 文字列で表すと "abcdefgh" で 10 バイト
+            ("(set *a* \"abcdefgh\")", Ok(Type::Str("abcdefgh".into()))),
+            ("(set *a* \"abcdefghi\")", Err(EvalError::LimitExceeded)),
+            ("(define *a* (list 1 2 3 4 5))", Err(EvalError::LimitExceeded)),
+            ("(defconst *a* (list 1 2 3 4 5))", Err(EvalError::LimitExceeded)),
+            ("(let ((*a* 1)) (set *a* \"abcdefghijk\"))", Err(EvalError::LimitExceeded)),
+            // 上限を超えた代入では、変数は変わらない
+            ("(progn (set *a* 1) (try (set *a* \"abcdefghijk\") (catch *e* 0)) *a*)", Ok(Type::Int(1))),
+            // 引数や let で束縛する値は制限しない
+            ("(let ((*a* \"abcdefghijk\")) *a*)", Ok(Type::Str("abcdefghijk".into()))),
+            // 定数の書き換えは AssignToConstant
+            ("(progn (defconst *a* 1) (set *a* \"abcdefghijk\"))", Err(EvalError::AssignToConstant)),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            context.set_max_variables(Some(2));
+            context.set_max_value_size(Some(10));
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|v| match v {
+                Type::TypeList(l) => l.iter().last().cloned().unwrap(),
+                v => v,
+            });
+            assert_eq!(res, expected, "{}", src);
+        }
+
+        // ホスト側の define_var は制限しない
+        let mut context = Context::new();
+        context.set_max_variables(Some(1));
+        context.define_var("*a*", Type::Int(1)).unwrap();
+        context.define_var("*b*", Type::Int(2)).unwrap();
+        let exp = Expression::try_from("(set *c* 3)".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Err(EvalError::LimitExceeded)
+        );
+
+        // child で作った Context では、元の Context の変数も数える
+        let mut base = Context::new();
+        base.define_var("*a*", Type::Int(1)).unwrap();
+        base.set_max_variables(Some(2));
+        let mut child = base.child();
+        let exp = Expression::try_from("(progn (set *a* 10) (set *b* 2))".as_bytes()).unwrap();
+        assert!(eval_with_context(&exp, &mut child).is_ok());
+        let exp = Expression::try_from("(set *c* 3)".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut child),
+            Err(EvalError::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn depth_limit_tests() {
+        let defs = "(progn (defun forever (*n*) (add 1 (forever *n*))) \
+                    (defun ping (*n*) (funcall #'pong *n*)) \
+                    (defun pong (*n*) (ping *n*)) \
+                    (defun fact (*n*) (cond (eq *n* 0) 1 (mul *n* (fact (sub *n* 1))))))";
+        let cases = vec![
+            ("(forever 0)", Err(EvalError::DepthLimitExceeded)),
+            ("(ping 0)", Err(EvalError::DepthLimitExceeded)),
+            ("(fact 10)", Ok(Type::Int(3628800))),
+            // catch で捕捉できる
+            (
+                "(try (forever 0) (catch *e* *e*))",
+                Ok(Type::Atom("DepthLimitExceeded".into())),
+            ),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            context.set_max_depth(Some(100));
+            let exp = Expression::try_from(defs.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval_with_context(&exp, &mut context), expected, "{}", src);
+            // エラーで抜けた後も、深さは元に戻る
+            let exp = Expression::try_from("(fact 3)".as_bytes()).unwrap();
             assert_eq!(
-                exp,
-                Ok(Type::TypeList(Rc::new(TypeList::Cons(
-                    Type::Atom("a"),
-                    Rc::new(TypeList::Cons(
-                        Type::Atom("b"),
-                        Rc::new(TypeList::Cons(Type::Atom("c"), Rc::new(TypeList::Nil)))
-                    ))
-                ))))
+                eval_with_context(&exp, &mut context),
+                Ok(Type::Int(6)),
+                "{}",
+                src
             );
         }
 
-        // head
-        {
-            let exp =
-                eval(&Expression::try_from("(head (list 10 (list 20) 30))".as_bytes()).unwrap());
-            assert_eq!(exp, Ok(Type::Int(10)));
-        }
+        // 深さは stats の max_depth と同じく数える
+        let exp = Expression::try_from("(add 1 (add 1 1))".as_bytes()).unwrap();
+        let mut context = Context::new();
+        context.set_max_depth(Some(3));
+        assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(3)));
+        assert_eq!(context.stats().max_depth, 3);
+        context.set_max_depth(Some(2));
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Err(EvalError::DepthLimitExceeded)
+        );
+    }
 
-        // tail
-        {
-            let exp = eval(&Expression::try_from("(tail (list 1 2 3))".as_bytes()).unwrap());
+    #[test]
+    fn stats_tests() {
+        use crate::eval::*;
+
+        // (式, (steps, conses, max_depth))
+        let cases = vec![
+            ("1", (1, 0, 1)),
+            ("(add 1 (mul 2 3))", (5, 0, 3)),
+            ("(list 1 2 3)", (4, 3, 2)),
+            ("(take 2 (list 1 2 3))", (6, 5, 3)),
+            ("(drop 1 (range 3))", (4, 4, 3)),
+            // tail は先頭のセルだけを新しく作り、残りは元のリストと共有する
+            ("(progn (set *a* (list 1 2 3)) (tail *a*))", (8, 4, 4)),
+            ("(tail (list 1 2 3))", (5, 4, 3)),
+            ("(quote (1 2 3))", (1, 0, 1)),
+            (
+                "(progn (defun f (*n*) (cond (lt *n* 1) 0 (f (sub *n* 1)))) (f 3))",
+                (33, 0, 11),
+            ),
+        ];
+        for (src, expected) in cases {
+            let mut context = Context::new();
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            eval_with_context(&exp, &mut context).unwrap();
+            let stats = context.stats();
             assert_eq!(
-                exp,
-                Ok(Type::TypeList(Rc::new(TypeList::Cons(
-                    Type::Int(2),
-                    Rc::new(TypeList::Cons(Type::Int(3), Rc::new(TypeList::Nil)))
-                ))))
+                (stats.steps, stats.conses, stats.max_depth),
+                expected,
+                "{}",
+                src
             );
         }
+
+        let mut context = Context::new();
+        context.register_fn("pair", |args: &[Type]| {
+            return Ok(Type::TypeList(Rc::new(
+                TypeList::new().cons(&args[1]).cons(&args[0]),
+            )));
+        });
+        let exp = Expression::try_from(
+            "(progn (dotimes (*i* 3) (add *i* 1)) (pair 1 2) (sort (list 2 1)))".as_bytes(),
+        )
+        .unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+        let calls: Vec<(&str, u64)> = context.stats().builtin_calls.into_iter().collect();
+        assert_eq!(
+            calls,
+            vec![
+                ("add", 3),
+                ("dotimes", 1),
+                ("list", 1),
+                ("lt", 1),
+                ("progn", 1),
+                ("sort", 1)
+            ]
+        );
+        assert_eq!(context.stats().conses, 6);
+
+        // 統計は積算し、reset_stats で 0 に戻す
+        eval_with_context(&exp, &mut context).unwrap();
+        assert_eq!(context.stats().builtin_calls.get("add"), Some(&6));
+        context.reset_stats();
+        assert_eq!(context.stats(), EvalStats::default());
+        assert_eq!(context.child().stats(), EvalStats::default());
     }
 
     #[test]
-    fn cond_tests() {
-        {
-            let exp = Expression::try_from("(cond (eq 3 2) 10 (mul 20 10))".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(200)) => assert!(true),
-                _ => assert!(false),
-            }
+    fn compare_tests() {
+        use crate::eval::*;
+
+        let incomparable = |op: &str, left: &str, right: &str| {
+            return Err(EvalError::Incomparable {
+                op: op.into(),
+                left: left.into(),
+                right: right.into(),
+            });
+        };
+        let cases = vec![
+            ("(ge 2 1)", Ok(Type::Int(1))),
+            ("(ge 1 1)", Ok(Type::Int(1))),
+            ("(ge 1 2)", Ok(Type::Int(0))),
+            ("(le 1 2)", Ok(Type::Int(1))),
+            ("(le 2 2)", Ok(Type::Int(1))),
+            ("(le 3 2)", Ok(Type::Int(0))),
+            ("(ne 1 2)", Ok(Type::Int(1))),
+            ("(ne 1 1)", Ok(Type::Int(0))),
+            ("(ge b a)", Ok(Type::Int(1))),
+            ("(le :a :b)", Ok(Type::Int(1))),
+            ("(ge (div 1 2) (div 1 3))", Ok(Type::Int(1))),
+            ("(le 1 (div 1 2))", Ok(Type::Int(0))),
+            ("(ne (div 2 4) (div 1 2))", Ok(Type::Int(0))),
+            ("(ne \"a\" \"b\")", Ok(Type::Int(1))),
+            ("(ne \"a\" \"a\")", Ok(Type::Int(0))),
+            (
+                "(progn (define *l* (list 1)) (ne *l* *l*))",
+                Ok(Type::Int(0)),
+            ),
+            ("(ne (list 1) (list 1))", Ok(Type::Int(1))),
+            ("(ge 1)", Err(arity_error("ge", 1))),
+            ("(ne 1 2 3)", Err(arity_error("ne", 3))),
+            // 比較できない組み合わせは、演算子と両辺の型の名前を持つ
+            ("(gt 1 a)", incomparable("gt", "Int", "Atom")),
+            ("(lt a 1)", incomparable("lt", "Atom", "Int")),
+            ("(ge :a \"a\")", incomparable("ge", "Keyword", "Str")),
+            ("(le (div 1 2) a)", incomparable("le", "Ratio", "Atom")),
+            ("(ne 1 (list 1))", incomparable("ne", "Int", "TypeList")),
+            ("(lt \"a\" \"b\")", incomparable("lt", "Str", "Str")),
+            (
+                "(try (gt 1 :a) (catch *e* *e*))",
+                Ok(Type::TypeList(Rc::new(
+                    TypeList::new()
+                        .cons(&Type::Atom("Keyword".into()))
+                        .cons(&Type::Atom("Int".into()))
+                        .cons(&Type::Atom("gt".into()))
+                        .cons(&Type::Atom("Incomparable".into())),
+                ))),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
         }
-        {
-            let exp = Expression::try_from("(cond (eq 3 3) (div 10 2) 20)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(5)) => assert!(true),
-                _ => assert!(false),
-            }
+
+        let src = "(lt 1 :a)";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        let err = eval(&exp).unwrap_err();
+        assert_eq!(
+            err.render(&SourceMap::new(src), Span { start: 0, end: 9 }),
+            "eval error: Incomparable { op: \"lt\", left: \"Int\", right: \"Keyword\" }\n --> 1:1\n  |\n1 | (lt 1 :a)\n  | ^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn truthiness_tests() {
+        use crate::eval::*;
+
+        let list = |items: &[Int]| {
+            let l = items
+                .iter()
+                .rev()
+                .fold(TypeList::new(), |acc, i| acc.cons(&Type::Int(*i)));
+            return Type::TypeList(Rc::new(l));
+        };
+        let cases = vec![
+            // 0、Void、空のリストが偽で、それ以外はすべて真
+            ("(cond 0 1 2)", Ok(Type::Int(2))),
+            ("(cond (while 0 0) 1 2)", Ok(Type::Int(2))),
+            ("(cond (list) 1 2)", Ok(Type::Int(2))),
+            ("(cond () 1 2)", Ok(Type::Int(2))),
+            ("(equal () (list))", Ok(Type::Int(1))),
+            ("(nullp ())", Ok(Type::Int(1))),
+            ("(cond -1 1 2)", Ok(Type::Int(1))),
+            ("(cond \"\" 1 2)", Ok(Type::Int(1))),
+            ("(cond (quote a) 1 2)", Ok(Type::Int(1))),
+            ("(cond :a 1 2)", Ok(Type::Int(1))),
+            ("(cond (list 0) 1 2)", Ok(Type::Int(1))),
+            ("(cond (vector) 1 2)", Ok(Type::Int(1))),
+            ("(cond (div 1 2) 1 2)", Ok(Type::Int(1))),
+            (
+                "(progn (define *l* (list 1 2 3)) (define *n* 0) (while *l* (progn (incf *n*) (set *l* (tail *l*)))) *n*)",
+                Ok(Type::Int(3)),
+            ),
+            (
+                "(progn (defun rest (*l*) (tail *l*)) (find rest (list (list 1) (list 1 2))))",
+                Ok(list(&[1, 2])),
+            ),
+            ("(assert \"s\")", Ok(Type::Void)),
+            (
+                "(assert (list))",
+                Err(EvalError::AssertionFailed {
+                    expected: Type::Int(1),
+                    actual: list(&[]),
+                }),
+            ),
+            // and は最初の偽の値、or は最初の真の値で評価をやめる
+            ("(and)", Ok(Type::Int(1))),
+            ("(or)", Ok(Type::Int(0))),
+            ("(and 1 :a 3)", Ok(Type::Int(3))),
+            ("(and 1 (list) (raise 1))", Ok(list(&[]))),
+            ("(or 0 (list) 5)", Ok(Type::Int(5))),
+            ("(or 0 () 3)", Ok(Type::Int(3))),
+            ("(and 1 () (raise 1))", Ok(list(&[]))),
+            ("(or 0 (list))", Ok(list(&[]))),
+            ("(or :a (raise 1))", Ok(Type::Keyword("a".into()))),
+            ("(and 1 (raise 1))", Err(EvalError::Raised(Type::Int(1)))),
+            (
+                "(progn (define *n* 0) (or (incf *n*) (incf *n*)) *n*)",
+                Ok(Type::Int(1)),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
         }
     }
 
     #[test]
-    fn progn_tests() {
-        {
-            let exp =
-                Expression::try_from("(progn (set *a* 10) (add *a* (add *a* 20)))".as_bytes())
-                    .unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(40)) => assert!(true),
-                _ => assert!(false),
-            }
+    fn void_tests() {
+        use crate::eval::*;
+
+        let cases = vec![
+            ("(void)", Ok(Type::Void)),
+            ("(progn)", Ok(Type::Void)),
+            ("(progn (void) 1)", Ok(Type::Int(1))),
+            ("(void 1)", Err(arity_error("void", 1))),
+            // 数値の演算や大小の比較には使えない
+            ("(add 1 (void))", Err(EvalError::VoidValue)),
+            ("(mul (while 0 0) 2)", Err(EvalError::VoidValue)),
+            ("(floor (progn))", Err(EvalError::VoidValue)),
+            ("(lt (void) 1)", Err(EvalError::VoidValue)),
+            (
+                "(progn (define *x* (void)) (sub *x* 1))",
+                Err(EvalError::VoidValue),
+            ),
+            // eq と ne では、Void 同士だけが同じになる
+            ("(eq (void) (progn))", Ok(Type::Int(1))),
+            ("(eq (void) 0)", Ok(Type::Int(0))),
+            ("(ne (list) (void))", Ok(Type::Int(1))),
+            (
+                "(try (add (void) 1) (catch *e* *e*))",
+                Ok(Type::Atom("VoidValue".into())),
+            ),
+            // Void を返すマクロは (void) に展開される
+            (
+                "(progn (defmacro nothing () (void)) (macroexpand (nothing)))",
+                Ok(Type::TypeList(Rc::new(
+                    TypeList::new().cons(&Type::Atom("void".into())),
+                ))),
+            ),
+            (
+                "(progn (defmacro nothing () (void)) (nothing))",
+                Ok(Type::Void),
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&exp), expected, "{}", src);
         }
+
+        let exp = Expression::try_from("(list 1 (void))".as_bytes()).unwrap();
+        assert_eq!(eval(&exp).unwrap().to_string(), "(1 #<void>)");
+        assert_eq!(
+            type_to_expression(&Type::Void).map(|e| e.to_string()),
+            Ok("(void)".to_string())
+        );
     }
 
     #[test]
-    fn while_tests() {
-        {
-            let exp = Expression::try_from("(progn (set *i* 0) (set *a* 0) (while (lt *i* 10) (progn (set *a* (add *i* *a*)) (set *i* (add *i* 1)))) *a*)".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Int(45)) => assert!(true),
-                _ => assert!(false),
-            }
+    fn function_tests() {
+        use crate::eval::*;
+
+        let cases =
+            vec![
+            ("(function add)", Ok("#'add")),
+            ("#'add", Ok("#'add")),
+            ("(funcall #'add 1 2)", Ok("3")),
+            ("(funcall (function list))", Ok("()")),
+            (
+                "(progn (defun sq (*x*) (mul *x* *x*)) (funcall #'sq 3))",
+                Ok("9"),
+            ),
+            // 関数名の Atom も呼び出せる
+            ("(funcall (quote sub) 3 1)", Ok("2")),
+            ("(progn (define *f* #'gt) (funcall *f* 2 1))", Ok("1")),
+            ("(sort (list 1 3 2) #'gt)", Ok("(3 2 1)")),
+            ("(find #'intp (list a 2 3))", Ok("2")),
+            ("(count (function atomp) (list a 2 b))", Ok("2")),
+            ("(eq #'add (function add))", Ok("1")),
+            ("(eq #'add #'sub)", Ok("0")),
+            // Context を使う組み込み関数も、特殊形式でなければ関数として扱える
+            ("(function sort)", Ok("#'sort")),
+            ("(funcall #'sort (list 3 1 2))", Ok("(1 2 3)")),
+            ("(funcall #'member 2 (list 1 2 3))", Ok("(2 3)")),
+            ("(funcall #'random-seed 7)", Ok("7")),
+            ("(funcall #'run-tests)", Ok("(() ())")),
+            ("(pmap #'sort (list (list 2 1) (list 4 3)))", Ok("((1 2) (3 4))")),
+            // モジュール内では、モジュールで修飾した関数を参照する
+            (
+                "(progn (module m (defun f () 1) (define *g* #'f)) (list *m:g* (funcall *m:g*)))",
+                Ok("(#'m:f 1)"),
+            ),
+            // 呼び出す時点の定義を使う
+            (
+                "(progn (defun f () 1) (define *g* #'f) (defun f () 2) (funcall *g*))",
+                Ok("2"),
+            ),
+            ("(function undefined)", Err(EvalError::NotFoundFunctionName)),
+            ("(function cond)", Err(EvalError::NotFoundFunctionName)),
+            ("(function \"add\")", Err(EvalError::TypeMismatch)),
+            ("(function add sub)", Err(arity_error("function", 2))),
+            ("(funcall)", Err(arity_error("funcall", 0))),
+            ("(funcall 1 2)", Err(EvalError::TypeMismatch)),
+            ("(funcall #'add 1)", Err(arity_error("add", 1))),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval(&exp).map(|t| t.to_string());
+            assert_eq!(res, expected.map(String::from), "{}", src);
         }
-        // whileは Void を返す
-        {
-            let exp = Expression::try_from("(progn (set *i* 0) (set *a* 0) (while (lt *i* 10) (progn (set *a* (add *i* *a*)) (set *i* (add *i* 1)))))".as_bytes()).unwrap();
-            match eval(&exp) {
-                Ok(Type::Void) => assert!(true),
-                _ => assert!(false),
+
+        let mut context = Context::new();
+        context.register_fn("twice", |x: i32| x * 2);
+        let exp = Expression::try_from("(funcall #'twice 4)".as_bytes()).unwrap();
+        assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(8)));
+        assert_eq!(
+            type_to_expression(&Type::Function("add".into())).map(|e| e.to_string()),
+            Ok("(function add)".to_string())
+        );
+    }
+
+    #[test]
+    fn describe_tests() {
+        use crate::eval::*;
+
+        let mut context = Context::new();
+        context.register_fn("twice", |x: i32| x * 2);
+        let src = "(progn \
+                   (defun f (*a* &optional *b*) \"f の説明\" (list *a* *b*)) \
+                   (defun g (*a* &rest *r*) *a*) \
+                   (defun h () \"h は文字列を返す\") \
+                   (defmacro m (*x*) \"m の説明\" *x*) \
+                   (module mod (defun k () \"k の説明\" 1)))";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+
+        let d = context.describe("f").unwrap();
+        assert_eq!(
+            d,
+            Description {
+                name: "f".into(),
+                kind: SymbolKind::Function,
+                arity: Some((1, Some(2))),
+                doc: Some("f の説明".into()),
             }
+        );
+        assert_eq!(context.describe("g").unwrap().arity, Some((1, None)));
+        // 後に式の無い文字列は、ドキュメント文字列ではなく戻り値になる
+        assert_eq!(context.describe("h").unwrap().doc, None);
+        let exp = Expression::try_from("(h)".as_bytes()).unwrap();
+        assert_eq!(
+            eval_with_context(&exp, &mut context),
+            Ok(Type::Str("h は文字列を返す".into()))
+        );
+        assert_eq!(context.describe("m").unwrap().kind, SymbolKind::Macro);
+        assert_eq!(
+            context.describe("mod:k").unwrap().doc,
+            Some("k の説明".into())
+        );
+        assert_eq!(
+            context.describe("twice"),
+            Some(Description {
+                name: "twice".into(),
+                kind: SymbolKind::HostFunction,
+                arity: None,
+                doc: None,
+            })
+        );
+        let d = context.describe("add").unwrap();
+        assert_eq!((d.kind, d.arity), (SymbolKind::Builtin, Some((2, Some(2)))));
+        let d = context.describe("list").unwrap();
+        assert_eq!((d.kind, d.arity), (SymbolKind::Builtin, Some((0, None))));
+        assert_eq!(
+            context.describe("cond").unwrap().kind,
+            SymbolKind::SpecialForm
+        );
+        assert_eq!(context.describe("sort").unwrap().kind, SymbolKind::Builtin);
+        assert_eq!(context.describe("undefined"), None);
+
+        let cases = vec![
+            (
+                "(describe f)",
+                Ok("((:name f) (:kind :function) (:min-args 1) (:max-args 2) (:doc \"f の説明\"))"),
+            ),
+            (
+                "(describe #'g)",
+                Ok("((:name g) (:kind :function) (:min-args 1) (:max-args ()) (:doc ()))"),
+            ),
+            (
+                "(describe twice)",
+                Ok("((:name twice) (:kind :host-function) (:min-args ()) (:max-args ()) (:doc ()))"),
+            ),
+            (
+                "(describe while)",
+                Ok("((:name while) (:kind :special-form) (:min-args 2) (:max-args 5) (:doc ()))"),
+            ),
+            ("(describe undefined)", Err(EvalError::NotFoundFunctionName)),
+            ("(describe 1)", Err(EvalError::TypeMismatch)),
+            ("(describe)", Err(arity_error("describe", 0))),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let res = eval_with_context(&exp, &mut context).map(|t| t.to_string());
+            assert_eq!(res, expected.map(String::from), "{}", src);
         }
     }
 
     #[test]
-    fn set_tests() {
-        let exp = Expression::try_from("(set *i* 1)".as_bytes()).unwrap();
-        match eval(&exp) {
-            Ok(Type::Int(1)) => assert!(true),
-            _ => assert!(false),
+    fn complete_tests() {
+        use crate::eval::*;
+
+        let mut context = Context::new();
+        context.register_fn("li-host", |x: i32| x);
+        context.bind_dynamic("*litime*", || Type::Int(0));
+        let src = "(progn (define *lix* 1) (defun li-fn () 1) (defmacro li-macro () 1) (defun list () 2))";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        eval_with_context(&exp, &mut context).unwrap();
+
+        let items = |context: &Context, prefix: &str| {
+            return context
+                .complete(prefix)
+                .iter()
+                .map(|c| (c.label.to_string(), c.kind))
+                .collect::<Vec<_>>();
+        };
+        // 組み込み関数の list は、ユーザ定義関数で上書きされている
+        assert_eq!(
+            items(&context, "li"),
+            vec![
+                (String::from("li-fn"), SymbolKind::Function),
+                (String::from("li-host"), SymbolKind::HostFunction),
+                (String::from("li-macro"), SymbolKind::Macro),
+                (String::from("list"), SymbolKind::Function),
+                (String::from("list->vector"), SymbolKind::Builtin),
+                (String::from("listp"), SymbolKind::Builtin),
+            ]
+        );
+        assert_eq!(
+            items(&context, "*li"),
+            vec![
+                (String::from("*litime*"), SymbolKind::Variable),
+                (String::from("*lix*"), SymbolKind::Variable),
+            ]
+        );
+        assert_eq!(
+            items(&context, "whi"),
+            vec![(String::from("while"), SymbolKind::SpecialForm)]
+        );
+        assert!(items(&context, "zzz").is_empty());
+
+        context.disable_builtins(&["while"]);
+        assert!(items(&context, "whi").is_empty());
+    }
+
+    #[test]
+    fn print_tests() {
+        let output = MemoryOutput::new();
+        let mut context = Context::new();
+        context.set_output(Box::new(output.clone()));
+        let cases = vec![
+            (
+                "(print \"a b\" 1 (list \"c\" :k) [1])",
+                "a b 1 (\"c\" :k) #(1)\n",
+            ),
+            ("(print)", "\n"),
+            ("(dotimes (*i* 2) (print *i*))", "0\n1\n"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(
+                eval_with_context(&exp, &mut context),
+                Ok(Type::Void),
+                "{}",
+                src
+            );
+            assert_eq!(output.take(), expected, "{}", src);
         }
+
+        // child で作った Context は、書き込み先を共有する
+        let exp = Expression::try_from("(print 1)".as_bytes()).unwrap();
+        eval_with_context(&exp, &mut context.child()).unwrap();
+        assert_eq!(output.take(), "1\n");
+
+        // replace_output で、書き込み先を一時的に置き換えられる
+        let other = MemoryOutput::new();
+        let previous = context.replace_output(Box::new(other.clone()));
+        eval_with_context(&exp, &mut context).unwrap();
+        context.replace_output(previous);
+        eval_with_context(&exp, &mut context).unwrap();
+        assert_eq!(
+            (other.contents(), output.contents()),
+            ("1\n".into(), "1\n".into())
+        );
     }
 
+    #[test]
+    fn eval_with_args_tests() {
+        let run = |src: &str, args: &[(&str, Type)]| {
+            return eval_with_args(&Expression::try_from(src.as_bytes()).unwrap(), args);
+        };
+        let list = Type::TypeList(Rc::new(
+            TypeList::new().cons(&Type::Int(2)).cons(&Type::Int(1)),
+        ));
+        let cases = vec![
+            (
+                "(add *a* *b*)",
+                vec![("*a*", Type::Int(1)), ("*b*", Type::Int(2))],
+                Ok(Type::Int(3)),
+            ),
+            ("(head *l*)", vec![("*l*", list)], Ok(Type::Int(1))),
+            // 同じ名前は後のもので上書きする
+            (
+                "*a*",
+                vec![("*a*", Type::Int(1)), ("*a*", Type::Int(5))],
+                Ok(Type::Int(5)),
+            ),
+            // 定義した変数は、通常のグローバルな変数として書き換えられる
+            (
+                "(progn (incf *a*) *a*)",
+                vec![("*a*", Type::Int(1))],
+                Ok(Type::Int(2)),
+            ),
+            ("*a*", vec![], Err(EvalError::UndefinedVariableReference)),
+            ("1", vec![("a", Type::Int(1))], Err(EvalError::TypeMismatch)),
+            (
+                "1",
+                vec![("*a* *b*", Type::Int(1))],
+                Err(EvalError::TypeMismatch),
+            ),
+            (
+                "1",
+                vec![("*a", Type::Int(1))],
+                Err(EvalError::TypeMismatch),
+            ),
+        ];
+        for (src, args, expected) in cases {
+            assert_eq!(run(src, &args), expected, "{}", src);
+        }
+    }
 }
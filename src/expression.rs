@@ -2,164 +2,700 @@
 //! lisp構造の表現型、及び文字列からの変換関数を定義
 //!
 
+use crate::arena::*;
+use crate::intern::Interner;
+use crate::lexer::*;
+use crate::source_map::SourceMap;
+use crate::types::Int;
 use crate::util::*;
-use std::convert::TryFrom;
-use std::rc::Rc;
+use crate::visit::{fold, walk, ExpressionVisitor};
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
 
-pub type ExpressionList<'a> = List<Expression<'a>>;
+pub type ExpressionList = List<Expression>;
 
 /// Lispの式定義
-#[derive(Debug, Clone, PartialEq)]
-pub enum Expression<'a> {
-    Int(i32),
-    Atom(&'a str), // Expressionをcloneしたとき、Stringがcloneされるとコピーコストが大きくなる恐れがある（未検証）ので、Rcingする
-    Var(&'a str),
-    ExpressionList(Rc<ExpressionList<'a>>),
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expression {
+    Int(Int),
+    Atom(Rc<str>), // Expressionをcloneしたとき、Stringがcloneされるとコピーコストが大きくなる恐れがある（未検証）ので、Rcingする
+    Var(Rc<str>),
+    Str(Rc<str>),
+    Keyword(Rc<str>), // :x の形式。名前は先頭の : を除いて持つ
+    ExpressionList(Rc<ExpressionList>),
 }
 
 /// byte列を Expression に変換したときに発生したエラー
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExpressionConversionError {
     InvalidToken,
+    UnexpectedEof,
+    IntOverflow,
+    TooDeep,  // リストや quote の入れ子が ParseLimits::max_depth を超えた
+    TooLarge, // 入力が ParseLimits::max_size を超えた
     Unexpected(String),
 }
 
-impl<'a> TryFrom<&'a [u8]> for Expression<'a> {
+/// `Expression::metrics` で数えた、式の大きさと複雑さ
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExpressionMetrics {
+    pub nodes: usize,                 // リストも含めた、全ての式の数
+    pub depth: usize,                 // リストの入れ子の深さ。リストでない式は 0、`(a (b))` は 2
+    pub atoms: usize, // リスト以外の式（整数、シンボル、変数、文字列、キーワード）の数
+    pub functions: BTreeSet<Rc<str>>, // 評価される位置で呼び出される関数や特殊形式の名前
+}
+
+/// 読み込みのエラーと、その原因になったソース中の位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub error: ExpressionConversionError,
+    pub span: Span, // 原因になったトークンの位置。入力が途中で終わった場合は、入力の末尾の長さ 0 の位置
+}
+
+impl ParseError {
+    /// `span` の位置で起きた `error` の `ParseError` を新規作成
+    pub fn new(error: ExpressionConversionError, span: Span) -> ParseError {
+        return ParseError { error, span };
+    }
+
+    /// `map` を使って、エラーの位置をソースの行と共に示す、複数行のメッセージにする
+    pub fn render(&self, map: &SourceMap) -> String {
+        return map.render(self.span, &format!("parse error: {:?}", self.error));
+    }
+}
+
+/// 信頼できない入力を読み込む時の制限。
+/// 読み込みは再帰的に行うので、入れ子の深さを制限しないと、深い入力でスタックオーバーフローする
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseLimits {
+    pub max_depth: usize, // リストや quote の入れ子の深さの上限
+    pub max_size: usize,  // 入力のバイト数の上限
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        return ParseLimits {
+            max_depth: 1024,
+            max_size: usize::MAX,
+        };
+    }
+}
+
+/// 読み込む構文の設定。入れ子の深さの上限は `limits.max_depth` で設定する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReaderOptions {
+    pub limits: ParseLimits,
+    pub brackets: bool,       // [a b] を (vector a b) として読み込む
+    pub braces: bool,         // {k v ...} を連想リスト (list (list k v) ...) として読み込む
+    pub comments: bool,       // ; から行末までをコメントとして読み飛ばす
+    pub negative_ints: bool,  // -12 のような負の整数を読み込む
+    pub unicode_atoms: bool, // atom や var、keyword の名前に ASCII 以外の文字を使える。false なら英字は ASCII のみ
+    pub multiple_forms: bool, // トップレベルに複数の式を並べられる
+    pub hash_consing: bool, // 同じ形の部分式が 1 つの Rc を共有するように読み込む（intern::Interner を参照）
+}
+
+impl Default for ReaderOptions {
+    /// `ParseLimits::default()` の制限で、`{k v}` 以外の構文を全て読み込む
+    fn default() -> Self {
+        return ReaderOptions {
+            limits: ParseLimits::default(),
+            brackets: true,
+            braces: false,
+            comments: true,
+            negative_ints: true,
+            unicode_atoms: true,
+            multiple_forms: true,
+            hash_consing: false,
+        };
+    }
+}
+
+impl TryFrom<&[u8]> for Expression {
     type Error = ExpressionConversionError;
-    fn try_from(bytes: &'a [u8]) -> Result<Expression<'a>, Self::Error> {
-        let mut index = 0;
-        let res = Self::try_from_(&mut index, bytes);
-        if index != bytes.len() {
-            return Err(Self::Error::InvalidToken);
-        }
-        return res;
+    /// `ReaderOptions::default()` の設定で、入力全体を 1 つの式として読み込む
+    fn try_from(bytes: &[u8]) -> Result<Expression, Self::Error> {
+        let arena = Arena::new();
+        let node = Self::parse_in_with(&arena, bytes, &ReaderOptions::default())?;
+        return Ok(node.to_expression());
     }
 }
 
-impl<'a> Expression<'a> {
-    fn try_from_(
-        index: &mut usize,
-        bytes: &'a [u8],
-    ) -> Result<Expression<'a>, ExpressionConversionError> {
-        let head_ch = char::from(bytes[*index]);
-        let mut list = ExpressionList::new();
-        // list
-        if head_ch == '(' {
-            *index += 1;
-            loop {
-                // space or \n を飛ばす
-                while *index < bytes.len()
-                    && (char::from(bytes[*index]) == ' ' || char::from(bytes[*index]) == '\n')
-                {
-                    *index += 1;
+/// `Expression` を、`Expression::try_from` で読み込める形式の文字列として出力する
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Int(i) => {
+                return write!(f, "{}", i);
+            }
+            Expression::Atom(a) => {
+                return write!(f, "{}", a);
+            }
+            Expression::Var(v) => {
+                return write!(f, "{}", v);
+            }
+            Expression::Str(s) => {
+                return write_escaped(f, s);
+            }
+            Expression::Keyword(k) => {
+                return write!(f, ":{}", k);
+            }
+            Expression::ExpressionList(l) => {
+                write!(f, "(")?;
+                for (i, e) in l.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", e)?;
                 }
+                return write!(f, ")");
+            }
+        }
+    }
+}
+
+/// 複数の式が並んだソース（ファイルの内容など）を、先頭から順に `Expression` に変換する
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::parse_program;
+///
+/// let program = parse_program("(set *a* 1)\n(add *a* 2)\n").unwrap();
+/// assert_eq!(program.len(), 2);
+/// ```
+pub fn parse_program(src: &str) -> Result<Vec<Expression>, ExpressionConversionError> {
+    return parse_program_with(src, &ReaderOptions::default());
+}
+
+/// `limits` の制限のもとで、`parse_program` と同様に変換する
+pub fn parse_program_with_limits(
+    src: &str,
+    limits: &ParseLimits,
+) -> Result<Vec<Expression>, ExpressionConversionError> {
+    let options = ReaderOptions {
+        limits: *limits,
+        ..ReaderOptions::default()
+    };
+    return parse_program_with(src, &options);
+}
+
+/// `options` の設定で、`parse_program` と同様に変換する。
+/// `options.multiple_forms` が false なら、式が 2 つ以上ある場合は `InvalidToken`
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::{parse_program_with, ReaderOptions};
+///
+/// let options = ReaderOptions { braces: true, ..ReaderOptions::default() };
+/// let program = parse_program_with("[1 2] {:a 1}", &options).unwrap();
+/// assert_eq!(program[0].to_string(), "(vector 1 2)");
+/// assert_eq!(program[1].to_string(), "(list (list :a 1))");
+/// ```
+pub fn parse_program_with(
+    src: &str,
+    options: &ReaderOptions,
+) -> Result<Vec<Expression>, ExpressionConversionError> {
+    let program = parse_program_located(src, options).map_err(|e| e.error)?;
+    return Ok(program.into_iter().map(|(exp, _)| exp).collect());
+}
+
+/// `parse_program_with` と同様に変換し、各式をソース中の位置と組にして返す。
+/// エラーも、原因になった位置と組にして返す。位置は `SourceMap` で行と列に変換できる
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::{parse_program_located, ExpressionConversionError, ReaderOptions};
+/// use liblisp::lexer::Span;
+///
+/// let options = ReaderOptions::default();
+/// let program = parse_program_located("1 (add 1 2)", &options).unwrap();
+/// assert_eq!(program[1].1, Span { start: 2, end: 11 });
+///
+/// let err = parse_program_located("(add 1 ]", &options).unwrap_err();
+/// assert_eq!(err.error, ExpressionConversionError::InvalidToken);
+/// assert_eq!(err.span, Span { start: 7, end: 8 });
+/// ```
+pub fn parse_program_located(
+    src: &str,
+    options: &ReaderOptions,
+) -> Result<Vec<(Expression, Span)>, ParseError> {
+    let arena = Arena::new();
+    let mut parser = Parser::new(&arena, src.as_bytes(), options)?;
+    let mut res = Vec::new();
+    // hash_consing が true なら、ソース中の全ての式で部分式を共有する
+    let mut interner = if options.hash_consing {
+        Some(Interner::new())
+    } else {
+        None
+    };
+    while let Some(start) = parser.peek_start() {
+        if !options.multiple_forms && !res.is_empty() {
+            return Err(parser.trailing_token());
+        }
+        let node = parser.parse(0)?;
+        let span = Span {
+            start,
+            end: parser.last_end,
+        };
+        let exp = match &mut interner {
+            Some(interner) => interner.intern(&node.to_expression()),
+            None => node.to_expression(),
+        };
+        res.push((exp, span));
+    }
+    return Ok(res);
+}
+
+impl Expression {
+    /// 式の大きさと複雑さを数える。利用者のスクリプトを評価する前に、複雑すぎるものを拒否するのに使う。
+    /// `nodes`、`depth`、`atoms` は quote されたデータも含めた構造全体から、
+    /// `functions` は `visit::walk` と同様に評価される位置の式だけから数える
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let exp = Expression::try_from("(defun f (*x*) (add *x* (quote (g 1))))".as_bytes()).unwrap();
+    /// let metrics = exp.metrics();
+    /// assert_eq!(metrics.nodes, 13);
+    /// assert_eq!(metrics.depth, 4);
+    /// assert_eq!(metrics.atoms, 8);
+    /// let functions: Vec<&str> = metrics.functions.iter().map(|name| &**name).collect();
+    /// assert_eq!(functions, vec!["add", "defun", "quote"]);
+    /// ```
+    pub fn metrics(&self) -> ExpressionMetrics {
+        let mut metrics = ExpressionMetrics::default();
+        count_nodes(self, 0, &mut metrics);
+        walk(self, &mut FunctionCollector(&mut metrics.functions));
+        return metrics;
+    }
 
-                // 終端判定
-                if *index == bytes.len() {
-                    // TODO : error handling
-                    panic!("occured unexpected error");
-                } else if char::from(bytes[*index]) == ')' {
-                    // end
-                    *index += 1;
-                    return Ok(Expression::ExpressionList(Rc::new(list.reverse())));
+    /// 式の正規形の文字列。利用者が送ったスクリプトの重複を除くためのキーに使う。
+    ///
+    /// 読み込んだ時点で空白やコメント、`'x` や `[a b]` のような省略記法の違いは無くなっているので、
+    /// 正規形は `Display` と同じく要素を 1 つの空白で区切った形式とする。
+    /// 文字列はエスケープして出力するので、異なる式が同じ正規形になることは無い。
+    ///
+    /// 変数は動的スコープで解決し、呼び出した先の関数からも名前で参照できるので、仮引数や `let` の変数の名前は変えない。
+    /// 名前だけが異なる式は、異なる正規形になる
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::expression::{Expression, ReaderOptions};
+    ///
+    /// let options = ReaderOptions::default();
+    /// let a = Expression::parse_with("(list 'a  [1 2] ; comment\n )", &options).unwrap();
+    /// let b = Expression::parse_with("(list (quote a) (vector 1 2))", &options).unwrap();
+    /// assert_eq!(a.normalize(), b.normalize());
+    /// assert_eq!(a.normalize(), "(list (quote a) (vector 1 2))");
+    /// ```
+    pub fn normalize(&self) -> String {
+        return self.to_string();
+    }
+
+    /// 評価される位置の変数の参照を、`bindings` の式で置き換えた式を作る。評価はしない。
+    /// 一度読み込んだプログラムを、引数を変えながらテンプレートとして使い回すのに使う。
+    ///
+    /// `visit::fold` と同様に、仮引数や `set` の変数のような束縛する位置の変数と、quote されたデータの中は置き換えない。
+    /// 置き換えた式の中はさらに置き換えない。
+    /// 式の中の `let` や `defun` の仮引数で束縛した同名の変数も、参照は置き換えるので、テンプレートの引数には式の中で束縛しない名前を使う。
+    /// `bindings` は std feature が無効な場合は `BTreeMap`
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let template = Expression::try_from("(list *name* (quote *name*) (mul *n* 2))".as_bytes()).unwrap();
+    /// let bindings = vec![
+    ///     ("*name*", Expression::Str("apple".into())),
+    ///     ("*n*", Expression::try_from("(add 1 2)".as_bytes()).unwrap()),
+    /// ]
+    /// .into_iter()
+    /// .collect();
+    /// assert_eq!(
+    ///     template.substitute(&bindings).to_string(),
+    ///     "(list \"apple\" (quote *name*) (mul (add 1 2) 2))"
+    /// );
+    /// ```
+    pub fn substitute(&self, bindings: &Map<&str, Expression>) -> Expression {
+        return fold(self, &mut |exp| {
+            if let Expression::Var(name) = &exp {
+                if let Some(replacement) = bindings.get(&**name) {
+                    return replacement.clone();
                 }
+            }
+            return exp;
+        });
+    }
+
+    /// `limits` の制限のもとで、byte 列を `Expression` に変換する
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::expression::{Expression, ExpressionConversionError, ParseLimits};
+    ///
+    /// let limits = ParseLimits { max_depth: 2, max_size: 64 };
+    /// assert!(Expression::try_from_with_limits("(a (b))".as_bytes(), &limits).is_ok());
+    /// assert_eq!(
+    ///     Expression::try_from_with_limits("(a (b (c)))".as_bytes(), &limits),
+    ///     Err(ExpressionConversionError::TooDeep)
+    /// );
+    /// ```
+    pub fn try_from_with_limits(
+        bytes: &[u8],
+        limits: &ParseLimits,
+    ) -> Result<Expression, ExpressionConversionError> {
+        let options = ReaderOptions {
+            limits: *limits,
+            ..ReaderOptions::default()
+        };
+        let arena = Arena::new();
+        let node = Self::parse_in_with(&arena, bytes, &options)?;
+        return Ok(node.to_expression());
+    }
 
-                // 新しい要素を追加
-                let result = Self::try_from_(index, bytes)?;
-                list = list.cons(&result);
+    /// `options` の設定で、`src` を `Expression` に変換する。
+    /// `options.multiple_forms` が true でトップレベルに式が 2 つ以上ある場合は、
+    /// それらを順に評価する `(progn ...)` にまとめる。false なら `src` 全体が 1 つの式でなければならない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::expression::{Expression, ExpressionConversionError, ReaderOptions};
+    ///
+    /// let options = ReaderOptions::default();
+    /// let exp = Expression::parse_with("; comment\n(define *x* -1) [*x*]", &options).unwrap();
+    /// assert_eq!(exp.to_string(), "(progn (define *x* -1) (vector *x*))");
+    ///
+    /// let strict = ReaderOptions { comments: false, multiple_forms: false, ..options };
+    /// assert_eq!(
+    ///     Expression::parse_with("1 2", &strict),
+    ///     Err(ExpressionConversionError::InvalidToken)
+    /// );
+    /// ```
+    pub fn parse_with(
+        src: &str,
+        options: &ReaderOptions,
+    ) -> Result<Expression, ExpressionConversionError> {
+        if !options.multiple_forms {
+            let arena = Arena::new();
+            let node = Self::parse_in_with(&arena, src.as_bytes(), options)?;
+            if options.hash_consing {
+                return Ok(Interner::new().intern(&node.to_expression()));
             }
+            return Ok(node.to_expression());
         }
-        // int
-        else if head_ch.is_ascii_digit() {
-            let mut num: i32 = 0;
-            while *index < bytes.len() {
-                let c = char::from(bytes[*index]);
-                if c.is_ascii_digit() {
-                    // unwrapしているが、直前のif文で数字かどうかを判定しているので panic は発生しない
-                    num = num * 10 + c.to_digit(10).unwrap() as i32;
-                } else {
-                    // 括弧 or space or 改行 以外の文字が続いていたら異常
-                    if !(c == ')' || c == ' ' || c == '\n') {
-                        return Err(ExpressionConversionError::InvalidToken);
-                    }
-                    break;
-                }
-                *index += 1;
+        let mut program = parse_program_with(src, options)?;
+        match program.len() {
+            0 => {
+                return Err(ExpressionConversionError::UnexpectedEof);
+            }
+            1 => {
+                return Ok(program.pop().unwrap());
+            }
+            _ => {
+                let l = program
+                    .iter()
+                    .rev()
+                    .fold(ExpressionList::new(), |acc, e| acc.cons(e))
+                    .cons(&Expression::Atom(Rc::from("progn")));
+                return Ok(Expression::ExpressionList(Rc::new(l)));
             }
-            return Ok(Expression::Int(num));
         }
-        // atom
-        // atomは 簡単のために、alphabetから始まり、alphabetと数字のみ含むものとする
-        else if head_ch.is_alphabetic() {
-            let start = *index;
-            while *index < bytes.len() {
-                let c = char::from(bytes[*index]);
-                if c.is_ascii_digit() || c.is_alphabetic() {
-                } else {
-                    // 括弧 or space or 改行 以外の文字が続いていたら異常
-                    if !(c == ')' || c == ' ' || c == '\n') {
-                        return Err(ExpressionConversionError::InvalidToken);
-                    }
-                    break;
-                }
-                *index += 1;
+    }
+
+    /// `src` を、リストの要素を `arena` に確保した `Node` として読み込む。
+    /// `Expression` のように要素ごとに `Rc` を確保しないので、大きな入力を速く読み込める。
+    /// 速くなるのは読み込みだけで、評価する時には `Expression` への変換が要る（`eval::eval_node` を参照）。
+    /// 設定は `ReaderOptions::default()` とする
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::arena::{Arena, Node};
+    /// use liblisp::expression::Expression;
+    ///
+    /// let arena = Arena::new();
+    /// let node = Expression::parse_in(&arena, "(add 1 *a*)").unwrap();
+    /// assert_eq!(node, Node::List(&[Node::Atom("add"), Node::Int(1), Node::Var("*a*")]));
+    /// ```
+    pub fn parse_in<'a>(
+        arena: &'a Arena<'a>,
+        src: &'a str,
+    ) -> Result<Node<'a>, ExpressionConversionError> {
+        return Self::parse_in_with(arena, src.as_bytes(), &ReaderOptions::default());
+    }
+
+    // options の設定で、入力全体を 1 つの式として arena に読み込む。multiple_forms は使わない
+    fn parse_in_with<'a>(
+        arena: &'a Arena<'a>,
+        bytes: &'a [u8],
+        options: &ReaderOptions,
+    ) -> Result<Node<'a>, ExpressionConversionError> {
+        let mut parser = Parser::new(arena, bytes, options).map_err(|e| e.error)?;
+        let res = parser.parse(0).map_err(|e| e.error)?;
+        // 1 つ目の式の後に、さらにトークンが続いていたら異常
+        if parser.peek_start().is_some() {
+            return Err(ExpressionConversionError::InvalidToken);
+        }
+        return Ok(res);
+    }
+}
+
+// exp 以下の式の数と深さを metrics に加える。depth は exp を囲むリストの数
+fn count_nodes(exp: &Expression, depth: usize, metrics: &mut ExpressionMetrics) {
+    metrics.nodes += 1;
+    metrics.depth = core::cmp::max(metrics.depth, depth);
+    match exp {
+        Expression::ExpressionList(l) => {
+            metrics.depth = core::cmp::max(metrics.depth, depth + 1);
+            for e in l.iter() {
+                count_nodes(e, depth + 1, metrics);
             }
-            let end = *index;
+        }
+        _ => {
+            metrics.atoms += 1;
+        }
+    }
+}
 
-            match std::str::from_utf8(&bytes[start..end]) {
-                Ok(res) => {
-                    return Ok(Expression::Atom(res));
-                }
-                Err(e) => {
-                    // 失敗することは想定していない
-                    return Err(ExpressionConversionError::Unexpected(e.to_string()));
-                }
+// 評価される位置のリストの、先頭の名前を集める
+struct FunctionCollector<'m>(&'m mut BTreeSet<Rc<str>>);
+
+impl ExpressionVisitor for FunctionCollector<'_> {
+    fn enter(&mut self, exp: &Expression) -> bool {
+        if let Expression::ExpressionList(l) = exp {
+            if let Some(Expression::Atom(name)) = l.head() {
+                self.0.insert(name.clone());
             }
         }
-        // var
-        // *と*で囲まれた形式を想定
-        else if head_ch == '*' {
-            let mut asta_count = 1;
-            let start = *index;
-            *index += 1;
-            let second_ch = char::from(bytes[*index]);
-            if second_ch.is_alphabetic() {
-                while *index < bytes.len() {
-                    let c = char::from(bytes[*index]);
-                    if c.is_ascii_digit() || c.is_alphabetic() || c == '*' {
-                        if c == '*' {
-                            asta_count += 1;
-                        }
-                    } else {
-                        // 括弧 or space or 改行 以外の文字が続いていたら異常
-                        if !(c == ')' || c == ' ' || c == '\n') {
-                            return Err(ExpressionConversionError::InvalidToken);
-                        }
-                        break;
-                    }
-                    *index += 1;
+        return true;
+    }
+}
+
+// 開き括弧や quote の記号から始まる、入れ子になる形式
+enum Nested {
+    List,                // (a b)
+    Vector,              // [a b]
+    Map,                 // {k v}
+    Quote(&'static str), // 'x などの省略記法。quote などの名前を持つ
+}
+
+// トークンの列を読み込んで、arena に式を作る
+struct Parser<'a, 'o> {
+    arena: &'a Arena<'a>,
+    tokens: core::iter::Peekable<Lexer<'a>>,
+    options: &'o ReaderOptions,
+    len: usize,      // 入力のバイト数
+    last_end: usize, // 最後に読み込んだトークンの終端
+}
+
+impl<'a, 'o> Parser<'a, 'o> {
+    fn new(
+        arena: &'a Arena<'a>,
+        bytes: &'a [u8],
+        options: &'o ReaderOptions,
+    ) -> Result<Parser<'a, 'o>, ParseError> {
+        if bytes.len() > options.limits.max_size {
+            return Err(ParseError {
+                error: ExpressionConversionError::TooLarge,
+                span: Span {
+                    start: options.limits.max_size,
+                    end: bytes.len(),
+                },
+            });
+        }
+        return Ok(Parser {
+            arena,
+            tokens: Lexer::with_options(bytes, options).peekable(),
+            options,
+            len: bytes.len(),
+            last_end: 0,
+        });
+    }
+
+    // 次のトークンの開始位置。トークンが残っていなければ None
+    fn peek_start(&mut self) -> Option<usize> {
+        match self.tokens.peek()? {
+            Ok(token) => {
+                return Some(token.span.start);
+            }
+            Err(e) => {
+                return Some(e.span.start);
+            }
+        }
+    }
+
+    // 式の後に、あってはならないトークンが続いていた時のエラー
+    fn trailing_token(&mut self) -> ParseError {
+        let span = match self.tokens.next() {
+            Some(Ok(token)) => token.span,
+            Some(Err(e)) => e.span,
+            None => Span {
+                start: self.len,
+                end: self.len,
+            },
+        };
+        return ParseError {
+            error: ExpressionConversionError::InvalidToken,
+            span,
+        };
+    }
+
+    // 次のトークンを読み込む。入力が終わっていたら UnexpectedEof
+    fn next_token(&mut self) -> Result<Token<'a>, ParseError> {
+        match self.tokens.next() {
+            Some(Ok(token)) => {
+                self.last_end = token.span.end;
+                return Ok(token);
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            None => {
+                return Err(ParseError {
+                    error: ExpressionConversionError::UnexpectedEof,
+                    span: Span {
+                        start: self.len,
+                        end: self.len,
+                    },
+                });
+            }
+        }
+    }
+
+    // depth は、読み込み中の式を囲むリストや quote の数。
+    // 深い入力ではこの関数が再帰的に呼び出されるので、1 回の呼び出しで使うスタックを小さく保つため、
+    // 各形式の読み込みは別の関数に分けている
+    fn parse(&mut self, depth: usize) -> Result<Node<'a>, ParseError> {
+        let token = self.next_token()?;
+        let nested = match token.kind {
+            TokenKind::Int(i) => {
+                return Ok(Node::Int(i));
+            }
+            TokenKind::Atom(a) => {
+                return Ok(Node::Atom(a));
+            }
+            TokenKind::Var(v) => {
+                return Ok(Node::Var(v));
+            }
+            TokenKind::Str(s) => {
+                return Ok(Node::Str(s));
+            }
+            TokenKind::Keyword(k) => {
+                return Ok(Node::Keyword(k));
+            }
+            TokenKind::LParen => Nested::List,
+            TokenKind::LBracket if self.options.brackets => Nested::Vector,
+            TokenKind::LBrace if self.options.braces => Nested::Map,
+            TokenKind::Quote => Nested::Quote("quote"),
+            TokenKind::Quasiquote => Nested::Quote("quasiquote"),
+            TokenKind::Unquote => Nested::Quote("unquote"),
+            TokenKind::UnquoteSplicing => Nested::Quote("unquote-splicing"),
+            TokenKind::FunctionQuote => Nested::Quote("function"),
+            // 対応する開き括弧の無い閉じ括弧や、有効でない括弧
+            TokenKind::RParen
+            | TokenKind::LBracket
+            | TokenKind::RBracket
+            | TokenKind::LBrace
+            | TokenKind::RBrace => {
+                return Err(ParseError::new(
+                    ExpressionConversionError::InvalidToken,
+                    token.span,
+                ));
+            }
+        };
+        if depth >= self.options.limits.max_depth {
+            return Err(ParseError::new(
+                ExpressionConversionError::TooDeep,
+                token.span,
+            ));
+        }
+        match nested {
+            Nested::List => {
+                let items = self.items(TokenKind::RParen, depth)?;
+                return Ok(Node::List(self.arena.alloc_list(items)));
+            }
+            Nested::Vector => {
+                return self.vector(depth);
+            }
+            Nested::Map => {
+                return self.map(token.span, depth);
+            }
+            Nested::Quote(name) => {
+                return self.quote(name, depth);
+            }
+        }
+    }
+
+    // 閉じ括弧 close までの要素。開き括弧は読み込み済み
+    fn items(
+        &mut self,
+        close: TokenKind<'static>,
+        depth: usize,
+    ) -> Result<Vec<Node<'a>>, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                Some(Ok(token)) if token.kind == close => {
+                    self.next_token()?;
+                    return Ok(items);
                 }
-                let end = *index;
-                // bytes[start..end] の先頭と末尾のみ * が存在
-                // 先頭が * になっているのは、ここ以前の条件分岐から明らかなので、末尾だけ調べる
-                if asta_count == 2 && bytes[end - 1] == '*' as u8 {
-                    match std::str::from_utf8(&bytes[start..end]) {
-                        Ok(res) => {
-                            return Ok(Expression::Var(res));
-                        }
-                        Err(e) => {
-                            // 失敗することは想定していない
-                            return Err(ExpressionConversionError::Unexpected(e.to_string()));
-                        }
-                    }
-                } else {
-                    return Err(ExpressionConversionError::InvalidToken);
+                // 閉じ括弧が来る前に入力が終わった場合は、parse が UnexpectedEof を返す
+                _ => {
+                    items.push(self.parse(depth + 1)?);
                 }
-            } else {
-                return Err(ExpressionConversionError::InvalidToken);
             }
         }
-        return Err(ExpressionConversionError::InvalidToken);
+    }
+
+    // [a b ...] を (vector a b ...) に変換する。開き括弧は読み込み済み
+    fn vector(&mut self, depth: usize) -> Result<Node<'a>, ParseError> {
+        let mut items = vec![Node::Atom("vector")];
+        items.extend(self.items(TokenKind::RBracket, depth)?);
+        return Ok(Node::List(self.arena.alloc_list(items)));
+    }
+
+    // {k v ...} を連想リスト (list (list k v) ...) に変換する。開き括弧は読み込み済みで、open はその位置。
+    // 要素の数が奇数なら InvalidToken
+    fn map(&mut self, open: Span, depth: usize) -> Result<Node<'a>, ParseError> {
+        let items = self.items(TokenKind::RBrace, depth)?;
+        if items.len() % 2 != 0 {
+            return Err(ParseError {
+                error: ExpressionConversionError::InvalidToken,
+                span: Span {
+                    start: open.start,
+                    end: self.last_end,
+                },
+            });
+        }
+        let mut entries = vec![Node::Atom("list")];
+        for pair in items.chunks(2) {
+            let entry = vec![Node::Atom("list"), pair[0], pair[1]];
+            entries.push(Node::List(self.arena.alloc_list(entry)));
+        }
+        return Ok(Node::List(self.arena.alloc_list(entries)));
+    }
+
+    // quote 系の省略記法
+    // 'x, `x, ,x, ,@x, #'x をそれぞれ (quote x), (quasiquote x), (unquote x), (unquote-splicing x), (function x) に変換する。
+    // 記号は読み込み済みで、name はその記号に対応する名前
+    fn quote(&mut self, name: &'static str, depth: usize) -> Result<Node<'a>, ParseError> {
+        let quoted = self.parse(depth + 1)?;
+        return Ok(Node::List(
+            self.arena.alloc_list(vec![Node::Atom(name), quoted]),
+        ));
     }
 }
 
@@ -175,11 +711,15 @@ mod tests {
         );
         assert_eq!(
             Expression::try_from("atom".as_bytes()),
-            Ok(Expression::Atom("atom"))
+            Ok(Expression::Atom("atom".into()))
         );
         assert_eq!(
             Expression::try_from("atom123".as_bytes()),
-            Ok(Expression::Atom("atom123"))
+            Ok(Expression::Atom("atom123".into()))
+        );
+        assert_eq!(
+            Expression::try_from("atom-123".as_bytes()),
+            Ok(Expression::Atom("atom-123".into()))
         );
         assert_eq!(
             Expression::try_from("123atom".as_bytes()),
@@ -199,7 +739,7 @@ mod tests {
         assert_eq!(
             Expression::try_from("(atom ( ) )".as_bytes()),
             Ok(Expression::ExpressionList(Rc::new(ExpressionList::Cons(
-                Expression::Atom("atom"),
+                Expression::Atom("atom".into()),
                 Rc::new(ExpressionList::Cons(
                     Expression::ExpressionList(Rc::new(ExpressionList::Nil)),
                     Rc::new(ExpressionList::Nil)
@@ -208,7 +748,23 @@ mod tests {
         );
         assert_eq!(
             Expression::try_from("*abcdefg*".as_bytes()),
-            Ok(Expression::Var("*abcdefg*"))
+            Ok(Expression::Var("*abcdefg*".into()))
+        );
+        assert_eq!(
+            Expression::try_from(":key-1".as_bytes()),
+            Ok(Expression::Keyword("key-1".into()))
+        );
+        assert_eq!(
+            Expression::try_from(":".as_bytes()),
+            Ok(Expression::Atom(":".into()))
+        );
+        assert_eq!(
+            Expression::try_from("(*x* : int)".as_bytes()).map(|e| e.to_string()),
+            Ok("(*x* : int)".into())
+        );
+        assert_eq!(
+            Expression::try_from(":1".as_bytes()),
+            Err(ExpressionConversionError::InvalidToken)
         );
 
         assert_eq!(
@@ -219,6 +775,208 @@ mod tests {
             Expression::try_from("(abc def) ()".as_bytes()),
             Err(ExpressionConversionError::InvalidToken)
         );
+
+        // 入力が途中で終わっている場合も panic せずにエラーを返す
+        assert_eq!(
+            Expression::try_from("".as_bytes()),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        assert_eq!(
+            Expression::try_from("(add 1".as_bytes()),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        assert_eq!(
+            Expression::try_from("*".as_bytes()),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        assert_eq!(
+            Expression::try_from("10000000000000000000000000000000000000000".as_bytes()),
+            Err(ExpressionConversionError::IntOverflow)
+        );
+    }
+
+    #[test]
+    fn quote_syntax_tests() {
+        use crate::expression::*;
+
+        assert_eq!(
+            Expression::try_from("'(a 1)".as_bytes()),
+            Expression::try_from("(quote (a 1))".as_bytes())
+        );
+        assert_eq!(
+            Expression::try_from("`(a ,*b* ,@*c*)".as_bytes()),
+            Expression::try_from(
+                "(quasiquote (a (unquote *b*) (unquote-splicing *c*)))".as_bytes()
+            )
+        );
+        assert_eq!(
+            Expression::try_from("(sort *l* #'gt)".as_bytes()),
+            Expression::try_from("(sort *l* (function gt))".as_bytes())
+        );
+        assert_eq!(
+            Expression::try_from("'".as_bytes()),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn string_tests() {
+        use crate::expression::*;
+
+        assert_eq!(
+            Expression::try_from("\"hello world\"".as_bytes()),
+            Ok(Expression::Str("hello world".into()))
+        );
+        assert_eq!(
+            Expression::try_from("(load \"lib.lisp\")".as_bytes()),
+            Ok(Expression::ExpressionList(Rc::new(ExpressionList::Cons(
+                Expression::Atom("load".into()),
+                Rc::new(ExpressionList::Cons(
+                    Expression::Str("lib.lisp".into()),
+                    Rc::new(ExpressionList::Nil)
+                ))
+            ))))
+        );
+        assert_eq!(
+            Expression::try_from("\"abc".as_bytes()),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        assert_eq!(
+            Expression::try_from("\"abc\"d".as_bytes()),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn string_escape_tests() {
+        use crate::expression::*;
+        use crate::format::{format_source, FormatOptions};
+
+        // (ソース, 文字列の内容, Display で出力するソース)
+        let cases = vec![
+            (r#""a\nb""#, "a\nb", r#""a\nb""#),
+            (r#""\tx\t""#, "\tx\t", r#""\tx\t""#),
+            (r#""say \"hi\"""#, "say \"hi\"", r#""say \"hi\"""#),
+            (r#""C:\\tmp\\""#, "C:\\tmp\\", r#""C:\\tmp\\""#),
+            (r#""\u{1F600}""#, "😀", r#""😀""#),
+            (r#""\u{7}\u{d}\u{a}""#, "\u{7}\r\n", r#""\u{7}\u{d}\n""#),
+            (r#""""#, "", r#""""#),
+        ];
+        for (src, content, printed) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(exp, Expression::Str(content.into()), "{}", src);
+            assert_eq!(exp.to_string(), printed, "{}", src);
+            assert_eq!(
+                Expression::try_from(exp.to_string().as_bytes()),
+                Ok(exp.clone()),
+                "{}",
+                src
+            );
+        }
+
+        // リストの中の文字列も、出力して読み込み直すと同じ式になる
+        let src = r#"(write-file "out.txt" "a\t\"b\"\n\\\u{1b}[0m")"#;
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        assert_eq!(
+            exp.to_string(),
+            r#"(write-file "out.txt" "a\t\"b\"\n\\\u{1b}[0m")"#
+        );
+        assert_eq!(Expression::try_from(exp.to_string().as_bytes()), Ok(exp));
+        let formatted = format_source(src, &FormatOptions::default()).unwrap();
+        assert_eq!(formatted, format!("{}\n", src));
+
+        assert_eq!(
+            Expression::try_from(r#""\e""#.as_bytes()),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn qualified_name_tests() {
+        use crate::expression::*;
+
+        assert_eq!(
+            Expression::try_from("math:gcd".as_bytes()),
+            Ok(Expression::Atom("math:gcd".into()))
+        );
+        assert_eq!(
+            Expression::try_from("*math:pi*".as_bytes()),
+            Ok(Expression::Var("*math:pi*".into()))
+        );
+    }
+
+    #[test]
+    fn limits_tests() {
+        use crate::expression::*;
+
+        // 非常に深い入力でもスタックオーバーフローせず、エラーになる
+        let deep = "(".repeat(100_000);
+        assert_eq!(
+            Expression::try_from(deep.as_bytes()),
+            Err(ExpressionConversionError::TooDeep)
+        );
+        let deep_quote = format!("{}a", "'".repeat(100_000));
+        assert_eq!(
+            Expression::try_from(deep_quote.as_bytes()),
+            Err(ExpressionConversionError::TooDeep)
+        );
+
+        let limits = ParseLimits {
+            max_depth: 3,
+            max_size: 16,
+        };
+        assert!(Expression::try_from_with_limits("(((1)))".as_bytes(), &limits).is_ok());
+        assert_eq!(
+            Expression::try_from_with_limits("((((1))))".as_bytes(), &limits),
+            Err(ExpressionConversionError::TooDeep)
+        );
+        assert_eq!(
+            Expression::try_from_with_limits("(('(1)))".as_bytes(), &limits),
+            Err(ExpressionConversionError::TooDeep)
+        );
+        assert_eq!(
+            Expression::try_from_with_limits("(list 1 2 3 4 5 6 7)".as_bytes(), &limits),
+            Err(ExpressionConversionError::TooLarge)
+        );
+        assert_eq!(
+            parse_program_with_limits("(a) (b) (c) (d) (e)", &limits),
+            Err(ExpressionConversionError::TooLarge)
+        );
+        assert_eq!(
+            parse_program_with_limits("(((a))) (b)", &limits).map(|p| p.len()),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn parse_program_tests() {
+        use crate::expression::*;
+
+        let program = parse_program("\t(set *a* 1)\r\n\n(add *a*\t2) 3\n").unwrap();
+        assert_eq!(
+            program,
+            vec![
+                Expression::try_from("(set *a* 1)".as_bytes()).unwrap(),
+                Expression::try_from("(add *a* 2)".as_bytes()).unwrap(),
+                Expression::Int(3),
+            ]
+        );
+        assert_eq!(parse_program("  \n"), Ok(vec![]));
+        assert_eq!(
+            parse_program("(add 1 2) (add 1"),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn display_tests() {
+        use crate::expression::*;
+
+        let src = "(progn (set *a* 10) (list a (add *a* 1) \"str\") ())";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        assert_eq!(exp.to_string(), src);
+        assert_eq!(Expression::Int(12).to_string(), "12");
+        assert_eq!(Expression::Var("*abc*".into()).to_string(), "*abc*");
     }
 
     #[test]
@@ -228,7 +986,7 @@ mod tests {
         let list1 = ExpressionList::Cons(
             Expression::Int(32),
             Rc::new(ExpressionList::Cons(
-                Expression::Atom("a"),
+                Expression::Atom("a".into()),
                 Rc::new(ExpressionList::Nil),
             )),
         );
@@ -247,7 +1005,7 @@ mod tests {
         // tail test
         assert_eq!(
             list1.tail(),
-            &ExpressionList::Cons(Expression::Atom("a"), Rc::new(ExpressionList::Nil))
+            &ExpressionList::Cons(Expression::Atom("a".into()), Rc::new(ExpressionList::Nil))
         );
 
         // cons test
@@ -261,14 +1019,388 @@ mod tests {
 
         // partial_eqの挙動をついでにテスト。rcの中身もちゃんと見ている様子。
         {
-            let t1 = Expression::Atom("abc");
-            let t2 = Expression::Atom("abc");
+            let t1 = Expression::Atom("abc".into());
+            let t2 = Expression::Atom("abc".into());
             assert_eq!(t1, t2);
         }
         {
-            let t1 = Expression::Atom("abc");
-            let t2 = Expression::Atom("ab");
+            let t1 = Expression::Atom("abc".into());
+            let t2 = Expression::Atom("ab".into());
             assert_ne!(t1, t2);
         }
     }
+
+    #[test]
+    fn bracket_syntax_tests() {
+        use crate::expression::*;
+
+        let braces = ReaderOptions {
+            braces: true,
+            ..ReaderOptions::default()
+        };
+        let cases = vec![
+            ("[1 2 3]", "(vector 1 2 3)"),
+            ("[]", "(vector)"),
+            ("(vref [a [b]] 1)", "(vref (vector a (vector b)) 1)"),
+            ("'[1 *x*]", "(quote (vector 1 *x*))"),
+            (
+                "{:a 1 :b (add 1 1)}",
+                "(list (list :a 1) (list :b (add 1 1)))",
+            ),
+            ("{}", "(list)"),
+            ("{k [1]}", "(list (list k (vector 1)))"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                Expression::parse_with(src, &braces).map(|e| e.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                src
+            );
+        }
+
+        let errors = vec![
+            ("[1 2", ExpressionConversionError::UnexpectedEof),
+            ("[1 2)", ExpressionConversionError::InvalidToken),
+            ("(1 2]", ExpressionConversionError::InvalidToken),
+            ("]", ExpressionConversionError::InvalidToken),
+            ("{:a}", ExpressionConversionError::InvalidToken),
+            ("{:a 1", ExpressionConversionError::UnexpectedEof),
+        ];
+        for (src, expected) in errors {
+            assert_eq!(
+                Expression::parse_with(src, &braces),
+                Err(expected),
+                "{}",
+                src
+            );
+        }
+
+        // 設定で無効にした括弧は読み込まない
+        let strict = ReaderOptions {
+            brackets: false,
+            ..ReaderOptions::default()
+        };
+        assert_eq!(
+            Expression::parse_with("[1]", &strict),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            Expression::try_from("{:a 1}".as_bytes()),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        // 入れ子の深さは、どの括弧も同じように数える
+        let shallow = ReaderOptions {
+            limits: ParseLimits {
+                max_depth: 2,
+                max_size: 64,
+            },
+            ..braces
+        };
+        assert!(Expression::parse_with("[{}]", &shallow).is_ok());
+        assert_eq!(
+            Expression::parse_with("[{k []}]", &shallow),
+            Err(ExpressionConversionError::TooDeep)
+        );
+    }
+
+    #[test]
+    fn reader_options_tests() {
+        use crate::expression::*;
+
+        let default = ReaderOptions::default();
+        assert_eq!(
+            Expression::parse_with("(add -1 2) ; tail", &default),
+            Ok(Expression::try_from("(add -1 2)".as_bytes()).unwrap())
+        );
+        assert_eq!(
+            Expression::parse_with("(define *x* 1)\n*x*", &default).map(|e| e.to_string()),
+            Ok("(progn (define *x* 1) *x*)".to_string())
+        );
+        assert_eq!(
+            Expression::parse_with("; only a comment", &default),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        assert_eq!(Expression::Int(-5).to_string().as_bytes(), b"-5");
+        assert_eq!(
+            Expression::try_from("-5".as_bytes()),
+            Ok(Expression::Int(-5))
+        );
+
+        let single = ReaderOptions {
+            multiple_forms: false,
+            ..default
+        };
+        assert_eq!(
+            Expression::parse_with("1 2", &single),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            parse_program_with("1 2", &single),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            parse_program_with("1 ; 2", &single),
+            Ok(vec![Expression::Int(1)])
+        );
+
+        let no_comments = ReaderOptions {
+            comments: false,
+            ..default
+        };
+        assert_eq!(
+            parse_program_with("1 ; 2", &no_comments),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn located_tests() {
+        use crate::expression::*;
+
+        let options = ReaderOptions::default();
+        let program = parse_program_located(" 1\n'(a [b]) ; c\n\"s\"", &options).unwrap();
+        let spans: Vec<(String, usize, usize)> = program
+            .iter()
+            .map(|(e, span)| (e.to_string(), span.start, span.end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("1".into(), 1, 2),
+                ("(quote (a (vector b)))".into(), 3, 11),
+                ("\"s\"".into(), 16, 19),
+            ]
+        );
+
+        let with_braces = ReaderOptions {
+            braces: true,
+            ..options
+        };
+        let cases = vec![
+            (
+                "(a",
+                &options,
+                ExpressionConversionError::UnexpectedEof,
+                2,
+                2,
+            ),
+            (
+                "(a))",
+                &options,
+                ExpressionConversionError::InvalidToken,
+                3,
+                4,
+            ),
+            (
+                "(a 1x b)",
+                &options,
+                ExpressionConversionError::InvalidToken,
+                3,
+                5,
+            ),
+            (
+                "{1 2}",
+                &options,
+                ExpressionConversionError::InvalidToken,
+                0,
+                1,
+            ),
+            (
+                "(f {1 2 3})",
+                &with_braces,
+                ExpressionConversionError::InvalidToken,
+                3,
+                10,
+            ),
+            (
+                "\"abc",
+                &options,
+                ExpressionConversionError::UnexpectedEof,
+                0,
+                4,
+            ),
+        ];
+        for (src, options, error, start, end) in cases {
+            assert_eq!(
+                parse_program_located(src, options),
+                Err(ParseError::new(error, Span { start, end })),
+                "{}",
+                src
+            );
+        }
+
+        let deep = ReaderOptions {
+            limits: ParseLimits {
+                max_depth: 1,
+                max_size: 8,
+            },
+            ..options
+        };
+        assert_eq!(
+            parse_program_located("(a (b))", &deep),
+            Err(ParseError::new(
+                ExpressionConversionError::TooDeep,
+                Span { start: 3, end: 4 }
+            ))
+        );
+        assert_eq!(
+            parse_program_located("(a b c d e)", &deep),
+            Err(ParseError::new(
+                ExpressionConversionError::TooLarge,
+                Span { start: 8, end: 11 }
+            ))
+        );
+        let single = ReaderOptions {
+            multiple_forms: false,
+            ..options
+        };
+        assert_eq!(
+            parse_program_located("(a) (b)", &single),
+            Err(ParseError::new(
+                ExpressionConversionError::InvalidToken,
+                Span { start: 4, end: 5 }
+            ))
+        );
+    }
+
+    #[test]
+    fn hash_consing_tests() {
+        use crate::expression::*;
+
+        let src = "(f (g 1)) (h (g 1))";
+        let shared = |program: &[Expression]| -> bool {
+            match (program[0].clone(), program[1].clone()) {
+                (Expression::ExpressionList(a), Expression::ExpressionList(b)) => {
+                    match (a.get(1), b.get(1)) {
+                        (
+                            Some(Expression::ExpressionList(x)),
+                            Some(Expression::ExpressionList(y)),
+                        ) => {
+                            return Rc::ptr_eq(x, y);
+                        }
+                        _ => {
+                            return false;
+                        }
+                    }
+                }
+                _ => {
+                    return false;
+                }
+            }
+        };
+        let options = ReaderOptions {
+            hash_consing: true,
+            ..ReaderOptions::default()
+        };
+        let program = parse_program_with(src, &options).unwrap();
+        assert!(shared(&program));
+        assert_eq!(program, parse_program(src).unwrap());
+        assert!(!shared(&parse_program(src).unwrap()));
+
+        // 1 つの式として読み込む場合も、その中で共有する
+        let single = ReaderOptions {
+            multiple_forms: false,
+            ..options
+        };
+        let exp = Expression::parse_with("((f (g 1)) (h (g 1)))", &single).unwrap();
+        match exp {
+            Expression::ExpressionList(l) => {
+                let forms: Vec<Expression> = l.iter().cloned().collect();
+                assert!(shared(&forms));
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn metrics_tests() {
+        use crate::expression::*;
+
+        let cases = vec![
+            ("1", (1, 0, 1, vec![])),
+            ("()", (1, 1, 0, vec![])),
+            ("(add 1 2)", (4, 1, 3, vec!["add"])),
+            ("(add 1 (mul 2 3))", (7, 2, 5, vec!["add", "mul"])),
+            ("(f (f (f)))", (6, 3, 3, vec!["f"])),
+            // quote されたデータは数えるが、関数の名前には含めない
+            ("(quote (a (b)))", (6, 3, 3, vec!["quote"])),
+            // 仮引数のリストは関数の呼び出しではない
+            ("(lambda (*x*) (g *x*))", (7, 2, 4, vec!["g", "lambda"])),
+            ("((lambda (*x*) *x*) 1)", (7, 3, 4, vec!["lambda"])),
+        ];
+        for (src, (nodes, depth, atoms, functions)) in cases {
+            let metrics = Expression::try_from(src.as_bytes()).unwrap().metrics();
+            assert_eq!(
+                (metrics.nodes, metrics.depth, metrics.atoms),
+                (nodes, depth, atoms),
+                "{}",
+                src
+            );
+            let names: Vec<&str> = metrics.functions.iter().map(|name| &**name).collect();
+            assert_eq!(names, functions, "{}", src);
+        }
+    }
+
+    #[test]
+    fn substitute_tests() {
+        use crate::expression::*;
+
+        let mut bindings = Map::new();
+        bindings.insert("*a*", Expression::Int(1));
+        bindings.insert("*b*", Expression::Var("*a*".into()));
+        let cases = vec![
+            ("*a*", "1"),
+            ("*c*", "*c*"),
+            ("(add *a* (mul *a* *c*))", "(add 1 (mul 1 *c*))"),
+            // 置き換えた式の中は置き換えない
+            ("(list *b*)", "(list *a*)"),
+            // quote されたデータと、束縛する位置は置き換えない
+            (
+                "(list (quote *a*) `(*a* ,*a*))",
+                "(list (quote *a*) (quasiquote (*a* (unquote 1))))",
+            ),
+            ("(set *a* *a*)", "(set *a* 1)"),
+            ("(defun f (*a*) *b*)", "(defun f (*a*) *a*)"),
+            ("(let ((*a* *a*)) *c*)", "(let ((*a* 1)) *c*)"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(exp.substitute(&bindings).to_string(), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn normalize_tests() {
+        use crate::expression::*;
+
+        let cases = vec![
+            ("1", "1"),
+            ("(add  1\n\t2)", "(add 1 2)"),
+            (
+                "; comment\n(list :a \"b c\") ; comment",
+                "(list :a \"b c\")",
+            ),
+            (
+                "`(a ,b ,@c)",
+                "(quasiquote (a (unquote b) (unquote-splicing c)))",
+            ),
+            ("[*x* ()]", "(vector *x* ())"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::parse_with(src, &ReaderOptions::default()).unwrap();
+            assert_eq!(exp.normalize(), expected, "{}", src);
+        }
+
+        // 文字列の中の " で、異なる式が同じ正規形にならない
+        let one = Expression::ExpressionList(Rc::new(
+            ExpressionList::new().cons(&Expression::Str("a\" \"b".into())),
+        ));
+        let two = Expression::try_from("(\"a\" \"b\")".as_bytes()).unwrap();
+        assert_ne!(one.to_string(), two.to_string());
+        assert_ne!(one.normalize(), two.normalize());
+        assert_eq!(one.normalize(), "(\"a\\\" \\\"b\")");
+        let backslash = Expression::Str("\\".into());
+        assert_eq!(backslash.normalize(), "\"\\\\\"");
+    }
 }
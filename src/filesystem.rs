@@ -0,0 +1,86 @@
+//!
+//! `slurp` 、`spit` 及び `file-exists` で使う、ファイルの読み書きの方法を定義
+//!
+
+use crate::eval::EvalError;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use core::cell::RefCell;
+
+/// ファイル全体の読み書きを行う。
+/// 組み込み先に合わせて、実際のファイルシステムの代わりにメモリ上の仮想的なファイルを使える。
+/// 組み込み関数からの利用は `Capabilities::allow_fs` で許可する必要がある
+pub trait FileSystem {
+    /// ファイル全体を文字列として読み込む
+    fn read(&self, path: &str) -> Result<String, EvalError>;
+    /// ファイル全体を `contents` で置き換える。ファイルが無ければ作成する
+    fn write(&mut self, path: &str, contents: &str) -> Result<(), EvalError>;
+    /// ファイルが存在するかどうか
+    fn exists(&self, path: &str) -> bool;
+}
+
+/// 実際のファイルシステムを読み書きする。`Context` のデフォルト。`std` feature が必要
+#[cfg(feature = "std")]
+pub struct StdFileSystem;
+
+#[cfg(feature = "std")]
+impl FileSystem for StdFileSystem {
+    fn read(&self, path: &str) -> Result<String, EvalError> {
+        return std::fs::read_to_string(path)
+            .map_err(|e| EvalError::IoFailed(format!("{}: {}", path, e)));
+    }
+
+    fn write(&mut self, path: &str, contents: &str) -> Result<(), EvalError> {
+        return std::fs::write(path, contents)
+            .map_err(|e| EvalError::IoFailed(format!("{}: {}", path, e)));
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        return std::path::Path::new(path).exists();
+    }
+}
+
+/// メモリ上のファイルを読み書きする。テストやサンドボックス環境向け。
+/// `clone` したものは同じファイルを共有するので、`Context` に渡した後もホスト側から内容を確認できる
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Rc<RefCell<BTreeMap<String, String>>>,
+}
+
+impl MemoryFileSystem {
+    /// ファイルが 1 つも無い状態で作成する
+    pub fn new() -> MemoryFileSystem {
+        return MemoryFileSystem::default();
+    }
+
+    /// ファイル `path` の内容を `contents` にする
+    pub fn insert(&self, path: &str, contents: &str) {
+        self.files
+            .borrow_mut()
+            .insert(path.to_string(), contents.to_string());
+    }
+
+    /// ファイル `path` の内容。存在しなければ `None`
+    pub fn get(&self, path: &str) -> Option<String> {
+        return self.files.borrow().get(path).cloned();
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &str) -> Result<String, EvalError> {
+        return self
+            .get(path)
+            .ok_or_else(|| EvalError::IoFailed(format!("{}: no such file", path)));
+    }
+
+    fn write(&mut self, path: &str, contents: &str) -> Result<(), EvalError> {
+        self.insert(path, contents);
+        return Ok(());
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        return self.files.borrow().contains_key(path);
+    }
+}
@@ -0,0 +1,189 @@
+//!
+//! Lisp のソースを、決まった形にインデントして出力するフォーマッタを定義
+//!
+
+use crate::expression::*;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// フォーマットの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatOptions {
+    pub width: usize,  // 1 行の最大幅。収まらない式は複数行に分割する
+    pub indent: usize, // 複数行に分割した時の、本体のインデント幅
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        return FormatOptions {
+            width: 80,
+            indent: 2,
+        };
+    }
+}
+
+/// `Expression` を整形した文字列に変換する。
+/// 1 行に収まる式はそのまま 1 行で、収まらない式は要素ごとに改行して出力する。
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::Expression;
+/// use liblisp::format::{format_expression, FormatOptions};
+/// use std::convert::TryFrom;
+///
+/// let exp = Expression::try_from("(defun f (*x*) (add *x* 1))".as_bytes()).unwrap();
+/// let options = FormatOptions { width: 20, indent: 2 };
+/// assert_eq!(format_expression(&exp, &options), "(defun f (*x*)\n  (add *x* 1))");
+/// ```
+pub fn format_expression(exp: &Expression, options: &FormatOptions) -> String {
+    let mut out = String::new();
+    write_expression(&mut out, exp, 0, options);
+    return out;
+}
+
+/// 複数の式が並んだソースを整形する。トップレベルの式の間には空行を 1 行入れる
+pub fn format_source(
+    src: &str,
+    options: &FormatOptions,
+) -> Result<String, ExpressionConversionError> {
+    let program = parse_program(src)?;
+    let mut out = String::new();
+    for (i, exp) in program.iter().enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+        write_expression(&mut out, exp, 0, options);
+        out.push('\n');
+    }
+    return Ok(out);
+}
+
+// 関数名と同じ行に置く引数の数。defun の名前と仮引数のように、本体より前に置くものを数える
+fn header_count(name: &str) -> usize {
+    match name {
+        "defun" | "defmacro" => {
+            return 2;
+        }
+        "let" | "dotimes" | "dolist" | "while" | "module" | "deftest" | "define" | "set"
+        | "try" => {
+            return 1;
+        }
+        _ => {
+            return 0;
+        }
+    }
+}
+
+// out の最後の行の、現在の桁位置
+fn current_column(out: &str) -> usize {
+    let line_start = out.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    return out[line_start..].chars().count();
+}
+
+// column 桁目から exp を書き出す
+fn write_expression(out: &mut String, exp: &Expression, column: usize, options: &FormatOptions) {
+    let flat = exp.to_string();
+    let elements: Vec<Expression> = match exp {
+        Expression::ExpressionList(l)
+            if !l.is_empty() && column + flat.chars().count() > options.width =>
+        {
+            l.iter().cloned().collect()
+        }
+        _ => {
+            out.push_str(&flat);
+            return;
+        }
+    };
+
+    out.push('(');
+    if let Expression::Atom(name) = &elements[0] {
+        // (name header ...
+        //   body ...)
+        out.push_str(name);
+        let headers = header_count(name).min(elements.len() - 1);
+        for e in &elements[1..=headers] {
+            out.push(' ');
+            let col = current_column(out);
+            write_expression(out, e, col, options);
+        }
+        let body_column = column + options.indent;
+        for e in &elements[headers + 1..] {
+            out.push('\n');
+            out.push_str(&" ".repeat(body_column));
+            write_expression(out, e, body_column, options);
+        }
+    } else {
+        // 先頭が Atom でないリストは、要素の先頭を揃える
+        for (i, e) in elements.iter().enumerate() {
+            if i != 0 {
+                out.push('\n');
+                out.push_str(&" ".repeat(column + 1));
+            }
+            write_expression(out, e, column + 1, options);
+        }
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expression::*;
+    use crate::format::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn format_expression_tests() {
+        let options = FormatOptions {
+            width: 30,
+            indent: 2,
+        };
+        // 幅に収まる式は 1 行のまま
+        {
+            let exp = Expression::try_from("(add 1   (mul 2 3))".as_bytes()).unwrap();
+            assert_eq!(format_expression(&exp, &options), "(add 1 (mul 2 3))");
+        }
+        {
+            let exp = Expression::try_from(
+                "(defun sum (*l*) (define *a* 0) (dolist (*x* *l*) (set *a* (add *a* *x*))) *a*)"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                format_expression(&exp, &options),
+                "(defun sum (*l*)\n  (define *a* 0)\n  (dolist (*x* *l*)\n    (set *a* (add *a* *x*)))\n  *a*)"
+            );
+        }
+        {
+            let exp = Expression::try_from(
+                "(let ((*first* 100) (*second* 200)) (add *first* *second*))".as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(
+                format_expression(&exp, &options),
+                "(let ((*first* 100)\n      (*second* 200))\n  (add *first* *second*))"
+            );
+        }
+    }
+
+    #[test]
+    fn format_source_tests() {
+        let options = FormatOptions::default();
+        assert_eq!(
+            format_source("(set *a* 1)   (add *a*\n 2)", &options),
+            Ok("(set *a* 1)\n\n(add *a* 2)\n".to_string())
+        );
+        assert_eq!(format_source("", &options), Ok("".to_string()));
+        assert_eq!(
+            format_source("(add 1", &options),
+            Err(ExpressionConversionError::UnexpectedEof)
+        );
+        // 整形結果を読み込むと、元と同じ式になる
+        let src = "(progn (defun f (*x*) (cond (eq *x* 0) 1 (mul *x* (f (sub *x* 1))))) (f 5))";
+        let narrow = FormatOptions {
+            width: 10,
+            indent: 2,
+        };
+        let formatted = format_source(src, &narrow).unwrap();
+        assert_eq!(parse_program(&formatted), parse_program(src));
+    }
+}
@@ -0,0 +1,250 @@
+//!
+//! シンタックスハイライトのために、ソースを字句解析器と同じ規則で分割し、各部分を種類ごとに分類する
+//!
+//! 字句解析器が読み飛ばすコメントも返す。不正なトークンがあっても、その部分を `HighlightKind::Error` として続きを分類する
+//!
+
+use crate::eval::{is_builtin, is_special_form};
+use crate::expression::ReaderOptions;
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+use alloc::vec::Vec;
+
+/// ハイライトの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    Paren,       // ( ) [ ] { }
+    Quote,       // ' ` , ,@ #'
+    Int,         // 整数
+    Atom,        // 組み込み関数以外の atom
+    Builtin,     // add のような、引数を評価してから受け取る組み込み関数の名前
+    SpecialForm, // defun や cond のような、特殊形式の名前
+    Var,         // *x* の形式の変数
+    Str,         // 前後の " も含めた文字列
+    Keyword,     // :k の形式のキーワード
+    Comment,     // ; から行末までのコメント
+    Error,       // 読み込めなかった部分
+}
+
+/// ソース中の、1 つの種類でハイライトする部分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Highlight {
+    pub kind: HighlightKind,
+    pub span: Span,
+}
+
+/// `src` を `ReaderOptions::default()` の設定で分類し、ソース中の順に返す。空白は返さない
+///
+/// # Examples
+/// ```
+/// use liblisp::highlight::{highlight, HighlightKind};
+///
+/// let src = "(defun f (*x*) (add *x* 1)) ; 足し算";
+/// let kinds: Vec<(HighlightKind, &str)> = highlight(src)
+///     .iter()
+///     .map(|h| (h.kind, &src[h.span.start..h.span.end]))
+///     .collect();
+/// assert_eq!(kinds[1], (HighlightKind::SpecialForm, "defun"));
+/// assert_eq!(kinds[2], (HighlightKind::Atom, "f"));
+/// assert_eq!(kinds[7], (HighlightKind::Builtin, "add"));
+/// assert_eq!(kinds.last(), Some(&(HighlightKind::Comment, "; 足し算")));
+/// ```
+pub fn highlight(src: &str) -> Vec<Highlight> {
+    return highlight_with(src, &ReaderOptions::default());
+}
+
+/// `src` を `options` の設定で分類し、ソース中の順に返す。
+/// `options` のうち、コメント・負の整数・名前に使える文字の設定を使う
+pub fn highlight_with(src: &str, options: &ReaderOptions) -> Vec<Highlight> {
+    let bytes = src.as_bytes();
+    let mut res = Vec::new();
+    let mut offset = 0;
+    // 字句解析器はエラーの後を読み進めないので、エラーの直後から読み直す
+    loop {
+        let mut prev = offset;
+        let mut error = false;
+        for token in Lexer::with_options(&bytes[offset..], options) {
+            let (kind, span) = match token {
+                Ok(Token { kind, span }) => (classify(kind), span),
+                Err(e) => {
+                    error = true;
+                    (HighlightKind::Error, e.span)
+                }
+            };
+            let span = Span {
+                start: offset + span.start,
+                end: offset + span.end,
+            };
+            comments(bytes, prev, span.start, options, &mut res);
+            res.push(Highlight { kind, span });
+            prev = span.end;
+        }
+        if !error {
+            comments(bytes, prev, bytes.len(), options, &mut res);
+            return res;
+        }
+        offset = prev;
+    }
+}
+
+// トークンの種類からハイライトの種類を決める
+fn classify(kind: TokenKind) -> HighlightKind {
+    match kind {
+        TokenKind::LParen
+        | TokenKind::RParen
+        | TokenKind::LBracket
+        | TokenKind::RBracket
+        | TokenKind::LBrace
+        | TokenKind::RBrace => {
+            return HighlightKind::Paren;
+        }
+        TokenKind::Quote
+        | TokenKind::Quasiquote
+        | TokenKind::Unquote
+        | TokenKind::UnquoteSplicing
+        | TokenKind::FunctionQuote => {
+            return HighlightKind::Quote;
+        }
+        TokenKind::Int(_) => {
+            return HighlightKind::Int;
+        }
+        TokenKind::Atom(name) => {
+            if is_special_form(name) {
+                return HighlightKind::SpecialForm;
+            }
+            if is_builtin(name) {
+                return HighlightKind::Builtin;
+            }
+            return HighlightKind::Atom;
+        }
+        TokenKind::Var(_) => {
+            return HighlightKind::Var;
+        }
+        TokenKind::Str(_) => {
+            return HighlightKind::Str;
+        }
+        TokenKind::Keyword(_) => {
+            return HighlightKind::Keyword;
+        }
+    }
+}
+
+// トークンの間 bytes[start..end] にあるコメントを res に追加する。トークンの間には、空白とコメントしか無い
+fn comments(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+    options: &ReaderOptions,
+    res: &mut Vec<Highlight>,
+) {
+    if !options.comments {
+        return;
+    }
+    let mut index = start;
+    while index < end {
+        if bytes[index] != b';' {
+            index += 1;
+            continue;
+        }
+        let comment_start = index;
+        while index < end && bytes[index] != b'\n' {
+            index += 1;
+        }
+        res.push(Highlight {
+            kind: HighlightKind::Comment,
+            span: Span {
+                start: comment_start,
+                end: index,
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::*;
+    use alloc::vec;
+
+    fn kinds<'a>(src: &'a str, options: &ReaderOptions) -> Vec<(HighlightKind, &'a str)> {
+        return highlight_with(src, options)
+            .iter()
+            .map(|h| (h.kind, &src[h.span.start..h.span.end]))
+            .collect();
+    }
+
+    #[test]
+    fn highlight_tests() {
+        use HighlightKind::*;
+        let options = ReaderOptions::default();
+        assert_eq!(
+            kinds(
+                "; 先頭\n(cond (listp '(:k \"a;b\")) [-1 #'f]) ;; 末尾",
+                &options
+            ),
+            vec![
+                (Comment, "; 先頭"),
+                (Paren, "("),
+                (SpecialForm, "cond"),
+                (Paren, "("),
+                (Builtin, "listp"),
+                (Quote, "'"),
+                (Paren, "("),
+                (Keyword, ":k"),
+                (Str, "\"a;b\""),
+                (Paren, ")"),
+                (Paren, ")"),
+                (Paren, "["),
+                (Int, "-1"),
+                (Quote, "#'"),
+                (Atom, "f"),
+                (Paren, "]"),
+                (Paren, ")"),
+                (Comment, ";; 末尾"),
+            ]
+        );
+        assert_eq!(
+            kinds("`(a ,*x* ,@y)", &options),
+            vec![
+                (Quote, "`"),
+                (Paren, "("),
+                (Atom, "a"),
+                (Quote, ","),
+                (Var, "*x*"),
+                (Quote, ",@"),
+                (Atom, "y"),
+                (Paren, ")"),
+            ]
+        );
+        assert_eq!(kinds(" \n ", &options), vec![]);
+
+        // 読み込めない部分の後も、続きを分類する
+        assert_eq!(
+            kinds("(add 1x *y) ; c\n(f)", &options),
+            vec![
+                (Paren, "("),
+                (Builtin, "add"),
+                (Error, "1x"),
+                (Error, "*y"),
+                (Paren, ")"),
+                (Comment, "; c"),
+                (Paren, "("),
+                (Atom, "f"),
+                (Paren, ")"),
+            ]
+        );
+        assert_eq!(
+            kinds("(f \"abc", &options),
+            vec![(Paren, "("), (Atom, "f"), (Error, "\"abc")]
+        );
+
+        // コメントを読み込まない設定では、; はトークンとして扱う
+        let options = ReaderOptions {
+            comments: false,
+            negative_ints: false,
+            ..ReaderOptions::default()
+        };
+        assert_eq!(
+            kinds("a ;b -1", &options),
+            vec![(Atom, "a"), (Error, ";b"), (Error, "-1")]
+        );
+    }
+}
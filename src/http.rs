@@ -0,0 +1,69 @@
+//!
+//! `http-get` 及び `http-post` で使う、HTTP クライアントを定義。`http` feature が必要
+//!
+
+use crate::eval::EvalError;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// HTTP リクエスト
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String, // "GET" や "POST"
+    pub url: String,
+    pub body: Option<String>, // POST で送る本文
+}
+
+/// HTTP レスポンス。4xx や 5xx のステータスもエラーにせず、そのまま返す
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>, // ヘッダの名前と値。受け取った順に並べる
+    pub body: String,
+}
+
+/// HTTP リクエストを送る。`Context::set_http_client` で差し替えて、
+/// 組み込み先が用意したクライアントや、テスト用のクライアントを使える。
+/// 組み込み関数からの利用は `Capabilities::allow_net` で許可する必要がある
+pub trait HttpClient {
+    /// リクエストを送り、レスポンスを受け取る。接続できなかった場合などは `EvalError::IoFailed`
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, EvalError>;
+}
+
+/// ureq を使う HTTP クライアント。`Context` のデフォルト。TLS には対応しない
+pub struct UreqClient;
+
+impl HttpClient for UreqClient {
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, EvalError> {
+        let req = ureq::request(&request.method, &request.url);
+        let res = match &request.body {
+            Some(body) => req.send_string(body),
+            None => req.call(),
+        };
+        let res = match res {
+            Ok(res) => res,
+            Err(ureq::Error::Status(_, res)) => res,
+            Err(e) => {
+                return Err(EvalError::IoFailed(format!("{}: {}", request.url, e)));
+            }
+        };
+        let status = res.status();
+        let headers = res
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = res.header(&name)?.into();
+                return Some((name, value));
+            })
+            .collect();
+        let body = res
+            .into_string()
+            .map_err(|e| EvalError::IoFailed(format!("{}: {}", request.url, e)))?;
+        return Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        });
+    }
+}
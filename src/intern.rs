@@ -0,0 +1,194 @@
+//!
+//! 同じ形の部分式が 1 つの `Rc` を共有するように式を作り直す（hash-consing）`Interner` を定義
+//!
+//! 生成したコードのように同じ部分式が繰り返し現れるプログラムで、メモリの使用量を減らす。
+//! `Expression` の比較は、同じ `Rc` を指していれば中身を辿らずに等しいと判定するので、共有した式どうしの比較も速くなる
+//!
+
+use crate::expression::*;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
+
+/// 式を作り直すたびに、それまでに作った部分式と同じ形のものを再利用する。
+/// 名前（atom・変数・文字列・キーワード）も、同じ文字列は 1 つの `Rc<str>` を共有する。
+/// リストは末尾を共有するセル単位で再利用するので、`(a b c)` と `(x b c)` の `(b c)` の部分も共有する
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::{parse_program, Expression};
+/// use liblisp::intern::Interner;
+/// use std::rc::Rc;
+///
+/// let program = parse_program("(add (mul 2 *x*) 1) (sub (mul 2 *x*) 1)").unwrap();
+/// let mut interner = Interner::new();
+/// let first = interner.intern(&program[0]);
+/// let second = interner.intern(&program[1]);
+/// let operand = |exp: &Expression| match exp {
+///     Expression::ExpressionList(l) => l.get(1).cloned().unwrap(),
+///     _ => unreachable!(),
+/// };
+/// match (operand(&first), operand(&second)) {
+///     (Expression::ExpressionList(a), Expression::ExpressionList(b)) => assert!(Rc::ptr_eq(&a, &b)),
+///     _ => unreachable!(),
+/// }
+/// assert_eq!(first, program[0]);
+/// ```
+#[derive(Debug)]
+pub struct Interner {
+    names: BTreeSet<Rc<str>>, // 作り直した式に現れる名前や文字列
+    cells: Map<(u8, u128, usize), Rc<ExpressionList>>, // (先頭の要素の種類, 先頭の要素, 残りのリスト) をキーにした、リストのセル
+    nil: Rc<ExpressionList>,                           // 空リスト
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        return Interner::new();
+    }
+}
+
+impl Interner {
+    /// 何も共有していない状態で作成する
+    pub fn new() -> Interner {
+        return Interner {
+            names: BTreeSet::new(),
+            cells: Map::new(),
+            nil: Rc::new(ExpressionList::new()),
+        };
+    }
+
+    /// `exp` と等しい式を、これまでに作った部分式を再利用して作る。
+    /// 同じ `Interner` で作った式どうしは、同じ形の部分式が同じ `Rc` を指す
+    pub fn intern(&mut self, exp: &Expression) -> Expression {
+        match exp {
+            Expression::Int(i) => {
+                return Expression::Int(*i);
+            }
+            Expression::Atom(a) => {
+                return Expression::Atom(self.name(a));
+            }
+            Expression::Var(v) => {
+                return Expression::Var(self.name(v));
+            }
+            Expression::Str(s) => {
+                return Expression::Str(self.name(s));
+            }
+            Expression::Keyword(k) => {
+                return Expression::Keyword(self.name(k));
+            }
+            Expression::ExpressionList(l) => {
+                let items: alloc::vec::Vec<Expression> = l.iter().map(|e| self.intern(e)).collect();
+                let mut list = self.nil.clone();
+                for item in items.iter().rev() {
+                    list = self.cell(item, list);
+                }
+                return Expression::ExpressionList(list);
+            }
+        }
+    }
+
+    /// これまでに作ったリストのセルの数。空リストは数えない
+    pub fn cells(&self) -> usize {
+        return self.cells.len();
+    }
+
+    // name と同じ文字列の Rc<str>。初めて現れた文字列なら登録する
+    fn name(&mut self, name: &Rc<str>) -> Rc<str> {
+        if let Some(shared) = self.names.get(&**name) {
+            return shared.clone();
+        }
+        self.names.insert(name.clone());
+        return name.clone();
+    }
+
+    // 先頭が head 、残りが tail のリストのセル。head と tail は、既にこの Interner で作り直したもの
+    fn cell(&mut self, head: &Expression, tail: Rc<ExpressionList>) -> Rc<ExpressionList> {
+        // 作り直した式は、同じ形なら同じ Rc を指すので、アドレスで区別できる。
+        // Interner が全ての Rc を保持しているので、アドレスが別の式に再利用されることは無い。
+        // 整数は、どの幅の Int でも値ごとに異なる u128 に変換する
+        let (kind, id) = match head {
+            Expression::Int(i) => (0, *i as u128),
+            Expression::Atom(a) => (1, Rc::as_ptr(a) as *const u8 as usize as u128),
+            Expression::Var(v) => (2, Rc::as_ptr(v) as *const u8 as usize as u128),
+            Expression::Str(s) => (3, Rc::as_ptr(s) as *const u8 as usize as u128),
+            Expression::Keyword(k) => (4, Rc::as_ptr(k) as *const u8 as usize as u128),
+            Expression::ExpressionList(l) => (5, Rc::as_ptr(l) as usize as u128),
+        };
+        let key = (kind, id, Rc::as_ptr(&tail) as usize);
+        return self
+            .cells
+            .entry(key)
+            .or_insert_with(|| {
+                return Rc::new(ExpressionList::Cons(head.clone(), tail));
+            })
+            .clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intern::*;
+    use core::convert::TryFrom;
+
+    fn list(exp: &Expression) -> &Rc<ExpressionList> {
+        match exp {
+            Expression::ExpressionList(l) => {
+                return l;
+            }
+            _ => panic!("not a list: {}", exp),
+        }
+    }
+
+    #[test]
+    fn intern_tests() {
+        let src = "(f (g 1 \"s\") (g 1 \"s\") (h (g 1 \"s\")) () ())";
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        let mut interner = Interner::new();
+        let interned = interner.intern(&exp);
+        assert_eq!(interned, exp);
+        assert_eq!(interned.to_string(), src);
+
+        let items: alloc::vec::Vec<&Expression> = list(&interned).iter().collect();
+        // 同じ形の部分式は 1 つの Rc を共有する
+        assert!(Rc::ptr_eq(list(items[1]), list(items[2])));
+        assert!(Rc::ptr_eq(
+            list(items[1]),
+            list(list(items[3]).get(1).unwrap())
+        ));
+        assert!(Rc::ptr_eq(list(items[4]), list(items[5])));
+        match (list(items[1]).get(2), list(items[2]).get(2)) {
+            (Some(Expression::Str(a)), Some(Expression::Str(b))) => assert!(Rc::ptr_eq(a, b)),
+            _ => assert!(false),
+        }
+        // (f ...) の 6 セル、(g 1 "s") の 3 セル、(h ...) の 2 セル
+        assert_eq!(interner.cells(), 11);
+
+        // 同じ Interner で作り直した別の式とも共有する
+        let other = Expression::try_from("(k (g 1 \"s\"))".as_bytes()).unwrap();
+        let other = interner.intern(&other);
+        assert!(Rc::ptr_eq(
+            list(items[1]),
+            list(list(&other).get(1).unwrap())
+        ));
+        // 末尾が同じリストは、末尾のセルを共有する
+        let suffix = Expression::try_from("(x 1 \"s\")".as_bytes()).unwrap();
+        let suffix = interner.intern(&suffix);
+        match (&**list(&suffix), &**list(items[1])) {
+            (ExpressionList::Cons(_, a), ExpressionList::Cons(_, b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => assert!(false),
+        }
+
+        // 同じ名前や値でも、種類の違う式は区別する
+        let kinds = Expression::try_from("(a \"a\" :a *a* 1 -1 (a) (1))".as_bytes()).unwrap();
+        assert_eq!(interner.intern(&kinds), kinds);
+        // 下位 32 bit が同じ整数も区別する
+        #[cfg(not(all(feature = "int32", not(feature = "int64"), not(feature = "int128"))))]
+        {
+            let wide = Expression::try_from("((0) (4294967296))".as_bytes()).unwrap();
+            assert_eq!(interner.intern(&wide), wide);
+        }
+    }
+}
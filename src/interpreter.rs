@@ -0,0 +1,391 @@
+//!
+//! ソースの読み込みと評価をまとめて行う、インタプリタを定義
+//!
+
+use crate::analyze::{analyze_program, Diagnostic};
+use crate::convert::IntoNativeFn;
+use crate::eval::*;
+use crate::expression::*;
+use crate::output::MemoryOutput;
+use crate::types::*;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+/// `Interpreter::eval_str` 呼び出し時のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum LispError {
+    Parse(ExpressionConversionError), // ソースを式に変換できなかった
+    Eval(EvalError),                  // 式の評価に失敗した
+}
+
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LispError::Parse(e) => {
+                return write!(f, "parse error: {:?}", e);
+            }
+            LispError::Eval(e) => {
+                return write!(f, "eval error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LispError {}
+
+impl From<ExpressionConversionError> for LispError {
+    fn from(e: ExpressionConversionError) -> Self {
+        return LispError::Parse(e);
+    }
+}
+
+impl From<EvalError> for LispError {
+    fn from(e: EvalError) -> Self {
+        return LispError::Eval(e);
+    }
+}
+
+/// `Interpreter::eval_str_detailed` の結果。REPL などのフロントエンドで表示する情報をまとめたもの
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    pub value: Result<Type, LispError>, // eval_str と同じ評価結果
+    pub display: String, // 値を Display で表した文字列。エラーになった場合は、エラーを Display で表した文字列
+    pub output: String,  // 評価中に print で出力した文字列
+    pub warnings: Vec<Diagnostic>, // 評価前に静的解析で見つかった、誤りの可能性がある箇所
+    pub elapsed: Option<Duration>, // 読み込みと評価にかかった時間。時刻を取得できない wasm32 及び no_std では None
+}
+
+/// `Context` を持ち、文字列のソースを読み込んで評価するインタプリタ。
+/// 評価で行った変数や関数の定義は、次の `eval_str` 呼び出しに引き継がれる
+///
+/// # Examples
+/// ```
+/// use liblisp::interpreter::Interpreter;
+/// use liblisp::types::{Int, Type};
+///
+/// let mut interp = Interpreter::new();
+/// interp.define_var("*base*", Type::Int(10)).unwrap();
+/// interp.register_fn("len", |args: &[Type]| Ok(Type::Int(args.len() as Int)));
+/// interp.register_fn("mul3", |a: i32, b: i32, c: i32| a * b * c);
+/// interp.eval_str("(defun inc (*x*) (add *x* 1))").unwrap();
+/// assert_eq!(interp.eval_str("(inc *base*)"), Ok(Type::Int(11)));
+/// assert_eq!(interp.eval_str("(len a b c)"), Ok(Type::Int(3)));
+/// assert_eq!(interp.eval_str("(mul3 2 3 4)"), Ok(Type::Int(24)));
+/// ```
+pub struct Interpreter {
+    context: Context,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        return Interpreter::new();
+    }
+}
+
+impl Interpreter {
+    /// 何も定義されていない `Context` を持つインタプリタを新規作成
+    pub fn new() -> Interpreter {
+        return Interpreter {
+            context: Context::new(),
+        };
+    }
+
+    /// 標準ライブラリを読み込んだ `Context` を持つインタプリタを新規作成
+    pub fn new_with_stdlib() -> Interpreter {
+        return Interpreter {
+            context: Context::new_with_stdlib(),
+        };
+    }
+
+    /// ソース中のトップレベルの式を先頭から順に評価し、最後に評価した式の値を返す。
+    /// 式が一つもない場合は `Type::Void` を返す。途中でエラーになった場合、それまでに評価した式による定義は残る
+    pub fn eval_str(&mut self, src: &str) -> Result<Type, LispError> {
+        let program = parse_program(src)?;
+        let mut res = Type::Void;
+        for exp in &program {
+            res = eval_with_context(exp, &mut self.context)?;
+        }
+        return Ok(res);
+    }
+
+    /// `eval_str` と同様にソースを評価し、値と合わせて、`print` の出力、静的解析の警告、かかった時間を返す。
+    /// `print` の出力は `EvalReport::output` に取り込み、`Context` に設定された `Output` には書き込まない。
+    /// 警告からは、これまでの評価やホスト側で定義された関数や変数に関するものを除く
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::analyze::Diagnostic;
+    /// use liblisp::interpreter::Interpreter;
+    /// use liblisp::types::Type;
+    ///
+    /// let mut interp = Interpreter::new();
+    /// interp.eval_str("(define *n* 2)").unwrap();
+    /// let report = interp.eval_str_detailed("(progn (print \"n is\" *n*) (list *n* *m*))");
+    /// assert_eq!(report.output, "n is 2\n");
+    /// assert_eq!(report.warnings, vec![Diagnostic::UndefinedVariable("*m*".into())]);
+    /// assert!(report.value.is_err());
+    /// assert_eq!(report.display, "eval error: UndefinedVariableReference");
+    /// ```
+    pub fn eval_str_detailed(&mut self, src: &str) -> EvalReport {
+        #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+        let start = std::time::Instant::now();
+        let captured = MemoryOutput::new();
+        let previous = self.context.replace_output(Box::new(captured.clone()));
+        let mut warnings = Vec::new();
+        let value = parse_program(src)
+            .map_err(LispError::from)
+            .and_then(|program| {
+                warnings = analyze_program(&program)
+                    .into_iter()
+                    .filter(|d| !self.is_known(d))
+                    .collect();
+                let mut res = Type::Void;
+                for exp in &program {
+                    res = eval_with_context(exp, &mut self.context)?;
+                }
+                return Ok(res);
+            });
+        self.context.replace_output(previous);
+        #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+        let elapsed = Some(start.elapsed());
+        #[cfg(not(all(feature = "std", not(target_arch = "wasm32"))))]
+        let elapsed = None;
+        let display = match &value {
+            Ok(v) => v.to_string(),
+            Err(e) => e.to_string(),
+        };
+        return EvalReport {
+            value,
+            display,
+            output: captured.take(),
+            warnings,
+            elapsed,
+        };
+    }
+
+    // 静的解析はソースだけを見るので、Context に既にある定義に関する警告を除く
+    fn is_known(&self, d: &Diagnostic) -> bool {
+        match d {
+            Diagnostic::UndefinedVariable(v) => {
+                return self.context.is_bound(v);
+            }
+            Diagnostic::UnknownFunction(name) => {
+                return self.context.describe(name).is_some();
+            }
+            Diagnostic::BadArity { name, .. } => {
+                // 組み込み関数を defun や register_fn で置き換えていれば、引数の数は異なりうる
+                return self.context.describe(name).is_some_and(|d| {
+                    return !matches!(d.kind, SymbolKind::Builtin | SymbolKind::SpecialForm);
+                });
+            }
+        }
+    }
+
+    /// グローバルな変数 `name` （`*x*` の形式）を定義する
+    pub fn define_var(&mut self, name: &str, val: Type) -> Result<(), LispError> {
+        self.context.define_var(name, val)?;
+        return Ok(());
+    }
+
+    /// Rust の関数を、Lisp から `name` という名前で呼び出せる関数として登録する
+    pub fn register_fn<Args>(&mut self, name: &str, f: impl IntoNativeFn<Args>) {
+        self.context.register_fn(name, f);
+    }
+
+    /// Lisp で定義した関数、もしくは登録した関数 `name` を、評価済みの引数で呼び出す（`Context::call`）
+    pub fn call(&mut self, name: &str, args: &[Type]) -> Result<Type, LispError> {
+        return Ok(self.context.call(name, args)?);
+    }
+
+    /// 評価に使う `Context`
+    pub fn context(&self) -> &Context {
+        return &self.context;
+    }
+
+    /// 評価に使う `Context` 。`SourceLoader` や `Capabilities` などの設定に使う
+    pub fn context_mut(&mut self) -> &mut Context {
+        return &mut self.context;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::*;
+
+    #[test]
+    fn interpreter_tests() {
+        let mut interp = Interpreter::new();
+        // 定義は呼び出しをまたいで引き継がれる
+        assert_eq!(
+            interp.eval_str("(define *a* 1) (define *b* 2)"),
+            Ok(Type::Int(2))
+        );
+        assert_eq!(interp.eval_str("(add *a* *b*)"), Ok(Type::Int(3)));
+        assert_eq!(interp.eval_str(""), Ok(Type::Void));
+
+        interp.define_var("*c*", Type::Int(5)).unwrap();
+        assert_eq!(interp.eval_str("*c*"), Ok(Type::Int(5)));
+        interp.eval_str("(defconst *d* 1)").unwrap();
+        assert_eq!(
+            interp.define_var("*d*", Type::Int(2)),
+            Err(LispError::Eval(EvalError::AssignToConstant))
+        );
+
+        interp.register_fn("sum", |args: &[Type]| {
+            let mut s = 0;
+            for a in args {
+                match a {
+                    Type::Int(i) => s += i,
+                    _ => return Err(EvalError::TypeMismatch),
+                }
+            }
+            return Ok(Type::Int(s));
+        });
+        assert_eq!(interp.eval_str("(sum 1 2 (add 1 2))"), Ok(Type::Int(6)));
+        assert_eq!(
+            interp.eval_str("(sum a)"),
+            Err(LispError::Eval(EvalError::TypeMismatch))
+        );
+
+        assert_eq!(
+            interp.eval_str("(add 1"),
+            Err(LispError::Parse(ExpressionConversionError::UnexpectedEof))
+        );
+        assert_eq!(
+            interp.eval_str("(div 1 0)").unwrap_err().to_string(),
+            "eval error: DivisionByZero"
+        );
+
+        let mut interp = Interpreter::new_with_stdlib();
+        assert_eq!(interp.eval_str("(max 1 2)"), Ok(Type::Int(2)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn native_fn_panic_tests() {
+        // 登録した関数が panic しても、エラーとして返り、その後も評価を続けられる
+        let mut interp = Interpreter::new();
+        interp.register_fn("boom", |a: i32| -> i32 { panic!("boom {}", a) });
+        interp.register_fn("oops", || -> i32 { panic!("oops") });
+        assert_eq!(
+            interp.eval_str("(boom 1)"),
+            Err(LispError::Eval(EvalError::NativeFunctionPanicked(
+                "boom 1".into()
+            )))
+        );
+        assert_eq!(
+            interp.eval_str("(oops)"),
+            Err(LispError::Eval(EvalError::NativeFunctionPanicked(
+                "oops".into()
+            )))
+        );
+        assert_eq!(
+            interp.eval_str("(try (boom 2) (catch *e* 0))"),
+            Ok(Type::Int(0))
+        );
+        assert_eq!(interp.eval_str("(add 1 2)"), Ok(Type::Int(3)));
+    }
+
+    #[test]
+    fn call_tests() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval_str(
+                "(defun on-event (*name* *n*) (progn (incf *count* *n*) (list *name* *count*)))
+                 (set *count* 0)
+                 (defun early (*x*) (progn (return *x*) 0))
+                 (module m (defun f () 1))",
+            )
+            .unwrap();
+        interp.register_fn("twice", |a: i32| a * 2);
+
+        let res = interp.call("on-event", &[Type::Atom("click".into()), Type::Int(3)]);
+        assert_eq!(res.map(|t| t.to_string()), Ok("(click 3)".into()));
+        let res = interp.call("on-event", &[Type::Atom("key".into()), Type::Int(2)]);
+        assert_eq!(res.map(|t| t.to_string()), Ok("(key 5)".into()));
+        assert_eq!(interp.call("early", &[Type::Int(7)]), Ok(Type::Int(7)));
+        assert_eq!(interp.call("m:f", &[]), Ok(Type::Int(1)));
+        assert_eq!(interp.call("twice", &[Type::Int(4)]), Ok(Type::Int(8)));
+
+        let cases = vec![
+            ("on-event", vec![Type::Int(1)], EvalError::BadArrity),
+            ("add", vec![Type::Int(1)], EvalError::NotFoundFunctionName),
+            ("undefined", vec![], EvalError::NotFoundFunctionName),
+            (
+                "twice",
+                vec![Type::Void],
+                EvalError::ArgumentTypeMismatch {
+                    name: "twice".into(),
+                    index: 1,
+                    expected: "i32".into(),
+                },
+            ),
+        ];
+        for (name, args, expected) in cases {
+            assert_eq!(
+                interp.call(name, &args),
+                Err(LispError::Eval(expected)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn eval_str_detailed_tests() {
+        let output = MemoryOutput::new();
+        let mut interp = Interpreter::new();
+        interp.context_mut().set_output(Box::new(output.clone()));
+        interp.register_fn("host", |a: i32| a);
+        interp
+            .eval_str("(defun add (*x*) *x*) (define *a* 1)")
+            .unwrap();
+
+        let report = interp.eval_str_detailed("(print \"hi\") (list (add *a*) (host 2) (sub 1))");
+        assert_eq!(
+            report.value,
+            Err(LispError::Eval(EvalError::ArityMismatch {
+                name: "sub".into(),
+                min: 2,
+                max: Some(2),
+                actual: 1
+            }))
+        );
+        assert_eq!(report.output, "hi\n");
+        // 置き換えた add と、既に定義された変数・関数は警告しない
+        assert_eq!(
+            report.warnings,
+            vec![Diagnostic::BadArity {
+                name: "sub".into(),
+                min: 2,
+                max: Some(2),
+                actual: 1
+            }]
+        );
+        assert_eq!(report.display, "eval error: sub expects 2 arguments, got 1");
+        // 取り込んだ出力は、設定された Output には書き込まない
+        assert_eq!(output.contents(), "");
+        #[cfg(feature = "std")]
+        assert!(report.elapsed.is_some());
+
+        let report = interp.eval_str_detailed("(list (add *a*) (host 2) \"s\") (void)");
+        assert_eq!(report.value, Ok(Type::Void));
+        assert_eq!(report.display, "");
+        assert!(report.warnings.is_empty());
+
+        let report = interp.eval_str_detailed("(print 1) (add");
+        assert_eq!(
+            report.value,
+            Err(LispError::Parse(ExpressionConversionError::UnexpectedEof))
+        );
+        assert_eq!(report.output, "");
+
+        // 評価後は、設定された Output に書き込む
+        interp.eval_str("(print 2)").unwrap();
+        assert_eq!(output.contents(), "2\n");
+    }
+}
@@ -0,0 +1,802 @@
+//!
+//! ソースの文字列を、括弧や名前などのトークンの列に分割する字句解析器を定義
+//!
+//! `Expression` への変換（`Expression::try_from` など）は、この字句解析器が返すトークンの列を読み込んで行う
+//!
+
+use crate::expression::{ExpressionConversionError, ParseError, ReaderOptions};
+use crate::types::Int;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// トークンの、ソース中の位置。`start` から `end` の直前までのバイトがトークンにあたる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// トークンの種類。名前や文字列は、ソースの文字列をそのまま参照する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind<'a> {
+    LParen,
+    RParen,
+    LBracket, // [
+    RBracket, // ]
+    LBrace,   // {
+    RBrace,   // }
+    Int(Int),
+    Atom(&'a str),
+    Var(&'a str),
+    Str(&'a str),     // 前後の " を除いた内容。エスケープシーケンスはそのまま含む
+    Keyword(&'a str), // 先頭の : を除いた名前
+    Quote,            // '
+    Quasiquote,       // `
+    Unquote,          // ,
+    UnquoteSplicing,  // ,@
+    FunctionQuote,    // #'
+}
+
+/// 位置付きのトークン
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Span,
+}
+
+/// byte 列を先頭から順にトークンに分割する `Iterator`。
+/// トークンの間の空白は読み飛ばす。不正なトークンがあればエラーを返し、それ以降は何も返さない
+pub struct Lexer<'a> {
+    bytes: &'a [u8],
+    index: usize,
+    options: ReaderOptions,
+}
+
+/// `src` 全体をトークンに分割する
+///
+/// # Examples
+/// ```
+/// use liblisp::lexer::{tokenize, Span, Token, TokenKind};
+///
+/// let tokens = tokenize("'(f *x*)").unwrap();
+/// let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     vec![
+///         TokenKind::Quote,
+///         TokenKind::LParen,
+///         TokenKind::Atom("f"),
+///         TokenKind::Var("*x*"),
+///         TokenKind::RParen,
+///     ]
+/// );
+/// assert_eq!(tokens[3].span, Span { start: 4, end: 7 });
+/// ```
+pub fn tokenize(src: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    return Lexer::new(src.as_bytes()).collect();
+}
+
+/// `name` が、前後の空白を含まない、`*x*` の形式の変数 1 つだけからなるなら true
+///
+/// # Examples
+/// ```
+/// use liblisp::lexer::is_var_name;
+///
+/// assert!(is_var_name("*input*"));
+/// assert!(!is_var_name("input"));
+/// assert!(!is_var_name("*a* *b*"));
+/// ```
+pub fn is_var_name(name: &str) -> bool {
+    let mut lexer = Lexer::new(name.as_bytes());
+    return matches!(
+        lexer.next(),
+        Some(Ok(Token {
+            kind: TokenKind::Var(_),
+            span,
+        })) if span.start == 0 && span.end == name.len()
+    );
+}
+
+// 要素の区切りとして扱う空白文字
+fn is_space(c: char) -> bool {
+    return c == ' ' || c == '\n' || c == '\t' || c == '\r';
+}
+
+/// 文字列リテラルの中身 `raw` （前後の `"` を除いたもの）のエスケープシーケンスを、それが表す文字に置き換える。
+/// `\n` `\t` `\"` `\\` と、1 から 6 桁の 16 進数で符号位置を表す `\u{1F600}` の形式を扱う。
+/// それ以外のエスケープシーケンスを含む場合は `ExpressionConversionError::InvalidToken`
+///
+/// # Examples
+/// ```
+/// use liblisp::lexer::unescape;
+///
+/// assert_eq!(unescape(r#"a\tb\n\"\\\u{1F600}"#), Ok("a\tb\n\"\\😀".to_string()));
+/// assert!(unescape(r"\x").is_err());
+/// ```
+pub fn unescape(raw: &str) -> Result<String, ExpressionConversionError> {
+    let mut res = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('"') => res.push('"'),
+            Some('\\') => res.push('\\'),
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err(ExpressionConversionError::InvalidToken);
+                }
+                let mut code: u32 = 0;
+                let mut digits = 0;
+                loop {
+                    match chars.next() {
+                        Some('}') if digits > 0 => {
+                            break;
+                        }
+                        Some(d) if digits < 6 && d.is_ascii_hexdigit() => {
+                            code = code * 16 + d.to_digit(16).unwrap_or(0);
+                            digits += 1;
+                        }
+                        _ => {
+                            return Err(ExpressionConversionError::InvalidToken);
+                        }
+                    }
+                }
+                // サロゲートや U+10FFFF を超える値は文字にならない
+                match core::char::from_u32(code) {
+                    Some(c) => res.push(c),
+                    None => {
+                        return Err(ExpressionConversionError::InvalidToken);
+                    }
+                }
+            }
+            _ => {
+                return Err(ExpressionConversionError::InvalidToken);
+            }
+        }
+    }
+    return Ok(res);
+}
+
+/// `s` を、読み込むと `s` に戻る `"` で囲んだ文字列リテラルとして `out` に書き込む。
+/// `"` `\` 改行・タブはエスケープシーケンスに、それ以外の制御文字は `\u{..}` の形式にする
+///
+/// # Examples
+/// ```
+/// use liblisp::lexer::write_escaped;
+///
+/// let mut out = String::new();
+/// write_escaped(&mut out, "say \"hi\"\n\r").unwrap();
+/// assert_eq!(out, r#""say \"hi\"\n\u{d}""#);
+/// ```
+pub fn write_escaped<W: fmt::Write>(out: &mut W, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\t' => out.write_str("\\t")?,
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    return out.write_char('"');
+}
+
+impl<'a> Lexer<'a> {
+    /// `bytes` を `ReaderOptions::default()` の設定で先頭から読む `Lexer` を新規作成
+    pub fn new(bytes: &'a [u8]) -> Lexer<'a> {
+        return Lexer::with_options(bytes, &ReaderOptions::default());
+    }
+
+    /// `bytes` を `options` の設定で先頭から読む `Lexer` を新規作成。
+    /// `options` のうち、コメント・負の整数・名前に使える文字の設定を使う
+    pub fn with_options(bytes: &'a [u8], options: &ReaderOptions) -> Lexer<'a> {
+        return Lexer {
+            bytes,
+            index: 0,
+            options: *options,
+        };
+    }
+
+    /// 次に読むバイトの位置
+    pub fn offset(&self) -> usize {
+        return self.index;
+    }
+
+    // 現在の位置の文字と、その UTF-8 でのバイト数。
+    // UTF-8 として不正なバイトは、名前にも区切りにも使えない文字 U+FFFD として 1 バイトずつ扱う
+    fn peek_char(&self) -> Option<(char, usize)> {
+        let b = *self.bytes.get(self.index)?;
+        if b.is_ascii() {
+            return Some((char::from(b), 1));
+        }
+        let width = match b {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let end = core::cmp::min(self.index + width, self.bytes.len());
+        match core::str::from_utf8(&self.bytes[self.index..end]) {
+            Ok(s) => {
+                return s.chars().next().map(|c| (c, width));
+            }
+            Err(_) => {
+                return Some((char::REPLACEMENT_CHARACTER, 1));
+            }
+        }
+    }
+
+    // 名前の先頭に使える文字。unicode_atoms が false なら ASCII の英字だけ
+    fn is_letter(&self, c: char) -> bool {
+        if self.options.unicode_atoms {
+            return c.is_alphabetic();
+        }
+        return c.is_ascii_alphabetic();
+    }
+
+    // 名前や整数の直後に置ける、トークンの区切りになる文字。コメントを読み込む設定なら ; も区切りになる
+    fn is_delimiter(&self, c: char) -> bool {
+        return c == ')'
+            || c == ']'
+            || c == '}'
+            || is_space(c)
+            || (self.options.comments && c == ';');
+    }
+
+    // 空白と、コメントを読み込む設定ならコメントを読み飛ばす
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_while(is_space);
+            if !(self.options.comments && self.bytes.get(self.index) == Some(&b';')) {
+                return;
+            }
+            while self.index < self.bytes.len() && self.bytes[self.index] != b'\n' {
+                self.index += 1;
+            }
+        }
+    }
+
+    // 現在の位置から 1 つのトークンを読む。呼び出し時点で、現在の位置は空白でない文字を指している
+    fn token(&mut self) -> Result<TokenKind<'a>, ExpressionConversionError> {
+        let head_ch = char::from(self.bytes[self.index]);
+        let kind = match head_ch {
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            '\'' => TokenKind::Quote,
+            '`' => TokenKind::Quasiquote,
+            ',' => {
+                if self.bytes.get(self.index + 1) == Some(&b'@') {
+                    self.index += 1;
+                    TokenKind::UnquoteSplicing
+                } else {
+                    TokenKind::Unquote
+                }
+            }
+            '#' if self.bytes.get(self.index + 1) == Some(&b'\'') => {
+                self.index += 1;
+                TokenKind::FunctionQuote
+            }
+            _ => {
+                return self.atom_like();
+            }
+        };
+        self.index += 1;
+        return Ok(kind);
+    }
+
+    // int, atom, string, var, keyword といった、括弧や空白で区切られるトークン
+    fn atom_like(&mut self) -> Result<TokenKind<'a>, ExpressionConversionError> {
+        let kind = self.scan_atom_like()?;
+        // 閉じ括弧 or 空白 以外の文字が続いていたら異常
+        if let Some(&b) = self.bytes.get(self.index) {
+            let c = char::from(b);
+            if !self.is_delimiter(c) {
+                return Err(ExpressionConversionError::InvalidToken);
+            }
+        }
+        return Ok(kind);
+    }
+
+    fn scan_atom_like(&mut self) -> Result<TokenKind<'a>, ExpressionConversionError> {
+        let head_ch = char::from(self.bytes[self.index]);
+        // int
+        // 負の整数を読み込む設定なら、- の直後に数字が続くものも int とする
+        let negative = self.options.negative_ints
+            && head_ch == '-'
+            && self
+                .bytes
+                .get(self.index + 1)
+                .is_some_and(|b| b.is_ascii_digit());
+        if head_ch.is_ascii_digit() || negative {
+            if negative {
+                self.index += 1;
+            }
+            let mut num: Int = 0;
+            while let Some(d) = self
+                .bytes
+                .get(self.index)
+                .and_then(|b| char::from(*b).to_digit(10))
+            {
+                // 負の数は、Int::MIN を読み込めるよう、負の方向に累積する
+                let next = num.checked_mul(10).and_then(|n| {
+                    if negative {
+                        return n.checked_sub(Int::from(d as u8));
+                    }
+                    return n.checked_add(Int::from(d as u8));
+                });
+                num = match next {
+                    Some(n) => n,
+                    None => {
+                        return Err(ExpressionConversionError::IntOverflow);
+                    }
+                };
+                self.index += 1;
+            }
+            return Ok(TokenKind::Int(num));
+        }
+        // atom
+        // atomは 簡単のために、alphabetから始まり、alphabetと数字と - と : と > のみ含むものとする
+        // : は math:gcd のように、モジュール名で修飾するために使う。> は list->vector のような変換関数の名前に使う
+        // 例外として、単独の _ も atom とする（match のパターンで、任意の値にマッチさせるために使う）
+        // 単独の : も atom とする（(*x* : int) のような型注釈の区切りに使う）
+        // また、&optional のように & から始まる atom も許す（仮引数リストの区切りに使う）
+        else if head_ch == '_' {
+            self.index += 1;
+            return Ok(TokenKind::Atom("_"));
+        } else if head_ch == '&' || self.peek_char().is_some_and(|(c, _)| self.is_letter(c)) {
+            let start = self.index;
+            if head_ch == '&' {
+                self.index += 1;
+                match self.peek_char() {
+                    None => {
+                        return Err(ExpressionConversionError::UnexpectedEof);
+                    }
+                    Some((c, _)) if !self.is_letter(c) => {
+                        return Err(ExpressionConversionError::InvalidToken);
+                    }
+                    Some(_) => {}
+                }
+            }
+            self.skip_name(|c| {
+                return c == '-' || c == ':' || c == '>';
+            });
+            return Ok(TokenKind::Atom(self.slice(start, self.index)?));
+        }
+        // keyword
+        // : の後に alphabet から始まり、alphabetと数字と - のみ含む名前が続く形式を想定
+        else if head_ch == ':' {
+            self.index += 1;
+            let start = self.index;
+            match self.peek_char() {
+                None => {
+                    return Ok(TokenKind::Atom(":"));
+                }
+                Some((c, _)) if self.is_delimiter(c) => {
+                    return Ok(TokenKind::Atom(":"));
+                }
+                Some((c, _)) if !self.is_letter(c) => {
+                    return Err(ExpressionConversionError::InvalidToken);
+                }
+                Some(_) => {}
+            }
+            self.skip_name(|c| {
+                return c == '-';
+            });
+            return Ok(TokenKind::Keyword(self.slice(start, self.index)?));
+        }
+        // string
+        // " と " で囲まれた形式を想定。\ の後の文字とあわせてエスケープシーケンスになる
+        else if head_ch == '"' {
+            self.index += 1;
+            let start = self.index;
+            while self.index < self.bytes.len() && self.bytes[self.index] != b'"' {
+                // \" の " は文字列の終わりではない
+                if self.bytes[self.index] == b'\\' {
+                    self.index += 1;
+                }
+                self.index += 1;
+            }
+            if self.index >= self.bytes.len() {
+                // 閉じる " が来る前に入力が終わった
+                return Err(ExpressionConversionError::UnexpectedEof);
+            }
+            let end = self.index;
+            self.index += 1;
+            let raw = self.slice(start, end)?;
+            // トークンはソースの文字列を参照するので、ここではエスケープシーケンスが正しいかだけを確かめる
+            if raw.contains('\\') {
+                unescape(raw)?;
+            }
+            return Ok(TokenKind::Str(raw));
+        }
+        // var
+        // *と*で囲まれた形式を想定
+        else if head_ch == '*' {
+            let start = self.index;
+            self.index += 1;
+            match self.peek_char() {
+                None => {
+                    return Err(ExpressionConversionError::UnexpectedEof);
+                }
+                Some((c, _)) if !self.is_letter(c) => {
+                    return Err(ExpressionConversionError::InvalidToken);
+                }
+                Some(_) => {}
+            }
+            self.skip_name(|c| {
+                return c == ':' || c == '*';
+            });
+            let end = self.index;
+            // bytes[start..end] の先頭と末尾のみ * が存在
+            // 先頭が * になっているのは、ここ以前の条件分岐から明らかなので、末尾と個数だけ調べる
+            let asta_count = self.bytes[start..end]
+                .iter()
+                .filter(|b| **b == b'*')
+                .count();
+            if asta_count == 2 && self.bytes[end - 1] == b'*' {
+                return Ok(TokenKind::Var(self.slice(start, end)?));
+            }
+            return Err(ExpressionConversionError::InvalidToken);
+        }
+        return Err(ExpressionConversionError::InvalidToken);
+    }
+
+    // 名前に使える文字（英字・数字と、extra を満たす記号）が続く間、読み進める
+    fn skip_name(&mut self, extra: impl Fn(char) -> bool) {
+        while let Some((c, width)) = self.peek_char() {
+            if !(c.is_ascii_digit() || self.is_letter(c) || extra(c)) {
+                return;
+            }
+            self.index += width;
+        }
+    }
+
+    // pred を満たす ASCII の文字が続く間、読み進める
+    fn skip_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.index < self.bytes.len() && pred(char::from(self.bytes[self.index])) {
+            self.index += 1;
+        }
+    }
+
+    // bytes[start..end] を文字列として返す
+    fn slice(&self, start: usize, end: usize) -> Result<&'a str, ExpressionConversionError> {
+        match core::str::from_utf8(&self.bytes[start..end]) {
+            Ok(res) => {
+                return Ok(res);
+            }
+            Err(e) => {
+                // 文字列の中身が UTF-8 として不正な場合
+                return Err(ExpressionConversionError::Unexpected(e.to_string()));
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_blank();
+        if self.index >= self.bytes.len() {
+            return None;
+        }
+        let start = self.index;
+        match self.token() {
+            Ok(kind) => {
+                let span = Span {
+                    start,
+                    end: self.index,
+                };
+                return Some(Ok(Token { kind, span }));
+            }
+            Err(error) => {
+                // 位置は、読み込みに失敗したトークンの先頭から、次の区切りの手前まで（少なくとも 1 バイト）
+                while let Some((c, width)) = self.peek_char() {
+                    if self.is_delimiter(c) || c == '(' {
+                        break;
+                    }
+                    self.index += width;
+                }
+                let end = core::cmp::max(self.index, start + 1);
+                let span = Span {
+                    start,
+                    end: core::cmp::min(end, self.bytes.len()),
+                };
+                // エラーの後は読み進めない
+                self.index = self.bytes.len();
+                return Some(Err(ParseError { error, span }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::*;
+
+    fn kinds(src: &str) -> Result<Vec<TokenKind<'_>>, ExpressionConversionError> {
+        return tokenize(src)
+            .map(|tokens| tokens.iter().map(|t| t.kind).collect())
+            .map_err(|e| e.error);
+    }
+
+    #[test]
+    fn tokenize_tests() {
+        assert_eq!(
+            kinds("(add 12 *a*)"),
+            Ok(vec![
+                TokenKind::LParen,
+                TokenKind::Atom("add"),
+                TokenKind::Int(12),
+                TokenKind::Var("*a*"),
+                TokenKind::RParen,
+            ])
+        );
+        assert_eq!(
+            kinds("`(:k ,x ,@y \"s t\")"),
+            Ok(vec![
+                TokenKind::Quasiquote,
+                TokenKind::LParen,
+                TokenKind::Keyword("k"),
+                TokenKind::Unquote,
+                TokenKind::Atom("x"),
+                TokenKind::UnquoteSplicing,
+                TokenKind::Atom("y"),
+                TokenKind::Str("s t"),
+                TokenKind::RParen,
+            ])
+        );
+        assert_eq!(
+            kinds("(*x* : int) _ &rest math:gcd"),
+            Ok(vec![
+                TokenKind::LParen,
+                TokenKind::Var("*x*"),
+                TokenKind::Atom(":"),
+                TokenKind::Atom("int"),
+                TokenKind::RParen,
+                TokenKind::Atom("_"),
+                TokenKind::Atom("&rest"),
+                TokenKind::Atom("math:gcd"),
+            ])
+        );
+        assert_eq!(kinds(" \n\t"), Ok(vec![]));
+        assert_eq!(
+            kinds("[1 a]{:k x}"),
+            Ok(vec![
+                TokenKind::LBracket,
+                TokenKind::Int(1),
+                TokenKind::Atom("a"),
+                TokenKind::RBracket,
+                TokenKind::LBrace,
+                TokenKind::Keyword("k"),
+                TokenKind::Atom("x"),
+                TokenKind::RBrace,
+            ])
+        );
+        assert_eq!(
+            kinds("#'add"),
+            Ok(vec![TokenKind::FunctionQuote, TokenKind::Atom("add")])
+        );
+
+        // 閉じ括弧の直後は、区切りなしで次のトークンを置ける
+        assert_eq!(
+            kinds("(a)b"),
+            Ok(vec![
+                TokenKind::LParen,
+                TokenKind::Atom("a"),
+                TokenKind::RParen,
+                TokenKind::Atom("b"),
+            ])
+        );
+
+        let errors = vec![
+            ("123atom", ExpressionConversionError::InvalidToken),
+            ("a(b)", ExpressionConversionError::InvalidToken),
+            ("\"abc\"d", ExpressionConversionError::InvalidToken),
+            ("*a", ExpressionConversionError::InvalidToken),
+            (":1", ExpressionConversionError::InvalidToken),
+            ("#a", ExpressionConversionError::InvalidToken),
+            ("\"abc", ExpressionConversionError::UnexpectedEof),
+            ("*", ExpressionConversionError::UnexpectedEof),
+            ("&", ExpressionConversionError::UnexpectedEof),
+            (
+                "10000000000000000000000000000000000000000",
+                ExpressionConversionError::IntOverflow,
+            ),
+        ];
+        for (src, expected) in errors {
+            assert_eq!(kinds(src), Err(expected), "{}", src);
+        }
+    }
+
+    #[test]
+    fn escape_tests() {
+        // トークンはエスケープシーケンスをそのまま含み、unescape で文字に置き換える
+        let cases = vec![
+            (r#""a\"b""#, r#"a\"b"#, "a\"b"),
+            (r#""\\""#, r#"\\"#, "\\"),
+            (r#""line\n\ttab""#, r#"line\n\ttab"#, "line\n\ttab"),
+            (r#""\u{1F600}\u{41}""#, r#"\u{1F600}\u{41}"#, "😀A"),
+            (
+                r#""\u{0}\u{10FFFF}""#,
+                r#"\u{0}\u{10FFFF}"#,
+                "\u{0}\u{10FFFF}",
+            ),
+            (r#""\"(exit 1)\"""#, r#"\"(exit 1)\""#, "\"(exit 1)\""),
+        ];
+        for (src, raw, expected) in cases {
+            assert_eq!(kinds(src), Ok(vec![TokenKind::Str(raw)]), "{}", src);
+            assert_eq!(unescape(raw), Ok(expected.to_string()), "{}", src);
+        }
+        let tokens = tokenize(r#"("a\"b" c)"#).unwrap();
+        assert_eq!(tokens[1].span, Span { start: 1, end: 7 });
+        assert_eq!(tokens[2].kind, TokenKind::Atom("c"));
+
+        let errors = vec![
+            (r#""\x""#, ExpressionConversionError::InvalidToken),
+            (r#""\u41""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{}""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{41""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{1234567}""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{110000}""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{D800}""#, ExpressionConversionError::InvalidToken),
+            (r#""\u{4g}""#, ExpressionConversionError::InvalidToken),
+            (r#""abc\""#, ExpressionConversionError::UnexpectedEof),
+            (r#""abc\"#, ExpressionConversionError::UnexpectedEof),
+        ];
+        for (src, expected) in errors {
+            assert_eq!(kinds(src), Err(expected), "{}", src);
+        }
+        // 不正なエスケープシーケンスは、文字列全体をエラーの位置にする
+        assert_eq!(
+            tokenize(r#""a\qb" c"#).map_err(|e| e.span),
+            Err(Span { start: 0, end: 6 })
+        );
+
+        // 書き込んだ文字列リテラルを読み込むと、元の文字列に戻る
+        let cases = vec![
+            ("", r#""""#),
+            ("plain", r#""plain""#),
+            ("say \"hi\"", r#""say \"hi\"""#),
+            ("C:\\dir\\", r#""C:\\dir\\""#),
+            ("a\nb\tc", r#""a\nb\tc""#),
+            ("\r\u{0}\u{7f}\u{1b}[0m", r#""\u{d}\u{0}\u{7f}\u{1b}[0m""#),
+            ("😀 りんご", r#""😀 りんご""#),
+        ];
+        for (s, expected) in cases {
+            let mut out = String::new();
+            write_escaped(&mut out, s).unwrap();
+            assert_eq!(out, expected, "{:?}", s);
+            assert_eq!(
+                kinds(&out),
+                Ok(vec![TokenKind::Str(&out[1..out.len() - 1])])
+            );
+            assert_eq!(unescape(&out[1..out.len() - 1]), Ok(s.to_string()));
+        }
+    }
+
+    #[test]
+    fn span_tests() {
+        let tokens = tokenize(" (f \"ab\")\n,@x").unwrap();
+        let spans: Vec<(usize, usize)> =
+            tokens.iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(
+            spans,
+            vec![(1, 2), (2, 3), (4, 8), (8, 9), (10, 12), (12, 13)]
+        );
+
+        // エラーの後は何も返さない
+        let mut lexer = Lexer::new("1x 2".as_bytes());
+        assert_eq!(
+            lexer.next(),
+            Some(Err(ParseError {
+                error: ExpressionConversionError::InvalidToken,
+                span: Span { start: 0, end: 2 },
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexer.offset(), 4);
+    }
+
+    #[test]
+    fn options_tests() {
+        let with = |src: &'static str, options: &ReaderOptions| {
+            return Lexer::with_options(src.as_bytes(), options)
+                .map(|t| t.map(|t| t.kind).map_err(|e| e.error))
+                .collect::<Result<Vec<_>, _>>();
+        };
+        let default = ReaderOptions::default();
+        let strict = ReaderOptions {
+            comments: false,
+            negative_ints: false,
+            unicode_atoms: false,
+            ..default
+        };
+
+        assert_eq!(
+            with("(a ; x y\n b);end", &default),
+            Ok(vec![
+                TokenKind::LParen,
+                TokenKind::Atom("a"),
+                TokenKind::Atom("b"),
+                TokenKind::RParen,
+            ])
+        );
+        assert_eq!(with("a;x", &default), Ok(vec![TokenKind::Atom("a")]));
+        assert_eq!(
+            with("; a", &strict),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+
+        assert_eq!(
+            with("-12 -2147483648 1-", &default),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            with("-12 -2147483648", &default),
+            Ok(vec![TokenKind::Int(-12), TokenKind::Int(-2147483648)])
+        );
+        // Int の範囲を超える整数は読み込めない
+        for src in [Int::MIN.to_string(), Int::MAX.to_string()].iter() {
+            let kind = |src: &str| {
+                return Lexer::with_options(src.as_bytes(), &default)
+                    .next()
+                    .map(|t| {
+                        t.map(|t| t.kind == TokenKind::Int(src.parse().unwrap()))
+                            .map_err(|e| e.error)
+                    });
+            };
+            assert_eq!(kind(src), Some(Ok(true)), "{}", src);
+            assert_eq!(
+                kind(&format!("{}0", src)),
+                Some(Err(ExpressionConversionError::IntOverflow)),
+                "{}0",
+                src
+            );
+        }
+        assert_eq!(
+            with("-", &default),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            with("-1", &strict),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+
+        assert_eq!(
+            with("(りんご *数* :色)", &default),
+            Ok(vec![
+                TokenKind::LParen,
+                TokenKind::Atom("りんご"),
+                TokenKind::Var("*数*"),
+                TokenKind::Keyword("色"),
+                TokenKind::RParen,
+            ])
+        );
+        assert_eq!(
+            with("りんご", &strict),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        assert_eq!(
+            with("aé", &strict),
+            Err(ExpressionConversionError::InvalidToken)
+        );
+        // 文字列の中身は、設定によらず任意の文字を使える
+        assert_eq!(
+            with("\"りんご\"", &strict),
+            Ok(vec![TokenKind::Str("りんご")])
+        );
+    }
+}
@@ -1,4 +1,42 @@
+// テストでは match の分岐ごとに assert!(true) / assert!(false) と書くスタイルをとっている
+#![cfg_attr(test, allow(clippy::assertions_on_constants))]
+// std feature を無効にした場合は no_std + alloc でビルドする。テストは std を使う
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
+
+pub mod analyze;
+pub mod arena;
+pub mod cache;
+pub mod capabilities;
+pub mod convert;
+mod env;
 pub mod eval;
 pub mod expression;
+pub mod filesystem;
+pub mod format;
+pub mod highlight;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod intern;
+pub mod interpreter;
+pub mod lexer;
+pub mod loader;
+pub mod lsp;
+pub mod observer;
+pub mod opaque;
+pub mod optimize;
+pub mod output;
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod profiler;
+pub mod program;
+pub mod random;
+pub mod source_map;
+pub mod trace;
+pub mod typecheck;
 pub mod types;
 pub mod util;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,37 @@
+//!
+//! `load` 及び `Context::eval_file` で使う、ソースの読み込み方法を定義
+//!
+
+use crate::eval::EvalError;
+use alloc::format;
+use alloc::string::String;
+
+/// `load` 及び `Context::eval_file` に渡されたパスから、ソースを読み込む。
+/// 組み込み先に合わせて、ファイルシステムへのアクセスを禁止したり、仮想的なファイルを提供したりできる。
+pub trait SourceLoader {
+    fn load(&self, path: &str) -> Result<String, EvalError>;
+}
+
+/// ファイルシステムからソースを読み込む。`Context` のデフォルト。`std` feature が必要
+#[cfg(feature = "std")]
+pub struct FsLoader;
+
+#[cfg(feature = "std")]
+impl SourceLoader for FsLoader {
+    fn load(&self, path: &str) -> Result<String, EvalError> {
+        return std::fs::read_to_string(path)
+            .map_err(|e| EvalError::LoadFailed(format!("{}: {}", path, e)));
+    }
+}
+
+/// 全ての読み込みを `EvalError::LoadFailed` にする。サンドボックス環境向け
+pub struct DisabledLoader;
+
+impl SourceLoader for DisabledLoader {
+    fn load(&self, path: &str) -> Result<String, EvalError> {
+        return Err(EvalError::LoadFailed(format!(
+            "{}: loading is disabled",
+            path
+        )));
+    }
+}
@@ -0,0 +1,594 @@
+//!
+//! エディタ向けに、Language Server Protocol (LSP) の機能（診断・ホバー・定義へのジャンプ・補完）を提供する
+//! `LanguageService` を定義。
+//! JSON-RPC による通信は扱わず、位置は LSP と同じく 0 始まりの行と、行頭から UTF-16 のコード単位で数えた列で表す。
+//! 標準入出力で通信する LSP のサーバは、`lsp` feature を有効にしてビルドする `lisp-lsp`（src/bin/lisp-lsp.rs）
+//!
+
+use crate::analyze::{analyze_program_located, arity_text};
+use crate::eval::{CompletionItem, Context, SymbolKind};
+use crate::expression::*;
+use crate::format::{format_source, FormatOptions};
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// ドキュメント中の位置。`line` は 0 始まりの行、`character` は行頭からの UTF-16 のコード単位の数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// ドキュメント中の範囲。`end` は範囲の直後の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// 診断の重大度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,   // 読み込みに失敗した
+    Warning, // 静的解析で見つかった、誤りの可能性がある箇所
+}
+
+/// ドキュメントの診断。静的解析で見つかった箇所は、それを含むトップレベルの式の範囲を示す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// 開いているドキュメントごとに、診断・ホバー・定義へのジャンプ・補完の結果を返す。
+/// ドキュメントは評価せず、読み込みと静的解析の結果だけを使う。
+/// 組み込み関数と標準ライブラリの関数の説明には、`Context::new_with_stdlib` の `Context` を使う
+///
+/// # Examples
+/// ```
+/// use liblisp::lsp::{LanguageService, Position, Range};
+///
+/// let mut service = LanguageService::new();
+/// let diagnostics = service.update("file:///a.lisp", "(defun sq (*x*) \"2 乗\" (mul *x* *x*))\n(sq *y*)");
+/// assert_eq!(diagnostics[0].message, "variable *y* is never defined");
+///
+/// let hover = service.hover("file:///a.lisp", Position { line: 1, character: 2 }).unwrap();
+/// assert_eq!(hover, "```lisp\n(sq *x*)\n```\n\n2 乗");
+/// let (uri, range) = service.definition("file:///a.lisp", Position { line: 1, character: 1 }).unwrap();
+/// assert_eq!(uri, "file:///a.lisp");
+/// assert_eq!(range.start, Position { line: 0, character: 7 });
+/// ```
+pub struct LanguageService {
+    context: Context,
+    documents: BTreeMap<String, Document>,
+}
+
+// 開いているドキュメントと、そこから読み取った情報
+struct Document {
+    src: String,
+    line_starts: Vec<usize>, // 各行の先頭のバイト位置
+    definitions: Vec<Definition>,
+    variables: Vec<Rc<str>>, // ドキュメント中に現れる変数の名前。重複を除いて名前順に並べる
+    diagnostics: Vec<Diagnostic>,
+}
+
+// ドキュメント中の defun / defmacro による定義
+struct Definition {
+    name: Rc<str>,
+    kind: SymbolKind,        // Function か Macro
+    span: Span,              // 名前のトークンの位置
+    params: Option<Rc<str>>, // 仮引数のリストを文字列にしたもの。読み込みに失敗したドキュメントでは None
+    doc: Option<Rc<str>>,
+}
+
+impl Default for LanguageService {
+    fn default() -> Self {
+        return LanguageService::new();
+    }
+}
+
+impl LanguageService {
+    /// ドキュメントを 1 つも開いていない `LanguageService` を新規作成
+    pub fn new() -> LanguageService {
+        return LanguageService {
+            context: Context::new_with_stdlib(),
+            documents: BTreeMap::new(),
+        };
+    }
+
+    /// `uri` のドキュメントの内容を `text` にし、その診断を返す。開いた時と変更した時の両方で、内容全体を渡して呼び出す
+    pub fn update(&mut self, uri: &str, text: &str) -> Vec<Diagnostic> {
+        let document = Document::new(text);
+        let diagnostics = document.diagnostics.clone();
+        self.documents.insert(String::from(uri), document);
+        return diagnostics;
+    }
+
+    /// `uri` のドキュメントを閉じる。開いていれば true
+    pub fn close(&mut self, uri: &str) -> bool {
+        return self.documents.remove(uri).is_some();
+    }
+
+    /// `uri` のドキュメントの診断。開いていなければ空
+    pub fn diagnostics(&self, uri: &str) -> &[Diagnostic] {
+        return self
+            .documents
+            .get(uri)
+            .map_or(&[], |d| d.diagnostics.as_slice());
+    }
+
+    /// `position` にある関数名の説明を、Markdown の文字列で返す。
+    /// 開いているドキュメントで定義した関数やマクロなら、呼び出しの形とドキュメント文字列を、
+    /// それ以外は `Context::describe` で得た種類と引数の数を示す。関数名の上でなければ None
+    pub fn hover(&self, uri: &str, position: Position) -> Option<String> {
+        let document = self.documents.get(uri)?;
+        let name = match document.token_at(position)?.kind {
+            TokenKind::Atom(name) => name,
+            _ => {
+                return None;
+            }
+        };
+        if let Some((_, d)) = self.find_definition(uri, name) {
+            let signature = match &d.params {
+                Some(params) if &**params != "()" => {
+                    format!("({} {}", d.name, &params[1..])
+                }
+                _ => format!("({})", d.name),
+            };
+            let mut res = format!("```lisp\n{}\n```", signature);
+            if let Some(doc) = &d.doc {
+                res.push_str("\n\n");
+                res.push_str(doc);
+            }
+            return Some(res);
+        }
+        let d = self.context.describe(name)?;
+        let kind = match d.kind {
+            SymbolKind::Builtin => "built-in function",
+            SymbolKind::SpecialForm => "special form",
+            SymbolKind::Function => "function",
+            SymbolKind::Macro => "macro",
+            SymbolKind::HostFunction => "host function",
+            SymbolKind::Variable => "variable",
+        };
+        let mut res = format!("**{}** ({})", d.name, kind);
+        if let Some((min, max)) = d.arity {
+            res.push_str(&format!("\n\ntakes {} argument(s)", arity_text(min, max)));
+        }
+        if let Some(doc) = &d.doc {
+            res.push_str("\n\n");
+            res.push_str(doc);
+        }
+        return Some(res);
+    }
+
+    /// `position` にある関数名を定義した `defun` / `defmacro` の、ドキュメントの URI と名前の範囲。
+    /// `uri` のドキュメントを優先し、無ければ他の開いているドキュメントから探す。見つからなければ None
+    pub fn definition(&self, uri: &str, position: Position) -> Option<(String, Range)> {
+        let document = self.documents.get(uri)?;
+        let name = match document.token_at(position)?.kind {
+            TokenKind::Atom(name) => name,
+            _ => {
+                return None;
+            }
+        };
+        let (found, d) = self.find_definition(uri, name)?;
+        let range = self.documents[found].range(d.span);
+        return Some((String::from(found), range));
+    }
+
+    /// `position` の直前まで入力された名前で始まる、補完の候補を名前順に返す。
+    /// `Context::complete` の候補に、開いているドキュメントで定義した関数・マクロと、ドキュメント中の変数を加える
+    pub fn completion(&self, uri: &str, position: Position) -> Vec<CompletionItem> {
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => {
+                return Vec::new();
+            }
+        };
+        let prefix = document.prefix(position);
+        let mut items: BTreeMap<Rc<str>, SymbolKind> = self
+            .context
+            .complete(prefix)
+            .into_iter()
+            .map(|c| (c.label, c.kind))
+            .collect();
+        for document in self.documents.values() {
+            for d in &document.definitions {
+                items.insert(d.name.clone(), d.kind);
+            }
+            for v in &document.variables {
+                items.insert(v.clone(), SymbolKind::Variable);
+            }
+        }
+        return items
+            .into_iter()
+            .filter(|(label, _)| label.starts_with(prefix))
+            .map(|(label, kind)| CompletionItem { label, kind })
+            .collect();
+    }
+
+    /// `uri` のドキュメント全体を `format::format_source` で整形した文字列。
+    /// 開いていないか、読み込みに失敗するドキュメントなら None。コメントは整形後に残らない
+    pub fn formatting(&self, uri: &str, options: &FormatOptions) -> Option<String> {
+        let document = self.documents.get(uri)?;
+        return format_source(&document.src, options).ok();
+    }
+
+    // 名前 name の定義を、uri のドキュメント、他の開いているドキュメントの順に探す
+    fn find_definition<'a>(
+        &'a self,
+        uri: &'a str,
+        name: &str,
+    ) -> Option<(&'a str, &'a Definition)> {
+        if let Some(d) = self.documents.get(uri).and_then(|d| d.definition(name)) {
+            return Some((uri, d));
+        }
+        return self
+            .documents
+            .iter()
+            .find_map(|(u, document)| document.definition(name).map(|d| (u.as_str(), d)));
+    }
+}
+
+impl Document {
+    fn new(src: &str) -> Document {
+        let mut line_starts = vec![0];
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        let mut document = Document {
+            src: String::from(src),
+            line_starts,
+            definitions: Vec::new(),
+            variables: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        document.read();
+        return document;
+    }
+
+    // ソースを読み込み、定義・変数・診断を集める
+    fn read(&mut self) {
+        let tokens = self.tokens();
+        let mut definitions = Vec::new();
+        for w in tokens.windows(3) {
+            if let [TokenKind::LParen, TokenKind::Atom(form), TokenKind::Atom(name)] =
+                [w[0].kind, w[1].kind, w[2].kind]
+            {
+                let kind = match form {
+                    "defun" => SymbolKind::Function,
+                    "defmacro" => SymbolKind::Macro,
+                    _ => {
+                        continue;
+                    }
+                };
+                definitions.push(Definition {
+                    name: Rc::from(name),
+                    kind,
+                    span: w[2].span,
+                    params: None,
+                    doc: None,
+                });
+            }
+        }
+        let mut variables: Vec<Rc<str>> = tokens
+            .iter()
+            .filter_map(|t| match t.kind {
+                TokenKind::Var(v) => Some(Rc::from(v)),
+                _ => None,
+            })
+            .collect();
+        variables.sort();
+        variables.dedup();
+        self.definitions = definitions;
+        self.variables = variables;
+
+        match parse_program_located(&self.src, &ReaderOptions::default()) {
+            Ok(program) => {
+                for (exp, _) in &program {
+                    self.collect_procedures(exp);
+                }
+                for (d, span) in analyze_program_located(&program) {
+                    self.diagnostics.push(Diagnostic {
+                        range: self.range(span),
+                        severity: Severity::Warning,
+                        message: d.to_string(),
+                    });
+                }
+            }
+            Err(e) => {
+                self.diagnostics.push(Diagnostic {
+                    range: self.range(e.span),
+                    severity: Severity::Error,
+                    message: format!("parse error: {:?}", e.error),
+                });
+            }
+        }
+    }
+
+    // 読み込めたところまでのトークン。コメントは読み飛ばす
+    fn tokens(&self) -> Vec<Token<'_>> {
+        return Lexer::with_options(self.src.as_bytes(), &ReaderOptions::default())
+            .map_while(Result::ok)
+            .collect();
+    }
+
+    // (defun name params [: type] [doc] body ...) の形式の式を探し、仮引数とドキュメント文字列を定義に記録する
+    fn collect_procedures(&mut self, exp: &Expression) {
+        let l = match exp {
+            Expression::ExpressionList(l) => l,
+            _ => {
+                return;
+            }
+        };
+        let items: Vec<&Expression> = l.iter().collect();
+        if let [Expression::Atom(form), Expression::Atom(name), params, rest @ ..] = &items[..] {
+            if &**form == "defun" || &**form == "defmacro" {
+                let mut body = rest;
+                if let [Expression::Atom(colon), _, tail @ ..] = body {
+                    if &**colon == ":" {
+                        body = tail;
+                    }
+                }
+                let doc = match body {
+                    [Expression::Str(doc), _, ..] => Some(doc.clone()),
+                    _ => None,
+                };
+                let params: Rc<str> = Rc::from(params.to_string());
+                if let Some(d) = self
+                    .definitions
+                    .iter_mut()
+                    .find(|d| d.name == *name && d.params.is_none())
+                {
+                    d.params = Some(params);
+                    d.doc = doc;
+                }
+            }
+        }
+        for e in items {
+            self.collect_procedures(e);
+        }
+    }
+
+    // 名前 name の定義。同じ名前の定義が複数あれば、最初のもの
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        return self.definitions.iter().find(|d| &*d.name == name);
+    }
+
+    // position にある名前（atom か var）のトークン。トークンの直後の位置も、そのトークンにあるとみなす
+    fn token_at(&self, position: Position) -> Option<Token<'_>> {
+        let offset = self.offset(position);
+        return self.tokens().into_iter().find(|t| {
+            return matches!(t.kind, TokenKind::Atom(_) | TokenKind::Var(_))
+                && t.span.start <= offset
+                && offset <= t.span.end;
+        });
+    }
+
+    // position の直前まで入力された名前。入力途中の *x のように、トークンとして読み込めないものも含む
+    fn prefix(&self, position: Position) -> &str {
+        let offset = self.offset(position);
+        let start = self.src[..offset]
+            .rfind(|c: char| c.is_whitespace() || "()[]{}'`,\"".contains(c))
+            .map_or(0, |i| i + 1);
+        return &self.src[start..offset];
+    }
+
+    // 位置をバイト位置に変換する。行末や、ソースの末尾を超える位置は、行末やソースの末尾とみなす
+    fn offset(&self, position: Position) -> usize {
+        let line = (position.line as usize).min(self.line_starts.len() - 1);
+        let start = self.line_starts[line];
+        let mut units = 0;
+        for (i, c) in self.src[start..].char_indices() {
+            if c == '\n' || units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        return self.src.len();
+    }
+
+    // バイト位置を位置に変換する
+    fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.src.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let character: usize = self.src[self.line_starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        return Position {
+            line: line as u32,
+            character: character as u32,
+        };
+    }
+
+    fn range(&self, span: Span) -> Range {
+        return Range {
+            start: self.position(span.start),
+            end: self.position(span.end),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsp::*;
+
+    fn pos(line: u32, character: u32) -> Position {
+        return Position { line, character };
+    }
+
+    #[test]
+    fn diagnostics_tests() {
+        let mut service = LanguageService::new();
+        let diagnostics = service.update("a", "(define *x* 1)\n(add *x*)\n(frob)");
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    range: Range {
+                        start: pos(1, 0),
+                        end: pos(1, 9)
+                    },
+                    severity: Severity::Warning,
+                    message: String::from("add expects 2 arguments, got 1"),
+                },
+                Diagnostic {
+                    range: Range {
+                        start: pos(2, 0),
+                        end: pos(2, 6)
+                    },
+                    severity: Severity::Warning,
+                    message: String::from("unknown function frob"),
+                },
+            ]
+        );
+
+        let diagnostics = service.update("a", "(add 1\n  (list 2 ]");
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                range: Range {
+                    start: pos(1, 10),
+                    end: pos(1, 11)
+                },
+                severity: Severity::Error,
+                message: String::from("parse error: InvalidToken"),
+            }]
+        );
+        assert_eq!(service.diagnostics("a"), diagnostics.as_slice());
+        assert!(service.close("a"));
+        assert!(service.diagnostics("a").is_empty());
+    }
+
+    #[test]
+    fn hover_tests() {
+        let mut service = LanguageService::new();
+        service.update(
+            "a",
+            "; 日本語のコメント\n(defun f (*a* &optional *b*) : int \"f の説明\" *a*)\n(defmacro m () 1)\n(f (m) (abs 1) \"s\")",
+        );
+        assert_eq!(
+            service.hover("a", pos(3, 1)).as_deref(),
+            Some("```lisp\n(f *a* &optional *b*)\n```\n\nf の説明")
+        );
+        assert_eq!(
+            service.hover("a", pos(3, 5)).as_deref(),
+            Some("```lisp\n(m)\n```")
+        );
+        assert_eq!(
+            service.hover("a", pos(3, 9)).as_deref(),
+            Some("**abs** (function)\n\ntakes 1 argument(s)")
+        );
+        assert_eq!(
+            service.hover("a", pos(1, 1)).as_deref(),
+            Some("**defun** (special form)\n\ntakes at least 3 argument(s)")
+        );
+        assert_eq!(service.hover("a", pos(3, 17)), None);
+        assert_eq!(service.hover("b", pos(0, 0)), None);
+    }
+
+    #[test]
+    fn definition_tests() {
+        let mut service = LanguageService::new();
+        service.update("a", "(defun f () 1)\n(module m (defun g () 2))");
+        service.update("b", "(f)\n(g)\n(h)");
+        assert_eq!(
+            service.definition("b", pos(0, 1)),
+            Some((
+                String::from("a"),
+                Range {
+                    start: pos(0, 7),
+                    end: pos(0, 8)
+                }
+            ))
+        );
+        assert_eq!(
+            service.definition("b", pos(1, 2)),
+            Some((
+                String::from("a"),
+                Range {
+                    start: pos(1, 17),
+                    end: pos(1, 18)
+                }
+            ))
+        );
+        assert_eq!(service.definition("b", pos(2, 1)), None);
+
+        // 同じ名前の定義があれば、そのドキュメントの定義を優先する
+        service.update("b", "(defun f () 3)\n(f)");
+        assert_eq!(
+            service.definition("b", pos(1, 1)).map(|(uri, _)| uri),
+            Some(String::from("b"))
+        );
+    }
+
+    #[test]
+    fn completion_tests() {
+        let mut service = LanguageService::new();
+        service.update(
+            "a",
+            "(defun list-sum (*lst*) 0)\n(define *limit* 1)\n(lis *l",
+        );
+        let labels = |items: Vec<CompletionItem>| {
+            return items
+                .iter()
+                .map(|c| (c.label.to_string(), c.kind))
+                .collect::<Vec<_>>();
+        };
+        assert_eq!(
+            labels(service.completion("a", pos(2, 4))),
+            vec![
+                (String::from("list"), SymbolKind::Builtin),
+                (String::from("list->vector"), SymbolKind::Builtin),
+                (String::from("list-sum"), SymbolKind::Function),
+                (String::from("listp"), SymbolKind::Builtin),
+            ]
+        );
+        assert_eq!(
+            labels(service.completion("a", pos(2, 7))),
+            vec![
+                (String::from("*limit*"), SymbolKind::Variable),
+                (String::from("*lst*"), SymbolKind::Variable),
+            ]
+        );
+        // カーソルより後ろは補完の対象にしない
+        let items = service.completion("a", pos(2, 2));
+        assert!(items.iter().any(|c| &*c.label == "let"));
+        assert!(items.iter().all(|c| c.label.starts_with('l')));
+        assert!(service.completion("b", pos(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn position_tests() {
+        // 列は UTF-16 のコード単位で数える
+        let document = Document::new("ab\n\u{1F600}x\n");
+        assert_eq!(document.offset(pos(1, 2)), 7);
+        assert_eq!(document.position(7), pos(1, 2));
+        assert_eq!(document.offset(pos(0, 10)), 2);
+        assert_eq!(document.offset(pos(5, 0)), 9);
+        assert_eq!(document.position(100), pos(2, 0));
+    }
+
+    #[test]
+    fn formatting_tests() {
+        let mut service = LanguageService::new();
+        service.update("a", "(defun f (*x*)\n(add *x* 1))  (f 2)");
+        service.update("b", "(f");
+        let options = FormatOptions::default();
+        assert_eq!(
+            service.formatting("a", &options).as_deref(),
+            Some("(defun f (*x*) (add *x* 1))\n\n(f 2)\n")
+        );
+        assert_eq!(service.formatting("b", &options), None);
+        assert_eq!(service.formatting("c", &options), None);
+    }
+}
@@ -0,0 +1,25 @@
+//!
+//! 評価の様子を外部から観測するためのフックを定義
+//!
+
+use crate::eval::EvalError;
+use crate::expression::Expression;
+use crate::types::Type;
+
+/// 式の評価が終わった時の結果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvalExit<'a> {
+    Value(&'a Type),      // 値が得られた
+    Error(&'a EvalError), // エラーになった
+    Escape,               // break / continue / return / exit によって、評価を途中で抜けた
+}
+
+/// `Context::set_tracer` で登録し、式の評価の開始時と終了時に呼び出されるコールバック。
+/// `depth` はトップレベルの式を 0 とした、評価の入れ子の深さ。
+/// ステップ実行を行うデバッガや、プロファイラを作るために使う。
+pub trait EvalObserver {
+    /// 式の評価を開始する直前に呼び出される
+    fn enter(&mut self, _exp: &Expression, _depth: usize) {}
+    /// 式の評価が終了した直後に呼び出される
+    fn exit(&mut self, _exp: &Expression, _depth: usize, _result: EvalExit) {}
+}
@@ -0,0 +1,244 @@
+//!
+//! ホスト側の Rust の値を、Lisp の値として持ち回すための `Opaque` を定義
+//!
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use core::any::{Any, TypeId};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+/// ホスト側の値（ファイル、DB への接続、ゲームのエンティティなど）への参照。`Type::Opaque` として持ち回す。
+///
+/// Lisp からは中身を見られず、`Context::register_method` で登録したメソッドを `send` で呼び出すことだけができる。
+///
+/// - 同一性: 複製しても同じ値を指し、`equal` や `eq` は同じ `Opaque::new` から得た値同士の場合のみ 1 になる。
+///   順序と `Hash` はアドレスに基づくので、実行ごとに変わりうる
+/// - 寿命: 値は、ホスト側・変数・リスト・`ContextSnapshot` などが持つ最後の参照が無くなった時に解放される。
+///   Lisp から `Opaque` の中に値を入れることはできないので、中身がホスト側で `Type` を持たない限り、参照は循環しない。
+///   中身が `Type` を持ち、引数をそこに入れるメソッドなどで自分自身を含む値を入れると循環し、`Rc` は循環を回収しないので解放されない。
+///   中身から他の `Opaque` を参照する場合は、`Opaque::downgrade` で得た解放を妨げない参照を持てば循環しない
+///   解放時に処理を行うには `Opaque::with_finalizer` を、解放を妨げずにホスト側から参照するには `Opaque::downgrade` を使う
+/// - 制限: 式には変換できないので、`quote` した式やマクロの展開結果には含められない（`EvalError::TypeMismatch`）。
+///   シリアライズもできない
+///
+/// # Examples
+/// ```
+/// use liblisp::opaque::Opaque;
+///
+/// let a = Opaque::new(String::from("handle"));
+/// assert_eq!(a.downcast_ref::<String>().map(String::as_str), Some("handle"));
+/// assert_eq!(a.downcast_ref::<i32>(), None);
+/// assert_eq!(a, a.clone());
+/// assert_ne!(a, Opaque::new(String::from("handle")));
+/// ```
+#[derive(Clone)]
+pub struct Opaque {
+    handle: Rc<Handle>, // Type の大きさを増やさないよう、型の情報も Handle に持つ
+}
+
+// 中身の所有権を受け取る、解放時の処理
+type Finalizer = Box<dyn FnOnce(Box<dyn Any>)>;
+
+// Opaque の中身と、解放時に呼び出すファイナライザ
+struct Handle {
+    value: Box<dyn Any>,
+    type_id: TypeId,
+    type_name: &'static str, // Debug や Display で表示する、中身の型の名前
+    finalizer: Option<Finalizer>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(finalizer) = self.finalizer.take() {
+            let value = core::mem::replace(&mut self.value, Box::new(()));
+            finalizer(value);
+        }
+    }
+}
+
+impl Opaque {
+    /// `value` を包んだ `Opaque` を新規作成
+    pub fn new<T: Any>(value: T) -> Opaque {
+        return Opaque::from_value(value, None);
+    }
+
+    /// `value` を包んだ `Opaque` を新規作成する。
+    /// 最後の参照が無くなった時に、`finalizer` が中身の所有権を受け取って一度だけ呼び出される。
+    /// ソケットやファイルのように、明示的に閉じる必要があるホスト側の資源を確実に解放するのに使う。
+    ///
+    /// `Context` やその `ContextSnapshot` が参照を持っている間は呼び出されない。
+    /// ファイナライザは値の解放の途中で呼び出されるので、panic してはならない。
+    /// また、`Context` を受け取らないので、Lisp の関数は呼び出せない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::{eval_with_context, Context};
+    /// use liblisp::expression::Expression;
+    /// use liblisp::opaque::Opaque;
+    /// use liblisp::types::Type;
+    /// use std::cell::Cell;
+    /// use std::convert::TryFrom;
+    /// use std::rc::Rc;
+    ///
+    /// let closed = Rc::new(Cell::new(false));
+    /// let flag = closed.clone();
+    /// let socket = Opaque::with_finalizer(8080, move |_port: i32| flag.set(true));
+    ///
+    /// let mut context = Context::new();
+    /// context.define_var("*socket*", Type::Opaque(socket)).unwrap();
+    /// let exp = Expression::try_from("(set *socket* 0)".as_bytes()).unwrap();
+    /// assert!(!closed.get());
+    /// eval_with_context(&exp, &mut context).unwrap();
+    /// assert!(closed.get());
+    /// ```
+    pub fn with_finalizer<T: Any>(value: T, finalizer: impl FnOnce(T) + 'static) -> Opaque {
+        let finalizer: Finalizer = Box::new(move |value: Box<dyn Any>| {
+            // Handle には T の値しか入れないので、常に成功する
+            if let Ok(value) = value.downcast::<T>() {
+                finalizer(*value);
+            }
+        });
+        return Opaque::from_value(value, Some(finalizer));
+    }
+
+    // value とファイナライザから Opaque を作る
+    fn from_value<T: Any>(value: T, finalizer: Option<Finalizer>) -> Opaque {
+        return Opaque {
+            handle: Rc::new(Handle {
+                value: Box::new(value),
+                type_id: TypeId::of::<T>(),
+                type_name: core::any::type_name::<T>(),
+                finalizer,
+            }),
+        };
+    }
+
+    /// 解放を妨げない参照を作る。ホスト側で、Lisp が持っている間だけ値を使いたい場合に使う
+    pub fn downgrade(&self) -> WeakOpaque {
+        return WeakOpaque {
+            handle: Rc::downgrade(&self.handle),
+            type_name: self.handle.type_name,
+        };
+    }
+
+    /// 中身が `T` なら、その参照を返す
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        return self.handle.value.downcast_ref::<T>();
+    }
+
+    /// 中身の型が `T` かどうか
+    pub fn is<T: Any>(&self) -> bool {
+        return self.handle.type_id == TypeId::of::<T>();
+    }
+
+    /// 中身の型の `TypeId`
+    pub fn type_id(&self) -> TypeId {
+        return self.handle.type_id;
+    }
+
+    /// 中身の型の名前（`core::any::type_name` の値）
+    pub fn type_name(&self) -> &'static str {
+        return self.handle.type_name;
+    }
+
+    // 同一性の比較に使う、中身のアドレス
+    fn addr(&self) -> usize {
+        return Rc::as_ptr(&self.handle) as usize;
+    }
+}
+
+/// `Opaque::downgrade` で作る、解放を妨げない `Opaque` への参照
+///
+/// # Examples
+/// ```
+/// use liblisp::opaque::Opaque;
+///
+/// let handle = Opaque::new(1);
+/// let weak = handle.downgrade();
+/// assert_eq!(weak.upgrade(), Some(handle.clone()));
+/// drop(handle);
+/// assert_eq!(weak.upgrade(), None);
+/// ```
+#[derive(Clone)]
+pub struct WeakOpaque {
+    handle: Weak<Handle>,
+    type_name: &'static str, // 解放後も Debug で表示できるよう、型の名前を持つ
+}
+
+impl WeakOpaque {
+    /// 値がまだ解放されていなければ、`Opaque` を返す
+    pub fn upgrade(&self) -> Option<Opaque> {
+        return Some(Opaque {
+            handle: self.handle.upgrade()?,
+        });
+    }
+}
+
+/// `#<weak-opaque 型名>` の形式で出力する
+impl fmt::Debug for WeakOpaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "#<weak-opaque {}>", self.type_name);
+    }
+}
+
+impl PartialEq for Opaque {
+    fn eq(&self, other: &Opaque) -> bool {
+        return self.addr() == other.addr();
+    }
+}
+
+impl Eq for Opaque {}
+
+impl PartialOrd for Opaque {
+    fn partial_cmp(&self, other: &Opaque) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Opaque {
+    fn cmp(&self, other: &Opaque) -> Ordering {
+        return self.addr().cmp(&other.addr());
+    }
+}
+
+impl Hash for Opaque {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+/// `#<opaque 型名>` の形式で出力する。中身は出力しない
+impl fmt::Debug for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "#<opaque {}>", self.handle.type_name);
+    }
+}
+
+impl fmt::Display for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "#<opaque {}>", self.handle.type_name);
+    }
+}
+
+/// シリアライズは常に失敗する
+#[cfg(feature = "serde")]
+impl serde::Serialize for Opaque {
+    fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        return Err(serde::ser::Error::custom(alloc::format!(
+            "{:?} can not be serialized",
+            self
+        )));
+    }
+}
+
+/// デシリアライズは常に失敗する
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Opaque {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Opaque, D::Error> {
+        return Err(serde::de::Error::custom(
+            "opaque values can not be deserialized",
+        ));
+    }
+}
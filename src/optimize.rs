@@ -0,0 +1,300 @@
+//!
+//! 評価結果を変えずに式を簡単にする最適化を定義
+//!
+
+use crate::eval::{eval, is_builtin, Context};
+use crate::expression::*;
+use crate::types::Type;
+use crate::visit::*;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+/// 式を、評価結果が同じで、より簡単な式に変換する。
+///
+/// - 引数が全て整数のリテラルである算術・比較（`add` `sub` `mul` `div` `gt` `lt` `eq`）を、結果の整数に置き換える。
+///   結果が整数にならない場合や、ゼロ除算のようなエラーになる場合は置き換えない
+/// - 条件が整数のリテラルである `cond` を、選ばれる方の式に置き換える
+/// - 入れ子になった `progn` を 1 つにまとめ、式が 1 つだけの `progn` はその式に置き換える
+///
+/// マクロは引数を評価せずに受け取るので、組み込み関数以外の呼び出しの引数は変換しない。
+/// 組み込み関数は `defun` や `defmacro` で上書きできるので、式の中でこれらによって定義される名前は、
+/// 組み込み関数とみなさない。式の外（評価する `Context`）での上書きも考慮する場合は `optimize_in` を使う
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::Expression;
+/// use liblisp::optimize::optimize;
+/// use std::convert::TryFrom;
+///
+/// let exp = Expression::try_from("(progn (define *x* (add 1 2)) (progn (cond (gt 2 1) *x* 0)))".as_bytes()).unwrap();
+/// let expected = Expression::try_from("(progn (define *x* 3) *x*)".as_bytes()).unwrap();
+/// assert_eq!(optimize(&exp), expected);
+/// ```
+pub fn optimize(exp: &Expression) -> Expression {
+    return Optimizer::new(exp, None).optimize(exp);
+}
+
+/// `context` で評価する式を、`optimize` と同様に変換する。
+/// `context` で取り除かれている組み込み関数や、ユーザ定義のマクロ・関数、ホスト側の関数で上書きされている組み込み関数は、
+/// 組み込み関数とみなさない
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::Context;
+/// use liblisp::expression::Expression;
+/// use liblisp::optimize::optimize_in;
+/// use std::convert::TryFrom;
+///
+/// let mut context = Context::new();
+/// context.register_fn("mul", |a: i32, b: i32| a + b);
+/// let exp = Expression::try_from("(add (mul 2 3) (sub 5 1))".as_bytes()).unwrap();
+/// let expected = Expression::try_from("(add (mul 2 3) 4)".as_bytes()).unwrap();
+/// assert_eq!(optimize_in(&exp, &context), expected);
+/// ```
+pub fn optimize_in(exp: &Expression, context: &Context) -> Expression {
+    return Optimizer::new(exp, Some(context)).optimize(exp);
+}
+
+// 組み込み関数の呼び出しを変換する
+struct Optimizer<'a> {
+    shadowed: BTreeSet<Rc<str>>, // 式の中で defun や defmacro によって定義される名前
+    context: Option<&'a Context>,
+}
+
+// 式の中で defun や defmacro によって定義される名前を集める
+struct Definitions(BTreeSet<Rc<str>>);
+
+impl ExpressionVisitor for Definitions {
+    fn enter(&mut self, exp: &Expression) -> bool {
+        if let Expression::ExpressionList(l) = exp {
+            if let (Some(Expression::Atom(form)), Some(Expression::Atom(name))) =
+                (l.head(), l.tail().head())
+            {
+                if &**form == "defun" || &**form == "defmacro" {
+                    self.0.insert(name.clone());
+                }
+            }
+        }
+        return true;
+    }
+}
+
+impl<'a> Optimizer<'a> {
+    fn new(exp: &Expression, context: Option<&'a Context>) -> Optimizer<'a> {
+        let mut definitions = Definitions(BTreeSet::new());
+        walk(exp, &mut definitions);
+        return Optimizer {
+            shadowed: definitions.0,
+            context,
+        };
+    }
+
+    // (name ...) の呼び出しが、組み込み関数の呼び出しなら true
+    fn is_builtin(&self, name: &str) -> bool {
+        if self.shadowed.contains(name) {
+            return false;
+        }
+        match self.context {
+            Some(context) => {
+                return context.calls_builtin(name);
+            }
+            None => {
+                return is_builtin(name);
+            }
+        }
+    }
+
+    fn optimize(&self, exp: &Expression) -> Expression {
+        let name = match exp {
+            Expression::ExpressionList(l) => match l.head() {
+                Some(Expression::Atom(name)) if self.is_builtin(name) => name.clone(),
+                _ => {
+                    return exp.clone();
+                }
+            },
+            _ => {
+                return exp.clone();
+            }
+        };
+        let exp = map_children(exp, |child, role| {
+            if role == Role::Code {
+                return self.optimize(child);
+            }
+            return child.clone();
+        });
+        let args = match &exp {
+            Expression::ExpressionList(l) => elements(l.tail()),
+            _ => {
+                return exp;
+            }
+        };
+        match &*name {
+            "add" | "sub" | "mul" | "div" | "gt" | "lt" | "ge" | "le" | "eq" | "ne" => {
+                return fold_constant(&exp).unwrap_or(exp);
+            }
+            "cond" => {
+                if let [Expression::Int(test), ok, ng] = &args[..] {
+                    return if *test != 0 {
+                        (*ok).clone()
+                    } else {
+                        (*ng).clone()
+                    };
+                }
+                return exp;
+            }
+            "progn" => {
+                let mut body = Vec::new();
+                for e in args {
+                    match self.progn_body(e) {
+                        Some(inner) => body.extend(inner.into_iter().cloned()),
+                        None => body.push(e.clone()),
+                    }
+                }
+                if body.len() == 1 {
+                    return body.pop().unwrap();
+                }
+                let l = body
+                    .iter()
+                    .rev()
+                    .fold(ExpressionList::new(), |acc, e| acc.cons(e))
+                    .cons(&Expression::Atom(name));
+                return Expression::ExpressionList(Rc::new(l));
+            }
+            _ => {
+                return exp;
+            }
+        }
+    }
+
+    // 組み込み関数の (progn ...) なら、その本体の式を返す
+    fn progn_body<'e>(&self, exp: &'e Expression) -> Option<Vec<&'e Expression>> {
+        if let Expression::ExpressionList(l) = exp {
+            if let Some(Expression::Atom(a)) = l.head() {
+                if &**a == "progn" && self.is_builtin(a) {
+                    return Some(elements(l.tail()));
+                }
+            }
+        }
+        return None;
+    }
+}
+
+// 引数が全て整数のリテラルなら評価し、結果が整数ならその整数の式を返す。
+// 組み込み関数の呼び出しであることは確かめてあるので、新しい Context で評価してよい
+fn fold_constant(call: &Expression) -> Option<Expression> {
+    if let Expression::ExpressionList(l) = call {
+        if !elements(l.tail())
+            .iter()
+            .all(|e| matches!(e, Expression::Int(_)))
+        {
+            return None;
+        }
+    }
+    if let Ok(Type::Int(i)) = eval(call) {
+        return Some(Expression::Int(i));
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::*;
+    use crate::optimize::*;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn optimize_tests() {
+        let cases = vec![
+            ("(add 1 2)", "3"),
+            ("(mul (add 1 2) (sub 10 4))", "18"),
+            ("(gt 2 1)", "1"),
+            ("(add *x* (mul 2 3))", "(add *x* 6)"),
+            // 整数にならない結果やエラーは畳み込まない
+            ("(div 1 2)", "(div 1 2)"),
+            ("(div 1 0)", "(div 1 0)"),
+            ("(add 1 2 3)", "(add 1 2 3)"),
+            ("(cond 1 (add 1 1) *y*)", "2"),
+            ("(cond (eq 1 2) *x* (list 1))", "(list 1)"),
+            ("(cond *t* 1 2)", "(cond *t* 1 2)"),
+            ("(progn 1 (progn 2 (progn 3)) 4)", "(progn 1 2 3 4)"),
+            ("(progn (add 1 1))", "2"),
+            ("(quote (add 1 2))", "(quote (add 1 2))"),
+            ("(quasiquote (add 1 2))", "(quasiquote (add 1 2))"),
+            (
+                "(defun f (*x*) (add *x* (add 1 1)))",
+                "(defun f (*x*) (add *x* 2))",
+            ),
+            (
+                "(let ((*a* (add 1 2))) (mul *a* (add 1 1)))",
+                "(let ((*a* 3)) (mul *a* 2))",
+            ),
+            (
+                "(dolist (*e* (list (add 1 1))) (add 2 2))",
+                "(dolist (*e* (list 2)) 4)",
+            ),
+            (
+                "(match (add 1 1) (2 (add 2 2)) (_ 0))",
+                "(match 2 (2 4) (_ 0))",
+            ),
+            (
+                "(try (add 1 2) (catch *e* (add 3 4)))",
+                "(try 3 (catch *e* 7))",
+            ),
+            // マクロかもしれない呼び出しの引数は変換しない
+            ("(my-macro (add 1 2))", "(my-macro (add 1 2))"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let expected = Expression::try_from(expected.as_bytes()).unwrap();
+            assert_eq!(optimize(&exp), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn optimize_preserves_result_tests() {
+        let cases = vec![
+            "(progn (define *x* 0) (progn (set *x* (add *x* (mul 2 3))) (progn *x*)))",
+            "(progn (defun f (*n*) (cond (lt *n* (add 1 1)) *n* (add (f (sub *n* 1)) (f (sub *n* 2))))) (f 10))",
+            "(let ((*a* (add 1 2))) (cond (gt *a* 2) (list *a* (mul 2 2)) 0))",
+            "(try (div 1 (sub 1 1)) (catch *e* *e*))",
+        ];
+        for src in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(eval(&optimize(&exp)), eval(&exp), "{}", src);
+        }
+    }
+
+    #[test]
+    fn shadowed_builtin_tests() {
+        // 式の中で上書きされる組み込み関数の呼び出しは、変換しない
+        let cases = vec![
+            (
+                "(progn (defun add (*a* *b*) (mul *a* *b*)) (add 2 3) (sub 3 1))",
+                "(progn (defun add (*a* *b*) (mul *a* *b*)) (add 2 3) 2)",
+            ),
+            (
+                "(progn (defmacro progn (*x*) *x*) (progn 1 (add 1 1)))",
+                "(progn (defmacro progn (*x*) *x*) (progn 1 (add 1 1)))",
+            ),
+            (
+                "(progn (defmacro cond (*a* *b* *c*) *c*) (cond 1 2 3))",
+                "(progn (defmacro cond (*a* *b* *c*) *c*) (cond 1 2 3))",
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let expected = Expression::try_from(expected.as_bytes()).unwrap();
+            assert_eq!(optimize(&exp), expected, "{}", src);
+            assert_eq!(eval(&optimize(&exp)), eval(&exp), "{}", src);
+        }
+
+        // Context で上書き・削除された組み込み関数
+        let mut context = Context::new();
+        context.register_fn("sub", |a: i32, b: i32| a * b);
+        context.remove_builtin("gt");
+        let exp = Expression::try_from("(list (add 1 2) (sub 2 3) (gt 2 1))".as_bytes()).unwrap();
+        let expected = Expression::try_from("(list 3 (sub 2 3) (gt 2 1))".as_bytes()).unwrap();
+        assert_eq!(optimize_in(&exp, &context), expected);
+    }
+}
@@ -0,0 +1,75 @@
+//!
+//! `print` で出力する文字列の書き込み先を定義
+//!
+
+use alloc::rc::Rc;
+use alloc::string::String;
+use core::cell::RefCell;
+
+/// `print` で出力する文字列の書き込み先。`Context::set_output` で差し替えられる
+pub trait Output {
+    /// 文字列 `s` を書き込む。改行は `s` に含めて渡す
+    fn write(&mut self, s: &str);
+}
+
+/// 標準出力に書き込む。wasm32 以外で `std` feature が有効な場合の、`Context` のデフォルト
+#[cfg(feature = "std")]
+pub struct StdoutOutput;
+
+#[cfg(feature = "std")]
+impl Output for StdoutOutput {
+    fn write(&mut self, s: &str) {
+        use std::io::Write;
+        let _ = std::io::stdout().write_all(s.as_bytes());
+    }
+}
+
+/// 書き込んだ文字列を捨てる。標準出力の無い wasm32 及び no_std での、`Context` のデフォルト
+pub struct NullOutput;
+
+impl Output for NullOutput {
+    fn write(&mut self, _s: &str) {}
+}
+
+/// 書き込んだ文字列をメモリ上に溜める。REPL などで、出力を画面の決まった位置に表示したい場合向け。
+/// `clone` したものは同じ内容を共有するので、`Context` に渡した後もホスト側から内容を取り出せる
+///
+/// # Examples
+/// ```
+/// use liblisp::interpreter::Interpreter;
+/// use liblisp::output::MemoryOutput;
+///
+/// let output = MemoryOutput::new();
+/// let mut interp = Interpreter::new();
+/// interp.context_mut().set_output(Box::new(output.clone()));
+/// interp.eval_str("(print \"x =\" 1)").unwrap();
+/// assert_eq!(output.take(), "x = 1\n");
+/// assert_eq!(output.contents(), "");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryOutput {
+    buffer: Rc<RefCell<String>>,
+}
+
+impl MemoryOutput {
+    /// 何も書き込まれていない状態で作成する
+    pub fn new() -> MemoryOutput {
+        return MemoryOutput::default();
+    }
+
+    /// これまでに書き込まれた文字列
+    pub fn contents(&self) -> String {
+        return self.buffer.borrow().clone();
+    }
+
+    /// これまでに書き込まれた文字列を取り出し、空にする
+    pub fn take(&self) -> String {
+        return core::mem::take(&mut *self.buffer.borrow_mut());
+    }
+}
+
+impl Output for MemoryOutput {
+    fn write(&mut self, s: &str) {
+        self.buffer.borrow_mut().push_str(s);
+    }
+}
@@ -0,0 +1,185 @@
+//!
+//! `match` や分配束縛で使う、パターンに関する定義
+//!
+
+use crate::eval::EvalError;
+use crate::expression::*;
+use crate::typecheck::split_annotation;
+use crate::types::*;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+/// 値の形を表すパターン。`Expression` から `Pattern::compile` で作成する
+///
+/// - `_` は任意の値にマッチし、何も束縛しない
+/// - `*x*` は任意の値にマッチし、その値を `*x*` に束縛する
+/// - `1` や `"s"` 、`a` 、`:k` のような Int ・ Str ・ Atom ・ Keyword は、等しい値にのみマッチする
+/// - `(list p1 p2 ...)` は、要素数が同じで、各要素が対応するパターンにマッチするリストにマッチする
+///
+/// `let` や関数の仮引数の分配束縛では、`Pattern::compile_binding` で作成する
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    Wildcard,
+    Var(Rc<str>),
+    Literal(Type),
+    List(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// 式をパターンに変換する。パターンとして解釈できない式なら `TypeMismatch`
+    pub fn compile(exp: &Expression) -> Result<Pattern, EvalError> {
+        match exp {
+            Expression::Atom(a) if &**a == "_" => {
+                return Ok(Pattern::Wildcard);
+            }
+            Expression::Atom(a) => {
+                return Ok(Pattern::Literal(Type::Atom(a.clone())));
+            }
+            Expression::Var(v) => {
+                return Ok(Pattern::Var(v.clone()));
+            }
+            Expression::Int(i) => {
+                return Ok(Pattern::Literal(Type::Int(*i)));
+            }
+            Expression::Str(s) => {
+                return Ok(Pattern::Literal(Type::Str(s.clone())));
+            }
+            Expression::Keyword(k) => {
+                return Ok(Pattern::Literal(Type::Keyword(k.clone())));
+            }
+            Expression::ExpressionList(l) => {
+                match l.head() {
+                    Some(Expression::Atom(a)) if &**a == "list" => {}
+                    _ => {
+                        return Err(EvalError::TypeMismatch);
+                    }
+                }
+                let mut elems = Vec::new();
+                for e in l.tail().iter() {
+                    elems.push(Pattern::compile(e)?);
+                }
+                return Ok(Pattern::List(elems));
+            }
+        }
+    }
+
+    /// 分配束縛の左辺を、パターンに変換する。
+    /// `*x*` と `_` に加えて、`(*a* (*b* *c*))` のように `list` を付けずに書いたリストを受け付ける。
+    /// それ以外の式なら `TypeMismatch`
+    pub fn compile_binding(exp: &Expression) -> Result<Pattern, EvalError> {
+        match exp {
+            Expression::Atom(a) if &**a == "_" => {
+                return Ok(Pattern::Wildcard);
+            }
+            Expression::Var(v) => {
+                return Ok(Pattern::Var(v.clone()));
+            }
+            // (pattern : type) の型注釈は、評価時には無視する
+            Expression::ExpressionList(_) if split_annotation(exp).is_some() => {
+                let (pattern, _) = split_annotation(exp).unwrap();
+                return Pattern::compile_binding(pattern);
+            }
+            Expression::ExpressionList(l) => {
+                let mut elems = Vec::new();
+                for e in l.iter() {
+                    elems.push(Pattern::compile_binding(e)?);
+                }
+                return Ok(Pattern::List(elems));
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+
+    /// 値がパターンにマッチするなら、束縛する変数と値の一覧を返す。マッチしないなら `None`
+    pub fn bind(&self, val: &Type) -> Option<Vec<(Rc<str>, Type)>> {
+        let mut bindings = Vec::new();
+        if self.bind_(val, &mut bindings) {
+            return Some(bindings);
+        } else {
+            return None;
+        }
+    }
+
+    fn bind_(&self, val: &Type, bindings: &mut Vec<(Rc<str>, Type)>) -> bool {
+        match self {
+            Pattern::Wildcard => {
+                return true;
+            }
+            Pattern::Var(v) => {
+                bindings.push((v.clone(), val.clone()));
+                return true;
+            }
+            Pattern::Literal(t) => {
+                return t == val;
+            }
+            Pattern::List(elems) => {
+                if let Type::TypeList(l) = val {
+                    if l.len() as usize != elems.len() {
+                        return false;
+                    }
+                    return elems
+                        .iter()
+                        .zip(l.iter())
+                        .all(|(p, t)| p.bind_(t, bindings));
+                } else {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pattern::*;
+    use core::convert::TryFrom;
+
+    fn compile(src: &str) -> Pattern {
+        return Pattern::compile(&Expression::try_from(src.as_bytes()).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bind_tests() {
+        let list = |v: &[Type]| {
+            let l = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+            return Type::TypeList(Rc::new(l));
+        };
+        let val = list(&[Type::Int(1), list(&[Type::Atom("a".into()), Type::Int(2)])]);
+
+        assert_eq!(
+            compile("(list 1 (list a *y*))").bind(&val),
+            Some(vec![("*y*".into(), Type::Int(2))])
+        );
+        assert_eq!(
+            compile("(list *x* _)").bind(&val),
+            Some(vec![("*x*".into(), Type::Int(1))])
+        );
+        assert_eq!(compile("_").bind(&Type::Void), Some(vec![]));
+        assert_eq!(compile("(list 2 _)").bind(&val), None);
+        assert_eq!(compile("(list *x*)").bind(&val), None);
+        assert_eq!(compile("(list)").bind(&list(&[])), Some(vec![]));
+        assert_eq!(compile("(list)").bind(&Type::Int(0)), None);
+        assert_eq!(compile("\"s\"").bind(&Type::Str("s".into())), Some(vec![]));
+        assert_eq!(compile("s").bind(&Type::Str("s".into())), None);
+
+        let exp = Expression::try_from("(vector 1)".as_bytes()).unwrap();
+        assert_eq!(Pattern::compile(&exp), Err(EvalError::TypeMismatch));
+
+        // 分配束縛
+        let exp = Expression::try_from("(*a* (_ *b*))".as_bytes()).unwrap();
+        let pattern = Pattern::compile_binding(&exp).unwrap();
+        let val = list(&[Type::Int(1), list(&[Type::Int(2), Type::Int(3)])]);
+        assert_eq!(
+            pattern.bind(&val),
+            Some(vec![
+                ("*a*".into(), Type::Int(1)),
+                ("*b*".into(), Type::Int(3))
+            ])
+        );
+        let exp = Expression::try_from("(*a* 1)".as_bytes()).unwrap();
+        assert_eq!(Pattern::compile_binding(&exp), Err(EvalError::TypeMismatch));
+    }
+}
@@ -0,0 +1,19 @@
+(defun not (*x*)
+  (cond *x* 0 1))
+
+(defun abs (*x*)
+  (cond (lt *x* 0) (sub 0 *x*) *x*))
+
+(defun max (*a* *b*)
+  (cond (gt *a* *b*) *a* *b*))
+
+(defun min (*a* *b*)
+  (cond (lt *a* *b*) *a* *b*))
+
+(defun second (*l*)
+  (head (tail *l*)))
+
+(defun last (*l*)
+  (define *res* (head *l*))
+  (dolist (*x* *l*) (set *res* *x*))
+  *res*)
@@ -0,0 +1,92 @@
+//!
+//! 組み込み関数及びユーザ定義関数の呼び出し回数と実行時間を計測するプロファイラを定義
+//!
+
+use crate::expression::Expression;
+use crate::observer::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// 関数ごとの計測結果
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProfileEntry {
+    pub calls: u64,      // 呼び出し回数
+    pub total: Duration, // 累積の実行時間。内側で呼び出した関数の実行時間を含む
+}
+
+/// `Context::profile_report` で得られる計測結果。累積の実行時間が長い順に並ぶ
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProfileReport {
+    pub entries: Vec<(Rc<str>, ProfileEntry)>,
+}
+
+impl ProfileReport {
+    /// 関数名 name の計測結果
+    pub fn get(&self, name: &str) -> Option<&ProfileEntry> {
+        return self
+            .entries
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, e)| e);
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<24} {:>10} {:>14}", "name", "calls", "total(us)")?;
+        for (name, entry) in &self.entries {
+            writeln!(
+                f,
+                "{:<24} {:>10} {:>14}",
+                name,
+                entry.calls,
+                entry.total.as_micros()
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+/// `EvalObserver` として評価の開始・終了の通知を受け取り、関数呼び出しごとに計測する
+#[derive(Debug, Default)]
+pub struct Profiler {
+    stack: Vec<Option<(Rc<str>, Instant)>>, // 評価中の式。関数呼び出しなら関数名と開始時刻
+    entries: HashMap<Rc<str>, ProfileEntry>,
+}
+
+impl Profiler {
+    /// `Profiler` を新規作成
+    pub fn new() -> Profiler {
+        return Profiler::default();
+    }
+
+    /// これまでの計測結果
+    pub fn report(&self) -> ProfileReport {
+        let mut entries: Vec<(Rc<str>, ProfileEntry)> =
+            self.entries.iter().map(|(n, e)| (n.clone(), *e)).collect();
+        entries.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(&b.0)));
+        return ProfileReport { entries };
+    }
+}
+
+impl EvalObserver for Profiler {
+    fn enter(&mut self, exp: &Expression, _depth: usize) {
+        let mut call = None;
+        if let Expression::ExpressionList(l) = exp {
+            if let Some(Expression::Atom(name)) = l.head() {
+                call = Some((name.clone(), Instant::now()));
+            }
+        }
+        self.stack.push(call);
+    }
+
+    fn exit(&mut self, _exp: &Expression, _depth: usize, _result: EvalExit) {
+        if let Some(Some((name, start))) = self.stack.pop() {
+            let entry = self.entries.entry(name).or_default();
+            entry.calls += 1;
+            entry.total += start.elapsed();
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//!
+//! 読み込んだトップレベルの式の列 `Program` と、変数に値を埋め込んで `Program` を組み立てる `Template` を定義
+//!
+//! `Template` は値をソースの文字列に連結せず、式の上で置き換える。
+//! 利用者の入力のような信頼できない文字列を埋め込んでも、それがコードとして評価されることはない
+//!
+
+use crate::eval::*;
+use crate::expression::*;
+use crate::interpreter::LispError;
+use crate::lexer::is_var_name;
+use crate::types::*;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as Map;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashMap as Map;
+
+/// 読み込んだトップレベルの式の列
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    forms: Vec<Expression>,
+}
+
+/// `Program::template` で作る、変数に値を埋め込む前のプログラム。
+/// 読み込みや `bind` のエラーは `build` でまとめて返す
+///
+/// # Examples
+/// ```
+/// use liblisp::eval::Context;
+/// use liblisp::program::Program;
+/// use liblisp::types::Type;
+///
+/// let user_input = "\") (exit 1) (\"";
+/// let program = Program::template("(defun greet (*who*) *who*) (greet *name*)")
+///     .bind("*name*", Type::Str(user_input.into()))
+///     .build()
+///     .unwrap();
+/// let mut context = Context::new();
+/// assert_eq!(program.eval(&mut context), Ok(Type::Str(user_input.into())));
+/// ```
+pub struct Template {
+    forms: Result<Vec<Expression>, LispError>,
+    bindings: Map<Rc<str>, Expression>,
+}
+
+impl Program {
+    /// ソースを読み込んで `Program` を作る
+    pub fn parse(src: &str) -> Result<Program, ExpressionConversionError> {
+        return Ok(Program {
+            forms: parse_program(src)?,
+        });
+    }
+
+    /// ソースを、変数に値を埋め込むテンプレートとして読み込む。値は `Template::bind` で与える
+    pub fn template(src: &str) -> Template {
+        return Template {
+            forms: parse_program(src).map_err(LispError::from),
+            bindings: Map::new(),
+        };
+    }
+
+    /// トップレベルの式の列
+    pub fn forms(&self) -> &[Expression] {
+        return &self.forms;
+    }
+
+    /// トップレベルの式を先頭から順に `context` で評価し、最後に評価した式の値を返す。
+    /// 式が一つもない場合は `Type::Void` を返す
+    pub fn eval(&self, context: &mut Context) -> Result<Type, EvalError> {
+        let mut res = Type::Void;
+        for exp in &self.forms {
+            res = eval_with_context(exp, context)?;
+        }
+        return Ok(res);
+    }
+}
+
+/// トップレベルの式を 1 行に 1 つずつ出力する。文字列はエスケープして出力するので、
+/// 出力したソースを読み込み直すと同じ `Program` になる
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, exp) in self.forms.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", exp)?;
+        }
+        return Ok(());
+    }
+}
+
+impl Template {
+    /// 変数 `name` （`*x*` の形式）の参照を、評価すると `value` になる式に置き換える。
+    /// 同じ名前を複数回指定した場合は、最後の値を使う。
+    ///
+    /// 整数・文字列・キーワードはそのまま、atom やリストは `quote` して埋め込むので、値がコードとして評価されることはない。
+    /// `*x*` の形式でない名前や、`Vector` のように式で表せない値を含む場合は、`build` が `EvalError::TypeMismatch` を返す
+    pub fn bind(mut self, name: &str, value: Type) -> Template {
+        if self.forms.is_err() {
+            return self;
+        }
+        if !is_var_name(name) {
+            self.forms = Err(LispError::Eval(EvalError::TypeMismatch));
+            return self;
+        }
+        match literal(&value) {
+            Ok(exp) => {
+                self.bindings.insert(Rc::from(name), exp);
+            }
+            Err(e) => {
+                self.forms = Err(LispError::Eval(e));
+            }
+        }
+        return self;
+    }
+
+    /// 変数を置き換えた `Program` を作る。`bind` で指定しなかった変数は、そのまま変数として残す
+    pub fn build(self) -> Result<Program, LispError> {
+        let forms = self.forms?;
+        let bindings: Map<&str, Expression> = self
+            .bindings
+            .iter()
+            .map(|(name, exp)| (&**name, exp.clone()))
+            .collect();
+        return Ok(Program {
+            forms: forms.iter().map(|exp| exp.substitute(&bindings)).collect(),
+        });
+    }
+}
+
+// 評価すると value になる式。評価しても値が変わらないものはそのまま、それ以外は quote する
+fn literal(value: &Type) -> Result<Expression, EvalError> {
+    match value {
+        Type::Int(_) | Type::Str(_) | Type::Keyword(_) | Type::Void | Type::Function(_) => {
+            return type_to_expression(value);
+        }
+        _ => {
+            let exp = type_to_expression(value)?;
+            // リストの要素の Void や関数は、quote すると別の値になるので埋め込めない
+            if expression_to_type(&exp) != *value {
+                return Err(EvalError::TypeMismatch);
+            }
+            let quoted = ExpressionList::new()
+                .cons(&exp)
+                .cons(&Expression::Atom(Rc::from("quote")));
+            return Ok(Expression::ExpressionList(Rc::new(quoted)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program::*;
+    use alloc::string::ToString;
+
+    fn list(elements: &[Type]) -> Type {
+        let l = elements
+            .iter()
+            .rev()
+            .fold(TypeList::new(), |acc, t| acc.cons(t));
+        return Type::TypeList(Rc::new(l));
+    }
+
+    #[test]
+    fn template_tests() {
+        let values = vec![
+            Type::Int(-3),
+            Type::Str("\") (exit 1) (\"".into()),
+            Type::Str("\\\"\n\t\u{1b}".into()),
+            Type::Str("*x*".into()),
+            Type::Keyword("k".into()),
+            Type::Atom("exit".into()),
+            Type::Atom("*x*".into()),
+            list(&[]),
+            list(&[Type::Atom("exit".into()), Type::Int(1)]),
+            list(&[Type::Str("a".into()), list(&[Type::Atom("*x*".into())])]),
+            Type::Void,
+            Type::Function("head".into()),
+        ];
+        for value in values {
+            let program = Program::template("(define *x* 0) (list *v* *x*)")
+                .bind("*v*", value.clone())
+                .build()
+                .unwrap();
+            let mut context = Context::new();
+            assert_eq!(
+                program.eval(&mut context),
+                Ok(list(&[value.clone(), Type::Int(0)])),
+                "{}",
+                program
+            );
+            // 出力したソースを読み込み直すと同じ Program になる
+            assert_eq!(Program::parse(&program.to_string()), Ok(program));
+        }
+
+        let program = Program::template("(add *a* *b*)")
+            .bind("*a*", Type::Int(1))
+            .bind("*a*", Type::Int(2))
+            .build()
+            .unwrap();
+        assert_eq!(program.to_string(), "(add 2 *b*)");
+        let mut context = Context::new();
+        context.define_var("*b*", Type::Int(10)).unwrap();
+        assert_eq!(program.eval(&mut context), Ok(Type::Int(12)));
+        // quote の中の変数と、文字列は置き換えない
+        let program = Program::template("'(*a*) \"*a*\"")
+            .bind("*a*", Type::Int(1))
+            .build()
+            .unwrap();
+        assert_eq!(program.to_string(), "(quote (*a*))\n\"*a*\"");
+        assert_eq!(Program::template("").build().unwrap().forms(), &[]);
+
+        let vector = Type::Vector(Rc::new(vec![Type::Int(1)]));
+        let errors = vec![
+            (
+                Program::template("(add 1").bind("*a*", Type::Int(1)),
+                LispError::Parse(ExpressionConversionError::UnexpectedEof),
+            ),
+            (
+                Program::template("*a*").bind("a", Type::Int(1)),
+                LispError::Eval(EvalError::TypeMismatch),
+            ),
+            (
+                Program::template("*a*").bind("*a* *b*", Type::Int(1)),
+                LispError::Eval(EvalError::TypeMismatch),
+            ),
+            (
+                Program::template("*a*").bind("*a*", vector),
+                LispError::Eval(EvalError::TypeMismatch),
+            ),
+            (
+                Program::template("*a*").bind("*a*", list(&[Type::Void])),
+                LispError::Eval(EvalError::TypeMismatch),
+            ),
+        ];
+        for (template, expected) in errors {
+            assert_eq!(template.build(), Err(expected));
+        }
+    }
+}
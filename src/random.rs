@@ -0,0 +1,65 @@
+//!
+//! `random` 及び `random-seed` で使う乱数生成器を定義
+//!
+
+/// `Context` が使う乱数生成器。`Context::set_random_source` で差し替えられる
+pub trait RandomSource {
+    /// 次の乱数
+    fn next_u64(&mut self) -> u64;
+    /// 乱数の種を設定する。同じ種からは同じ乱数列が得られる
+    fn seed(&mut self, seed: u64);
+}
+
+/// SplitMix64 による乱数生成器。`Context` のデフォルト。
+/// 暗号用途には使えないが、同じ種からは環境によらず同じ乱数列が得られる
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// 種 seed の `SplitMix64` を新規作成
+    pub fn new(seed: u64) -> SplitMix64 {
+        return SplitMix64 { state: seed };
+    }
+}
+
+impl Default for SplitMix64 {
+    fn default() -> Self {
+        return SplitMix64::new(0);
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        return z ^ (z >> 31);
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::random::*;
+
+    #[test]
+    fn splitmix64_tests() {
+        // 参照実装の出力と一致する
+        let mut rng = SplitMix64::new(1234567);
+        assert_eq!(rng.next_u64(), 6457827717110365317);
+        assert_eq!(rng.next_u64(), 3203168211198807973);
+
+        // 種を設定し直すと、同じ乱数列に戻る
+        let mut a = SplitMix64::new(42);
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        a.seed(42);
+        let second: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,299 @@
+//!
+//! ソース中のバイト位置を行と列に変換し、エラーの位置を示すメッセージを作る `SourceMap` を定義
+//!
+
+use crate::lexer::Span;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// ソース中の位置。行と列はどちらも 1 始まりで、列は文字単位で数える
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// ソースと、その各行の開始位置。
+/// `Span` のようなバイト位置を行と列に変換し、REPL や CLI で表示するための、
+/// 該当する行と `^` で位置を示したメッセージを作る
+///
+/// # Examples
+/// ```
+/// use liblisp::lexer::Span;
+/// use liblisp::source_map::{Location, SourceMap};
+///
+/// let map = SourceMap::new("(define *x* 1)\n(add *x* :a)\n");
+/// assert_eq!(map.location(20), Location { line: 2, column: 6 });
+/// assert_eq!(
+///     map.render(Span { start: 20, end: 23 }, "error"),
+///     "error\n --> 2:6\n  |\n2 | (add *x* :a)\n  |      ^^^\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    src: String,
+    line_starts: Vec<usize>, // 各行の先頭のバイト位置
+}
+
+impl SourceMap {
+    /// `src` の `SourceMap` を新規作成
+    pub fn new(src: &str) -> SourceMap {
+        let mut line_starts = vec![0];
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        return SourceMap {
+            src: String::from(src),
+            line_starts,
+        };
+    }
+
+    /// 元のソース
+    pub fn source(&self) -> &str {
+        return &self.src;
+    }
+
+    /// 行数。ソースが改行で終わる場合は、その後の空の行も数える
+    pub fn line_count(&self) -> usize {
+        return self.line_starts.len();
+    }
+
+    /// バイト位置 `offset` の行と列。ソースの長さを超える位置は、ソースの末尾とみなす
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = self.char_boundary(offset);
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let column = self.src[self.line_starts[line]..offset].chars().count() + 1;
+        return Location {
+            line: line + 1,
+            column,
+        };
+    }
+
+    /// 1 始まりで `n` 行目の内容。改行は含まない。範囲外なら None
+    pub fn line(&self, n: usize) -> Option<&str> {
+        let start = *self.line_starts.get(n.checked_sub(1)?)?;
+        let end = match self.line_starts.get(n) {
+            Some(next) => next - 1,
+            None => self.src.len(),
+        };
+        return Some(self.src[start..end].trim_end_matches('\r'));
+    }
+
+    /// `span` の先頭がある行と、その下に `span` の位置を `^` で示した行からなる、2 行の文字列。
+    /// `span` が複数行にわたる場合は、先頭の行の末尾までを示す。長さ 0 の `span` も 1 文字分を示す。
+    /// 長い行は、`span` の先頭の周りの 100 文字だけを切り出し、省略した側に `...` を付ける
+    pub fn snippet(&self, span: Span) -> String {
+        let mut res = String::new();
+        self.write_snippet(&mut res, span, "");
+        return res;
+    }
+
+    /// `message` と、`span` の位置（行:列）、`snippet` と同じ内容を、Rust のコンパイラのエラーに似た
+    /// 複数行の形式にまとめる
+    pub fn render(&self, span: Span, message: &str) -> String {
+        let location = self.location(span.start);
+        let width = digits(location.line);
+        let mut res = String::new();
+        let _ = writeln!(res, "{}", message);
+        let _ = writeln!(
+            res,
+            "{:width$}--> {}:{}",
+            "",
+            location.line,
+            location.column,
+            width = width
+        );
+        let _ = writeln!(res, "{:width$} |", "", width = width);
+        self.write_snippet(&mut res, span, "|");
+        return res;
+    }
+
+    // snippet を res に書き込む。gutter が空でなければ、行番号と gutter を各行の前に付ける
+    fn write_snippet(&self, res: &mut String, span: Span, gutter: &str) {
+        let location = self.location(span.start);
+        let text = self.line(location.line).unwrap_or("");
+        // span の終わりは、先頭の行の末尾まで
+        let end = core::cmp::min(
+            self.char_boundary(span.end),
+            self.line_starts[location.line - 1] + text.len(),
+        );
+        let start = self.char_boundary(span.start);
+        let carets = core::cmp::max(
+            self.src
+                .get(start..end)
+                .map_or(0, |s: &str| s.chars().count()),
+            1,
+        );
+        let (text, indent, carets) = excerpt(text, location.column - 1, carets);
+        if gutter.is_empty() {
+            let _ = writeln!(res, "{}", text);
+            let _ = writeln!(res, "{:indent$}{}", "", "^".repeat(carets), indent = indent);
+        } else {
+            let width = digits(location.line);
+            let _ = writeln!(res, "{} {} {}", location.line, gutter, text);
+            let _ = writeln!(
+                res,
+                "{:width$} {} {:indent$}{}",
+                "",
+                gutter,
+                "",
+                "^".repeat(carets),
+                width = width,
+                indent = indent
+            );
+        }
+    }
+
+    // offset を、ソースの範囲内で、それ以前の最も近い文字の境界に丸める
+    fn char_boundary(&self, offset: usize) -> usize {
+        let mut offset = core::cmp::min(offset, self.src.len());
+        while !self.src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        return offset;
+    }
+}
+
+// 長い行を切り出す時に、表示する最大の文字数（省略を示す ... を除く）
+const EXCERPT_WIDTH: usize = 100;
+// 長い行を切り出す時に、span の先頭より前に残す文字数
+const EXCERPT_BEFORE: usize = 40;
+
+// 行 text のうち、列 column（0 始まり）から carets 文字の span の周りを EXCERPT_WIDTH 文字以内で切り出す。
+// 切り出した行と、その中での span の列と文字数を返す。省略した側には ... を付ける
+fn excerpt(text: &str, column: usize, carets: usize) -> (String, usize, usize) {
+    let len = text.chars().count();
+    if len <= EXCERPT_WIDTH {
+        return (String::from(text), column, carets);
+    }
+    let mut start = column.saturating_sub(EXCERPT_BEFORE);
+    let end = core::cmp::min(start + EXCERPT_WIDTH, len);
+    // 行の末尾に近い場合は、その分だけ前を多く残す
+    start = core::cmp::min(start, end - EXCERPT_WIDTH);
+    let mut res = String::new();
+    if start > 0 {
+        res.push_str("...");
+    }
+    res.extend(text.chars().skip(start).take(end - start));
+    if end < len {
+        res.push_str("...");
+    }
+    let indent = column - start + if start > 0 { 3 } else { 0 };
+    let carets = core::cmp::max(core::cmp::min(carets, end.saturating_sub(column)), 1);
+    return (res, indent, carets);
+}
+
+// 10 進数での桁数
+fn digits(mut n: usize) -> usize {
+    let mut res = 1;
+    while n >= 10 {
+        n /= 10;
+        res += 1;
+    }
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expression::*;
+    use crate::source_map::*;
+
+    #[test]
+    fn location_tests() {
+        let map = SourceMap::new("ab\nりんご x\n\n");
+        let cases = vec![
+            (0, (1, 1)),
+            (2, (1, 3)),
+            (3, (2, 1)),
+            (12, (2, 4)),
+            (13, (2, 5)),
+            // 文字の途中の位置は、その文字の先頭とみなす
+            (4, (2, 1)),
+            (14, (2, 6)),
+            (15, (3, 1)),
+            (16, (4, 1)),
+            (100, (4, 1)),
+        ];
+        for (offset, (line, column)) in cases {
+            assert_eq!(
+                map.location(offset),
+                Location { line, column },
+                "{}",
+                offset
+            );
+        }
+        assert_eq!(map.line_count(), 4);
+        assert_eq!(map.line(1), Some("ab"));
+        assert_eq!(map.line(2), Some("りんご x"));
+        assert_eq!(map.line(4), Some(""));
+        assert_eq!(map.line(0), None);
+        assert_eq!(map.line(5), None);
+        assert_eq!(SourceMap::new("a\r\nb").line(1), Some("a"));
+    }
+
+    #[test]
+    fn snippet_tests() {
+        let map = SourceMap::new("(f りんご\n  x)");
+        assert_eq!(
+            map.snippet(Span { start: 3, end: 12 }),
+            "(f りんご\n   ^^^\n"
+        );
+        // 複数行にわたる span は、先頭の行の末尾まで
+        assert_eq!(
+            map.snippet(Span { start: 0, end: 17 }),
+            "(f りんご\n^^^^^^\n"
+        );
+        // 長さ 0 の span
+        assert_eq!(map.snippet(Span { start: 17, end: 17 }), "  x)\n    ^\n");
+
+        // 長い行は、span の周りだけを切り出す
+        let src = format!("{}(f x){}", "a".repeat(200_000), "b".repeat(200_000));
+        let map = SourceMap::new(&src);
+        assert_eq!(
+            map.snippet(Span {
+                start: 200_000,
+                end: 200_005
+            }),
+            format!(
+                "...{}(f x){}...\n{}^^^^^\n",
+                "a".repeat(40),
+                "b".repeat(55),
+                " ".repeat(43)
+            )
+        );
+        // 行の先頭と末尾の近く
+        let src = "x".repeat(300);
+        let map = SourceMap::new(&src);
+        assert_eq!(
+            map.snippet(Span { start: 0, end: 300 }),
+            format!("{}...\n{}\n", "x".repeat(100), "^".repeat(100))
+        );
+        assert_eq!(
+            map.snippet(Span {
+                start: 300,
+                end: 300
+            }),
+            format!("...{}\n{}^\n", "x".repeat(100), " ".repeat(103))
+        );
+    }
+
+    #[test]
+    fn render_tests() {
+        let src = "1\n2\n3\n4\n5\n6\n7\n8\n9\n(add 1";
+        let err = parse_program_located(src, &ReaderOptions::default()).unwrap_err();
+        assert_eq!(
+            err.render(&SourceMap::new(src)),
+            "parse error: UnexpectedEof\n  --> 10:7\n   |\n10 | (add 1\n   |       ^\n"
+        );
+
+        let src = "(list 1 2 3) )";
+        let err = parse_program_located(src, &ReaderOptions::default()).unwrap_err();
+        assert_eq!(
+            err.render(&SourceMap::new(src)),
+            "parse error: InvalidToken\n --> 1:14\n  |\n1 | (list 1 2 3) )\n  |              ^\n"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//!
+//! 評価した式と結果を、評価を始めた順に記録するトレースを定義
+//!
+//! 記録する内容は式と結果の文字列、入れ子の深さだけで、時刻などの実行ごとに変わる情報を含まない。
+//! そのため、同じスクリプトは常に同じトレースになり、期待するトレースと比較するテストに使える
+//!
+
+use crate::expression::Expression;
+use crate::observer::*;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// 式の評価の結果
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TraceResult {
+    Value(String), // 値が得られた。値を Display で出力した文字列
+    Error(String), // エラーになった。エラーを Debug で出力した文字列
+    Escape,        // break / continue / return / exit などによって、評価を途中で抜けた
+}
+
+/// 評価した 1 つの式の記録
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    pub depth: usize, // トップレベルの式を 0 とした、評価の入れ子の深さ
+    pub form: String, // 評価した式を Display で出力した文字列
+    pub result: TraceResult,
+}
+
+/// `Context::trace` で得られる、評価を始めた順に並んだ記録。
+///
+/// `Display` は 1 行に 1 つの式を、深さに応じてインデントして
+/// `式 => 値`、`式 !! エラー`、`式 -> escape` の形式で出力する
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            write!(f, "{:indent$}{}", "", entry.form, indent = entry.depth * 2)?;
+            match &entry.result {
+                TraceResult::Value(v) => writeln!(f, " => {}", v)?,
+                TraceResult::Error(e) => writeln!(f, " !! {}", e)?,
+                TraceResult::Escape => writeln!(f, " -> escape")?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// `EvalObserver` として評価の開始・終了の通知を受け取り、`Trace` を作る
+#[derive(Debug, Default)]
+pub struct TraceRecorder {
+    trace: Trace,
+    stack: Vec<usize>, // 評価中の式の、entries での位置
+}
+
+impl TraceRecorder {
+    /// `TraceRecorder` を新規作成
+    pub fn new() -> TraceRecorder {
+        return TraceRecorder::default();
+    }
+
+    /// これまでの記録
+    pub fn trace(&self) -> &Trace {
+        return &self.trace;
+    }
+
+    /// これまでの記録を取り出し、記録を空にする
+    pub fn take(&mut self) -> Trace {
+        return core::mem::take(&mut self.trace);
+    }
+}
+
+impl EvalObserver for TraceRecorder {
+    fn enter(&mut self, exp: &Expression, depth: usize) {
+        self.stack.push(self.trace.entries.len());
+        // 結果は exit で書き換える
+        self.trace.entries.push(TraceEntry {
+            depth,
+            form: exp.to_string(),
+            result: TraceResult::Escape,
+        });
+    }
+
+    fn exit(&mut self, _exp: &Expression, _depth: usize, result: EvalExit) {
+        let index = match self.stack.pop() {
+            Some(index) => index,
+            None => {
+                return;
+            }
+        };
+        if let Some(entry) = self.trace.entries.get_mut(index) {
+            entry.result = match result {
+                EvalExit::Value(v) => TraceResult::Value(v.to_string()),
+                EvalExit::Error(e) => TraceResult::Error(format!("{:?}", e)),
+                EvalExit::Escape => TraceResult::Escape,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::*;
+
+    #[test]
+    fn display_tests() {
+        let trace = Trace {
+            entries: vec![
+                TraceEntry {
+                    depth: 0,
+                    form: "(f)".into(),
+                    result: TraceResult::Error("BadArrity".into()),
+                },
+                TraceEntry {
+                    depth: 1,
+                    form: "(break)".into(),
+                    result: TraceResult::Escape,
+                },
+                TraceEntry {
+                    depth: 0,
+                    form: "\"s\"".into(),
+                    result: TraceResult::Value("\"s\"".into()),
+                },
+            ],
+        };
+        assert_eq!(
+            trace.to_string(),
+            "(f) !! BadArrity\n  (break) -> escape\n\"s\" => \"s\"\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_tests() {
+        let trace = Trace {
+            entries: vec![TraceEntry {
+                depth: 0,
+                form: "(add 1 2)".into(),
+                result: TraceResult::Value("3".into()),
+            }],
+        };
+        let json = serde_json::to_string(&trace).unwrap();
+        assert_eq!(serde_json::from_str::<Trace>(&json).unwrap(), trace);
+    }
+}
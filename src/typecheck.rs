@@ -0,0 +1,692 @@
+//!
+//! 型注釈と、評価前に型の誤りを見つける型検査を定義
+//!
+//! 関数の仮引数と戻り値、let で束縛する変数に、次のように型を注釈できる。注釈は省略でき、評価時には無視する
+//!
+//! ```lisp
+//! (defun f ((*x* : int) (*y* : int)) : int
+//!   (add *x* *y*))
+//! (let (((*s* : str) "a")) *s*)
+//! ```
+//!
+
+use crate::expression::*;
+use crate::visit::*;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// 型注釈で使える型。`Any` は注釈が無いなど、型が分からない値の型で、どの型とも適合する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Any,
+    Int,
+    Str,
+    Atom,
+    Keyword,
+    List,
+    Vector,
+}
+
+impl Ty {
+    /// 型注釈に書く名前から型を得る。知らない名前なら `None`
+    pub fn from_name(name: &str) -> Option<Ty> {
+        return match name {
+            "any" => Some(Ty::Any),
+            "int" => Some(Ty::Int),
+            "str" => Some(Ty::Str),
+            "atom" => Some(Ty::Atom),
+            "keyword" => Some(Ty::Keyword),
+            "list" => Some(Ty::List),
+            "vector" => Some(Ty::Vector),
+            _ => None,
+        };
+    }
+
+    /// この型を期待する位置に、actual 型の値を置けるなら true
+    pub fn accepts(self, actual: Ty) -> bool {
+        return self == Ty::Any || actual == Ty::Any || self == actual;
+    }
+
+    // 2 つの式のどちらかの値になる式の型
+    fn join(self, other: Ty) -> Ty {
+        if self == other {
+            return self;
+        }
+        return Ty::Any;
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Ty::Any => "any",
+            Ty::Int => "int",
+            Ty::Str => "str",
+            Ty::Atom => "atom",
+            Ty::Keyword => "keyword",
+            Ty::List => "list",
+            Ty::Vector => "vector",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+/// 型検査で見つかった誤り
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    Mismatch {
+        expected: Ty,
+        actual: Ty,
+        expression: Expression, // 型が合わなかった式
+    },
+    UnknownType(Expression), // 型注釈に、知らない型の名前が書かれている
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::Mismatch {
+                expected,
+                actual,
+                expression,
+            } => {
+                return write!(
+                    f,
+                    "expected {} but found {}: {}",
+                    expected, actual, expression
+                );
+            }
+            TypeError::UnknownType(t) => {
+                return write!(f, "unknown type {}", t);
+            }
+        }
+    }
+}
+
+/// (pattern : type) の形式の型注釈なら、pattern と type に分ける
+pub fn split_annotation(exp: &Expression) -> Option<(&Expression, &Expression)> {
+    if let Expression::ExpressionList(l) = exp {
+        if l.len() == 3 {
+            if let Some(Expression::Atom(a)) = l.tail().head() {
+                if &**a == ":" {
+                    return Some((l.head().unwrap(), l.tail().tail().head().unwrap()));
+                }
+            }
+        }
+    }
+    return None;
+}
+
+/// 1 つの式を検査する。`typecheck_program` に式を 1 つだけ渡した場合と同じ
+pub fn typecheck(exp: &Expression) -> Vec<TypeError> {
+    return typecheck_program(core::slice::from_ref(exp));
+}
+
+/// 複数の式からなるプログラムを検査し、見つかった誤りを出現順に返す。
+///
+/// 型の分かる式（リテラル、組み込み関数の呼び出し、型注釈のある関数の呼び出しと変数）について、
+/// 組み込み関数や型注釈のある関数の引数、注釈のある変数への代入、関数の戻り値の型が合うかを調べる。
+/// 型の分からない式は `Ty::Any` として扱い、誤りとはしない
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::parse_program;
+/// use liblisp::typecheck::{typecheck_program, Ty, TypeError};
+///
+/// let program = parse_program("(defun f ((*x* : int)) : int (add *x* 1)) (f \"a\")").unwrap();
+/// let errors = typecheck_program(&program);
+/// assert_eq!(errors.len(), 1);
+/// assert_eq!(errors[0].to_string(), "expected int but found str: \"a\"");
+/// ```
+pub fn typecheck_program(program: &[Expression]) -> Vec<TypeError> {
+    let mut checker = Checker::default();
+    for exp in program {
+        walk(exp, &mut checker);
+    }
+    checker.collecting = false;
+    for exp in program {
+        checker.infer(exp);
+    }
+    return checker.errors;
+}
+
+// 型注釈のある関数の型
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<Ty>, // 先頭から順の、&optional などより前の仮引数の型
+    ret: Ty,
+}
+
+// 1 回目に関数の型を集め（collecting が true）、2 回目に式の型を推論しながら検査する
+struct Checker {
+    collecting: bool,
+    signatures: BTreeMap<Rc<str>, Signature>,
+    scopes: Vec<BTreeMap<Rc<str>, Ty>>, // 型注釈のある変数の型。最後が最も内側
+    returns: Vec<Ty>,                   // 検査中の関数の戻り値の型。最後が最も内側
+    errors: Vec<TypeError>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        return Checker {
+            collecting: true,
+            signatures: BTreeMap::new(),
+            scopes: Vec::new(),
+            returns: Vec::new(),
+            errors: Vec::new(),
+        };
+    }
+}
+
+impl ExpressionVisitor for Checker {
+    fn enter(&mut self, exp: &Expression) -> bool {
+        if let Expression::ExpressionList(l) = exp {
+            let args = elements(l.tail());
+            match l.head() {
+                Some(Expression::Atom(a)) if &**a == "defun" => {
+                    if let Some(Expression::Atom(name)) = args.first() {
+                        let signature = self.signature(&args);
+                        self.signatures.insert(name.clone(), signature);
+                    }
+                }
+                // let の型注釈の型の名前は、ここで確かめておく
+                Some(Expression::Atom(a)) if &**a == "let" => {
+                    if let Some(Expression::ExpressionList(specs)) = args.first() {
+                        for spec in elements(specs) {
+                            if let Expression::ExpressionList(s) = spec {
+                                if let Some((_, t)) = s.head().and_then(split_annotation) {
+                                    self.annotation(t);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        return true;
+    }
+}
+
+impl Checker {
+    // (defun name (params ...) [: type] body ...) の引数から、関数の型を得る
+    fn signature(&mut self, args: &[&Expression]) -> Signature {
+        let mut params = Vec::new();
+        if let Some(Expression::ExpressionList(ps)) = args.get(1) {
+            for p in elements(ps) {
+                if let Expression::Atom(a) = p {
+                    if a.starts_with('&') {
+                        break;
+                    }
+                }
+                let ty = match split_annotation(p) {
+                    Some((_, t)) => self.annotation(t),
+                    None => Ty::Any,
+                };
+                params.push(ty);
+            }
+        }
+        let ret = match (args.get(2), args.get(3)) {
+            (Some(Expression::Atom(a)), Some(t)) if &**a == ":" => self.annotation(t),
+            _ => Ty::Any,
+        };
+        return Signature { params, ret };
+    }
+
+    // 型注釈の型。知らない型の名前なら、1 回目にだけ報告して Any とする
+    fn annotation(&mut self, t: &Expression) -> Ty {
+        if let Expression::Atom(name) = t {
+            if let Some(ty) = Ty::from_name(name) {
+                return ty;
+            }
+        }
+        if self.collecting {
+            self.errors.push(TypeError::UnknownType(t.clone()));
+        }
+        return Ty::Any;
+    }
+
+    // 式の型を推論し、その過程で見つかった誤りを記録する
+    fn infer(&mut self, exp: &Expression) -> Ty {
+        match exp {
+            Expression::Int(_) => {
+                return Ty::Int;
+            }
+            Expression::Str(_) => {
+                return Ty::Str;
+            }
+            Expression::Atom(_) => {
+                return Ty::Atom;
+            }
+            Expression::Keyword(_) => {
+                return Ty::Keyword;
+            }
+            Expression::Var(v) => {
+                return self.lookup(v);
+            }
+            Expression::ExpressionList(l) => {
+                if let Some(Expression::Atom(name)) = l.head() {
+                    return self.infer_call(exp, name, elements(l.tail()));
+                }
+                self.infer_children(exp);
+                return Ty::Any;
+            }
+        }
+    }
+
+    // (name args ...) の型を推論する
+    fn infer_call(&mut self, exp: &Expression, name: &str, args: Vec<&Expression>) -> Ty {
+        // defun で定義した関数は、同じ名前の組み込み関数より優先される
+        if let Some(signature) = self.signatures.get(name).cloned() {
+            self.expect_all(&signature.params, &args);
+            return signature.ret;
+        }
+        match name {
+            "quote" => {
+                return match args.first() {
+                    Some(Expression::Int(_)) => Ty::Int,
+                    Some(Expression::Str(_)) => Ty::Str,
+                    Some(Expression::Atom(_) | Expression::Var(_)) => Ty::Atom,
+                    Some(Expression::Keyword(_)) => Ty::Keyword,
+                    Some(Expression::ExpressionList(_)) => Ty::List,
+                    None => Ty::Any,
+                };
+            }
+            "add" | "sub" | "mul" => {
+                let tys: Vec<Ty> = args.iter().map(|a| self.expect(Ty::Int, a)).collect();
+                if tys.iter().all(|t| *t == Ty::Int) {
+                    return Ty::Int;
+                }
+                return Ty::Any;
+            }
+            "div" => {
+                self.expect_all(&[Ty::Int, Ty::Int], &args);
+                return Ty::Any;
+            }
+            "gt" | "lt" | "ge" | "le" => {
+                self.expect_all(&[Ty::Int, Ty::Int], &args);
+                return Ty::Int;
+            }
+            "eq" | "ne" | "equal" | "intp" | "atomp" | "listp" | "nullp" | "vectorp" => {
+                self.expect_all(&[], &args);
+                return Ty::Int;
+            }
+            "list" => {
+                self.expect_all(&[], &args);
+                return Ty::List;
+            }
+            "vector" => {
+                self.expect_all(&[], &args);
+                return Ty::Vector;
+            }
+            "head" => {
+                self.expect_all(&[Ty::List], &args);
+                return Ty::Any;
+            }
+            "tail" => {
+                self.expect_all(&[Ty::List], &args);
+                return Ty::List;
+            }
+            "list->vector" => {
+                self.expect_all(&[Ty::List], &args);
+                return Ty::Vector;
+            }
+            "vector->list" => {
+                self.expect_all(&[Ty::Vector], &args);
+                return Ty::List;
+            }
+            "vlen" => {
+                self.expect_all(&[Ty::Vector], &args);
+                return Ty::Int;
+            }
+            "vref" => {
+                self.expect_all(&[Ty::Vector, Ty::Int], &args);
+                return Ty::Any;
+            }
+            "vset" => {
+                self.expect_all(&[Ty::Vector, Ty::Int], &args);
+                return Ty::Vector;
+            }
+            "str-split" => {
+                self.expect_all(&[Ty::Str, Ty::Str], &args);
+                return Ty::List;
+            }
+            "str-join" => {
+                self.expect_all(&[Ty::List, Ty::Str], &args);
+                return Ty::Str;
+            }
+            "str-upper" | "str-lower" | "str-trim" => {
+                self.expect_all(&[Ty::Str], &args);
+                return Ty::Str;
+            }
+            "str-contains" => {
+                self.expect_all(&[Ty::Str, Ty::Str], &args);
+                return Ty::Int;
+            }
+            "to-string" => {
+                self.expect_all(&[], &args);
+                return Ty::Str;
+            }
+            "parse-int" => {
+                self.expect_all(&[Ty::Str], &args);
+                return Ty::Int;
+            }
+            "range" => {
+                self.expect_all(&[Ty::Int, Ty::Int, Ty::Int], &args);
+                return Ty::List;
+            }
+            "zip" => {
+                let tys: Vec<Ty> = args.iter().map(|_| Ty::List).collect();
+                self.expect_all(&tys, &args);
+                return Ty::List;
+            }
+            "enumerate" => {
+                self.expect_all(&[Ty::List], &args);
+                return Ty::List;
+            }
+            "take" | "drop" => {
+                self.expect_all(&[Ty::Int, Ty::List], &args);
+                return Ty::List;
+            }
+            "parse" => {
+                self.expect_all(&[Ty::Str], &args);
+                return Ty::Any;
+            }
+            "unparse" => {
+                self.expect_all(&[], &args);
+                return Ty::Str;
+            }
+            "sort" => {
+                self.expect_all(&[Ty::List, Ty::Atom], &args);
+                return Ty::List;
+            }
+            "member" => {
+                self.expect_all(&[Ty::Any, Ty::List], &args);
+                return Ty::List;
+            }
+            "find" | "position" => {
+                self.expect_all(&[Ty::Atom, Ty::List], &args);
+                return Ty::Any;
+            }
+            "count" => {
+                self.expect_all(&[Ty::Atom, Ty::List], &args);
+                return Ty::Int;
+            }
+            "cond" => {
+                // 条件はどの型の値でもよい（Type::is_truthy で真偽を判定する）
+                if let [test, ok, ng] = &args[..] {
+                    self.infer(test);
+                    let ok = self.infer(ok);
+                    let ng = self.infer(ng);
+                    return ok.join(ng);
+                }
+            }
+            "progn" => {
+                let mut res = Ty::Any;
+                for a in args {
+                    res = self.infer(a);
+                }
+                return res;
+            }
+            "set" | "define" => {
+                let mut res = Ty::Any;
+                for pair in args.chunks(2) {
+                    if let [Expression::Var(v), value] = pair {
+                        res = self.expect(self.lookup(v), value);
+                    }
+                }
+                return res;
+            }
+            "let" => {
+                return self.infer_let(exp, &args);
+            }
+            "defun" => {
+                self.infer_defun(&args);
+                return Ty::Atom;
+            }
+            "return" => {
+                let ret = self.returns.last().copied().unwrap_or(Ty::Any);
+                self.expect_all(&[ret], &args);
+                return Ty::Any;
+            }
+            _ => {}
+        }
+        self.infer_children(exp);
+        return Ty::Any;
+    }
+
+    // (let ((pattern init) ...) body ...) の型を推論する。型注釈のある変数だけを型とともに束縛する
+    fn infer_let(&mut self, exp: &Expression, args: &[&Expression]) -> Ty {
+        let specs = match args.first() {
+            Some(Expression::ExpressionList(specs)) => elements(specs),
+            _ => {
+                self.infer_children(exp);
+                return Ty::Any;
+            }
+        };
+        let mut scope = BTreeMap::new();
+        for spec in specs {
+            let (pattern, init) = match spec {
+                Expression::ExpressionList(s) if s.len() == 2 => {
+                    (s.head().unwrap(), s.tail().head().unwrap())
+                }
+                _ => {
+                    continue;
+                }
+            };
+            match split_annotation(pattern) {
+                Some((Expression::Var(v), t)) => {
+                    let ty = self.annotation(t);
+                    self.expect(ty, init);
+                    scope.insert(v.clone(), ty);
+                }
+                _ => {
+                    self.infer(init);
+                }
+            }
+        }
+        self.scopes.push(scope);
+        let mut res = Ty::Any;
+        for body in &args[1..] {
+            res = self.infer(body);
+        }
+        self.scopes.pop();
+        return res;
+    }
+
+    // (defun name (params ...) [: type] body ...) の本体を、仮引数の型のもとで検査する
+    fn infer_defun(&mut self, args: &[&Expression]) {
+        let signature = self.signature(args);
+        let mut scope = BTreeMap::new();
+        if let Some(Expression::ExpressionList(ps)) = args.get(1) {
+            for p in elements(ps) {
+                if let Some((Expression::Var(v), t)) = split_annotation(p) {
+                    let ty = self.annotation(t);
+                    scope.insert(v.clone(), ty);
+                }
+            }
+        }
+        let annotated = matches!(args.get(2), Some(Expression::Atom(a)) if &**a == ":");
+        let body = if annotated { 4 } else { 2 };
+
+        self.scopes.push(scope);
+        self.returns.push(signature.ret);
+        let mut last = None;
+        for e in args.iter().skip(body) {
+            last = Some((self.infer(e), *e));
+        }
+        if let Some((actual, e)) = last {
+            // 最後の式が return なら、return の引数で検査済み
+            if !signature.ret.accepts(actual) {
+                self.errors.push(TypeError::Mismatch {
+                    expected: signature.ret,
+                    actual,
+                    expression: e.clone(),
+                });
+            }
+        }
+        self.returns.pop();
+        self.scopes.pop();
+    }
+
+    // 評価される位置の部分式の型を推論する
+    fn infer_children(&mut self, exp: &Expression) {
+        for_each_child(exp, |child, role| {
+            if role == Role::Code {
+                self.infer(child);
+            }
+        });
+    }
+
+    // 式の型を推論し、expected 型と合わなければ記録する。推論した型を返す
+    fn expect(&mut self, expected: Ty, exp: &Expression) -> Ty {
+        let actual = self.infer(exp);
+        if !expected.accepts(actual) {
+            self.errors.push(TypeError::Mismatch {
+                expected,
+                actual,
+                expression: exp.clone(),
+            });
+        }
+        return actual;
+    }
+
+    // 引数を先頭から順に、対応する型と合うか検査する。対応する型の無い引数は推論だけ行う
+    fn expect_all(&mut self, expected: &[Ty], args: &[&Expression]) {
+        for (i, a) in args.iter().enumerate() {
+            let ty = expected.get(i).copied().unwrap_or(Ty::Any);
+            self.expect(ty, a);
+        }
+    }
+
+    // 型注釈のある変数なら、その型
+    fn lookup(&self, v: &str) -> Ty {
+        for scope in self.scopes.iter().rev() {
+            if let Some(ty) = scope.get(v) {
+                return *ty;
+            }
+        }
+        return Ty::Any;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::*;
+    use crate::typecheck::*;
+    use alloc::string::{String, ToString};
+    use core::convert::TryFrom;
+
+    fn errors(src: &str) -> Vec<String> {
+        let program = parse_program(src).unwrap();
+        return typecheck_program(&program)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+    }
+
+    #[test]
+    fn typecheck_tests() {
+        let defs = "(defun f ((*x* : int) (*y* : int)) : int (add *x* *y*)) \
+                    (defun name ((*s* : str)) : str *s*) ";
+        let clean = vec![
+            "(f 1 2)",
+            "(f (f 1 2) (head (list 1)))",
+            "(name \"a\")",
+            "(let (((*a* : int) 1)) (f *a* *a*))",
+            "(defun g (*x*) (add *x* (head *x*)))",
+            "(defun h ((*l* : list) &optional *n*) : int (cond (nullp *l*) (return 0) 1))",
+            "(defun k ((*x* : int)) : list (progn (set *x* 2) (list *x*)))",
+        ];
+        for src in clean {
+            let src = format!("{}{}", defs, src);
+            assert_eq!(errors(&src), Vec::<String>::new(), "{}", src);
+        }
+
+        let cases = vec![
+            ("(f \"a\" 1)", vec!["expected int but found str: \"a\""]),
+            (
+                "(f 1 (name \"a\"))",
+                vec!["expected int but found str: (name \"a\")"],
+            ),
+            (
+                "(name (f 1 2))",
+                vec!["expected str but found int: (f 1 2)"],
+            ),
+            (
+                "(add 1 (quote a))",
+                vec!["expected int but found atom: (quote a)"],
+            ),
+            // 型注釈の無い関数の中でも、組み込み関数の引数は検査する
+            (
+                "(defun g (*x*) (add *x* \"a\"))",
+                vec!["expected int but found str: \"a\""],
+            ),
+            (
+                "(vlen (list 1))",
+                vec!["expected vector but found list: (list 1)"],
+            ),
+            (
+                "(cond (add 1 \"s\") 1 2)",
+                vec!["expected int but found str: \"s\""],
+            ),
+            (
+                "(let (((*a* : str) 1)) *a*)",
+                vec!["expected str but found int: 1"],
+            ),
+            (
+                "(let (((*a* : str) \"s\")) (set *a* 1))",
+                vec!["expected str but found int: 1"],
+            ),
+            (
+                "(defun g ((*x* : int)) : str (add *x* 1))",
+                vec!["expected str but found int: (add *x* 1)"],
+            ),
+            (
+                "(defun g ((*x* : int)) : str (progn (return *x*) \"s\"))",
+                vec!["expected str but found int: *x*"],
+            ),
+            (
+                "(defun g ((*x* : integer)) 1)",
+                vec!["unknown type integer"],
+            ),
+            ("(let (((*a* : text) 1)) *a*)", vec!["unknown type text"]),
+            // 入れ子の式も検査する
+            (
+                "(dolist (*e* (list 1)) (f *e* \"a\"))",
+                vec!["expected int but found str: \"a\""],
+            ),
+        ];
+        for (src, expected) in cases {
+            let src = format!("{}{}", defs, src);
+            assert_eq!(errors(&src), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn annotation_eval_tests() {
+        // 型注釈は評価時には無視する
+        let cases = vec![
+            (
+                "(progn (defun f ((*x* : int) (*y* : int)) : int (add *x* *y*)) (f 1 2))",
+                "3",
+            ),
+            ("(progn (defun f ((*x* : int)) (mul *x* 2)) (f 4))", "8"),
+            (
+                "(let (((*a* : str) \"s\") (*b* 1)) (list *a* *b*))",
+                "(\"s\" 1)",
+            ),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            assert_eq!(
+                eval(&exp).map(|t| t.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                src
+            );
+        }
+    }
+}
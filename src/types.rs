@@ -2,16 +2,520 @@
 //! Lisp の型に関する定義
 //!
 
+use crate::eval::EvalError;
+use crate::lexer::write_escaped;
+use crate::opaque::Opaque;
 use crate::util::*;
-use std::rc::Rc;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
 
-pub type TypeList<'a> = List<Type<'a>>;
+pub type TypeList = List<Type>;
+
+/// `Int` と `Ratio` に使う整数の型。cargo feature の `int32`・`int64`・`int128` で幅を選ぶ。
+/// 複数を有効にした場合は最も広いものを使い、どれも有効にしない場合は 64 ビット（`i64`）とする
+#[cfg(feature = "int128")]
+pub type Int = i128;
+#[cfg(all(
+    not(feature = "int128"),
+    any(feature = "int64", not(feature = "int32"))
+))]
+pub type Int = i64;
+#[cfg(all(feature = "int32", not(feature = "int64"), not(feature = "int128")))]
+pub type Int = i32;
 
 /// Lispの型一覧
-#[derive(Debug, Clone, PartialEq)]
-pub enum Type<'a> {
-    Int(i32),
-    Atom(&'a str),
-    TypeList(Rc<TypeList<'a>>),
+///
+/// 異なる種類の値の大小は、`Int` < `BigInt` < `Ratio` < `BigRatio` < `Atom` < `Str` < `Keyword` < `TypeList` < `Vector` < `Function` < `Opaque` < `Void` の順とする。
+/// この順序は構造に基づくもので、数値の種類が異なる場合は数値の大小と一致しない。
+/// 同じ種類の値は、`Int` は数値の大小、`Atom` と `Str` と `Keyword` と `Function` は文字列の辞書式順序、`TypeList` と `Vector` は要素の辞書式順序で比較する。
+/// `Opaque` はアドレスで比較する。
+/// `Hash` は `Opaque` を除いて構造に対して決定的なので、ホスト側の `HashMap` や `BTreeMap` のキーとして使える
+///
+/// 値は作成後に変更されない。`vset` のような更新は、共有されている部分を複製した新しい値を作る。
+/// そのため、値は作成済みの値しか参照できず、`Opaque` を経由しない限り `Rc` による参照は循環しない。
+/// `Opaque` の中身がホスト側で `Type` を持つ場合は循環することがあり、循環した値は解放されない（`Opaque` を参照）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Type {
+    Int(Int),
+    #[cfg(feature = "bignum")]
+    BigInt(Rc<num_bigint::BigInt>), // Int に収まらない整数。Int に収まる値は常に Int で表す
+    Ratio(Int, Int), // 分数（分子, 分母）。常に既約で、分母は 2 以上。整数になる値は常に Int で表す
+    #[cfg(feature = "bignum")]
+    BigRatio(Rc<(num_bigint::BigInt, num_bigint::BigInt)>), // 分子か分母が Int に収まらない分数。Ratio で表せる値は常に Ratio で表す
+    Atom(Rc<str>),
+    Str(Rc<str>),
+    Keyword(Rc<str>), // :x の形式の、評価すると自分自身になる定数。名前は先頭の : を除いて持つ
+    TypeList(Rc<TypeList>),
+    Vector(Rc<Vec<Type>>), // 添字で O(1) でアクセスできる配列。要素の変更は複製を作って行う
+    Function(Rc<str>), // (function f) もしくは #'f で得た、関数への参照。関数の名前を持ち、呼び出す時点の定義を使う
+    Opaque(Opaque), // ホスト側の値への参照。Lisp からは send でメソッドを呼び出すことだけができる
+    // 値が無いことを表す。while や空の progn の結果、(void) で作る。
+    // 文の位置（progn の途中の式や、ループの本体など）で捨てられることを想定し、
+    // 数値の演算や大小の比較の引数にすると EvalError::VoidValue になる。真偽は偽とする
     Void,
 }
+
+impl Type {
+    /// 値の種類の名前。`Type::Int(1)` なら `"Int"` のように、バリアントの名前を返す。エラーのメッセージに使う
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Type::Int(_) => "Int",
+            #[cfg(feature = "bignum")]
+            Type::BigInt(_) => "BigInt",
+            Type::Ratio(_, _) => "Ratio",
+            #[cfg(feature = "bignum")]
+            Type::BigRatio(_) => "BigRatio",
+            Type::Atom(_) => "Atom",
+            Type::Str(_) => "Str",
+            Type::Keyword(_) => "Keyword",
+            Type::TypeList(_) => "TypeList",
+            Type::Vector(_) => "Vector",
+            Type::Function(_) => "Function",
+            Type::Opaque(_) => "Opaque",
+            Type::Void => "Void",
+        }
+    }
+
+    /// 条件として真かどうか。`cond`、`while`、`and`、`or` などの条件はすべてこれで判定する。
+    /// `Int(0)`、`Void`、空のリストが偽で、それ以外はすべて真
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Type::Int(0) | Type::Void => {
+                return false;
+            }
+            Type::TypeList(l) => {
+                return !l.is_empty();
+            }
+            _ => {
+                return true;
+            }
+        }
+    }
+
+    /// `Int` なら、その値を返す
+    pub fn as_int(&self) -> Option<Int> {
+        if let Type::Int(i) = self {
+            return Some(*i);
+        }
+        return None;
+    }
+
+    /// 関数として呼び出せる値なら、その関数の名前を返す。
+    /// `(function f)` で得た `Function` と、関数名として扱う `Atom` が該当する
+    pub fn as_function_name(&self) -> Option<&str> {
+        match self {
+            Type::Function(name) | Type::Atom(name) => {
+                return Some(name);
+            }
+            _ => {
+                return None;
+            }
+        }
+    }
+
+    /// `Atom` なら、その名前を返す
+    pub fn as_atom(&self) -> Option<&str> {
+        if let Type::Atom(a) = self {
+            return Some(a);
+        }
+        return None;
+    }
+
+    /// `Str` なら、その文字列を返す
+    pub fn as_str(&self) -> Option<&str> {
+        if let Type::Str(s) = self {
+            return Some(s);
+        }
+        return None;
+    }
+
+    /// `Keyword` なら、先頭の : を除いた名前を返す
+    pub fn as_keyword(&self) -> Option<&str> {
+        if let Type::Keyword(k) = self {
+            return Some(k);
+        }
+        return None;
+    }
+
+    /// `TypeList` なら、そのリストを返す
+    pub fn as_list(&self) -> Option<&TypeList> {
+        if let Type::TypeList(l) = self {
+            return Some(l);
+        }
+        return None;
+    }
+
+    /// `Vector` なら、その要素を返す
+    pub fn as_vector(&self) -> Option<&[Type]> {
+        if let Type::Vector(v) = self {
+            return Some(v);
+        }
+        return None;
+    }
+
+    /// `Opaque` で、中身が `T` なら、その参照を返す
+    pub fn as_opaque<T: core::any::Any>(&self) -> Option<&T> {
+        if let Type::Opaque(o) = self {
+            return o.downcast_ref::<T>();
+        }
+        return None;
+    }
+
+    /// `as_int` と同様。`Int` でなければ `EvalError::TypeMismatch`
+    pub fn expect_int(&self) -> Result<Int, EvalError> {
+        return self.as_int().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// `as_atom` と同様。`Atom` でなければ `EvalError::TypeMismatch`
+    pub fn expect_atom(&self) -> Result<&str, EvalError> {
+        return self.as_atom().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// `as_str` と同様。`Str` でなければ `EvalError::TypeMismatch`
+    pub fn expect_str(&self) -> Result<&str, EvalError> {
+        return self.as_str().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// `as_keyword` と同様。`Keyword` でなければ `EvalError::TypeMismatch`
+    pub fn expect_keyword(&self) -> Result<&str, EvalError> {
+        return self.as_keyword().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// `as_list` と同様。`TypeList` でなければ `EvalError::TypeMismatch`
+    pub fn expect_list(&self) -> Result<&TypeList, EvalError> {
+        return self.as_list().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// `as_vector` と同様。`Vector` でなければ `EvalError::TypeMismatch`
+    pub fn expect_vector(&self) -> Result<&[Type], EvalError> {
+        return self.as_vector().ok_or(EvalError::TypeMismatch);
+    }
+
+    /// Lisp の `equal` と同じ、構造による比較を行う。
+    ///
+    /// リストと `Vector` は要素ごとに再帰的に比較し、それ以外は値を比較する。
+    /// 数値は常に正規化されている（整数になる `Ratio` や i32 に収まる `BigInt` 、`Ratio` で表せる `BigRatio` は作られない）ので、
+    /// 種類の異なる数値が等しくなることはない。種類が異なる値は、リストと `Vector` も含めて等しくない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::eval::eval;
+    /// use liblisp::expression::Expression;
+    /// use std::convert::TryFrom;
+    ///
+    /// let run = |src: &str| eval(&Expression::try_from(src.as_bytes()).unwrap()).unwrap();
+    /// let a = run("(list 1 (list :k \"s\"))");
+    /// assert!(a.deep_eq(&run("(list 1 (list :k \"s\"))")));
+    /// assert!(!a.deep_eq(&run("(list->vector (list 1 (list :k \"s\")))")));
+    /// ```
+    pub fn deep_eq(&self, other: &Type) -> bool {
+        return self == other;
+    }
+
+    /// REPL やコマンドラインツールで、評価結果として利用者に見せる文字列。
+    /// `Str` は `"` で囲まずに中身をそのまま、`Void` は空文字列、それ以外は `Display` と同じにする。
+    /// リストや `Vector` の中の `Str` は、要素の区切りが分かるよう `"` で囲む
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::types::Type;
+    ///
+    /// assert_eq!(Type::Str("a b".into()).to_display_string(), "a b");
+    /// assert_eq!(Type::Vector(vec![Type::Str("a".into())].into()).to_display_string(), "#(\"a\")");
+    /// assert_eq!(Type::Void.to_display_string(), "");
+    /// ```
+    pub fn to_display_string(&self) -> String {
+        if let Type::Str(s) = self {
+            return String::from(&**s);
+        }
+        return self.to_string();
+    }
+
+    /// プログラムの最後の値を、プロセスの終了コードに変換する。
+    /// 整数は OS と同じく下位 8 ビットを符号なしで取り出した値（`-1` は 255、`256` は 0）、
+    /// `Void` を含むそれ以外の値は 0 とする。
+    /// 評価がエラーになった場合の終了コードは `EvalError::exit_code` で得られる
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Type::Int(i) => {
+                return i32::from(*i as u8);
+            }
+            #[cfg(feature = "bignum")]
+            Type::BigInt(i) => {
+                // 2 の補数表現の最下位バイト
+                return i32::from(i.to_signed_bytes_le()[0]);
+            }
+            _ => {
+                return 0;
+            }
+        }
+    }
+}
+
+// Int を各整数型として取り出す TryFrom を実装する。Int と同じ型の場合は try_from が同じ型への変換になる
+macro_rules! impl_try_from_type_for_int {
+    ($($t:ty),*) => {
+        $(
+            /// `Int` を取り出す。`Int` でなければ `EvalError::TypeMismatch`、この型に収まらなければ `EvalError::IntOverflow`
+            impl TryFrom<Type> for $t {
+                type Error = EvalError;
+                #[allow(clippy::useless_conversion)]
+                fn try_from(t: Type) -> Result<$t, EvalError> {
+                    return <$t>::try_from(t.expect_int()?).map_err(|_| EvalError::IntOverflow);
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_type_for_int!(i32, i64, i128);
+
+/// `Str` の文字列を取り出す。`Str` でなければ `EvalError::TypeMismatch`
+impl TryFrom<Type> for String {
+    type Error = EvalError;
+    fn try_from(t: Type) -> Result<String, EvalError> {
+        return t.expect_str().map(String::from);
+    }
+}
+
+/// `TypeList` もしくは `Vector` の要素を、先頭から順に取り出す。それ以外なら `EvalError::TypeMismatch`
+impl TryFrom<Type> for Vec<Type> {
+    type Error = EvalError;
+    fn try_from(t: Type) -> Result<Vec<Type>, EvalError> {
+        match t {
+            Type::TypeList(l) => {
+                return Ok(l.iter().cloned().collect());
+            }
+            Type::Vector(v) => {
+                return Ok((*v).clone());
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch);
+            }
+        }
+    }
+}
+
+/// 評価結果を、人が読むための文字列として出力する。`Void` は何も出力しないが、
+/// リストや `Vector` の要素の `Void` は、要素があることが分かるよう `#<void>` と出力する
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int(i) => {
+                return write!(f, "{}", i);
+            }
+            #[cfg(feature = "bignum")]
+            Type::BigInt(i) => {
+                return write!(f, "{}", i);
+            }
+            Type::Ratio(n, d) => {
+                return write!(f, "{}/{}", n, d);
+            }
+            #[cfg(feature = "bignum")]
+            Type::BigRatio(r) => {
+                return write!(f, "{}/{}", r.0, r.1);
+            }
+            Type::Atom(a) => {
+                return write!(f, "{}", a);
+            }
+            Type::Str(s) => {
+                return write_escaped(f, s);
+            }
+            Type::Keyword(k) => {
+                return write!(f, ":{}", k);
+            }
+            Type::TypeList(l) => {
+                write!(f, "(")?;
+                for (i, t) in l.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write_element(f, t)?;
+                }
+                return write!(f, ")");
+            }
+            Type::Vector(v) => {
+                write!(f, "#(")?;
+                for (i, t) in v.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ")?;
+                    }
+                    write_element(f, t)?;
+                }
+                return write!(f, ")");
+            }
+            Type::Function(name) => {
+                return write!(f, "#'{}", name);
+            }
+            Type::Opaque(o) => {
+                return write!(f, "{}", o);
+            }
+            Type::Void => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// リストや Vector の要素を出力する。Void は #<void> とする
+fn write_element(f: &mut fmt::Formatter, t: &Type) -> fmt::Result {
+    if let Type::Void = t {
+        return write!(f, "#<void>");
+    }
+    return write!(f, "{}", t);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::*;
+
+    #[test]
+    fn ordering_tests() {
+        use alloc::collections::BTreeSet;
+        use std::collections::HashSet;
+
+        let list = |v: &[Type]| {
+            let l = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+            return Type::TypeList(Rc::new(l));
+        };
+        // 種類ごとの順序
+        let ordered = [
+            Type::Int(-1),
+            Type::Int(2),
+            Type::Atom("a".into()),
+            Type::Atom("b".into()),
+            Type::Str("a".into()),
+            Type::Keyword("a".into()),
+            list(&[]),
+            list(&[Type::Int(1)]),
+            list(&[Type::Int(1), Type::Int(0)]),
+            list(&[Type::Int(2)]),
+            list(&[Type::Atom("a".into())]),
+            Type::Vector(Rc::new(vec![])),
+            Type::Vector(Rc::new(vec![Type::Int(1)])),
+            Type::Void,
+        ];
+        for w in ordered.windows(2) {
+            assert!(w[0] < w[1], "{:?} < {:?}", w[0], w[1]);
+        }
+        let set: BTreeSet<Type> = ordered.iter().rev().cloned().collect();
+        assert!(set.into_iter().eq(ordered.iter().cloned()));
+
+        // 同じ構造の値は、別々に作っても同じキーとして扱われる
+        let mut hs = HashSet::new();
+        hs.insert(list(&[Type::Int(1), Type::Str("x".into())]));
+        assert!(hs.contains(&list(&[Type::Int(1), Type::Str("x".into())])));
+        assert!(!hs.contains(&list(&[Type::Int(1), Type::Atom("x".into())])));
+    }
+
+    #[test]
+    fn extraction_tests() {
+        let list = TypeList::new().cons(&Type::Int(2)).cons(&Type::Int(1));
+        let list = Type::TypeList(Rc::new(list));
+        let vector = Type::Vector(Rc::new(vec![Type::Int(1), Type::Int(2)]));
+
+        assert_eq!(Type::Int(1).as_int(), Some(1));
+        assert_eq!(Type::Atom("a".into()).as_int(), None);
+        assert_eq!(Type::Atom("a".into()).as_atom(), Some("a"));
+        assert_eq!(Type::Str("s".into()).as_str(), Some("s"));
+        assert_eq!(Type::Str("s".into()).as_atom(), None);
+        assert_eq!(Type::Keyword("k".into()).as_keyword(), Some("k"));
+        assert_eq!(list.as_list().map(|l| l.len()), Some(2));
+        assert_eq!(vector.as_vector(), Some(&[Type::Int(1), Type::Int(2)][..]));
+
+        assert_eq!(Type::Int(1).expect_int(), Ok(1));
+        assert_eq!(Type::Void.expect_int(), Err(EvalError::TypeMismatch));
+        assert_eq!(Type::Int(1).expect_str(), Err(EvalError::TypeMismatch));
+        assert_eq!(Type::Int(1).expect_list(), Err(EvalError::TypeMismatch));
+
+        assert_eq!(i32::try_from(Type::Int(3)), Ok(3));
+        assert_eq!(
+            i32::try_from(Type::Ratio(1, 2)),
+            Err(EvalError::TypeMismatch)
+        );
+        assert_eq!(String::try_from(Type::Str("s".into())), Ok("s".into()));
+        assert_eq!(
+            String::try_from(Type::Atom("s".into())),
+            Err(EvalError::TypeMismatch)
+        );
+        let expected = vec![Type::Int(1), Type::Int(2)];
+        assert_eq!(Vec::<Type>::try_from(list), Ok(expected.clone()));
+        assert_eq!(Vec::<Type>::try_from(vector), Ok(expected));
+        assert_eq!(
+            Vec::<Type>::try_from(Type::Int(1)),
+            Err(EvalError::TypeMismatch)
+        );
+    }
+
+    #[test]
+    fn display_tests() {
+        let list = TypeList::new()
+            .cons(&Type::Str("s".into()))
+            .cons(&Type::TypeList(Rc::new(TypeList::new())))
+            .cons(&Type::Atom("a".into()))
+            .cons(&Type::Int(1));
+        assert_eq!(Type::TypeList(Rc::new(list)).to_string(), "(1 a () \"s\")");
+        assert_eq!(Type::Void.to_string(), "");
+        assert_eq!(Type::Keyword("x".into()).to_string(), ":x");
+        assert_eq!(Type::Ratio(-1, 3).to_string(), "-1/3");
+        let vector = Type::Vector(Rc::new(vec![Type::Int(1), Type::Atom("a".into())]));
+        assert_eq!(vector.to_string(), "#(1 a)");
+        let vector = Type::Vector(Rc::new(vec![Type::Void, Type::Int(1)]));
+        assert_eq!(vector.to_string(), "#(#<void> 1)");
+        // 文字列はエスケープして出力する。to_display_string はそのまま
+        let s = Type::Str("a \"b\"\n\\".into());
+        assert_eq!(s.to_string(), r#""a \"b\"\n\\""#);
+        assert_eq!(s.to_display_string(), "a \"b\"\n\\");
+    }
+
+    #[test]
+    fn deep_eq_tests() {
+        let list = |v: &[Type]| {
+            let l = v.iter().rev().fold(TypeList::new(), |acc, t| acc.cons(t));
+            return Type::TypeList(Rc::new(l));
+        };
+        let a = list(&[Type::Int(1), list(&[Type::Str("s".into())])]);
+        let b = list(&[Type::Int(1), list(&[Type::Str("s".into())])]);
+        assert!(a.deep_eq(&b));
+        assert!(!a.deep_eq(&list(&[Type::Int(1), list(&[Type::Atom("s".into())])])));
+        assert!(list(&[]).deep_eq(&list(&[])));
+        assert!(!list(&[Type::Int(1)]).deep_eq(&Type::Vector(Rc::new(vec![Type::Int(1)]))));
+        assert!(!Type::Int(1).deep_eq(&Type::Ratio(1, 2)));
+    }
+
+    #[test]
+    fn display_string_tests() {
+        let list = TypeList::new()
+            .cons(&Type::Str(Rc::from("b")))
+            .cons(&Type::Int(1));
+        let cases = vec![
+            (Type::Int(-3), "-3", 253),
+            (Type::Int(3), "3", 3),
+            (Type::Int(256), "256", 0),
+            (Type::Int(257), "257", 1),
+            (Type::Ratio(1, 2), "1/2", 0),
+            (Type::Str(Rc::from("a \"b\"")), "a \"b\"", 0),
+            (Type::Keyword(Rc::from("k")), ":k", 0),
+            (Type::TypeList(Rc::new(list)), "(1 \"b\")", 0),
+            (Type::Void, "", 0),
+        ];
+        for (t, display, code) in cases {
+            assert_eq!(t.to_display_string(), display, "{:?}", t);
+            assert_eq!(t.exit_code(), code, "{:?}", t);
+        }
+        // 終了コードは、i32 に収まらない整数も下位 8 ビットを取り出す
+        assert_eq!(Type::Int(Int::MAX).exit_code(), 255);
+        assert_eq!(Type::Int(Int::MIN).exit_code(), 0);
+        assert_eq!(Type::Int(Int::MIN + 1).exit_code(), 1);
+        #[cfg(feature = "bignum")]
+        {
+            let big: num_bigint::BigInt = num_bigint::BigInt::from(Int::MAX) * 256 + 7;
+            assert_eq!(Type::BigInt(Rc::new(big.clone())).exit_code(), 7);
+            assert_eq!(Type::BigInt(Rc::new(-big)).exit_code(), 249);
+        }
+    }
+}
@@ -2,10 +2,12 @@
 //! ライブラリ全体で用いる util な物を定義
 //!
 
-use std::rc::Rc;
+use alloc::rc::Rc;
+use core::cmp::Ordering;
 
 /// 連結リスト
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum List<T: Clone> {
     Cons(T, Rc<Self>),
     Nil,
@@ -40,6 +42,77 @@ impl<T: Clone> IntoIterator for List<T> {
     }
 }
 
+/// `List<T>` の要素を、複製せずに参照で先頭から順に返すイテレータ。`List::iter` で作る
+pub struct Iter<'a, T: Clone> {
+    list: &'a List<T>,
+}
+
+impl<'a, T: Clone> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.list {
+            List::<T>::Nil => {
+                return None;
+            }
+            List::<T>::Cons(hd, tl) => {
+                self.list = tl;
+                return Some(hd);
+            }
+        }
+    }
+}
+
+impl<'a, T: Clone> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        return self.iter();
+    }
+}
+
+/// 先頭の要素から順に比較する辞書式順序。短いリストは、それを先頭に含む長いリストより小さい
+impl<T: Clone + PartialOrd> PartialOrd for List<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (mut a, mut b) = (self, other);
+        loop {
+            match (a, b) {
+                (List::<T>::Nil, List::<T>::Nil) => {
+                    return Some(Ordering::Equal);
+                }
+                (List::<T>::Nil, _) => {
+                    return Some(Ordering::Less);
+                }
+                (_, List::<T>::Nil) => {
+                    return Some(Ordering::Greater);
+                }
+                (List::<T>::Cons(x, xs), List::<T>::Cons(y, ys)) => {
+                    match x.partial_cmp(y) {
+                        Some(Ordering::Equal) => {}
+                        res => {
+                            return res;
+                        }
+                    }
+                    a = xs;
+                    b = ys;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone + Ord> Ord for List<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // 要素が Ord なので、partial_cmp は必ず Some を返す
+        return self.partial_cmp(other).unwrap();
+    }
+}
+
+impl<T: Clone> Default for List<T> {
+    fn default() -> Self {
+        return List::<T>::new();
+    }
+}
+
 impl<T: Clone> List<T> {
     /// `List<T>` を新規作成。
     pub fn new() -> List<T> {
@@ -69,19 +142,43 @@ impl<T: Clone> List<T> {
         match self {
             List::<T>::Nil => return self,
             List::<T>::Cons(_, tail) => {
-                return &(**tail);
+                return tail;
             }
         }
     }
 
+    /// `n` 番目（0 始まり）の要素。範囲外なら `None`
+    pub fn get(&self, n: usize) -> Option<&T> {
+        return self.iter().nth(n);
+    }
+
+    /// 要素を先頭から順に参照で返すイテレータ。`into_iter` と異なり、リストの各セルを複製しない
+    ///
+    /// # Examples
+    /// ```
+    /// use liblisp::util::List;
+    ///
+    /// let l = List::new().cons(&3).cons(&2).cons(&1);
+    /// assert_eq!(l.iter().copied().collect::<Vec<i32>>(), vec![1, 2, 3]);
+    /// assert_eq!(l.get(1), Some(&2));
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T> {
+        return Iter { list: self };
+    }
+
     /// `List<T>` の長さ。
     pub fn len(&self) -> u32 {
+        return self.iter().count() as u32;
+    }
+
+    /// `List<T>` が空かどうか。
+    pub fn is_empty(&self) -> bool {
         match self {
             List::<T>::Nil => {
-                return 0;
+                return true;
             }
-            List::<T>::Cons(_, tail) => {
-                return tail.len() + 1;
+            List::<T>::Cons(_, _) => {
+                return false;
             }
         }
     }
@@ -97,7 +194,7 @@ impl<T: Clone> List<T> {
                 return new;
             }
             Some(hd) => {
-                return Self::reverse_(old.tail(), new.cons(&hd));
+                return Self::reverse_(old.tail(), new.cons(hd));
             }
         }
     }
@@ -0,0 +1,461 @@
+//!
+//! 式を辿ったり書き換えたりするための、汎用的な API を定義
+//!
+//! 特殊形式の引数には、評価される式の他に、関数名や仮引数、quote されたデータのように評価されないものがある。
+//! この module は特殊形式ごとの構造を知っていて、部分式をその役割（`Role`）とともに渡すので、
+//! 利用する側はリストの構造を直接扱わずに、解析や変換を書ける
+//!
+
+use crate::expression::*;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+/// 部分式が、それを含む式の中で果たす役割
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Code,    // 評価される式
+    Binding, // 変数を束縛・代入する位置の式。関数の仮引数、let や match のパターン、set の変数など
+    Name,    // defun や block などの名前
+    Data,    // quote された式のように、評価されない式
+}
+
+/// `walk` で式を辿る時に呼ばれるメソッドの集まり。
+/// 必要なメソッドだけを実装すればよい
+pub trait ExpressionVisitor {
+    /// 評価される位置の式に入る時に呼ばれる。false を返すと、その式の部分式は辿らない
+    fn enter(&mut self, _exp: &Expression) -> bool {
+        return true;
+    }
+
+    /// `enter` で true を返した式の、部分式を全て辿った後に呼ばれる
+    fn leave(&mut self, _exp: &Expression) {}
+
+    /// 変数を束縛・代入する位置の式（`Role::Binding`）に対して呼ばれる
+    fn binding(&mut self, _exp: &Expression) {}
+}
+
+/// 式を、評価される位置の部分式に限って、深さ優先で辿る。
+/// 名前や quote されたデータの中には入らず、束縛する位置の式は `ExpressionVisitor::binding` に渡す
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::Expression;
+/// use liblisp::visit::{walk, ExpressionVisitor};
+/// use std::convert::TryFrom;
+///
+/// // 評価される位置にある変数の参照を数える
+/// struct VarCounter(usize);
+/// impl ExpressionVisitor for VarCounter {
+///     fn enter(&mut self, exp: &Expression) -> bool {
+///         if let Expression::Var(_) = exp {
+///             self.0 += 1;
+///         }
+///         return true;
+///     }
+/// }
+///
+/// let exp = Expression::try_from("(defun f (*x*) (list *x* *y* (quote *z*)))".as_bytes()).unwrap();
+/// let mut counter = VarCounter(0);
+/// walk(&exp, &mut counter);
+/// assert_eq!(counter.0, 2);
+/// ```
+pub fn walk<V: ExpressionVisitor + ?Sized>(exp: &Expression, visitor: &mut V) {
+    if !visitor.enter(exp) {
+        return;
+    }
+    for_each_child(exp, |child, role| match role {
+        Role::Code => walk(child, visitor),
+        Role::Binding => visitor.binding(child),
+        Role::Name | Role::Data => {}
+    });
+    visitor.leave(exp);
+}
+
+/// 評価される位置の部分式を、内側から順に f で書き換える。
+/// 部分式を書き換えた後の式を f に渡し、その戻り値で置き換える
+///
+/// # Examples
+/// ```
+/// use liblisp::expression::Expression;
+/// use liblisp::visit::fold;
+/// use std::convert::TryFrom;
+///
+/// // 整数のリテラルを全て 2 倍にする
+/// let exp = Expression::try_from("(add 1 (quote 2) (mul 3 4))".as_bytes()).unwrap();
+/// let doubled = fold(&exp, &mut |e| match e {
+///     Expression::Int(i) => Expression::Int(i * 2),
+///     e => e,
+/// });
+/// assert_eq!(doubled.to_string(), "(add 2 (quote 2) (mul 6 8))");
+/// ```
+pub fn fold(exp: &Expression, f: &mut impl FnMut(Expression) -> Expression) -> Expression {
+    let exp = map_children(exp, |child, role| {
+        if role == Role::Code {
+            return fold(child, f);
+        }
+        return child.clone();
+    });
+    return f(exp);
+}
+
+/// 式の直下の部分式を、役割とともに順に f に渡す。リストの先頭の関数名は渡さない
+pub fn for_each_child(exp: &Expression, mut f: impl FnMut(&Expression, Role)) {
+    map_children(exp, |child, role| {
+        f(child, role);
+        return child.clone();
+    });
+}
+
+/// 式の直下の部分式を、役割とともに順に f に渡し、戻り値で置き換えた式を作る。
+/// let の束縛のように、部分式が入れ子のリストの中にある場合も、リストの構造は保つ
+pub fn map_children(
+    exp: &Expression,
+    mut f: impl FnMut(&Expression, Role) -> Expression,
+) -> Expression {
+    let l = match exp {
+        Expression::ExpressionList(l) => l,
+        _ => {
+            return exp.clone();
+        }
+    };
+    let name = match l.head() {
+        Some(Expression::Atom(name)) => name.clone(),
+        _ => {
+            return from_vec(map_roles(elements(l), |_| Role::Code, &mut f));
+        }
+    };
+    let args = elements(l.tail());
+    let args = match &*name {
+        "quote" | "boundp" => map_roles(args, |_| Role::Data, &mut f),
+        "quasiquote" => args
+            .into_iter()
+            .map(|template| map_unquoted(template, 1, &mut f))
+            .collect(),
+        // (defun name (params ...) body ...) 或いは (defun name (params ...) : type body ...)
+        "defun" | "defmacro" => {
+            let annotated = matches!(args.get(2), Some(Expression::Atom(a)) if &**a == ":");
+            map_roles(
+                args,
+                |i| match i {
+                    0 => Role::Name,
+                    1 => Role::Binding,
+                    2 | 3 if annotated => Role::Data,
+                    _ => Role::Code,
+                },
+                &mut f,
+            )
+        }
+        // (block name body ...)
+        "block" | "return-from" | "deftest" | "module" => map_roles(
+            args,
+            |i| if i == 0 { Role::Name } else { Role::Code },
+            &mut f,
+        ),
+        // (set *a* a *b* b ...)
+        "set" | "define" | "defconst" => map_roles(
+            args,
+            |i| {
+                if i % 2 == 0 {
+                    Role::Binding
+                } else {
+                    Role::Code
+                }
+            },
+            &mut f,
+        ),
+        // (let ((pattern init) ...) body ...)
+        "let" => {
+            let mut res = Vec::with_capacity(args.len());
+            for (i, arg) in args.into_iter().enumerate() {
+                match arg {
+                    Expression::ExpressionList(specs) if i == 0 => {
+                        let specs = elements(specs)
+                            .into_iter()
+                            .map(|spec| map_pattern_clause(spec, &mut f))
+                            .collect();
+                        res.push(from_vec(specs));
+                    }
+                    _ => {
+                        res.push(f(arg, Role::Code));
+                    }
+                }
+            }
+            res
+        }
+        // (dolist (*e* list) body ...)
+        "dolist" | "dotimes" => args
+            .into_iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                if i == 0 {
+                    return map_pattern_clause(arg, &mut f);
+                }
+                return f(arg, Role::Code);
+            })
+            .collect(),
+        // (match value (pattern body ...) ...)
+        "match" => args
+            .into_iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                if i == 0 {
+                    return f(arg, Role::Code);
+                }
+                return map_pattern_clause(arg, &mut f);
+            })
+            .collect(),
+        // (try body ... (catch *e* handler ...))
+        "try" => args
+            .into_iter()
+            .map(|arg| match arg {
+                Expression::ExpressionList(clause) if is_form(arg, "catch") => {
+                    let rest = map_roles(
+                        elements(clause.tail()),
+                        |i| if i == 0 { Role::Binding } else { Role::Code },
+                        &mut f,
+                    );
+                    let clause = list_from(rest).cons(clause.head().unwrap());
+                    return Expression::ExpressionList(Rc::new(clause));
+                }
+                _ => {
+                    return f(arg, Role::Code);
+                }
+            })
+            .collect(),
+        _ => map_roles(args, |_| Role::Code, &mut f),
+    };
+    let l = list_from(args).cons(&Expression::Atom(name));
+    return Expression::ExpressionList(Rc::new(l));
+}
+
+// 位置に応じた役割とともに、各要素を f で置き換える
+fn map_roles(
+    exps: Vec<&Expression>,
+    role: impl Fn(usize) -> Role,
+    f: &mut impl FnMut(&Expression, Role) -> Expression,
+) -> Vec<Expression> {
+    return exps
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| f(e, role(i)))
+        .collect();
+}
+
+// (pattern body ...) の形式のリストの、pattern を束縛する位置、残りを評価される式として置き換える
+fn map_pattern_clause(
+    exp: &Expression,
+    f: &mut impl FnMut(&Expression, Role) -> Expression,
+) -> Expression {
+    if let Expression::ExpressionList(l) = exp {
+        let clause = map_roles(
+            elements(l),
+            |i| if i == 0 { Role::Binding } else { Role::Code },
+            f,
+        );
+        return from_vec(clause);
+    }
+    return f(exp, Role::Code);
+}
+
+// quasiquote の中で、深さが 1 の unquote と unquote-splicing の引数を評価される式として置き換え、
+// それ以外はデータとして置き換える
+fn map_unquoted(
+    exp: &Expression,
+    depth: u32,
+    f: &mut impl FnMut(&Expression, Role) -> Expression,
+) -> Expression {
+    let l = match exp {
+        Expression::ExpressionList(l) => l,
+        _ => {
+            return f(exp, Role::Data);
+        }
+    };
+    let (name, depth) = if is_form(exp, "unquote") || is_form(exp, "unquote-splicing") {
+        (l.head().unwrap(), depth - 1)
+    } else if is_form(exp, "quasiquote") {
+        (l.head().unwrap(), depth + 1)
+    } else {
+        return from_vec(
+            elements(l)
+                .into_iter()
+                .map(|e| map_unquoted(e, depth, f))
+                .collect(),
+        );
+    };
+    let args = elements(l.tail()).into_iter().map(|e| {
+        if depth == 0 {
+            return f(e, Role::Code);
+        }
+        return map_unquoted(e, depth, f);
+    });
+    let args: Vec<Expression> = args.collect();
+    let l = list_from(args).cons(name);
+    return Expression::ExpressionList(Rc::new(l));
+}
+
+// (name ...) の形式の式かどうか
+fn is_form(exp: &Expression, name: &str) -> bool {
+    if let Expression::ExpressionList(l) = exp {
+        if let Some(Expression::Atom(a)) = l.head() {
+            return &**a == name;
+        }
+    }
+    return false;
+}
+
+/// リストの要素への参照を、先頭から順に並べる
+pub fn elements(l: &ExpressionList) -> Vec<&Expression> {
+    let mut res = Vec::new();
+    let mut rest = l;
+    while let Some(e) = rest.head() {
+        res.push(e);
+        rest = rest.tail();
+    }
+    return res;
+}
+
+// 式を先頭から順に並べたリストを作る
+fn list_from(v: Vec<Expression>) -> ExpressionList {
+    return v
+        .iter()
+        .rev()
+        .fold(ExpressionList::new(), |acc, e| acc.cons(e));
+}
+
+// list_from で作ったリストの式
+fn from_vec(v: Vec<Expression>) -> Expression {
+    return Expression::ExpressionList(Rc::new(list_from(v)));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::visit::*;
+    use alloc::string::{String, ToString};
+    use core::convert::TryFrom;
+
+    fn roles(src: &str) -> Vec<(String, Role)> {
+        let exp = Expression::try_from(src.as_bytes()).unwrap();
+        let mut res = Vec::new();
+        for_each_child(&exp, |child, role| res.push((child.to_string(), role)));
+        return res;
+    }
+
+    #[test]
+    fn role_tests() {
+        use Role::*;
+        let cases = vec![
+            ("(add 1 *x*)", vec![("1", Code), ("*x*", Code)]),
+            ("(quote (f *x*))", vec![("(f *x*)", Data)]),
+            (
+                "(defun f (*a*) *a*)",
+                vec![("f", Name), ("(*a*)", Binding), ("*a*", Code)],
+            ),
+            (
+                "(set *a* 1 *b* 2)",
+                vec![("*a*", Binding), ("1", Code), ("*b*", Binding), ("2", Code)],
+            ),
+            (
+                "(let ((*a* 1) (*b* 2)) *a*)",
+                vec![
+                    ("*a*", Binding),
+                    ("1", Code),
+                    ("*b*", Binding),
+                    ("2", Code),
+                    ("*a*", Code),
+                ],
+            ),
+            (
+                "(dolist (*e* *l*) *e*)",
+                vec![("*e*", Binding), ("*l*", Code), ("*e*", Code)],
+            ),
+            (
+                "(match *v* ((list *a*) *a*) (_ 0))",
+                vec![
+                    ("*v*", Code),
+                    ("(list *a*)", Binding),
+                    ("*a*", Code),
+                    ("_", Binding),
+                    ("0", Code),
+                ],
+            ),
+            (
+                "(try (f) (catch *e* *e*))",
+                vec![("(f)", Code), ("*e*", Binding), ("*e*", Code)],
+            ),
+            ("(block b 1)", vec![("b", Name), ("1", Code)]),
+            // quasiquote の中は、深さが 1 の unquote の引数だけが評価される
+            (
+                "`(a ,*x* ,@(f) `(b ,*y* ,,*z*))",
+                vec![
+                    ("a", Data),
+                    ("*x*", Code),
+                    ("(f)", Code),
+                    ("b", Data),
+                    ("*y*", Data),
+                    ("*z*", Code),
+                ],
+            ),
+        ];
+        for (src, expected) in cases {
+            let expected: Vec<(String, Role)> = expected
+                .into_iter()
+                .map(|(s, r)| (s.to_string(), r))
+                .collect();
+            assert_eq!(roles(src), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn map_children_tests() {
+        // 構造を保ったまま、評価される位置の式だけを置き換える
+        let cases = vec![
+            ("(let ((*a* 1)) (add *a* 2))", "(let ((*a* 0)) 0)"),
+            ("(try 1 (catch *e* 2))", "(try 0 (catch *e* 0))"),
+            ("`(a ,1 (b ,@2))", "`(a ,0 (b ,@0))"),
+            ("(defun f (*x*) 1 2)", "(defun f (*x*) 0 0)"),
+        ];
+        for (src, expected) in cases {
+            let exp = Expression::try_from(src.as_bytes()).unwrap();
+            let expected = Expression::try_from(expected.as_bytes()).unwrap();
+            let res = map_children(&exp, |child, role| {
+                if role == Role::Code {
+                    return Expression::Int(0);
+                }
+                return child.clone();
+            });
+            assert_eq!(res, expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn walk_tests() {
+        // enter / leave の順序と、enter で false を返した式の部分式を辿らないこと
+        struct Recorder(Vec<String>);
+        impl ExpressionVisitor for Recorder {
+            fn enter(&mut self, exp: &Expression) -> bool {
+                self.0.push(format!("enter {}", exp));
+                return !exp.to_string().starts_with("(skip");
+            }
+            fn leave(&mut self, exp: &Expression) {
+                self.0.push(format!("leave {}", exp));
+            }
+            fn binding(&mut self, exp: &Expression) {
+                self.0.push(format!("bind {}", exp));
+            }
+        }
+        let exp = Expression::try_from("(let ((*a* 1)) (skip *b*))".as_bytes()).unwrap();
+        let mut recorder = Recorder(Vec::new());
+        walk(&exp, &mut recorder);
+        assert_eq!(
+            recorder.0,
+            vec![
+                "enter (let ((*a* 1)) (skip *b*))",
+                "bind *a*",
+                "enter 1",
+                "leave 1",
+                "enter (skip *b*)",
+                "leave (let ((*a* 1)) (skip *b*))",
+            ]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//!
+//! ブラウザから利用するための wasm-bindgen によるバインディングを定義。`wasm` feature で有効になる。
+//! no_std でのビルドを妨げないよう、Cargo.toml では cdylib を指定していないので、次のようにビルドする。
+//!
+//! ```text
+//! cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen target/wasm32-unknown-unknown/release/liblisp.wasm --out-dir pkg
+//! ```
+//!
+
+use crate::eval::*;
+use crate::expression::*;
+use wasm_bindgen::prelude::*;
+
+/// ソース中のトップレベルの式を、標準ライブラリを読み込んだ `Context` で先頭から順に評価し、
+/// 最後の式の値を `Type::to_display_string` で文字列にして返す。読み込みや評価に失敗した場合は `error: ...` という文字列を返す
+#[wasm_bindgen]
+pub fn parse_and_eval(src: &str) -> String {
+    let program = match parse_program(src) {
+        Ok(p) => p,
+        Err(e) => {
+            return format!("error: {:?}", e);
+        }
+    };
+    let mut context = Context::new_with_stdlib();
+    let mut res = String::new();
+    for exp in &program {
+        match eval_with_context(exp, &mut context) {
+            Ok(v) => {
+                res = v.to_display_string();
+            }
+            Err(e) => {
+                return format!("error: {}", e);
+            }
+        }
+    }
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wasm::*;
+
+    #[test]
+    fn parse_and_eval_tests() {
+        assert_eq!(
+            parse_and_eval("(define *a* 2) (list *a* (max 1 3))"),
+            "(2 3)"
+        );
+        assert_eq!(parse_and_eval(""), "");
+        assert_eq!(parse_and_eval("\"a b\""), "a b");
+        assert_eq!(parse_and_eval("(add 1"), "error: UnexpectedEof");
+        assert_eq!(parse_and_eval("(div 1 0)"), "error: DivisionByZero");
+    }
+}
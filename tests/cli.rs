@@ -0,0 +1,108 @@
+#![cfg(feature = "std")]
+
+// src/bin/lisp.rs のコマンドラインツールを、実際にプロセスとして起動して確かめる
+
+use std::process::{Command, Output};
+
+// src をファイルに書き込み、lisp <command> <file> を実行する
+fn lisp(command: &str, name: &str, src: &str) -> Output {
+    let path =
+        std::env::temp_dir().join(format!("liblisp-cli-{}-{}.lisp", name, std::process::id()));
+    std::fs::write(&path, src).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lisp"))
+        .arg(command)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    return output;
+}
+
+fn stdout(output: &Output) -> String {
+    return String::from_utf8(output.stdout.clone()).unwrap();
+}
+
+fn stderr(output: &Output) -> String {
+    return String::from_utf8(output.stderr.clone()).unwrap();
+}
+
+#[test]
+fn run_test() {
+    // 最後の値が Int なら、その値が終了コードになる
+    let output = lisp("run", "run", "(define *a* 2)\n(max *a* (abs -5))\n");
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(stdout(&output), "5\n");
+
+    // Str は " で囲まずに出力する
+    let output = lisp("run", "run-str", "(list 1)\n\"a b\"\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "a b\n");
+
+    // Void は出力しない
+    let output = lisp(
+        "run",
+        "run-void",
+        "(set *a* 0)\n(while (lt *a* 2) (incf *a*))\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let output = lisp("run", "run-exit", "(exit 3)\n(add 1 2)\n");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = lisp("run", "run-error", "(define *a* 1)\n(add *a* :b)\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "eval error: TypeMismatch\n --> 2:1\n  |\n2 | (add *a* :b)\n  | ^^^^^^^^^^^^\n"
+    );
+
+    let output = lisp("run", "run-parse-error", "(add 1\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("parse error: UnexpectedEof\n --> 2:1\n"));
+}
+
+#[test]
+fn check_test() {
+    let output = lisp(
+        "check",
+        "check-clean",
+        "(defun f (*x*) (add *x* 1))\n(f 1)\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+
+    // 評価はしない
+    let output = lisp("check", "check", "(exit 5)\n(frob *y*)\n");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("warning: unknown function frob\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("warning: variable *y* is never defined\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn fmt_test() {
+    let output = lisp("fmt", "fmt", "(define *a*   1) (add\n *a*  2)");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(define *a* 1)\n\n(add *a* 2)\n");
+}
+
+#[test]
+fn usage_test() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lisp"))
+        .arg("frob")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("usage:"));
+}
@@ -1,8 +1,9 @@
-use std::convert::TryFrom;
+#![allow(clippy::assertions_on_constants)]
+
 use liblisp::eval::*;
 use liblisp::expression::*;
 use liblisp::types::*;
-
+use std::convert::TryFrom;
 
 #[test]
 fn make_expression_from_string_and_eval_test() {
@@ -16,3 +17,140 @@ fn make_expression_from_string_and_eval_test() {
 
     assert!(true);
 }
+
+#[test]
+fn eval_with_context_keeps_definitions_test() {
+    // 同じ Context を使えば、前の評価で定義した変数や関数を使える
+    let mut context = Context::new();
+    let def =
+        Expression::try_from("(progn (define *a* 10) (defun double (*x*) (mul *x* 2)))".as_bytes())
+            .unwrap();
+    eval_with_context(&def, &mut context).unwrap();
+
+    let exp = Expression::try_from("(double *a*)".as_bytes()).unwrap();
+    assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(20)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn eval_file_test() {
+    // デフォルトの FsLoader でファイルを読み込む
+    let path = std::env::temp_dir().join(format!("liblisp-eval-file-{}.lisp", std::process::id()));
+    std::fs::write(
+        &path,
+        "(define *a* 1)\n(defun inc (*x*) (add *x* 1))\n(inc *a*)\n",
+    )
+    .unwrap();
+
+    let mut context = Context::new();
+    let res = context.eval_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(res, Ok(Type::Int(2)));
+
+    let exp = Expression::try_from("(inc 10)".as_bytes()).unwrap();
+    assert_eq!(eval_with_context(&exp, &mut context), Ok(Type::Int(11)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn std_filesystem_test() {
+    // デフォルトの StdFileSystem で、実際のファイルを読み書きする
+    use liblisp::capabilities::Capabilities;
+
+    let path = std::env::temp_dir().join(format!("liblisp-spit-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let mut context = Context::new();
+    context.set_capabilities(Capabilities::all());
+    let src = format!(
+        "(progn (spit \"{0}\" \"report\") (list (file-exists \"{0}\") (slurp \"{0}\")))",
+        path
+    );
+    let exp = Expression::try_from(src.as_bytes()).unwrap();
+    let res = eval_with_context(&exp, &mut context);
+    let written = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written.unwrap(), "report");
+    assert_eq!(res.unwrap().to_string(), "(1 \"report\")");
+}
+
+#[test]
+fn interpreter_test() {
+    // Expression や Context を直接扱わずに、文字列のソースを評価できる
+    use liblisp::interpreter::*;
+
+    let mut interp = Interpreter::new();
+    interp.define_var("*n*", Type::Int(4)).unwrap();
+    interp.register_fn("square", |args: &[Type]| match args {
+        [Type::Int(i)] => Ok(Type::Int(i * i)),
+        _ => Err(EvalError::TypeMismatch),
+    });
+    interp
+        .eval_str("(defun f (*x*) (add (square *x*) 1))")
+        .unwrap();
+    assert_eq!(interp.eval_str("(f *n*)"), Ok(Type::Int(17)));
+    assert!(matches!(interp.eval_str("(f"), Err(LispError::Parse(_))));
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn derive_test() {
+    // 構造体をそのまま連想リストとしてスクリプトに渡し、スクリプトで作った連想リストから構造体に戻せる
+    use liblisp::convert::{FromLisp, ToLisp};
+    use liblisp::interpreter::*;
+
+    #[derive(Debug, PartialEq, ToLisp, FromLisp)]
+    struct Config {
+        name: String,
+        max_size: i32,
+        verbose: bool,
+        tag: Option<String>,
+    }
+
+    let config = Config {
+        name: "app".into(),
+        max_size: 10,
+        verbose: true,
+        tag: None,
+    };
+    let t = config.to_lisp();
+    assert_eq!(
+        t.to_string(),
+        "((:name \"app\") (:max-size 10) (:verbose 1) (:tag #<void>))"
+    );
+    assert_eq!(Config::from_lisp(&t), Ok(config));
+
+    let mut interp = Interpreter::new();
+    interp.define_var("*config*", t).unwrap();
+    assert_eq!(
+        interp.eval_str("(match *config* ((list _ (list :max-size *n*) _ _) *n*))"),
+        Ok(Type::Int(10))
+    );
+
+    // キーの順序は問わず、Option のフィールドは省略できる
+    let t = interp
+        .eval_str("(quote ((:verbose 0) (:max-size 3) (:name \"x\")))")
+        .unwrap();
+    let expected = Config {
+        name: "x".into(),
+        max_size: 3,
+        verbose: false,
+        tag: None,
+    };
+    assert_eq!(Config::from_lisp(&t), Ok(expected));
+
+    let cases = vec![
+        "(quote ((:name \"x\") (:verbose 0)))",
+        "(quote ((:name \"x\") (:max-size 3) (:verbose 0) (:unknown 1)))",
+        "(quote ((:name 1) (:max-size 3) (:verbose 0)))",
+        "1",
+    ];
+    for src in cases {
+        let t = interp.eval_str(src).unwrap();
+        assert_eq!(
+            Config::from_lisp(&t),
+            Err(EvalError::TypeMismatch),
+            "{}",
+            src
+        );
+    }
+}
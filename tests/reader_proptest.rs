@@ -0,0 +1,56 @@
+use liblisp::expression::*;
+use liblisp::format::*;
+use liblisp::types::Int;
+use liblisp::util::List;
+use proptest::prelude::*;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+fn expression_strategy() -> impl Strategy<Value = Expression> {
+    let leaf = prop_oneof![
+        (0..=Int::MAX).prop_map(Expression::Int),
+        "[a-zA-Z][a-zA-Z0-9-]{0,8}".prop_map(|a| Expression::Atom(Rc::from(a))),
+        "\\*[a-zA-Z][a-zA-Z0-9]{0,8}\\*".prop_map(|v| Expression::Var(Rc::from(v))),
+        // エスケープが必要な " や \ 、制御文字も含む任意の文字列
+        prop::collection::vec(any::<char>(), 0..12)
+            .prop_map(|s| Expression::Str(Rc::from(s.into_iter().collect::<String>()))),
+        "[a-zA-Z][a-zA-Z0-9-]{0,8}".prop_map(|k| Expression::Keyword(Rc::from(k))),
+    ];
+    return leaf.prop_recursive(8, 64, 8, |inner| {
+        prop::collection::vec(inner, 0..8).prop_map(|l| {
+            let list = l.iter().rev().fold(List::new(), |acc, e| acc.cons(e));
+            return Expression::ExpressionList(Rc::new(list));
+        })
+    });
+}
+
+proptest! {
+    // 出力した文字列を読み直すと、元の Expression に戻る
+    #[test]
+    fn print_and_reparse_roundtrip(exp in expression_strategy()) {
+        let printed = exp.to_string();
+        let reparsed = Expression::try_from(printed.as_bytes());
+        prop_assert_eq!(reparsed, Ok(exp));
+    }
+
+    // 整形した文字列を読み直しても、元の Expression に戻る
+    #[test]
+    fn format_and_reparse_roundtrip(exp in expression_strategy(), width in 0usize..40) {
+        let options = FormatOptions { width, indent: 2 };
+        let formatted = format_expression(&exp, &options);
+        let reparsed = Expression::try_from(formatted.as_bytes());
+        prop_assert_eq!(reparsed, Ok(exp));
+    }
+
+    // 任意の byte 列に対して panic しない
+    #[test]
+    fn never_panics_on_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = Expression::try_from(&bytes[..]);
+    }
+
+    // lisp らしい文字だけからなる入力に対しても panic しない
+    #[test]
+    fn never_panics_on_lisp_like_input(src in "[()* a-z0-9\\n'`,@\"\\\\{}-]{0,64}") {
+        let _ = Expression::try_from(src.as_bytes());
+    }
+}